@@ -0,0 +1,8 @@
+-- Server-side state of the admin sessions, the cookie only holds the session key
+CREATE TABLE sessions
+(
+    session_key TEXT        NOT NULL,
+    state       TEXT        NOT NULL,
+    expires_at  timestamptz NOT NULL,
+    PRIMARY KEY (session_key)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE subscriptions ADD COLUMN unsubscribed_at timestamptz NULL;
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_status_check
+    CHECK (status IN ('pending_confirmation', 'confirmed', 'unsubscribed'));
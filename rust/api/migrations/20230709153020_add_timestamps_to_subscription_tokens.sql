@@ -0,0 +1,8 @@
+-- Existing tokens get a week left
+BEGIN;
+    ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+    ALTER TABLE subscription_tokens
+        ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '7 days';
+    ALTER TABLE subscription_tokens ALTER COLUMN expires_at DROP DEFAULT;
+    CREATE INDEX subscription_tokens_subscriber_id_idx ON subscription_tokens (subscriber_id);
+COMMIT;
@@ -0,0 +1,48 @@
+-- every change of a template is a new version, the latest one is used to send emails
+CREATE TABLE email_templates
+(
+    name         TEXT        NOT NULL,
+    version      INT         NOT NULL,
+    subject      TEXT        NOT NULL,
+    html_content TEXT        NOT NULL,
+    -- generated from the HTML when missing
+    text_content TEXT        NULL,
+    created_at   timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (name, version)
+);
+
+INSERT INTO email_templates (name, version, subject, html_content, text_content)
+VALUES (
+    'layout',
+    1,
+    '{{ subject }}',
+    '<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{{ subject }}</title>
+</head>
+<body>
+{{ content | safe }}
+{% if unsubscribe_link %}
+    <p><small><a href="{{ unsubscribe_link | safe }}">Unsubscribe</a></small></p>
+{% endif %}
+</body>
+</html>',
+    '{{ content }}
+{% if unsubscribe_link %}
+--
+Unsubscribe: {{ unsubscribe_link }}
+{% endif %}'
+), (
+    'confirmation',
+    1,
+    'Welcome!',
+    '<p>Welcome to our newsletter, {{ name }}!</p>
+<p>Click <a href="{{ confirmation_link | safe }}">here</a> to confirm your subscription.</p>',
+    'Welcome to our newsletter, {{ name }}!
+Visit {{ confirmation_link }} to confirm your subscription.'
+);
+
+-- the text of an issue is generated from its HTML when missing
+ALTER TABLE newsletter_issues ALTER COLUMN text_content DROP NOT NULL;
@@ -0,0 +1,5 @@
+-- an issue scheduled for later has no `published_at` until the scheduler releases it
+ALTER TABLE newsletter_issues ADD COLUMN publish_at timestamptz NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+CREATE INDEX newsletter_issues_scheduled_idx ON newsletter_issues (publish_at)
+    WHERE published_at IS NULL;
@@ -0,0 +1,9 @@
+-- IANA time zone of the subscriber, like `Europe/Paris`. Nothing tells the time zone of the
+-- existing subscribers: they keep NULL, and get the issues scheduled at a wall-clock time in the
+-- `default_timezone` of the delivery settings until they give theirs
+ALTER TABLE subscriptions ADD COLUMN timezone TEXT NULL;
+-- an issue scheduled at a wall-clock time reaches every subscriber at that time in their own time
+-- zone: `publish_at` is the first of these instants, and the subscribers whose time has come are
+-- enqueued up to `released_until`
+ALTER TABLE newsletter_issues ADD COLUMN publish_at_local TIMESTAMP NULL;
+ALTER TABLE newsletter_issues ADD COLUMN released_until timestamptz NULL;
@@ -0,0 +1,13 @@
+-- the published issues are public, at `/issues/{slug}`
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL;
+UPDATE newsletter_issues
+SET slug = trim(BOTH '-' FROM lower(regexp_replace(title, '[^a-zA-Z0-9]+', '-', 'g')))
+    || '-' || left(newsletter_issue_id::text, 8);
+ALTER TABLE newsletter_issues ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
+
+-- unknown for the issues published before
+ALTER TABLE newsletter_issues ADD COLUMN author_user_id uuid NULL REFERENCES users (user_id);
+
+CREATE INDEX newsletter_issues_published_at_idx ON newsletter_issues (published_at DESC)
+    WHERE published_at IS NOT NULL;
@@ -0,0 +1,15 @@
+-- bearer tokens of the publishing API, only their SHA-256 hash is stored
+CREATE TABLE api_tokens
+(
+    id           uuid        NOT NULL PRIMARY KEY,
+    user_id      uuid        NOT NULL REFERENCES users (user_id),
+    name         TEXT        NOT NULL,
+    token_hash   TEXT        NOT NULL UNIQUE,
+    scopes       TEXT[]      NOT NULL,
+    created_at   timestamptz NOT NULL DEFAULT now(),
+    last_used_at timestamptz NULL,
+    -- never expires when missing
+    expires_at   timestamptz NULL,
+    revoked_at   timestamptz NULL
+);
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
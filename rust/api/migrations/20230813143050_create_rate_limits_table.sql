@@ -0,0 +1,7 @@
+-- requests counted in fixed windows, per key (`ip:<address>` or `email:<address>`)
+CREATE TABLE rate_limits
+(
+    key        TEXT        NOT NULL PRIMARY KEY,
+    hits       INT         NOT NULL,
+    expires_at timestamptz NOT NULL
+);
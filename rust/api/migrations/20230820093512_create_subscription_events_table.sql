@@ -0,0 +1,22 @@
+-- a suppressed address is never emailed again, and can't sign up again
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_status_check;
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_status_check
+    CHECK (status IN ('pending_confirmation', 'confirmed', 'unsubscribed', 'suppressed'));
+
+CREATE TABLE subscription_events
+(
+    id            BIGINT      GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    subscriber_id uuid        NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    event         TEXT        NOT NULL,
+    occurred_at   timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX subscription_events_subscriber_id_idx ON subscription_events (subscriber_id);
+
+-- the history known from before the events were recorded
+INSERT INTO subscription_events (subscriber_id, event, occurred_at)
+SELECT id, 'subscribed', subscribed_at FROM subscriptions;
+INSERT INTO subscription_events (subscriber_id, event, occurred_at)
+SELECT id, 'unsubscribed', unsubscribed_at FROM subscriptions WHERE unsubscribed_at IS NOT NULL;
+
+-- the order of the subscriber listing and of its cursors
+CREATE INDEX subscriptions_subscribed_at_idx ON subscriptions (subscribed_at DESC, id DESC);
@@ -0,0 +1,35 @@
+-- every export and erasure request (GDPR), kept after the erasure: no address, only the ids
+CREATE TABLE data_request_audit
+(
+    id            BIGINT      GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    -- no foreign key, the erased subscribers are gone; NULL for an unknown address
+    subscriber_id uuid        NULL,
+    action        TEXT        NOT NULL,
+    -- the admin who deleted the subscriber, NULL when the subscriber asked
+    user_id       uuid        NULL,
+    occurred_at   timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX data_request_audit_subscriber_id_idx ON data_request_audit (subscriber_id);
+
+INSERT INTO email_templates (name, version, subject, html_content, text_content)
+VALUES (
+    'data_export',
+    1,
+    'Your data',
+    '<p>Hello {{ name }},</p>
+<p>Click <a href="{{ export_link | safe }}">here</a> to download everything we store about you.</p>
+<p>The link works for a day. If you didn''t ask for it, you can ignore this email.</p>',
+    'Hello {{ name }},
+Visit {{ export_link }} to download everything we store about you.
+The link works for a day. If you didn''t ask for it, you can ignore this email.'
+), (
+    'data_erasure',
+    1,
+    'Erase your data',
+    '<p>Hello {{ name }},</p>
+<p>Click <a href="{{ erasure_link | safe }}">here</a> to erase everything we store about you, your subscription included.</p>
+<p>The link works for a day. If you didn''t ask for it, you can ignore this email.</p>',
+    'Hello {{ name }},
+Visit {{ erasure_link }} to erase everything we store about you, your subscription included.
+The link works for a day. If you didn''t ask for it, you can ignore this email.'
+);
@@ -0,0 +1,62 @@
+-- the addresses are compared in lowercase: the subscribers whose addresses only differ by their case
+-- are merged into one, the others are kept here for the admins to review
+CREATE TABLE merged_subscriptions
+(
+    id            uuid        NOT NULL PRIMARY KEY,
+    email         TEXT        NOT NULL,
+    name          TEXT        NOT NULL,
+    status        TEXT        NOT NULL,
+    subscribed_at timestamptz NOT NULL,
+    -- the subscriber kept for the address, erasing it erases the merged ones too
+    merged_into   uuid        NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    merged_at     timestamptz NOT NULL DEFAULT now()
+);
+
+-- one subscriber per address: a suppressed one first (it must never be emailed again), then a
+-- confirmed one, then the oldest
+INSERT INTO merged_subscriptions (id, email, name, status, subscribed_at, merged_into)
+SELECT id, email, name, status, subscribed_at, kept_id
+FROM (
+    SELECT id, email, name, status, subscribed_at,
+           first_value(id) OVER same_address AS kept_id,
+           row_number() OVER same_address AS rank
+    FROM subscriptions
+    WINDOW same_address AS (
+        PARTITION BY lower(email)
+        ORDER BY CASE status WHEN 'suppressed' THEN 0 WHEN 'confirmed' THEN 1 ELSE 2 END,
+                 subscribed_at, id
+    )
+) ranked
+WHERE rank > 1;
+
+DELETE FROM subscription_tokens WHERE subscriber_id IN (SELECT id FROM merged_subscriptions);
+DELETE FROM subscriptions WHERE id IN (SELECT id FROM merged_subscriptions);
+
+UPDATE subscriptions
+SET email = lower(email)
+WHERE email <> lower(email);
+
+-- the deliveries waiting in the queue go to the same addresses, once per address
+DELETE FROM issue_delivery_queue
+WHERE ctid IN (
+    SELECT ctid
+    FROM (
+        SELECT ctid,
+               row_number() OVER (
+                   PARTITION BY newsletter_issue_id, lower(subscriber_email)
+                   ORDER BY subscriber_email = lower(subscriber_email) DESC
+               ) AS rank
+        FROM issue_delivery_queue
+    ) ranked
+    WHERE rank > 1
+);
+UPDATE issue_delivery_queue
+SET subscriber_email = lower(subscriber_email)
+WHERE subscriber_email <> lower(subscriber_email);
+
+DO $$
+BEGIN
+    RAISE NOTICE '% subscribers merged into another one with the same address, see merged_subscriptions',
+        (SELECT count(*) FROM merged_subscriptions);
+END
+$$;
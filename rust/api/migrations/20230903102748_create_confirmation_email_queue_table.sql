@@ -0,0 +1,8 @@
+-- the confirmation emails of the imported subscribers, sent by the delivery workers
+CREATE TABLE confirmation_email_queue
+(
+    subscription_token TEXT        NOT NULL PRIMARY KEY
+        REFERENCES subscription_tokens (subscription_token) ON DELETE CASCADE,
+    n_retries          INT         NOT NULL DEFAULT 0,
+    execute_after      timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,10 @@
+-- the issues sent to every subscriber, part of their data: exported and erased with it
+CREATE TABLE issue_deliveries
+(
+    subscriber_id       uuid        NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    newsletter_issue_id uuid        NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    sent_at             timestamptz NOT NULL,
+    PRIMARY KEY (subscriber_id, newsletter_issue_id)
+);
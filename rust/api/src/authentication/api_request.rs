@@ -0,0 +1,126 @@
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use anyhow::Context;
+use base64::Engine;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{
+    validate_api_token, validate_credentials, ApiScope, AuthError, Credentials, PasswordHashing,
+};
+use crate::routes::error_chain_fmt;
+
+// why the request of an API client is turned down
+#[derive(thiserror::Error)]
+pub enum ApiAuthError {
+    #[error("Authentication failed")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error("The API token does not have the scope of this request")]
+    MissingScope,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ApiAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<AuthError> for ApiAuthError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => ApiAuthError::InvalidCredentials(e.into()),
+            AuthError::UnexpectedError(_) => ApiAuthError::UnexpectedError(e.into()),
+        }
+    }
+}
+
+// the answer to a request without valid credentials: either scheme is accepted
+pub fn unauthorized_response(realm: &str) -> HttpResponse {
+    let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+    for scheme in ["Basic", "Bearer"] {
+        response.headers_mut().append(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_str(&format!(r#"{scheme} realm="{realm}""#)).expect("Invalid realm"),
+        );
+    }
+    response
+}
+
+/*
+The user of an API token (`Authorization: Bearer`) having the scope, or of the 'Basic'
+credentials, recorded on the span of the request (its `username` and `user_id` fields).
+*/
+pub async fn authenticate_api_request(
+    request: &HttpRequest,
+    pool: &PgPool,
+    password_hashing: &PasswordHashing,
+    scope: ApiScope,
+) -> Result<Uuid, ApiAuthError> {
+    if let Some(token) =
+        bearer_token(request.headers()).map_err(ApiAuthError::InvalidCredentials)?
+    {
+        let owner = validate_api_token(&token, pool).await?;
+        tracing::Span::current().record("user_id", tracing::field::display(&owner.user_id));
+        if !owner.allows(scope) {
+            return Err(ApiAuthError::MissingScope);
+        }
+        return Ok(owner.user_id);
+    }
+    let credentials = basic_authentication(request.headers())
+        // bubble up error
+        .map_err(ApiAuthError::InvalidCredentials)?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let user_id = validate_credentials(credentials, pool, password_hashing).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    Ok(user_id)
+}
+
+// the token of the 'Bearer' scheme, if it is the scheme of the `Authorization` header
+fn bearer_token(headers: &HeaderMap) -> Result<Option<Secret<String>>, anyhow::Error> {
+    let Some(header_value) = headers.get("Authorization") else {
+        return Ok(None);
+    };
+    let header_value = header_value
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string")?;
+    Ok(header_value
+        .strip_prefix("Bearer ")
+        .map(|token| Secret::new(token.trim().to_owned())))
+}
+
+fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    // The header value, fi present, must be a valid UTF8 string
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string")?;
+    let base64encoded_credentials = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_credentials)
+        .context("Failed to base64-decode 'Basic' credentials")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8")?;
+
+    // split on ':' into segments
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth"))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth"))?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: Secret::new(password),
+    })
+}
@@ -0,0 +1,198 @@
+use anyhow::Context;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AuthError;
+
+// tells the tokens apart from other secrets, in logs and secret scanners
+const TOKEN_PREFIX: &str = "nlt_";
+
+/// What an API token is allowed to do. A user authenticated with a password can do everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiScope {
+    // publish an issue, now or later
+    Publish,
+    // list, reschedule and cancel the scheduled issues
+    Schedule,
+    // list, export, suppress and delete the subscribers
+    ManageSubscribers,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 3] = [
+        ApiScope::Publish,
+        ApiScope::Schedule,
+        ApiScope::ManageSubscribers,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Publish => "newsletters:publish",
+            ApiScope::Schedule => "newsletters:schedule",
+            ApiScope::ManageSubscribers => "subscribers:manage",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+// the owner of a valid token
+pub struct TokenOwner {
+    pub user_id: Uuid,
+    scopes: Vec<String>,
+}
+
+impl TokenOwner {
+    pub fn allows(&self, scope: ApiScope) -> bool {
+        self.scopes.iter().any(|s| s == scope.as_str())
+    }
+}
+
+/*
+The tokens are random, a fast hash is enough to keep them secret in the database (unlike
+passwords, they can't be guessed from a dictionary). The hash is also the lookup key.
+*/
+fn hash_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+fn generate_token() -> Secret<String> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let encoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+    Secret::new(format!("{TOKEN_PREFIX}{encoded}"))
+}
+
+// returns the token itself, it is not stored and can't be shown again
+#[tracing::instrument(name = "Create API token", skip(pool, scopes))]
+pub async fn create_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    scopes: &[ApiScope],
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<Secret<String>, anyhow::Error> {
+    let token = generate_token();
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_owned()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        hash_token(&token),
+        &scopes,
+        expires_at,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the API token")?;
+    Ok(token)
+}
+
+// the most recent first
+pub async fn list_api_tokens(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiToken>, sqlx::Error> {
+    sqlx::query_as!(
+        ApiToken,
+        r#"
+        SELECT id, name, scopes, created_at, last_used_at, expires_at, revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// false when the user has no such token, or it was already revoked
+#[tracing::instrument(name = "Revoke API token", skip(pool))]
+pub async fn revoke_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let n_revoked = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        token_id,
+        user_id
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_revoked > 0)
+}
+
+// a token neither revoked nor expired, its use is recorded
+#[tracing::instrument(name = "Validate API token", skip(token, pool))]
+pub async fn validate_api_token(
+    token: &Secret<String>,
+    pool: &PgPool,
+) -> Result<TokenOwner, AuthError> {
+    let owner = sqlx::query_as!(
+        TokenOwner,
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = now()
+        WHERE token_hash = $1
+            AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > now())
+        RETURNING user_id, scopes
+        "#,
+        hash_token(token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate the API token")?
+    .ok_or_else(|| {
+        AuthError::InvalidCredentials(anyhow::anyhow!("Unknown, revoked or expired API token"))
+    })?;
+    Ok(owner)
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::ExposeSecret;
+
+    use super::{generate_token, hash_token};
+
+    #[test]
+    fn tokens_are_prefixed_and_unique() {
+        let token = generate_token();
+        let other_token = generate_token();
+
+        assert!(token.expose_secret().starts_with("nlt_"));
+        assert_ne!(token.expose_secret(), other_token.expose_secret());
+    }
+
+    #[test]
+    fn the_hash_of_a_token_is_stable() {
+        let token = generate_token();
+
+        let hash = hash_token(&token);
+
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_token(&token));
+    }
+}
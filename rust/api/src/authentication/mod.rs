@@ -0,0 +1,15 @@
+mod api_request;
+mod api_tokens;
+mod middleware;
+mod password;
+
+pub use api_request::{authenticate_api_request, unauthorized_response, ApiAuthError};
+pub use api_tokens::{
+    create_api_token, list_api_tokens, revoke_api_token, validate_api_token, ApiScope, ApiToken,
+    TokenOwner,
+};
+pub use middleware::{reject_anonymous_users, UserId};
+pub use password::{
+    change_password, get_username, validate_credentials, validate_password_strength, AuthError,
+    Credentials, PasswordHashing,
+};
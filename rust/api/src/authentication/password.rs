@@ -0,0 +1,360 @@
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
+
+use crate::configuration::PasswordHashingSettings;
+use crate::telemetry::spawn_blocking_with_tracing;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/*
+Argon2 runs on the blocking threads of tokio, at most `max_concurrency` hashes at once: a hash
+takes tens of milliseconds of CPU by design, it would stall the async workers, and a burst of
+logins would take every blocking thread.
+*/
+#[derive(Clone)]
+pub struct PasswordHashing {
+    params: Params,
+    permits: Arc<Semaphore>,
+    // verified when the username is unknown, so that it takes as long as a wrong password
+    fallback_hash: Secret<String>,
+}
+
+impl PasswordHashing {
+    // hashes a random password for the fallback, the caller is blocked meanwhile
+    pub fn new(settings: &PasswordHashingSettings) -> Result<Self, anyhow::Error> {
+        let params = Params::new(
+            settings.memory_kib,
+            settings.iterations,
+            settings.parallelism,
+            None,
+        )
+        .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {e}"))?;
+        let fallback_password = Secret::new(Uuid::new_v4().to_string());
+        let fallback_hash = compute_password_hash(&params, fallback_password)?;
+        Ok(Self {
+            params,
+            permits: Arc::new(Semaphore::new(settings.max_concurrency.get())),
+            fallback_hash,
+        })
+    }
+
+    // a PHC string, with the configured parameters
+    pub async fn hash(&self, password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+        let params = self.params.clone();
+        self.run(move || compute_password_hash(&params, password))
+            .await?
+    }
+
+    /*
+    Check a password against a PHC string (which carries its own parameters).
+    Returns whether the hash should be computed again with the configured parameters.
+    */
+    async fn verify(
+        &self,
+        expected_password_hash: Secret<String>,
+        password: Secret<String>,
+    ) -> Result<bool, AuthError> {
+        let params = self.params.clone();
+        self.run(move || {
+            let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+                .context("Failed to parse hash in PHC string format")?;
+            Argon2::default()
+                .verify_password(password.expose_secret().as_bytes(), &expected_password_hash)
+                .context("Invalid password")
+                .map_err(AuthError::InvalidCredentials)?;
+            Ok(is_outdated(&expected_password_hash, &params))
+        })
+        .await?
+    }
+
+    /*
+    On a blocking thread, once a permit is available.
+    The permit goes with the closure: a caller that gives up waiting (a dropped request) doesn't
+    free it while the hash is still being computed.
+    */
+    async fn run<F, R>(&self, f: F) -> Result<R, anyhow::Error>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .context("The password hashing pool is closed")?;
+        spawn_blocking_with_tracing(move || {
+            let _permit = permit;
+            f()
+        })
+        .await
+        .context("Failed to spawn blocking task")
+    }
+}
+
+// not an Argon2id hash of the current version with the configured parameters
+fn is_outdated(password_hash: &PasswordHash, params: &Params) -> bool {
+    if password_hash.algorithm != Algorithm::Argon2id.ident()
+        || password_hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(password_hash) {
+        Ok(hash_params) => {
+            hash_params.m_cost() != params.m_cost()
+                || hash_params.t_cost() != params.t_cost()
+                || hash_params.p_cost() != params.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+/*
+An unknown username goes through a verification too, against the fallback hash: the time of
+the response doesn't tell which usernames exist.
+A stored hash with outdated parameters is replaced once the password is known to be correct.
+*/
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool, hashing))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+    hashing: &PasswordHashing,
+) -> Result<Uuid, AuthError> {
+    let user: Option<_> = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash
+        FROM users
+        WHERE username = $1
+        "#,
+        credentials.username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials")?;
+
+    let (user_id, expected_password_hash) = match user {
+        Some(user) => (Some(user.user_id), Secret::new(user.password_hash)),
+        None => (None, hashing.fallback_hash.clone()),
+    };
+    let outdated = hashing
+        .verify(expected_password_hash.clone(), credentials.password.clone())
+        .await?;
+    let user_id = user_id
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username")))?;
+
+    if outdated {
+        // the user is authenticated anyway, the next login tries again
+        if let Err(e) = rehash_password(
+            user_id,
+            credentials.password,
+            &expected_password_hash,
+            pool,
+            hashing,
+        )
+        .await
+        {
+            tracing::warn!(error.cause_chain = ?e, "Failed to rehash the password");
+        }
+    }
+    Ok(user_id)
+}
+
+// unless the password changed in the meantime
+#[tracing::instrument(name = "Rehash password", skip(password, previous_hash, pool, hashing))]
+async fn rehash_password(
+    user_id: Uuid,
+    password: Secret<String>,
+    previous_hash: &Secret<String>,
+    pool: &PgPool,
+    hashing: &PasswordHashing,
+) -> Result<(), anyhow::Error> {
+    let password_hash = hashing.hash(password).await?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2 AND password_hash = $3
+        "#,
+        password_hash.expose_secret(),
+        user_id,
+        previous_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the new password hash")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Change password", skip(password, pool, hashing))]
+pub async fn change_password(
+    user_id: Uuid,
+    password: Secret<String>,
+    pool: &PgPool,
+    hashing: &PasswordHashing,
+) -> Result<(), anyhow::Error> {
+    let password_hash = hashing.hash(password).await?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2
+        "#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change user's password in the database")?;
+    Ok(())
+}
+
+// blocks for the whole hash
+fn compute_password_hash(
+    params: &Params,
+    password: Secret<String>,
+) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+        .hash_password(password.expose_secret().as_bytes(), &salt)
+        .context("Failed to hash password")?
+        .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT username
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve a username")?;
+    Ok(row.username)
+}
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Strength rules of a new password, returns the message to show otherwise.
+pub fn validate_password_strength(password: &Secret<String>, username: &str) -> Result<(), String> {
+    let password = password.expose_secret();
+    let length = password.graphemes(true).count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "The new password must be at least {MIN_PASSWORD_LENGTH} characters long."
+        ));
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Err(format!(
+            "The new password must be at most {MAX_PASSWORD_LENGTH} characters long."
+        ));
+    }
+    if password.trim().is_empty() {
+        return Err("The new password must not be only whitespace.".into());
+    }
+    if !username.is_empty() && password.to_lowercase().contains(&username.to_lowercase()) {
+        return Err("The new password must not contain the username.".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::{validate_password_strength, PasswordHashing};
+    use crate::configuration::PasswordHashingSettings;
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use std::num::NonZeroUsize;
+
+    fn hashing(iterations: u32) -> PasswordHashing {
+        PasswordHashing::new(&PasswordHashingSettings {
+            memory_kib: 1024,
+            iterations,
+            parallelism: 1,
+            max_concurrency: NonZeroUsize::new(2).unwrap(),
+        })
+        .unwrap()
+    }
+
+    fn check(password: &str) -> Result<(), String> {
+        validate_password_strength(&Secret::new(password.to_string()), "editor")
+    }
+
+    #[test]
+    fn passwords_shorter_than_12_characters_are_rejected() {
+        assert_err!(check("eleven-char"));
+        assert_ok!(check("twelve-chars"));
+    }
+
+    #[test]
+    fn passwords_longer_than_128_characters_are_rejected() {
+        assert_ok!(check(&"ё".repeat(128)));
+        assert_err!(check(&"ё".repeat(129)));
+    }
+
+    #[test]
+    fn whitespace_only_passwords_are_rejected() {
+        assert_err!(check(&" ".repeat(20)));
+    }
+
+    #[test]
+    fn passwords_containing_the_username_are_rejected() {
+        assert_err!(check("my-Editor-password"));
+    }
+
+    #[tokio::test]
+    async fn a_hash_verifies_its_password_only() {
+        let hashing = hashing(1);
+        let hash = hashing.hash(Secret::new("password".into())).await.unwrap();
+
+        assert_ok!(
+            hashing
+                .verify(hash.clone(), Secret::new("password".into()))
+                .await
+        );
+        assert_err!(hashing.verify(hash, Secret::new("other".into())).await);
+    }
+
+    #[tokio::test]
+    async fn hashes_with_other_parameters_are_outdated() {
+        let hash = hashing(1)
+            .hash(Secret::new("password".into()))
+            .await
+            .unwrap();
+
+        let outdated = hashing(2)
+            .verify(hash.clone(), Secret::new("password".into()))
+            .await
+            .unwrap();
+        let up_to_date = hashing(1)
+            .verify(hash, Secret::new("password".into()))
+            .await
+            .unwrap();
+
+        assert!(outdated);
+        assert!(!up_to_date);
+    }
+}
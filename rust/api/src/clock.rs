@@ -0,0 +1,17 @@
+use chrono::{DateTime, Utc};
+
+/*
+The current time, as seen by the scheduling of the newsletter issues.
+The tests inject their own clock to move the time forward.
+*/
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
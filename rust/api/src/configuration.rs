@@ -1,15 +1,26 @@
 use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, FileSender, PostmarkSender, SesSender, SmtpSender};
+use anyhow::Context;
+use chrono_tz::Tz;
 use secrecy::ExposeSecret;
 use secrecy::Secret;
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
+use std::num::NonZeroUsize;
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub delivery: DeliverySettings,
+    pub subscriptions: SubscriptionSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub rate_limits: RateLimitSettings,
+    pub telemetry: TelemetrySettings,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -18,6 +29,24 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    // signs the session cookies of the admin area, at least 64 bytes
+    pub hmac_secret: Secret<String>,
+    // how long the response to a request with an `Idempotency-Key` is kept
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idempotency_ttl_seconds: u64,
+    // how long the requests in flight and the background tasks have to finish on shutdown
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub shutdown_timeout_seconds: u64,
+}
+
+impl ApplicationSettings {
+    pub fn idempotency_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.idempotency_ttl_seconds)
+    }
+
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_seconds)
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -56,10 +85,65 @@ impl DatabaseSettings {
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
-    pub base_url: String,
+    #[serde(default)]
+    pub backend: EmailBackend,
     pub sender_email: String,
-    pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    // postmark
+    pub base_url: String,
+    pub authorization_token: Secret<String>,
+    pub smtp: Option<SmtpSettings>,
+    pub ses: Option<SesSettings>,
+    pub file: Option<FileSettings>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailBackend {
+    #[default]
+    Postmark,
+    Smtp,
+    Ses,
+    // `.eml` files in a local directory, for development
+    File,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    // the default port of the `tls` mode when unset
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    // plaintext, for a local relay or SMTP sink only
+    None,
+    #[default]
+    Starttls,
+    // TLS from the start of the connection (port 465)
+    Tls,
+}
+
+// Amazon SES v2 API, or a compatible one
+#[derive(serde::Deserialize, Clone)]
+pub struct SesSettings {
+    // like `https://email.eu-west-1.amazonaws.com`
+    pub base_url: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct FileSettings {
+    pub directory: String,
 }
 
 impl EmailClientSettings {
@@ -70,6 +154,223 @@ impl EmailClientSettings {
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
+
+    /*
+    The settings of the backend are there and well formed. Nothing is sent: the readiness probe
+    runs this, a probe that sends emails would cost money.
+    */
+    pub fn check(&self) -> Result<(), String> {
+        self.sender()
+            .map_err(|e| format!("Invalid sender email address: {e}"))?;
+        match self.backend {
+            EmailBackend::Postmark => {
+                check_url(&self.base_url)?;
+                if self.authorization_token.expose_secret().trim().is_empty() {
+                    return Err("Missing Postmark authorization token".into());
+                }
+            }
+            EmailBackend::Smtp => {
+                let settings = self
+                    .smtp
+                    .as_ref()
+                    .ok_or("Missing `email_client.smtp` settings")?;
+                if settings.host.trim().is_empty() {
+                    return Err("Missing SMTP host".into());
+                }
+            }
+            EmailBackend::Ses => {
+                let settings = self
+                    .ses
+                    .as_ref()
+                    .ok_or("Missing `email_client.ses` settings")?;
+                check_url(&settings.base_url)?;
+                if settings.region.trim().is_empty()
+                    || settings.access_key_id.trim().is_empty()
+                    || settings.secret_access_key.expose_secret().trim().is_empty()
+                {
+                    return Err("Missing SES region or credentials".into());
+                }
+            }
+            EmailBackend::File => {
+                let settings = self
+                    .file
+                    .as_ref()
+                    .ok_or("Missing `email_client.file` settings")?;
+                if settings.directory.trim().is_empty() {
+                    return Err("Missing outbox directory".into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // fails when the settings of the backend are missing, the other checks are left to `check`
+    pub fn client(self) -> Result<EmailClient, anyhow::Error> {
+        let sender_email = self
+            .sender()
+            .map_err(|e| anyhow::anyhow!("Invalid sender email address: {e}"))?;
+        let timeout = self.timeout();
+        let client = match self.backend {
+            EmailBackend::Postmark => EmailClient::new(
+                sender_email,
+                PostmarkSender::new(self.base_url, self.authorization_token, timeout),
+            ),
+            EmailBackend::Smtp => {
+                let settings = self.smtp.context("Missing `email_client.smtp` settings")?;
+                let smtp_sender =
+                    SmtpSender::new(settings, timeout).context("Invalid SMTP settings")?;
+                EmailClient::new(sender_email, smtp_sender)
+            }
+            EmailBackend::Ses => {
+                let settings = self.ses.context("Missing `email_client.ses` settings")?;
+                EmailClient::new(sender_email, SesSender::new(settings, timeout))
+            }
+            EmailBackend::File => {
+                let settings = self.file.context("Missing `email_client.file` settings")?;
+                EmailClient::new(sender_email, FileSender::new(settings.directory))
+            }
+        };
+        Ok(client)
+    }
+}
+
+fn check_url(url: &str) -> Result<(), String> {
+    match reqwest::Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+        _ => Err(format!("Invalid backend URL: {url}")),
+    }
+}
+
+// settings of the background worker delivering newsletter issues
+#[derive(serde::Deserialize, Clone)]
+pub struct DeliverySettings {
+    // attempts per subscriber before a delivery is given up
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: i32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub backoff_base_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub backoff_max_milliseconds: u64,
+    // how long the worker sleeps when the queue is empty
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_milliseconds: u64,
+    // how often the scheduled issues that are due are released
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub scheduler_interval_milliseconds: u64,
+    // IANA time zone of the subscribers who did not give theirs, like `Europe/Paris`
+    pub default_timezone: Tz,
+}
+
+impl DeliverySettings {
+    /// Delay before the next attempt, after `n_retries` failed retries:
+    /// base * 2^n_retries, capped to the maximum.
+    pub fn backoff(&self, n_retries: i32) -> std::time::Duration {
+        let factor = 2u64.saturating_pow(n_retries.clamp(0, 63) as u32);
+        let delay = self
+            .backoff_base_milliseconds
+            .saturating_mul(factor)
+            .min(self.backoff_max_milliseconds);
+        std::time::Duration::from_millis(delay)
+    }
+
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_milliseconds)
+    }
+
+    pub fn scheduler_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.scheduler_interval_milliseconds)
+    }
+}
+
+// Argon2id parameters of the new password hashes, the stored ones are updated on login
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordHashingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub iterations: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+    // hashes computed at once, the other requests wait for their turn
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_concurrency: NonZeroUsize,
+}
+
+// confirmation of the new subscribers
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub confirmation_token_ttl_seconds: u64,
+    // minimum time between two confirmation emails to the same address
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub resend_interval_seconds: u64,
+    // how often the expired tokens and the stale pending subscribers are deleted
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_interval_seconds: u64,
+    // `ursula+news@example.com` signs up as `ursula@example.com`
+    #[serde(default)]
+    pub strip_plus_tags: bool,
+    // how long the emailed links to export or erase the data of a subscriber work
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub data_request_link_ttl_seconds: u64,
+}
+
+impl SubscriptionSettings {
+    pub fn confirmation_token_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.confirmation_token_ttl_seconds)
+    }
+
+    pub fn resend_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.resend_interval_seconds)
+    }
+
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_seconds)
+    }
+
+    pub fn data_request_link_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.data_request_link_ttl_seconds)
+    }
+}
+
+// limits of the sign up requests (subscribe, resend the confirmation email) and data requests
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitSettings {
+    pub per_ip: RateLimit,
+    // the address being signed up, whatever its case and plus tag
+    pub per_recipient: RateLimit,
+    /*
+    Number of proxies in front of the application, each adding the address it got the request from
+    to `X-Forwarded-For`. The client address is the one added by the outermost proxy, counting from
+    the right: the entries on its left come from the client. 0 = the address of the connection.
+    */
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    pub trusted_proxies: usize,
+}
+
+// at most `max_requests` in a window of `window_seconds`
+#[derive(serde::Deserialize, Clone, Copy)]
+pub struct RateLimit {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_requests: i32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+}
+
+// export of the traces, over OTLP/HTTP
+#[derive(serde::Deserialize, Clone)]
+pub struct TelemetrySettings {
+    // the collector, like `http://localhost:4318` (the spans go to `/v1/traces`), none when unset
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub export_timeout_milliseconds: u64,
+}
+
+impl TelemetrySettings {
+    pub fn export_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.export_timeout_milliseconds)
+    }
 }
 
 pub enum Environment {
@@ -129,3 +430,52 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         .build()?;
     settings.try_deserialize::<Settings>()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{DeliverySettings, PasswordHashingSettings};
+
+    fn settings() -> DeliverySettings {
+        DeliverySettings {
+            max_attempts: 5,
+            backoff_base_milliseconds: 1000,
+            backoff_max_milliseconds: 60_000,
+            poll_interval_milliseconds: 10_000,
+            scheduler_interval_milliseconds: 30_000,
+            default_timezone: chrono_tz::UTC,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_after_each_retry() {
+        let settings = settings();
+        assert_eq!(settings.backoff(0).as_millis(), 1000);
+        assert_eq!(settings.backoff(1).as_millis(), 2000);
+        assert_eq!(settings.backoff(3).as_millis(), 8000);
+    }
+
+    #[test]
+    fn backoff_is_capped_to_the_maximum() {
+        let settings = settings();
+        assert_eq!(settings.backoff(6).as_millis(), 60_000);
+        assert_eq!(settings.backoff(i32::MAX).as_millis(), 60_000);
+    }
+
+    #[test]
+    fn password_hashing_needs_one_hash_at_once_at_least() {
+        let settings = |max_concurrency: &str| {
+            config::Config::builder()
+                .set_default("memory_kib", "19456")
+                .and_then(|builder| builder.set_default("iterations", "2"))
+                .and_then(|builder| builder.set_default("parallelism", "1"))
+                .and_then(|builder| builder.set_default("max_concurrency", max_concurrency))
+                .unwrap()
+                .build()
+                .unwrap()
+                .try_deserialize::<PasswordHashingSettings>()
+        };
+
+        assert!(settings("4").is_ok());
+        assert!(settings("0").is_err());
+    }
+}
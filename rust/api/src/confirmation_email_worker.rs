@@ -0,0 +1,185 @@
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::configuration::DeliverySettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::routes::ConfirmationTemplates;
+use crate::startup::ApplicationBaseUrl;
+
+/*
+Dequeue one confirmation email of an imported subscriber and try to send it, retried like the
+issue deliveries. The task is dropped with its token: once the token expired, or the subscriber
+is not pending anymore.
+*/
+#[tracing::instrument(skip_all, err)]
+pub async fn try_send_confirmation(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &DeliverySettings,
+    base_url: &ApplicationBaseUrl,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+
+    let Some(subscriber) = get_pending_subscriber(&mut transaction, &task).await? else {
+        tracing::info!("Skipping a subscriber who is not waiting for a confirmation anymore");
+        delete_task(&mut transaction, &task).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the confirmation task")?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    };
+
+    match SubscriberEmail::parse(subscriber.email) {
+        Ok(email) => {
+            let templates = ConfirmationTemplates::get(&mut transaction).await?;
+            let outcome = async {
+                let rendered = templates.render(
+                    &subscriber.name,
+                    &email,
+                    &base_url.0,
+                    &task.subscription_token,
+                )?;
+                email_client
+                    .send_email(&email, &rendered.subject, &rendered.html, &rendered.text)
+                    .await
+            }
+            .await;
+            match outcome {
+                Ok(()) => delete_task(&mut transaction, &task).await?,
+                Err(error) if task.n_retries + 1 < settings.max_attempts => {
+                    let delay = settings.backoff(task.n_retries);
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        n_retries = task.n_retries,
+                        "Failed to send a confirmation email. Retrying in {delay:?}",
+                    );
+                    reschedule_task(&mut transaction, &task, delay).await?;
+                }
+                Err(error) => {
+                    // the subscriber can still ask for a new confirmation email
+                    tracing::error!(
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        "Failed to send a confirmation email. Giving up after {} attempts",
+                        settings.max_attempts,
+                    );
+                    delete_task(&mut transaction, &task).await?;
+                }
+            }
+        }
+        Err(error) => {
+            tracing::error!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                "Skipping a pending subscriber. Their stored contact details are invalid",
+            );
+            delete_task(&mut transaction, &task).await?;
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the confirmation task")?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct Task {
+    subscription_token: String,
+    n_retries: i32,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(Transaction<'static, Postgres>, Task)>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT subscription_token, n_retries
+        FROM confirmation_email_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to dequeue a confirmation task")?;
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut Transaction<'static, Postgres>,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM confirmation_email_queue WHERE subscription_token = $1",
+        task.subscription_token,
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to delete a confirmation task")?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut Transaction<'static, Postgres>,
+    task: &Task,
+    delay: std::time::Duration,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue
+        SET n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $2)
+        WHERE subscription_token = $1
+        "#,
+        task.subscription_token,
+        delay.as_secs_f64(),
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to reschedule a confirmation task")?;
+    Ok(())
+}
+
+struct PendingSubscriber {
+    email: String,
+    name: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_pending_subscriber(
+    transaction: &mut Transaction<'static, Postgres>,
+    task: &Task,
+) -> Result<Option<PendingSubscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        PendingSubscriber,
+        r#"
+        SELECT s.email, s.name
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_token = $1
+            AND t.expires_at > now()
+            AND s.status = 'pending_confirmation'
+        "#,
+        task.subscription_token,
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve the subscriber of a confirmation task")?;
+    Ok(subscriber)
+}
@@ -0,0 +1,185 @@
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+// what a subscriber can ask about their data (GDPR)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataRequest {
+    Export,
+    Erasure,
+}
+
+impl DataRequest {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataRequest::Export => "export",
+            DataRequest::Erasure => "erasure",
+        }
+    }
+}
+
+/*
+Token of the emailed link to export or erase the data of a subscriber: the subscriber id and the
+expiry of the link, followed by their HMAC-SHA256 tag, base64url-encoded. Like the unsubscribe
+tokens nothing is stored, and the tag binds the token to one kind of request.
+*/
+#[derive(Debug)]
+pub struct DataRequestToken(String);
+
+impl DataRequestToken {
+    pub fn sign(
+        subscriber_id: Uuid,
+        request: DataRequest,
+        expires_at: DateTime<Utc>,
+        secret: &Secret<String>,
+    ) -> Self {
+        let mut bytes = payload(subscriber_id, expires_at.timestamp());
+        let tag = mac(request, &bytes, secret).finalize().into_bytes();
+        bytes.extend_from_slice(&tag);
+        Self(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// Returns the subscriber id of a token signed with `secret` for `request`, until it expires.
+    pub fn verify(
+        token: &str,
+        request: DataRequest,
+        now: DateTime<Utc>,
+        secret: &Secret<String>,
+    ) -> Result<Uuid, String> {
+        let invalid = || {
+            format!(
+                "This link to the {} of your data is not valid",
+                request.as_str()
+            )
+        };
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| invalid())?;
+        if bytes.len() != 16 + 8 + 32 {
+            return Err(invalid());
+        }
+        let (payload, tag) = bytes.split_at(16 + 8);
+        // constant time comparison
+        mac(request, payload, secret)
+            .verify_slice(tag)
+            .map_err(|_| invalid())?;
+        let (id, expires_at) = payload.split_at(16);
+        let expires_at = i64::from_be_bytes(expires_at.try_into().map_err(|_| invalid())?);
+        if now.timestamp() >= expires_at {
+            return Err(format!(
+                "This link to the {} of your data has expired, please ask for a new one",
+                request.as_str()
+            ));
+        }
+        Uuid::from_slice(id).map_err(|_| invalid())
+    }
+}
+
+fn payload(subscriber_id: Uuid, expires_at: i64) -> Vec<u8> {
+    let mut bytes = subscriber_id.as_bytes().to_vec();
+    bytes.extend_from_slice(&expires_at.to_be_bytes());
+    bytes
+}
+
+fn mac(request: DataRequest, payload: &[u8], secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(b"data_request:");
+    mac.update(request.as_str().as_bytes());
+    mac.update(b":");
+    mac.update(payload);
+    mac
+}
+
+impl AsRef<str> for DataRequestToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{DataRequest, DataRequestToken};
+    use chrono::{Duration, Utc};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-secret".to_string())
+    }
+
+    #[test]
+    fn a_signed_token_is_verified_until_it_expires() {
+        let subscriber_id = Uuid::new_v4();
+        let now = Utc::now();
+        let expires_at = now + Duration::hours(1);
+        let token =
+            DataRequestToken::sign(subscriber_id, DataRequest::Export, expires_at, &secret());
+        assert_ok_eq!(
+            DataRequestToken::verify(token.as_ref(), DataRequest::Export, now, &secret()),
+            subscriber_id
+        );
+        assert_err!(DataRequestToken::verify(
+            token.as_ref(),
+            DataRequest::Export,
+            expires_at,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn an_export_token_does_not_erase() {
+        let now = Utc::now();
+        let token = DataRequestToken::sign(
+            Uuid::new_v4(),
+            DataRequest::Export,
+            now + Duration::hours(1),
+            &secret(),
+        );
+        assert_err!(DataRequestToken::verify(
+            token.as_ref(),
+            DataRequest::Erasure,
+            now,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_tampered_token_is_rejected() {
+        let now = Utc::now();
+        let token = DataRequestToken::sign(
+            Uuid::new_v4(),
+            DataRequest::Erasure,
+            now + Duration::hours(1),
+            &secret(),
+        );
+        let other_secret = Secret::new("other".to_string());
+        assert_err!(DataRequestToken::verify(
+            token.as_ref(),
+            DataRequest::Erasure,
+            now,
+            &other_secret
+        ));
+        let mut tampered = token.as_ref().to_string();
+        let last = if tampered.ends_with('A') { 'B' } else { 'A' };
+        tampered.pop();
+        tampered.push(last);
+        assert_err!(DataRequestToken::verify(
+            &tampered,
+            DataRequest::Erasure,
+            now,
+            &secret()
+        ));
+        assert_err!(DataRequestToken::verify(
+            "",
+            DataRequest::Erasure,
+            now,
+            &secret()
+        ));
+    }
+}
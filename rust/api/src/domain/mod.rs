@@ -1,7 +1,13 @@
+mod data_request_token;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_timezone;
+mod unsubscribe_token;
 
+pub use data_request_token::{DataRequest, DataRequestToken};
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_timezone::SubscriberTimezone;
+pub use unsubscribe_token::UnsubscribeToken;
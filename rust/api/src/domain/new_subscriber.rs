@@ -1,6 +1,27 @@
-use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::domain::{SubscriberEmail, SubscriberName, SubscriberTimezone};
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    // the default of the settings when unknown
+    pub timezone: Option<SubscriberTimezone>,
+}
+
+impl NewSubscriber {
+    // the rules of a sign up, whether through the form or an import
+    pub fn parse(name: String, email: String) -> Result<Self, String> {
+        let name = SubscriberName::parse(name)?;
+        let email = SubscriberEmail::parse(email)?;
+        if email.is_disposable() {
+            return Err(format!(
+                "Disposable email addresses ({}) are not accepted",
+                email.domain()
+            ));
+        }
+        Ok(Self {
+            email,
+            name,
+            timezone: None,
+        })
+    }
 }
@@ -1,16 +1,51 @@
 use validator::validate_email;
 
+// throwaway mailboxes, one domain per line (their subdomains are blocked too)
+const DISPOSABLE_DOMAINS: &str = include_str!("disposable_email_domains.txt");
+
 #[derive(Debug)]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
+    /// The address is trimmed and lowercased, so that `Ursula@Example.com` and
+    /// `ursula@example.com` are the same subscriber.
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if validate_email(&s) {
-            Ok(Self(s))
+        let email = s.trim().to_lowercase();
+        if validate_email(&email) {
+            Ok(Self(email))
         } else {
             Err(format!("{s} is not a valid subscriber email"))
         }
     }
+
+    // `ursula+news@example.com` becomes `ursula@example.com`
+    pub fn without_plus_tag(self) -> SubscriberEmail {
+        match self.0.split_once('@') {
+            Some((local_part, domain)) => match local_part.split_once('+') {
+                Some((mailbox, _tag)) if !mailbox.is_empty() => Self(format!("{mailbox}@{domain}")),
+                _ => self,
+            },
+            None => self,
+        }
+    }
+
+    pub fn domain(&self) -> &str {
+        self.0.rsplit_once('@').map_or("", |(_, domain)| domain)
+    }
+
+    pub fn is_disposable(&self) -> bool {
+        let domain = self.domain();
+        DISPOSABLE_DOMAINS
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .any(|blocked| {
+                domain == blocked
+                    || domain
+                        .strip_suffix(blocked)
+                        .is_some_and(|subdomain| subdomain.ends_with('.'))
+            })
+    }
 }
 
 impl AsRef<str> for SubscriberEmail {
@@ -62,6 +97,34 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn emails_are_trimmed_and_lowercased() {
+        let email = SubscriberEmail::parse(" Ursula@Example.COM ".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@example.com");
+    }
+
+    #[test]
+    fn the_plus_tag_can_be_removed() {
+        let email = SubscriberEmail::parse("ursula+news@example.com".to_string()).unwrap();
+        assert_eq!(email.without_plus_tag().as_ref(), "ursula@example.com");
+
+        let email = SubscriberEmail::parse("ursula@example.com".to_string()).unwrap();
+        assert_eq!(email.without_plus_tag().as_ref(), "ursula@example.com");
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_detected() {
+        let disposable = |email: &str| {
+            SubscriberEmail::parse(email.to_string())
+                .unwrap()
+                .is_disposable()
+        };
+        assert!(disposable("ursula@mailinator.com"));
+        assert!(disposable("ursula@eu.mailinator.com"));
+        assert!(!disposable("ursula@notmailinator.com"));
+        assert!(!disposable("ursula@example.com"));
+    }
+
     #[test]
     fn valid_email_should_be_parsed_successfully() {
         let email = SafeEmail().fake();
@@ -0,0 +1,40 @@
+use chrono_tz::Tz;
+
+// IANA time zone of a subscriber, like `Europe/Paris`
+#[derive(Debug, Clone, Copy)]
+pub struct SubscriberTimezone(Tz);
+
+impl SubscriberTimezone {
+    pub fn parse(s: String) -> Result<SubscriberTimezone, String> {
+        s.trim()
+            .parse::<Tz>()
+            .map(Self)
+            .map_err(|_| format!("{s} is not a valid time zone"))
+    }
+}
+
+impl AsRef<str> for SubscriberTimezone {
+    fn as_ref(&self) -> &str {
+        self.0.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberTimezone;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn iana_time_zones_are_valid() {
+        for timezone in ["Europe/Paris", "America/New_York", "Asia/Tokyo", "UTC"] {
+            assert_ok!(SubscriberTimezone::parse(timezone.to_string()));
+        }
+    }
+
+    #[test]
+    fn offsets_and_unknown_names_are_rejected() {
+        for timezone in ["", "+02:00", "CEST", "Europe/Atlantis"] {
+            assert_err!(SubscriberTimezone::parse(timezone.to_string()));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/*
+Unsubscribe token of a subscriber: the subscriber id followed by its HMAC-SHA256 tag,
+base64url-encoded. Nothing is stored, the token is checked against the HMAC secret.
+*/
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+impl UnsubscribeToken {
+    pub fn sign(subscriber_id: Uuid, secret: &Secret<String>) -> Self {
+        let mut bytes = subscriber_id.as_bytes().to_vec();
+        bytes.extend_from_slice(&mac(subscriber_id, secret).finalize().into_bytes());
+        Self(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// Returns the subscriber id of a token signed with `secret`.
+    pub fn verify(token: &str, secret: &Secret<String>) -> Result<Uuid, String> {
+        let invalid = || format!("{token} is not a valid unsubscribe token");
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| invalid())?;
+        if bytes.len() != 16 + 32 {
+            return Err(invalid());
+        }
+        let (id, tag) = bytes.split_at(16);
+        let subscriber_id = Uuid::from_slice(id).map_err(|_| invalid())?;
+        // constant time comparison
+        mac(subscriber_id, secret)
+            .verify_slice(tag)
+            .map_err(|_| invalid())?;
+        Ok(subscriber_id)
+    }
+}
+
+fn mac(subscriber_id: Uuid, secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    // a tag of this application can't be reused for anything else than unsubscribing
+    mac.update(b"unsubscribe:");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::UnsubscribeToken;
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-secret".to_string())
+    }
+
+    #[test]
+    fn a_signed_token_is_verified() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::sign(subscriber_id, &secret());
+        assert_ok_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret()),
+            subscriber_id
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = UnsubscribeToken::sign(Uuid::new_v4(), &Secret::new("other".to_string()));
+        assert_err!(UnsubscribeToken::verify(token.as_ref(), &secret()));
+    }
+
+    #[test]
+    fn a_tampered_token_is_rejected() {
+        let token = UnsubscribeToken::sign(Uuid::new_v4(), &secret());
+        let mut tampered = token.as_ref().to_string();
+        let last = if tampered.ends_with('A') { 'B' } else { 'A' };
+        tampered.pop();
+        tampered.push(last);
+        assert_err!(UnsubscribeToken::verify(&tampered, &secret()));
+        assert_err!(UnsubscribeToken::verify("", &secret()));
+        assert_err!(UnsubscribeToken::verify("not base64!", &secret()));
+    }
+}
@@ -0,0 +1,41 @@
+use anyhow::Context;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use super::{mime_message, Email, EmailSender};
+
+/// Local outbox for development: each email is written to `<directory>/<uuid>.eml`.
+pub struct FileSender {
+    directory: String,
+    transport: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileSender {
+    pub fn new(directory: String) -> Self {
+        let transport = AsyncFileTransport::new(&directory);
+        Self {
+            directory,
+            transport,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FileSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let message = mime_message(email)?;
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .context("Failed to create the outbox directory")?;
+        let id = self
+            .transport
+            .send(message)
+            .await
+            .context("Failed to write the email to the outbox")?;
+        tracing::info!("Email written to {}/{}.eml", self.directory, id);
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "file"
+    }
+}
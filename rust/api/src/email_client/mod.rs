@@ -0,0 +1,135 @@
+mod file;
+mod postmark;
+mod ses;
+mod smtp;
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::Message;
+use tracing::Instrument;
+
+use crate::domain::SubscriberEmail;
+use crate::metrics::Metrics;
+
+pub use file::FileSender;
+pub use postmark::PostmarkSender;
+pub use ses::SesSender;
+pub use smtp::SmtpSender;
+
+/// An email ready to be handed over to a delivery backend.
+pub struct Email<'a> {
+    pub from: &'a SubscriberEmail,
+    pub to: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    // extra message headers like `List-Unsubscribe`
+    pub headers: &'a [(&'a str, &'a str)],
+}
+
+/// A way to deliver emails: an HTTP API, an SMTP server, a local directory...
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error>;
+
+    // the label of the backend in the metrics
+    fn name(&self) -> &'static str;
+}
+
+/*
+Sends the emails of the application from the configured sender address, through the backend
+selected in the `email_client` settings.
+*/
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    backend: Arc<dyn EmailSender>,
+    metrics: Option<Metrics>,
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, backend: impl EmailSender + 'static) -> Self {
+        Self {
+            sender,
+            backend: Arc::new(backend),
+            metrics: None,
+        }
+    }
+
+    // count the emails sent and failed in the metrics of the application
+    pub fn with_metrics(self, metrics: Metrics) -> Self {
+        Self {
+            metrics: Some(metrics),
+            ..self
+        }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    // same as `send_email`, with extra message headers like `List-Unsubscribe`
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), anyhow::Error> {
+        let email = Email {
+            from: &self.sender,
+            to: recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        };
+        // the call to the provider, in the trace of the request or the delivery
+        let span = tracing::info_span!(
+            "Send email",
+            backend = self.backend.name(),
+            otel.kind = "client"
+        );
+        let outcome = self.backend.send(&email).instrument(span).await;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_email(self.backend.name(), outcome.is_ok());
+        }
+        outcome
+    }
+}
+
+// MIME message with a text and an HTML alternative, for the SMTP and file backends
+fn mime_message(email: &Email<'_>) -> Result<Message, anyhow::Error> {
+    let from: Mailbox = email
+        .from
+        .as_ref()
+        .parse()
+        .context("Invalid sender address")?;
+    let to: Mailbox = email
+        .to
+        .as_ref()
+        .parse()
+        .context("Invalid recipient address")?;
+    let mut builder = Message::builder().from(from).to(to).subject(email.subject);
+    for &(name, value) in email.headers {
+        let name = HeaderName::new_from_ascii(name.to_owned())
+            .with_context(|| format!("Invalid header name `{name}`"))?;
+        builder = builder.raw_header(HeaderValue::new(name, value.to_owned()));
+    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            email.text_content.to_owned(),
+            email.html_content.to_owned(),
+        ))
+        .context("Failed to build the email message")
+}
@@ -1,19 +1,19 @@
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
 
-use crate::domain::SubscriberEmail;
+use super::{Email, EmailSender};
+use crate::telemetry::trace_context_headers;
 
-pub struct EmailClient {
+/// Postmark email API: https://postmarkapp.com/developer/api/email-api
+pub struct PostmarkSender {
     http_client: Client,
     base_url: String,
-    sender: SubscriberEmail,
     authorization_token: Secret<String>,
 }
 
-impl EmailClient {
+impl PostmarkSender {
     pub fn new(
         base_url: String,
-        sender: SubscriberEmail,
         authorization_token: Secret<String>,
         timeout: std::time::Duration,
     ) -> Self {
@@ -21,28 +21,30 @@ impl EmailClient {
         Self {
             http_client,
             base_url,
-            sender,
             authorization_token,
         }
     }
+}
 
-    pub async fn send_email(
-        &self,
-        recipient: &SubscriberEmail,
-        subject: &str,
-        html_content: &str,
-        text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+#[async_trait::async_trait]
+impl EmailSender for PostmarkSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
         let url = format!("{}/email", self.base_url);
         let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
-            to: recipient.as_ref(),
-            subject,
-            html_body: html_content,
-            text_body: text_content,
+            from: email.from.as_ref(),
+            to: email.to.as_ref(),
+            subject: email.subject,
+            html_body: email.html_content,
+            text_body: email.text_content,
+            headers: email
+                .headers
+                .iter()
+                .map(|&(name, value)| EmailHeader { name, value })
+                .collect(),
         };
         self.http_client
             .post(&url)
+            .headers(trace_context_headers())
             .header(
                 "X-Postmark-Server-Token",
                 self.authorization_token.expose_secret(),
@@ -53,6 +55,10 @@ impl EmailClient {
             .error_for_status()?;
         Ok(())
     }
+
+    fn name(&self) -> &'static str {
+        "postmark"
+    }
 }
 
 #[derive(serde::Serialize)]
@@ -69,6 +75,15 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader<'a> {
+    name: &'a str,
+    value: &'a str,
 }
 
 #[cfg(test)]
@@ -78,11 +93,11 @@ mod tests {
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
     use secrecy::Secret;
-    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
     use wiremock::{Mock, MockServer, Request, ResponseTemplate};
 
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{EmailClient, PostmarkSender};
 
     struct SendEmailBodyMatcher;
 
@@ -117,10 +132,12 @@ mod tests {
 
     fn email_client(base_url: String) -> EmailClient {
         EmailClient::new(
-            base_url,
             email(),
-            Secret::new(Faker.fake()),
-            std::time::Duration::from_millis(200),
+            PostmarkSender::new(
+                base_url,
+                Secret::new(Faker.fake()),
+                std::time::Duration::from_millis(200),
+            ),
         )
     }
 
@@ -144,6 +161,39 @@ mod tests {
             .await;
     }
 
+    #[tokio::test]
+    async fn send_email_with_headers_adds_them_to_the_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [
+                    {"Name": "List-Unsubscribe", "Value": "<https://example.com/u>"},
+                    {"Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click"},
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email_with_headers(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &[
+                    ("List-Unsubscribe", "<https://example.com/u>"),
+                    ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+                ],
+            )
+            .await;
+
+        assert_ok!(outcome);
+    }
+
     #[tokio::test]
     async fn send_email_succeeds_if_the_mail_server_returns_200() {
         let mock_server = MockServer::start().await;
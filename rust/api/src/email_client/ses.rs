@@ -0,0 +1,362 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+
+use super::{Email, EmailSender};
+use crate::configuration::SesSettings;
+use crate::telemetry::trace_context_headers;
+
+const SEND_EMAIL_PATH: &str = "/v2/email/outbound-emails";
+
+/*
+Amazon SES v2 `SendEmail` API, or any HTTP API compatible with it.
+Requests are signed with AWS Signature Version 4.
+*/
+pub struct SesSender {
+    http_client: Client,
+    base_url: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: Secret<String>,
+}
+
+impl SesSender {
+    pub fn new(settings: SesSettings, timeout: std::time::Duration) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            base_url: settings.base_url.trim_end_matches('/').to_owned(),
+            region: settings.region,
+            access_key_id: settings.access_key_id,
+            secret_access_key: settings.secret_access_key,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SesSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let url = reqwest::Url::parse(&format!("{}{SEND_EMAIL_PATH}", self.base_url))
+            .context("Invalid SES base url")?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => anyhow::bail!("The SES base url has no host"),
+        };
+        let body = serde_json::to_vec(&send_email_request(email))?;
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = self.authorization(&host, &body, now);
+        self.http_client
+            .post(url)
+            // not signed, the signature covers the headers it lists only
+            .headers(trace_context_headers())
+            .header("Content-Type", "application/json")
+            .header("X-Amz-Date", &amz_date)
+            .header("Authorization", authorization)
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "ses"
+    }
+}
+
+impl SesSender {
+    // `Authorization` header of AWS Signature Version 4, signing `content-type`, `host` and `x-amz-date`
+    fn authorization(&self, host: &str, body: &[u8], now: DateTime<Utc>) -> String {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let request = SignedRequest {
+            method: "POST",
+            path: SEND_EMAIL_PATH,
+            query: "",
+            headers: &[
+                ("content-type", "application/json"),
+                ("host", host),
+                ("x-amz-date", &amz_date),
+            ],
+            body,
+        };
+        let credentials = Credentials {
+            access_key_id: &self.access_key_id,
+            secret_access_key: self.secret_access_key.expose_secret(),
+            region: &self.region,
+            service: "ses",
+        };
+        authorization_v4(&request, &credentials, now)
+    }
+}
+
+// what AWS Signature Version 4 covers of a request, the path and the query already URI-encoded
+struct SignedRequest<'a> {
+    method: &'a str,
+    path: &'a str,
+    query: &'a str,
+    // lowercase names, in alphabetical order
+    headers: &'a [(&'a str, &'a str)],
+    body: &'a [u8],
+}
+
+struct Credentials<'a> {
+    access_key_id: &'a str,
+    secret_access_key: &'a str,
+    region: &'a str,
+    service: &'a str,
+}
+
+fn authorization_v4(
+    request: &SignedRequest<'_>,
+    credentials: &Credentials<'_>,
+    now: DateTime<Utc>,
+) -> String {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let scope = format!(
+        "{date}/{}/{}/aws4_request",
+        credentials.region, credentials.service
+    );
+    let canonical_headers: String = request
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = request
+        .headers
+        .iter()
+        .map(|&(name, _)| name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{canonical_headers}\n{signed_headers}\n{}",
+        request.method,
+        request.path,
+        request.query,
+        hex::encode(Sha256::digest(request.body))
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let secret = format!("AWS4{}", credentials.secret_access_key);
+    let signing_key = [
+        date.as_str(),
+        credentials.region,
+        credentials.service,
+        "aws4_request",
+    ]
+    .iter()
+    .fold(secret.into_bytes(), |key, part| hmac_sha256(&key, part));
+    let signature = hex::encode(hmac_sha256(&signing_key, &string_to_sign));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        credentials.access_key_id
+    )
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take a key of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn send_email_request(email: &Email<'_>) -> serde_json::Value {
+    let headers: Vec<_> = email
+        .headers
+        .iter()
+        .map(|&(name, value)| serde_json::json!({"Name": name, "Value": value}))
+        .collect();
+    serde_json::json!({
+        "FromEmailAddress": email.from.as_ref(),
+        "Destination": {"ToAddresses": [email.to.as_ref()]},
+        "Content": {
+            "Simple": {
+                "Subject": {"Data": email.subject, "Charset": "UTF-8"},
+                "Body": {
+                    "Text": {"Data": email.text_content, "Charset": "UTF-8"},
+                    "Html": {"Data": email.html_content, "Charset": "UTF-8"},
+                },
+                "Headers": headers,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use wiremock::matchers::{body_partial_json, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    use super::{authorization_v4, Credentials, SignedRequest};
+    use crate::configuration::SesSettings;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, SesSender};
+
+    fn settings(base_url: String) -> SesSettings {
+        SesSettings {
+            base_url,
+            region: "eu-west-1".into(),
+            access_key_id: "AKIDEXAMPLE".into(),
+            secret_access_key: Secret::new("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into()),
+        }
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        EmailClient::new(
+            SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+            SesSender::new(settings(base_url), std::time::Duration::from_millis(200)),
+        )
+    }
+
+    fn recipient() -> SubscriberEmail {
+        SubscriberEmail::parse("ursula@example.com".into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_posts_a_signed_send_email_request() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/email/outbound-emails"))
+            .and(header("Content-Type", "application/json"))
+            .and(header_exists("X-Amz-Date"))
+            // the header values are split on commas
+            .and(|request: &Request| {
+                request.headers.iter().any(|(name, values)| {
+                    name.as_str().eq_ignore_ascii_case("authorization")
+                        && values[0]
+                            .as_str()
+                            .starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/")
+                })
+            })
+            .and(body_partial_json(serde_json::json!({
+                "FromEmailAddress": "newsletter@example.com",
+                "Destination": {"ToAddresses": ["ursula@example.com"]},
+                "Content": {"Simple": {
+                    "Subject": {"Data": "Subject"},
+                    "Headers": [{"Name": "List-Unsubscribe", "Value": "<https://example.com/u>"}],
+                }},
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email_with_headers(
+                &recipient(),
+                "Subject",
+                "<p>Body</p>",
+                "Body",
+                &[("List-Unsubscribe", "<https://example.com/u>")],
+            )
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_api_returns_an_error() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email(&recipient(), "Subject", "<p>Body</p>", "Body")
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[test]
+    fn the_signature_depends_on_the_body_and_the_date() {
+        let sender = SesSender::new(
+            settings("https://email.eu-west-1.amazonaws.com".into()),
+            std::time::Duration::from_secs(1),
+        );
+        let now = chrono::Utc::now();
+        let host = "email.eu-west-1.amazonaws.com";
+
+        let signature = sender.authorization(host, b"{}", now);
+
+        assert_eq!(signature, sender.authorization(host, b"{}", now));
+        assert_ne!(signature, sender.authorization(host, b"{ }", now));
+        assert_ne!(
+            signature,
+            sender.authorization(host, b"{}", now + chrono::Duration::seconds(1))
+        );
+    }
+
+    // the test suite of AWS Signature Version 4 and the example of its documentation use these
+    const EXAMPLE_CREDENTIALS: Credentials<'static> = Credentials {
+        access_key_id: "AKIDEXAMPLE",
+        secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+        region: "us-east-1",
+        service: "service",
+    };
+
+    fn example_date() -> chrono::DateTime<chrono::Utc> {
+        "2015-08-30T12:36:00Z".parse().unwrap()
+    }
+
+    // `get-vanilla` of the AWS Signature Version 4 test suite
+    #[test]
+    fn the_signature_matches_the_get_vanilla_test_vector() {
+        let request = SignedRequest {
+            method: "GET",
+            path: "/",
+            query: "",
+            headers: &[
+                ("host", "example.amazonaws.com"),
+                ("x-amz-date", "20150830T123600Z"),
+            ],
+            body: b"",
+        };
+
+        assert_eq!(
+            authorization_v4(&request, &EXAMPLE_CREDENTIALS, example_date()),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+            SignedHeaders=host;x-amz-date, \
+            Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    // the IAM `ListUsers` example of the AWS Signature Version 4 documentation
+    #[test]
+    fn the_signature_matches_the_documented_iam_example() {
+        let request = SignedRequest {
+            method: "GET",
+            path: "/",
+            query: "Action=ListUsers&Version=2010-05-08",
+            headers: &[
+                (
+                    "content-type",
+                    "application/x-www-form-urlencoded; charset=utf-8",
+                ),
+                ("host", "iam.amazonaws.com"),
+                ("x-amz-date", "20150830T123600Z"),
+            ],
+            body: b"",
+        };
+        let credentials = Credentials {
+            service: "iam",
+            ..EXAMPLE_CREDENTIALS
+        };
+
+        assert_eq!(
+            authorization_v4(&request, &credentials, example_date()),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+            SignedHeaders=content-type;host;x-amz-date, \
+            Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::Context;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::ExposeSecret;
+
+use super::{mime_message, Email, EmailSender};
+use crate::configuration::{SmtpSettings, SmtpTls};
+
+/// Any SMTP server, through a pool of connections.
+pub struct SmtpSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpSender {
+    pub fn new(
+        settings: SmtpSettings,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let mut builder = match settings.tls {
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+                    .port(settings.port.unwrap_or(25))
+            }
+            SmtpTls::Starttls => {
+                let tls = TlsParameters::new(settings.host.clone())
+                    .context("Failed to set up TLS for the SMTP server")?;
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+                    .port(settings.port.unwrap_or(587))
+                    .tls(Tls::Required(tls))
+            }
+            SmtpTls::Tls => {
+                let tls = TlsParameters::new(settings.host.clone())
+                    .context("Failed to set up TLS for the SMTP server")?;
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+                    .port(settings.port.unwrap_or(465))
+                    .tls(Tls::Wrapper(tls))
+            }
+        };
+        if let (Some(username), Some(password)) = (settings.username, settings.password) {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+        Ok(Self {
+            transport: builder.timeout(Some(timeout)).build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpSender {
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let message = mime_message(email)?;
+        self.transport
+            .send(message)
+            .await
+            .context("Failed to send the email to the SMTP server")?;
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+}
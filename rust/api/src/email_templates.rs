@@ -0,0 +1,326 @@
+use sqlx::{PgConnection, PgPool};
+use tera::{Context, Tera};
+
+// wraps every email, the rendered body is available as `content`
+pub const LAYOUT_TEMPLATE: &str = "layout";
+pub const CONFIRMATION_TEMPLATE: &str = "confirmation";
+pub const DATA_EXPORT_TEMPLATE: &str = "data_export";
+pub const DATA_ERASURE_TEMPLATE: &str = "data_erasure";
+
+// the lines are not wrapped: a link split over two lines is broken, mail clients wrap the text
+const TEXT_WIDTH: usize = 1000;
+
+// SQLSTATE of Postgres
+const UNIQUE_VIOLATION: &str = "23505";
+
+/*
+A version of a stored email template, in the Tera syntax (https://keats.github.io/tera/docs/).
+The recipient variables are `name` and `email`, plus `confirmation_link` for confirmation emails,
+`unsubscribe_link` for newsletter issues and `export_link` or `erasure_link` for data requests.
+The HTML is escaped, links are inserted with `{{ link | safe }}`.
+*/
+#[derive(Debug, Clone)]
+pub struct EmailTemplate {
+    pub name: String,
+    pub version: i32,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+// the latest version, unless a version is given
+#[tracing::instrument(name = "Get email template", skip(connection))]
+pub async fn get_template(
+    connection: &mut PgConnection,
+    name: &str,
+    version: Option<i32>,
+) -> Result<Option<EmailTemplate>, sqlx::Error> {
+    sqlx::query_as!(
+        EmailTemplate,
+        r#"
+        SELECT name, version, subject, html_content, text_content, created_at
+        FROM email_templates
+        WHERE name = $1 AND ($2::INT IS NULL OR version = $2)
+        ORDER BY version DESC
+        LIMIT 1
+        "#,
+        name,
+        version
+    )
+    .fetch_optional(connection)
+    .await
+}
+
+// the latest version of every template
+pub async fn list_templates(pool: &PgPool) -> Result<Vec<EmailTemplate>, sqlx::Error> {
+    sqlx::query_as!(
+        EmailTemplate,
+        r#"
+        SELECT DISTINCT ON (name) name, version, subject, html_content, text_content, created_at
+        FROM email_templates
+        ORDER BY name, version DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// two saves of the same template may pick the same next version, the second one tries again
+const SAVE_ATTEMPTS: usize = 5;
+
+// stored as the next version of the template, returns the version
+#[tracing::instrument(
+    name = "Save email template",
+    skip(pool, subject, html_content, text_content)
+)]
+pub async fn save_template_version(
+    pool: &PgPool,
+    name: &str,
+    subject: &str,
+    html_content: &str,
+    text_content: Option<&str>,
+) -> Result<i32, sqlx::Error> {
+    let mut attempt = 1;
+    loop {
+        let saved = sqlx::query!(
+            r#"
+            INSERT INTO email_templates (name, version, subject, html_content, text_content)
+            SELECT $1, COALESCE(MAX(version), 0) + 1, $2, $3, $4
+            FROM email_templates WHERE name = $1
+            RETURNING version
+            "#,
+            name,
+            subject,
+            html_content,
+            text_content
+        )
+        .fetch_one(pool)
+        .await;
+        match saved {
+            Ok(saved) => return Ok(saved.version),
+            Err(sqlx::Error::Database(e))
+                if e.code().as_deref() == Some(UNIQUE_VIOLATION) && attempt < SAVE_ATTEMPTS =>
+            {
+                tracing::info!("The version was saved concurrently, trying the next one");
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// variables of a recipient, to complete with the links of the email
+pub fn recipient_context(name: &str, email: &str) -> Context {
+    let mut context = Context::new();
+    context.insert("name", name);
+    context.insert("email", email);
+    context
+}
+
+// a made up subscriber, to preview and validate templates
+pub fn sample_context(base_url: &str) -> Context {
+    let mut context = recipient_context("Ursula Le Guin", "ursula_le_guin@example.com");
+    context.insert(
+        "confirmation_link",
+        &format!("{base_url}/subscriptions/confirm?subscription_token=sample"),
+    );
+    context.insert(
+        "unsubscribe_link",
+        &format!("{base_url}/subscriptions/unsubscribe?token=sample"),
+    );
+    context.insert(
+        "export_link",
+        &format!("{base_url}/subscriptions/data/export?token=sample"),
+    );
+    context.insert(
+        "erasure_link",
+        &format!("{base_url}/subscriptions/data/erase?token=sample"),
+    );
+    context
+}
+
+// the public version of an issue, in the archive and the feed, has no recipient
+pub fn reader_context() -> Context {
+    let mut context = recipient_context("reader", "");
+    context.insert("unsubscribe_link", "");
+    context
+}
+
+// a part of an issue (title or HTML) for the archive and the feed
+pub fn render_for_readers(source: &str, autoescape: bool) -> Result<String, tera::Error> {
+    Tera::one_off(source, &reader_context(), autoescape).map(|rendered| rendered.trim().to_owned())
+}
+
+/*
+Render an email for one recipient, inside the layout.
+The text is generated from the HTML when the template has none.
+*/
+pub fn render_email(
+    layout: &EmailTemplate,
+    subject: &str,
+    html_content: &str,
+    text_content: Option<&str>,
+    context: &Context,
+) -> Result<RenderedEmail, tera::Error> {
+    let subject = Tera::one_off(subject, context, false)?;
+    let html = Tera::one_off(html_content, context, true)?;
+    let text = match text_content {
+        Some(text_content) => Tera::one_off(text_content, context, false)?,
+        None => html_to_text(&html),
+    };
+
+    let mut context = context.clone();
+    context.insert("subject", &subject);
+    context.insert("content", &html);
+    let subject = Tera::one_off(&layout.subject, &context, false)?;
+    let html = Tera::one_off(&layout.html_content, &context, true)?;
+    context.insert("content", &text);
+    let text = match &layout.text_content {
+        Some(text_content) => Tera::one_off(text_content, &context, false)?,
+        None => html_to_text(&html),
+    };
+    Ok(RenderedEmail {
+        subject: subject.trim().to_owned(),
+        html,
+        text: text.trim().to_owned(),
+    })
+}
+
+pub fn render_template(
+    layout: &EmailTemplate,
+    template: &EmailTemplate,
+    context: &Context,
+) -> Result<RenderedEmail, tera::Error> {
+    render_email(
+        layout,
+        &template.subject,
+        &template.html_content,
+        template.text_content.as_deref(),
+        context,
+    )
+}
+
+// renders the parts of a template for the sample subscriber, returns the error message otherwise
+pub fn validate_template(
+    subject: &str,
+    html_content: &str,
+    text_content: Option<&str>,
+) -> Result<(), String> {
+    let mut context = sample_context("https://example.com");
+    // the variables of the layout
+    context.insert("subject", "Subject");
+    context.insert("content", "Content");
+    let parts = [(subject, false), (html_content, true)]
+        .into_iter()
+        .chain(text_content.map(|text| (text, false)));
+    for (source, autoescape) in parts {
+        Tera::one_off(source, &context, autoescape).map_err(|e| template_error_message(&e))?;
+    }
+    Ok(())
+}
+
+pub fn html_to_text(html: &str) -> String {
+    html2text::from_read(html.as_bytes(), TEXT_WIDTH)
+}
+
+// the template errors are nested, the useful part (line, column) is at the bottom
+pub fn template_error_message(error: &tera::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_err;
+
+    use super::{recipient_context, render_email, EmailTemplate};
+
+    fn layout() -> EmailTemplate {
+        EmailTemplate {
+            name: "layout".into(),
+            version: 1,
+            subject: "[News] {{ subject }}".into(),
+            html_content: "<div>{{ content | safe }}</div>".into(),
+            text_content: Some("{{ content }}\n--\nBye".into()),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn the_body_is_personalized_and_wrapped_in_the_layout() {
+        let context = recipient_context("Ursula", "ursula@example.com");
+
+        let email = render_email(
+            &layout(),
+            "Hello {{ name }}",
+            "<p>Hi {{ name }}</p>",
+            Some("Hi {{ name }}"),
+            &context,
+        )
+        .unwrap();
+
+        assert_eq!(email.subject, "[News] Hello Ursula");
+        assert_eq!(email.html, "<div><p>Hi Ursula</p></div>");
+        assert_eq!(email.text, "Hi Ursula\n--\nBye");
+    }
+
+    #[test]
+    fn variables_are_escaped_in_the_html() {
+        let context = recipient_context("<script>", "ursula@example.com");
+
+        let email = render_email(&layout(), "Hello", "<p>{{ name }}</p>", None, &context).unwrap();
+
+        assert!(email.html.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn the_text_is_generated_from_the_html_when_missing() {
+        let context = recipient_context("Ursula", "ursula@example.com");
+
+        let email = render_email(
+            &layout(),
+            "Hello",
+            "<h1>Title</h1><p>Hi <b>{{ name }}</b></p>",
+            None,
+            &context,
+        )
+        .unwrap();
+
+        assert!(!email.text.contains('<'));
+        assert!(email.text.contains("Title"));
+        assert!(email.text.contains("Ursula"));
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        let context = recipient_context("Ursula", "ursula@example.com");
+
+        assert_err!(render_email(
+            &layout(),
+            "Hello",
+            "<p>{{ name </p>",
+            None,
+            &context
+        ));
+        assert_err!(render_email(
+            &layout(),
+            "Hello",
+            "<p>{{ unknown_variable }}</p>",
+            None,
+            &context
+        ));
+    }
+}
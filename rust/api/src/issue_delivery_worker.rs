@@ -0,0 +1,326 @@
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::configuration::DeliverySettings;
+use crate::confirmation_email_worker::try_send_confirmation;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::email_templates::{
+    get_template, recipient_context, render_email, template_error_message, LAYOUT_TEMPLATE,
+};
+use crate::routes::unsubscribe_link;
+use crate::shutdown::Shutdown;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/*
+Process the delivery queue until the application stops.
+The worker sleeps when there is nothing to deliver, or when the database is unavailable.
+On shutdown, the delivery at hand is finished before the worker returns.
+*/
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_client: EmailClient,
+    settings: DeliverySettings,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+    mut shutdown: Shutdown,
+) {
+    while !shutdown.is_requested() {
+        match try_execute_task(&pool, &email_client, &settings, &base_url, &hmac_secret).await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                shutdown.sleep(settings.poll_interval()).await;
+            }
+            Err(_) => {
+                shutdown.sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+// the confirmation emails of the imported subscribers go first, they wait to sign up
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &DeliverySettings,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    match try_send_confirmation(pool, email_client, settings, base_url).await? {
+        ExecutionOutcome::TaskCompleted => Ok(ExecutionOutcome::TaskCompleted),
+        ExecutionOutcome::EmptyQueue => {
+            try_deliver_issue(pool, email_client, settings, base_url, hmac_secret).await
+        }
+    }
+}
+
+/*
+Dequeue one delivery and try to send it.
+The row stays locked (`FOR UPDATE SKIP LOCKED`) until the transaction ends,
+so several workers can process the queue concurrently.
+*/
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id=tracing::field::Empty, subscriber_email=tracing::field::Empty),
+    err
+)]
+async fn try_deliver_issue(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &DeliverySettings,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("newsletter_issue_id", display(task.newsletter_issue_id))
+        .record("subscriber_email", display(&task.subscriber_email));
+
+    // the subscriber may have left since the issue was published
+    let Some(subscriber) = get_confirmed_subscriber(&mut transaction, &task).await? else {
+        tracing::info!("Skipping a subscriber who is not confirmed anymore");
+        delete_task(&mut transaction, &task).await?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the delivery task")?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    };
+
+    match SubscriberEmail::parse(task.subscriber_email.clone()) {
+        Ok(email) => {
+            let issue = get_issue(&mut transaction, task.newsletter_issue_id).await?;
+            let layout = get_template(&mut transaction, LAYOUT_TEMPLATE, None)
+                .await
+                .context("Failed to retrieve the email layout")?
+                .context("The email layout template is missing")?;
+            // one-click unsubscribe, RFC 8058
+            let unsubscribe_link = unsubscribe_link(&base_url.0, subscriber.id, &hmac_secret.0);
+            let list_unsubscribe = format!("<{unsubscribe_link}>");
+            let headers = [
+                ("List-Unsubscribe", list_unsubscribe.as_str()),
+                ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+            ];
+            let mut context = recipient_context(&subscriber.name, email.as_ref());
+            context.insert("unsubscribe_link", &unsubscribe_link);
+            // the issue is a template, checked when it was published
+            let outcome = match render_email(
+                &layout,
+                &issue.title,
+                &issue.html_content,
+                issue.text_content.as_deref(),
+                &context,
+            ) {
+                Ok(rendered) => {
+                    email_client
+                        .send_email_with_headers(
+                            &email,
+                            &rendered.subject,
+                            &rendered.html,
+                            &rendered.text,
+                            &headers,
+                        )
+                        .await
+                }
+                Err(error) => Err(anyhow::anyhow!(template_error_message(&error))),
+            };
+            match outcome {
+                Ok(()) => {
+                    record_delivery(&mut transaction, subscriber.id, &task).await?;
+                    delete_task(&mut transaction, &task).await?;
+                }
+                Err(error) if task.n_retries + 1 < settings.max_attempts => {
+                    let delay = settings.backoff(task.n_retries);
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        n_retries = task.n_retries,
+                        "Failed to deliver issue to a confirmed subscriber. \
+                        Retrying in {delay:?}",
+                    );
+                    reschedule_task(&mut transaction, &task, delay).await?;
+                }
+                Err(error) => {
+                    tracing::error!(
+                        error.cause_chain = ?error,
+                        error.message = %error,
+                        "Failed to deliver issue to a confirmed subscriber. \
+                        Giving up after {} attempts",
+                        settings.max_attempts,
+                    );
+                    delete_task(&mut transaction, &task).await?;
+                }
+            }
+        }
+        Err(error) => {
+            tracing::error!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                "Skipping a confirmed subscriber. \
+                Their stored contact details are invalid",
+            );
+            delete_task(&mut transaction, &task).await?;
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the delivery task")?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i32,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(Transaction<'static, Postgres>, Task)>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to dequeue a delivery task")?;
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut Transaction<'static, Postgres>,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to delete a delivery task")?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut Transaction<'static, Postgres>,
+    task: &Task,
+    delay: std::time::Duration,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay.as_secs_f64(),
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to reschedule a delivery task")?;
+    Ok(())
+}
+
+// the delivery history of the subscriber, part of the data they can export
+#[tracing::instrument(skip_all)]
+async fn record_delivery(
+    transaction: &mut Transaction<'static, Postgres>,
+    subscriber_id: Uuid,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (subscriber_id, newsletter_issue_id, sent_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        task.newsletter_issue_id,
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to record a delivery")?;
+    Ok(())
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    name: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    transaction: &mut Transaction<'static, Postgres>,
+    task: &Task,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        ConfirmedSubscriber,
+        r#"
+        SELECT id, name
+        FROM subscriptions
+        WHERE email = $1 AND status = 'confirmed'
+        "#,
+        task.subscriber_email,
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve the subscriber of a delivery task")?;
+    Ok(subscriber)
+}
+
+struct NewsletterIssue {
+    title: String,
+    // generated from the HTML when missing
+    text_content: Option<String>,
+    html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    transaction: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_one(transaction)
+    .await
+    .context("Failed to retrieve the newsletter issue")?;
+    Ok(issue)
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::clock::Clock;
+use crate::configuration::DeliverySettings;
+use crate::routes::enqueue_delivery_tasks;
+use crate::shutdown::Shutdown;
+
+/*
+Release the scheduled issues that are due, every `scheduler_interval` until the application
+stops. Their delivery is left to the workers of `issue_delivery_worker`.
+*/
+pub async fn run_scheduler_until_stopped(
+    pool: PgPool,
+    clock: Arc<dyn Clock>,
+    settings: DeliverySettings,
+    mut shutdown: Shutdown,
+) {
+    while !shutdown.is_requested() {
+        // the error is logged by the span, the next run tries again
+        let _ = release_due_issues(&pool, clock.as_ref(), settings.default_timezone).await;
+        shutdown.sleep(settings.scheduler_interval()).await;
+    }
+}
+
+/*
+Publish the scheduled issues whose `publish_at` has passed, and enqueue one delivery per
+subscriber confirmed at that point. An issue scheduled at a wall-clock time is published when that
+time comes in the first time zone; every run then enqueues the subscribers whose own time came
+since the previous run, in their time zone or `default_timezone`, until the last time zone.
+The update locks the released rows: an issue is released once even with several schedulers, and
+a concurrent reschedule or cancellation finds it published.
+Returns the number of issues released, or released to more subscribers, by this run.
+*/
+#[tracing::instrument(skip_all, err)]
+pub async fn release_due_issues(
+    pool: &PgPool,
+    clock: &dyn Clock,
+    default_timezone: Tz,
+) -> Result<usize, anyhow::Error> {
+    let now = clock.now();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    // the wall-clock time reaches the last time zones (UTC-12:00) 26 hours after the first
+    let released = sqlx::query!(
+        r#"
+        WITH due AS (
+            SELECT newsletter_issue_id, released_until
+            FROM newsletter_issues
+            WHERE publish_at <= $1 AND (
+                published_at IS NULL
+                OR (publish_at_local IS NOT NULL AND released_until < publish_at + interval '26 hours')
+            )
+            FOR UPDATE
+        )
+        UPDATE newsletter_issues i
+        SET published_at = COALESCE(i.published_at, $1), released_until = $1
+        FROM due
+        WHERE i.newsletter_issue_id = due.newsletter_issue_id
+        RETURNING i.newsletter_issue_id, i.publish_at_local, due.released_until
+        "#,
+        now,
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to release the scheduled issues")?;
+    for issue in &released {
+        match issue.publish_at_local {
+            Some(publish_at_local) => {
+                enqueue_due_delivery_tasks(
+                    &mut transaction,
+                    issue.newsletter_issue_id,
+                    publish_at_local,
+                    issue.released_until,
+                    now,
+                    default_timezone,
+                )
+                .await
+            }
+            None => enqueue_delivery_tasks(&mut transaction, issue.newsletter_issue_id).await,
+        }
+        .context("Failed to enqueue delivery tasks")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the release of the scheduled issues")?;
+    if !released.is_empty() {
+        tracing::info!(
+            n_released_issues = released.len(),
+            "Released scheduled issues"
+        );
+    }
+    Ok(released.len())
+}
+
+/*
+One delivery per confirmed subscriber for whom `publish_at_local` came in (`released_until`, `now`].
+A subscriber already enqueued, after a change of time zone, is skipped.
+*/
+#[tracing::instrument(skip(transaction))]
+async fn enqueue_due_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    publish_at_local: NaiveDateTime,
+    released_until: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    default_timezone: Tz,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, email
+        FROM (
+            SELECT email, $2::timestamp AT TIME ZONE COALESCE(timezone, $5) AS due_at
+            FROM subscriptions
+            WHERE status = 'confirmed'
+        ) s
+        WHERE due_at <= $4 AND ($3::timestamptz IS NULL OR due_at > $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        publish_at_local,
+        released_until,
+        now,
+        default_timezone.name(),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
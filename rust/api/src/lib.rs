@@ -1,6 +1,23 @@
+pub mod authentication;
+pub mod clock;
 pub mod configuration;
+pub mod confirmation_email_worker;
 pub mod domain;
 pub mod email_client;
+pub mod email_templates;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod metrics;
+pub mod rate_limit;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
+pub mod shutdown;
 pub mod startup;
+pub mod subscriber_data;
+pub mod subscriber_events;
+pub mod subscriber_import;
+pub mod subscription_cleanup;
 pub mod telemetry;
+pub mod utils;
@@ -1,22 +1,83 @@
 use std::io::Error;
+use std::path::Path;
 
-use api::configuration::get_configuration;
+use api::configuration::{get_configuration, Settings};
 use api::startup::Application;
+use api::subscriber_import::{import_subscribers_file, ImportError, ImportMode, ImportReport};
 use api::telemetry;
-use telemetry::{get_subscriber, init_subscriber};
+use telemetry::{get_subscriber, init_subscriber, shutdown_tracer_provider};
+
+const IMPORT_USAGE: &str = "Usage: api import-subscribers [--confirmed] <file.csv>";
 
 #[actix_web::main]
 async fn main() -> Result<(), Error> {
+    let configuration = get_configuration().expect("Failed to read configuration");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("import-subscribers") {
+        import_subscribers(configuration, &args[1..]).await;
+        return Ok(());
+    }
+
     let subscriber = get_subscriber(
         // output formatted spans to stdout
         "api".into(),
         "info".into(),
         std::io::stdout,
+        &configuration.telemetry,
     );
     init_subscriber(subscriber);
 
-    let configuration = get_configuration().expect("Failed to read configuration");
     let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
-    Ok(())
+    let outcome = application.run_until_stopped().await;
+    shutdown_tracer_provider();
+    outcome
+}
+
+// prints the report as JSON, exits with 1 when the file can't be imported and 2 on a usage error:
+// the report of a file that is not valid CSV is printed, the rows before the error are imported
+async fn import_subscribers(configuration: Settings, args: &[String]) {
+    // stdout is for the report
+    let subscriber = get_subscriber(
+        "api".into(),
+        "info".into(),
+        std::io::stderr,
+        &configuration.telemetry,
+    );
+    init_subscriber(subscriber);
+
+    let (flags, paths): (Vec<&String>, Vec<&String>) =
+        args.iter().partition(|arg| arg.starts_with("--"));
+    let mode = match flags.as_slice() {
+        [] => ImportMode::SendConfirmations,
+        [flag] if flag.as_str() == "--confirmed" => ImportMode::PreConfirmed,
+        _ => exit_with_usage(),
+    };
+    let [path] = paths.as_slice() else {
+        exit_with_usage()
+    };
+
+    let outcome = import_subscribers_file(&configuration, Path::new(path), mode).await;
+    shutdown_tracer_provider();
+    let print = |report: &ImportReport| {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(report).expect("Failed to serialize the report")
+        )
+    };
+    match outcome {
+        Ok(report) => print(&report),
+        Err(ImportError::InvalidCsv(report)) => {
+            print(&report);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{e:?}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{IMPORT_USAGE}");
+    std::process::exit(2);
 }
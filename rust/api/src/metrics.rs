@@ -0,0 +1,171 @@
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::PgPool;
+
+/*
+The metrics of an instance of the application, exported in the Prometheus text format on
+`/metrics`. Every application has its own registry: the tests run several of them at once.
+The handles are cheap to clone, they all update the same metrics.
+*/
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    emails_sent: IntCounterVec,
+    emails_failed: IntCounterVec,
+    subscription_funnel: IntCounterVec,
+    delivery_queue_depth: IntGauge,
+}
+
+// the steps of a subscriber, from the sign up to the end
+#[derive(Debug, Clone, Copy)]
+pub enum FunnelStep {
+    Subscribed,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl FunnelStep {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FunnelStep::Subscribed => "subscribed",
+            FunnelStep::Confirmed => "confirmed",
+            FunnelStep::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests answered"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to answer HTTP requests",
+            ),
+            &["method", "route"],
+        )?;
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Connections of the Postgres pool"),
+            &["state"],
+        )?;
+        let emails_sent = IntCounterVec::new(
+            Opts::new("emails_sent_total", "Emails handed over to the backend"),
+            &["backend"],
+        )?;
+        let emails_failed = IntCounterVec::new(
+            Opts::new("emails_failed_total", "Emails the backend failed to send"),
+            &["backend"],
+        )?;
+        let subscription_funnel = IntCounterVec::new(
+            Opts::new(
+                "subscription_funnel_total",
+                "Subscribers reaching a step of the subscription",
+            ),
+            &["step"],
+        )?;
+        let delivery_queue_depth = IntGauge::new(
+            "delivery_queue_depth",
+            "Newsletter emails waiting to be delivered",
+        )?;
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(emails_sent.clone()))?;
+        registry.register(Box::new(emails_failed.clone()))?;
+        registry.register(Box::new(subscription_funnel.clone()))?;
+        registry.register(Box::new(delivery_queue_depth.clone()))?;
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            emails_sent,
+            emails_failed,
+            subscription_funnel,
+            delivery_queue_depth,
+        })
+    }
+
+    pub fn record_email(&self, backend: &str, sent: bool) {
+        let counter = if sent {
+            &self.emails_sent
+        } else {
+            &self.emails_failed
+        };
+        counter.with_label_values(&[backend]).inc();
+    }
+
+    pub fn record_funnel_step(&self, step: FunnelStep) {
+        self.subscription_funnel
+            .with_label_values(&[step.as_str()])
+            .inc();
+    }
+
+    // the gauges are sampled when the metrics are scraped
+    #[tracing::instrument(name = "Export metrics", skip_all)]
+    pub async fn export(&self, pool: &PgPool) -> Result<String, anyhow::Error> {
+        let idle = pool.num_idle() as i64;
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(i64::from(pool.size()) - idle);
+        let queue_depth = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+            .fetch_one(pool)
+            .await?
+            .count;
+        self.delivery_queue_depth.set(queue_depth);
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+}
+
+/*
+Middleware of the application (`middleware::from_fn`): count the requests and measure their
+latency, per route. The route is the pattern (`/issues/{slug}`), not the path, to keep the number
+of series bounded.
+*/
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = req
+        .app_data::<web::Data<Metrics>>()
+        .expect("The metrics are missing from the application data")
+        .clone();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_owned());
+    let start = Instant::now();
+
+    let outcome = next.call(req).await;
+
+    let status = match &outcome {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    metrics
+        .http_requests
+        .with_label_values(&[&method, &route, status.as_str()])
+        .inc();
+    metrics
+        .http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    outcome
+}
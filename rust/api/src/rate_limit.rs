@@ -0,0 +1,136 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{RETRY_AFTER, X_FORWARDED_FOR};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::net::IpAddr;
+
+use crate::configuration::{RateLimit, RateLimitSettings};
+use crate::domain::SubscriberEmail;
+use crate::utils::e500;
+
+// the field of the sign up forms holding the address
+#[derive(serde::Deserialize)]
+struct RecipientForm {
+    email: Option<String>,
+}
+
+/*
+Middleware of the sign up and data request forms (`middleware::from_fn`): a client, and an address, can only send
+so many requests per window. Over the limit, the answer is `429 Too Many Requests` with the
+number of seconds to wait in `Retry-After`.
+The counters are in Postgres, shared by every instance of the application.
+*/
+pub async fn limit_sign_up_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .expect("The database pool is missing from the application data")
+        .clone();
+    let settings = req
+        .app_data::<web::Data<RateLimitSettings>>()
+        .expect("The rate limit settings are missing from the application data")
+        .clone();
+
+    let client_ip = client_ip(&req, settings.trusted_proxies);
+    // the body is read to find the address, then put back for the handler
+    let body = req.extract::<web::Bytes>().await?;
+    let recipient = serde_urlencoded::from_bytes::<RecipientForm>(&body)
+        .ok()
+        .and_then(|form| form.email)
+        .and_then(|email| SubscriberEmail::parse(email).ok())
+        // an invalid address is rejected by the handler
+        .map(SubscriberEmail::without_plus_tag);
+    req.set_payload(Payload::from(body));
+
+    let keys = [
+        client_ip.map(|ip| (format!("ip:{ip}"), settings.per_ip)),
+        recipient.map(|email| (format!("email:{email}"), settings.per_recipient)),
+    ];
+    for (key, limit) in keys.into_iter().flatten() {
+        if let Some(retry_after) = hit(&pool, &key, limit).await.map_err(e500)? {
+            let response = HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, retry_after.to_string()))
+                .finish();
+            let e = anyhow::anyhow!("Too many sign up requests for {key}");
+            return Err(InternalError::from_response(e, response).into());
+        }
+    }
+    next.call(req).await
+}
+
+/*
+The address of the client, as seen by the outermost of the `trusted_proxies`: the entry it added to
+`X-Forwarded-For`, counting from the right. A request with fewer entries didn't go through every
+proxy, it is counted under the address of the connection.
+*/
+fn client_ip(req: &ServiceRequest, trusted_proxies: usize) -> Option<String> {
+    let peer_ip = req.peer_addr().map(|address| address.ip());
+    if trusted_proxies == 0 {
+        return peer_ip.map(|ip| ip.to_string());
+    }
+    // several headers make up a single list
+    let forwarded_for: Vec<&str> = req
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    forwarded_for
+        .len()
+        .checked_sub(trusted_proxies)
+        .and_then(|index| forwarded_for[index].parse::<IpAddr>().ok())
+        .or(peer_ip)
+        .map(|ip| ip.to_string())
+}
+
+/*
+Count a request in the current window of the key, a new window starts once the previous one has
+expired. Returns the seconds until the end of the window when the limit is exceeded.
+*/
+#[tracing::instrument(name = "Count a rate limited request", skip(pool, limit))]
+async fn hit(pool: &PgPool, key: &str, limit: RateLimit) -> Result<Option<i64>, sqlx::Error> {
+    let window = sqlx::query!(
+        r#"
+        INSERT INTO rate_limits (key, hits, expires_at)
+        VALUES ($1, 1, now() + make_interval(secs => $2))
+        ON CONFLICT (key) DO UPDATE SET
+            hits = CASE WHEN rate_limits.expires_at <= now() THEN 1 ELSE rate_limits.hits + 1 END,
+            expires_at = CASE
+                WHEN rate_limits.expires_at <= now() THEN EXCLUDED.expires_at
+                ELSE rate_limits.expires_at
+            END
+        RETURNING hits, expires_at
+        "#,
+        key,
+        limit.window_seconds as f64,
+    )
+    .fetch_one(pool)
+    .await?;
+    if window.hits <= limit.max_requests {
+        return Ok(None);
+    }
+    Ok(Some(seconds_until(window.expires_at)))
+}
+
+// rounded up, at least a second
+fn seconds_until(expires_at: DateTime<Utc>) -> i64 {
+    let milliseconds = (expires_at - Utc::now()).num_milliseconds();
+    ((milliseconds + 999) / 1000).max(1)
+}
+
+// the counters of the windows that have ended
+#[tracing::instrument(skip_all, err)]
+pub async fn delete_expired_rate_limits(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let n_deleted = sqlx::query!("DELETE FROM rate_limits WHERE expires_at <= now()")
+        .execute(pool)
+        .await?
+        .rows_affected();
+    Ok(n_deleted)
+}
@@ -0,0 +1,215 @@
+use actix_web::error::ErrorNotFound;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{
+    create_api_token, list_api_tokens, revoke_api_token, ApiScope, ApiToken, UserId,
+};
+use crate::session_state::{FlashMessage, TypedSession};
+use crate::utils::{e500, see_other};
+
+fn token_status(token: &ApiToken) -> String {
+    match (token.revoked_at, token.expires_at) {
+        (Some(revoked_at), _) => format!("revoked on {}", revoked_at.format("%Y-%m-%d %H:%M")),
+        (None, Some(expires_at)) if expires_at <= Utc::now() => {
+            format!("expired on {}", expires_at.format("%Y-%m-%d %H:%M"))
+        }
+        (None, Some(expires_at)) => {
+            format!("expires on {}", expires_at.format("%Y-%m-%d %H:%M"))
+        }
+        (None, None) => "never expires".into(),
+    }
+}
+
+// the tokens of the logged in user, and the form to create one
+pub async fn api_tokens(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let tokens = list_api_tokens(&pool, *user_id.into_inner())
+        .await
+        .map_err(e500)?;
+    let flash_html = session.take_flash_messages_html();
+    let rows: String = tokens
+        .iter()
+        .map(|token| {
+            let last_used_at = token
+                .last_used_at
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "never".into());
+            let revoke_form = if token.revoked_at.is_none() {
+                format!(
+                    r#"<form action="/admin/tokens/{}/revoke" method="post"><button type="submit">Revoke</button></form>"#,
+                    token.id
+                )
+            } else {
+                String::new()
+            };
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{revoke_form}</td></tr>"#,
+                htmlescape::encode_minimal(&token.name),
+                htmlescape::encode_minimal(&token.scopes.join(" ")),
+                token.created_at.format("%Y-%m-%d %H:%M"),
+                last_used_at,
+                token_status(token),
+            )
+        })
+        .collect();
+    let scope_checkboxes: String = ApiScope::ALL
+        .iter()
+        .map(|scope| {
+            format!(
+                r#"<label><input type="checkbox" name="{0}" value="on"> {0}</label><br>"#,
+                scope.as_str()
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>API tokens</title>
+</head>
+<body>
+    {flash_html}
+    <p>Your API tokens, sent as <code>Authorization: Bearer &lt;token&gt;</code>:</p>
+    <table>
+        <tr><th>Name</th><th>Scopes</th><th>Created</th><th>Last used</th><th>Status</th><th></th></tr>
+        {rows}
+    </table>
+    <p>New token:</p>
+    <form action="/admin/tokens" method="post">
+        <label>Name:<br>
+            <input type="text" placeholder="Where the token is used" name="name">
+        </label>
+        <br>
+        {scope_checkboxes}
+        <label>Expires in (days, never when empty):<br>
+            <input type="number" min="1" name="expires_in_days">
+        </label>
+        <br>
+        <button type="submit">Create</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ApiTokenFormData {
+    name: String,
+    #[serde(rename = "newsletters:publish")]
+    publish: Option<String>,
+    #[serde(rename = "newsletters:schedule")]
+    schedule: Option<String>,
+    #[serde(rename = "subscribers:manage")]
+    manage_subscribers: Option<String>,
+    expires_in_days: Option<String>,
+}
+
+/*
+The token is shown once, on the page answering the form: it is not kept in the session like a
+flash message would be.
+*/
+#[tracing::instrument(name = "Create an API token", skip(form, pool, session), fields(user_id=%*user_id))]
+pub async fn create_api_token_form(
+    form: web::Form<ApiTokenFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let ApiTokenFormData {
+        name,
+        publish,
+        schedule,
+        manage_subscribers,
+        expires_in_days,
+    } = form.0;
+    let name = name.trim();
+    let scopes: Vec<ApiScope> = [
+        (ApiScope::Publish, publish),
+        (ApiScope::Schedule, schedule),
+        (ApiScope::ManageSubscribers, manage_subscribers),
+    ]
+    .into_iter()
+    .filter(|(_, checked)| checked.is_some())
+    .map(|(scope, _)| scope)
+    .collect();
+    let checked_form = if name.is_empty() {
+        Err("The token needs a name.")
+    } else if scopes.is_empty() {
+        Err("The token needs at least one scope.")
+    } else {
+        expiry(expires_in_days.as_deref())
+    };
+    let expires_at = match checked_form {
+        Ok(expires_at) => expires_at,
+        Err(error) => {
+            session
+                .add_flash_message(FlashMessage::error(error))
+                .map_err(e500)?;
+            return Ok(see_other("/admin/tokens"));
+        }
+    };
+    let token = create_api_token(&pool, *user_id, name, &scopes, expires_at)
+        .await
+        .map_err(e500)?;
+    let name = htmlescape::encode_minimal(name);
+    let token = htmlescape::encode_minimal(token.expose_secret());
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>New API token</title>
+</head>
+<body>
+    <p>The token <b>{name}</b> has been created. Copy it now, it won't be shown again:</p>
+    <pre id="api-token">{token}</pre>
+    <p><a href="/admin/tokens">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+// the expiry date, from a number of days (never when empty)
+fn expiry(expires_in_days: Option<&str>) -> Result<Option<DateTime<Utc>>, &'static str> {
+    match expires_in_days.map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(days) => match days.parse::<u32>() {
+            Ok(days) if days > 0 => Ok(Some(Utc::now() + Duration::days(days.into()))),
+            _ => Err("The expiry must be a positive number of days."),
+        },
+    }
+}
+
+#[tracing::instrument(name = "Revoke an API token", skip(pool, session), fields(user_id=%*user_id))]
+pub async fn revoke_api_token_form(
+    token_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let revoked = revoke_api_token(&pool, *user_id.into_inner(), *token_id)
+        .await
+        .map_err(e500)?;
+    if !revoked {
+        return Err(ErrorNotFound("Unknown API token"));
+    }
+    session
+        .add_flash_message(FlashMessage::info("The token has been revoked."))
+        .map_err(e500)?;
+    Ok(see_other("/admin/tokens"))
+}
@@ -0,0 +1,12 @@
+use actix_web::HttpResponse;
+
+use crate::session_state::{FlashMessage, TypedSession};
+use crate::utils::{e500, see_other};
+
+pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
+    session.log_out();
+    session
+        .add_flash_message(FlashMessage::info("You have successfully logged out."))
+        .map_err(e500)?;
+    Ok(see_other("/login"))
+}
@@ -0,0 +1,13 @@
+mod api_tokens;
+mod dashboard;
+mod logout;
+mod newsletters;
+mod password;
+mod templates;
+
+pub use api_tokens::*;
+pub use dashboard::*;
+pub use logout::*;
+pub use newsletters::*;
+pub use password::*;
+pub use templates::*;
@@ -0,0 +1,128 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::authentication::UserId;
+use crate::email_templates::validate_template;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::routes::{enqueue_delivery_tasks, insert_newsletter_issue};
+use crate::session_state::{FlashMessage, TypedSession};
+use crate::startup::IdempotencyTtl;
+use crate::utils::{e400, e500, see_other};
+
+pub async fn publish_newsletter_form(session: TypedSession) -> HttpResponse {
+    let flash_html = session.take_flash_messages_html();
+    // a new key for every form, a double submit of the same form publishes once
+    let idempotency_key = uuid::Uuid::new_v4();
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Publish Newsletter Issue</title>
+</head>
+<body>
+    {flash_html}
+    <form action="/admin/newsletters" method="post">
+        <label>Title:<br>
+            <input type="text" placeholder="Enter the issue title" name="title">
+        </label>
+        <br>
+        <label>HTML content:<br>
+            <textarea placeholder="Enter the content in HTML format" name="html_content" rows="20" cols="50"></textarea>
+        </label>
+        <br>
+        <label>Plain text content (generated from the HTML when empty):<br>
+            <textarea placeholder="Enter the content in plain text" name="text_content" rows="20" cols="50"></textarea>
+        </label>
+        <p>The content can use the variables <code>{{{{ name }}}}</code>, <code>{{{{ email }}}}</code>
+        and <code>{{{{ unsubscribe_link | safe }}}}</code> of each subscriber.</p>
+        <br>
+        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+        <button type="submit">Publish</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        ))
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewsletterFormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+    idempotency_key: String,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue from the admin area",
+    skip_all,
+    fields(user_id=%*user_id)
+)]
+pub async fn publish_newsletter_issue(
+    form: web::Form<NewsletterFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    idempotency_ttl: web::Data<IdempotencyTtl>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let NewsletterFormData {
+        title,
+        text_content,
+        html_content,
+        idempotency_key,
+    } = form.0;
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    // the text is generated from the HTML
+    let text_content = Some(text_content).filter(|text| !text.trim().is_empty());
+    if let Err(e) = validate_template(&title, &html_content, text_content.as_deref()) {
+        session
+            .add_flash_message(FlashMessage::error(format!("Invalid template: {e}")))
+            .map_err(e500)?;
+        return Ok(see_other("/admin/newsletters"));
+    }
+    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id, idempotency_ttl.0)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message(&session)?;
+            return Ok(saved_response);
+        }
+    };
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        text_content.as_deref(),
+        &html_content,
+        None,
+        *user_id,
+    )
+    .await
+    .context("Failed to store newsletter issue details")
+    .map_err(e500)?;
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")
+        .map_err(e500)?;
+    let response = see_other("/admin/newsletters");
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+    success_message(&session)?;
+    Ok(response)
+}
+
+fn success_message(session: &TypedSession) -> Result<(), actix_web::Error> {
+    session
+        .add_flash_message(FlashMessage::info(
+            "The newsletter issue has been accepted - emails will go out shortly.",
+        ))
+        .map_err(e500)
+}
@@ -0,0 +1,115 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::authentication::{
+    get_username, validate_credentials, validate_password_strength, AuthError, Credentials,
+    PasswordHashing, UserId,
+};
+use crate::session_state::{FlashMessage, TypedSession};
+use crate::utils::{e500, see_other};
+
+pub async fn change_password_form(session: TypedSession) -> HttpResponse {
+    let flash_html = session.take_flash_messages_html();
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Change Password</title>
+</head>
+<body>
+    {flash_html}
+    <form action="/admin/password" method="post">
+        <label>Current password
+            <input type="password" placeholder="Enter current password" name="current_password">
+        </label>
+        <br>
+        <label>New password
+            <input type="password" placeholder="Enter new password" name="new_password">
+        </label>
+        <br>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again" name="new_password_check">
+        </label>
+        <br>
+        <button type="submit">Change password</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        ))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordFormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+pub async fn change_password(
+    form: web::Form<PasswordFormData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let PasswordFormData {
+        current_password,
+        new_password,
+        new_password_check,
+    } = form.0;
+
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        return password_form_error(
+            &session,
+            "You entered two different new passwords - the field values must match.",
+        );
+    }
+    if new_password.expose_secret() == current_password.expose_secret() {
+        return password_form_error(
+            &session,
+            "The new password must be different from the current one.",
+        );
+    }
+
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    if let Err(message) = validate_password_strength(&new_password, &username) {
+        return password_form_error(&session, &message);
+    }
+    let credentials = Credentials {
+        username,
+        password: current_password,
+    };
+    if let Err(e) = validate_credentials(credentials, &pool, &password_hashing).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                password_form_error(&session, "The current password is incorrect.")
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+
+    crate::authentication::change_password(*user_id, new_password, &pool, &password_hashing)
+        .await
+        .map_err(e500)?;
+    session
+        .add_flash_message(FlashMessage::info("Your password has been changed."))
+        .map_err(e500)?;
+    Ok(see_other("/admin/password"))
+}
+
+fn password_form_error(
+    session: &TypedSession,
+    message: &str,
+) -> Result<HttpResponse, actix_web::Error> {
+    session
+        .add_flash_message(FlashMessage::error(message))
+        .map_err(e500)?;
+    Ok(see_other("/admin/password"))
+}
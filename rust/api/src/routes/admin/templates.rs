@@ -0,0 +1,225 @@
+use actix_web::error::ErrorNotFound;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::email_templates::{
+    get_template, list_templates, render_email, render_template, sample_context,
+    save_template_version, template_error_message, validate_template, LAYOUT_TEMPLATE,
+};
+use crate::session_state::{FlashMessage, TypedSession};
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{e500, see_other};
+
+pub async fn email_templates(
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let templates = list_templates(&pool).await.map_err(e500)?;
+    let flash_html = session.take_flash_messages_html();
+    let rows: String = templates
+        .iter()
+        .map(|t| {
+            let name = htmlescape::encode_minimal(&t.name);
+            format!(
+                r#"<li>{name} (version {}, {}) - <a href="/admin/templates/{name}">Edit</a> - <a href="/admin/templates/{name}/preview">Preview</a></li>"#,
+                t.version,
+                t.created_at.format("%Y-%m-%d %H:%M"),
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Email templates</title>
+</head>
+<body>
+    {flash_html}
+    <p>Email templates:</p>
+    <ul>
+        {rows}
+    </ul>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+// the form starts from the latest version
+pub async fn edit_email_template_form(
+    name: web::Path<String>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut connection = pool.acquire().await.map_err(e500)?;
+    let template = get_template(&mut connection, &name, None)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| ErrorNotFound("Unknown email template"))?;
+    let flash_html = session.take_flash_messages_html();
+    let name = htmlescape::encode_minimal(&template.name);
+    let subject = htmlescape::encode_attribute(&template.subject);
+    let html_content = htmlescape::encode_minimal(&template.html_content);
+    let text_content = htmlescape::encode_minimal(template.text_content.as_deref().unwrap_or(""));
+    let version = template.version;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Edit email template</title>
+</head>
+<body>
+    {flash_html}
+    <p>Template <b>{name}</b>, version {version}. Saving creates a new version.</p>
+    <form action="/admin/templates/{name}" method="post">
+        <label>Subject:<br>
+            <input type="text" name="subject" value="{subject}" size="50">
+        </label>
+        <br>
+        <label>HTML content:<br>
+            <textarea name="html_content" rows="20" cols="80">{html_content}</textarea>
+        </label>
+        <br>
+        <label>Plain text content (generated from the HTML when empty):<br>
+            <textarea name="text_content" rows="20" cols="80">{text_content}</textarea>
+        </label>
+        <br>
+        <button type="submit">Save</button>
+    </form>
+    <p><a href="/admin/templates/{name}/preview">Preview</a></p>
+    <p><a href="/admin/templates">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailTemplateFormData {
+    subject: String,
+    html_content: String,
+    text_content: String,
+}
+
+#[tracing::instrument(
+    name = "Save a new version of an email template",
+    skip(form, pool, session)
+)]
+pub async fn save_email_template(
+    name: web::Path<String>,
+    form: web::Form<EmailTemplateFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = name.into_inner();
+    let mut connection = pool.acquire().await.map_err(e500)?;
+    // only the templates used by the application can be changed
+    get_template(&mut connection, &name, None)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| ErrorNotFound("Unknown email template"))?;
+    let EmailTemplateFormData {
+        subject,
+        html_content,
+        text_content,
+    } = form.0;
+    let text_content = Some(text_content).filter(|text| !text.trim().is_empty());
+    let location = format!("/admin/templates/{name}");
+    if let Err(e) = validate_template(&subject, &html_content, text_content.as_deref()) {
+        session
+            .add_flash_message(FlashMessage::error(format!("Invalid template: {e}")))
+            .map_err(e500)?;
+        return Ok(see_other(&location));
+    }
+    let version = save_template_version(
+        &pool,
+        &name,
+        &subject,
+        &html_content,
+        text_content.as_deref(),
+    )
+    .await
+    .map_err(e500)?;
+    session
+        .add_flash_message(FlashMessage::info(format!(
+            "The template has been saved as version {version}."
+        )))
+        .map_err(e500)?;
+    Ok(see_other(&location))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewParameters {
+    version: Option<i32>,
+}
+
+/*
+The template rendered for a made up subscriber, with the latest layout.
+The layout itself is previewed around a sample content.
+*/
+pub async fn preview_email_template(
+    name: web::Path<String>,
+    parameters: web::Query<PreviewParameters>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut connection = pool.acquire().await.map_err(e500)?;
+    let template = get_template(&mut connection, &name, parameters.version)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| ErrorNotFound("Unknown email template"))?;
+    let context = sample_context(&base_url.0);
+    let rendered = if template.name == LAYOUT_TEMPLATE {
+        render_email(
+            &template,
+            "Sample subject",
+            "<p>Hello {{ name }}, this is a sample content.</p>",
+            None,
+            &context,
+        )
+    } else {
+        let layout = get_template(&mut connection, LAYOUT_TEMPLATE, None)
+            .await
+            .map_err(e500)?
+            .ok_or_else(|| e500(anyhow::anyhow!("The email layout template is missing")))?;
+        render_template(&layout, &template, &context)
+    };
+    let body = match rendered {
+        Ok(email) => format!(
+            r#"<p>Subject: <b>{}</b></p>
+    <iframe srcdoc="{}" width="800" height="500"></iframe>
+    <pre>{}</pre>"#,
+            htmlescape::encode_minimal(&email.subject),
+            htmlescape::encode_attribute(&email.html),
+            htmlescape::encode_minimal(&email.text),
+        ),
+        Err(e) => format!(
+            r#"<p class="error"><i>{}</i></p>"#,
+            htmlescape::encode_minimal(&template_error_message(&e))
+        ),
+    };
+    let name = htmlescape::encode_minimal(&template.name);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Preview email template</title>
+</head>
+<body>
+    <p>Template <b>{name}</b>, version {}, for a sample subscriber:</p>
+    {body}
+    <p><a href="/admin/templates/{name}">&lt;- Back</a></p>
+</body>
+</html>"#,
+            template.version,
+        )))
+}
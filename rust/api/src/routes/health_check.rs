@@ -1,5 +1,155 @@
-use actix_web::HttpResponse;
+use std::collections::HashSet;
+
+use actix_web::{web, HttpResponse};
+use sqlx::migrate::Migrator;
+use sqlx::PgPool;
+
+use crate::configuration::{EmailBackend, EmailClientSettings};
+
+// the migrations the binary was built with
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+/*
+Liveness probe: the process answers. The dependencies are not checked, restarting the
+application doesn't fix a database that is down.
+*/
+pub async fn live() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "live": true }))
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum CheckStatus {
+    Ok,
+    Failed,
+}
+
+#[derive(serde::Serialize)]
+struct Check {
+    status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn new(outcome: Result<(), String>) -> Self {
+        match outcome {
+            Ok(()) => Self {
+                status: CheckStatus::Ok,
+                error: None,
+            },
+            Err(error) => Self {
+                status: CheckStatus::Failed,
+                error: Some(error),
+            },
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        matches!(self.status, CheckStatus::Ok)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct MigrationsCheck {
+    #[serde(flatten)]
+    check: Check,
+    // the versions not applied to the database yet
+    pending: Vec<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct EmailBackendCheck {
+    #[serde(flatten)]
+    check: Check,
+    backend: EmailBackend,
+}
+
+#[derive(serde::Serialize)]
+struct ReadinessChecks {
+    database: Check,
+    migrations: MigrationsCheck,
+    email_backend: EmailBackendCheck,
+}
+
+#[derive(serde::Serialize)]
+struct ReadinessReport {
+    ready: bool,
+    checks: ReadinessChecks,
+}
+
+/*
+Readiness probe: the application can serve its requests. 503 with the failed checks otherwise,
+the load balancer keeps the traffic away until they pass.
+*/
+#[tracing::instrument(name = "Readiness probe", skip_all)]
+pub async fn ready(
+    pool: web::Data<PgPool>,
+    email_client_settings: web::Data<EmailClientSettings>,
+) -> HttpResponse {
+    // the errors of the database are logged, the probe is public
+    let database = Check::new(
+        sqlx::query!("SELECT 1 AS one")
+            .fetch_one(pool.get_ref())
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                tracing::error!(error.cause_chain = ?e, "The database is unreachable");
+                "The database is unreachable".to_string()
+            }),
+    );
+    let migrations = match pending_migrations(&pool).await {
+        Ok(pending) if pending.is_empty() => MigrationsCheck {
+            check: Check::new(Ok(())),
+            pending,
+        },
+        Ok(pending) => MigrationsCheck {
+            check: Check::new(Err(format!("{} pending migrations", pending.len()))),
+            pending,
+        },
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to read the applied migrations");
+            MigrationsCheck {
+                check: Check::new(Err("Failed to read the applied migrations".into())),
+                pending: Vec::new(),
+            }
+        }
+    };
+    let email_backend = EmailBackendCheck {
+        check: Check::new(email_client_settings.check()),
+        backend: email_client_settings.backend,
+    };
+    let ready = database.is_ok() && migrations.check.is_ok() && email_backend.check.is_ok();
+    let report = ReadinessReport {
+        ready,
+        checks: ReadinessChecks {
+            database,
+            migrations,
+            email_backend,
+        },
+    };
+    if ready {
+        HttpResponse::Ok().json(report)
+    } else {
+        tracing::warn!("The application is not ready");
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+async fn pending_migrations(pool: &PgPool) -> Result<Vec<i64>, sqlx::Error> {
+    let applied: HashSet<i64> = sqlx::query!("SELECT version FROM _sqlx_migrations WHERE success")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| r.version)
+        .collect();
+    Ok(MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect())
+}
@@ -0,0 +1,209 @@
+use actix_web::error::ErrorNotFound;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::email_templates::render_for_readers;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{e400, e500};
+
+const ISSUES_PER_PAGE: i64 = 10;
+const ISSUES_IN_FEED: i64 = 20;
+
+struct PublishedIssue {
+    slug: String,
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+impl PublishedIssue {
+    // the issues are templates, rendered for an anonymous reader
+    fn title(&self) -> Result<String, actix_web::Error> {
+        render_for_readers(&self.title, false).map_err(e500)
+    }
+
+    fn html(&self) -> Result<String, actix_web::Error> {
+        render_for_readers(&self.html_content, true).map_err(e500)
+    }
+}
+
+// the most recent issues first, `limit` issues from `offset`
+async fn get_published_issues(
+    pool: &PgPool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<PublishedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        SELECT slug, title, html_content, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE published_at IS NOT NULL
+        ORDER BY published_at DESC
+        LIMIT $1 OFFSET $2
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(serde::Deserialize)]
+pub struct ArchiveParameters {
+    page: Option<i64>,
+}
+
+#[tracing::instrument(name = "Newsletter archive", skip(parameters, pool))]
+pub async fn issues_archive(
+    parameters: web::Query<ArchiveParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = parameters.page.unwrap_or(1);
+    if page < 1 {
+        return Err(e400("The page number starts at 1"));
+    }
+    let offset = (page - 1)
+        .checked_mul(ISSUES_PER_PAGE)
+        .ok_or_else(|| e400("The page number is too large"))?;
+    // one more issue tells whether there is a next page
+    let mut issues = get_published_issues(&pool, ISSUES_PER_PAGE + 1, offset)
+        .await
+        .map_err(e500)?;
+    let has_older_issues = issues.len() as i64 > ISSUES_PER_PAGE;
+    issues.truncate(ISSUES_PER_PAGE as usize);
+    let mut rows = String::new();
+    for issue in &issues {
+        rows.push_str(&format!(
+            r#"<li><a href="/issues/{}">{}</a> ({})</li>"#,
+            htmlescape::encode_minimal(&issue.slug),
+            htmlescape::encode_minimal(&issue.title()?),
+            issue.published_at.format("%Y-%m-%d"),
+        ));
+    }
+    if issues.is_empty() {
+        rows.push_str("<li>No issue yet.</li>");
+    }
+    let mut navigation = Vec::new();
+    if page > 1 {
+        navigation.push(format!(
+            r#"<a href="/issues?page={}">Newer issues</a>"#,
+            page - 1
+        ));
+    }
+    if has_older_issues {
+        navigation.push(format!(
+            r#"<a href="/issues?page={}">Older issues</a>"#,
+            page + 1
+        ));
+    }
+    let navigation = navigation.join(" - ");
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <link rel="alternate" type="application/rss+xml" title="Newsletter" href="/feed.xml">
+    <title>Newsletter archive</title>
+</head>
+<body>
+    <p>Newsletter archive:</p>
+    <ul>
+        {rows}
+    </ul>
+    <p>{navigation}</p>
+    <p><a href="/feed.xml">Follow the newsletter by feed</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Newsletter issue page", skip(pool))]
+pub async fn issue_page(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = sqlx::query_as!(
+        PublishedIssue,
+        r#"
+        SELECT slug, title, html_content, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE slug = $1 AND published_at IS NOT NULL
+        "#,
+        *slug
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(e500)?
+    .ok_or_else(|| ErrorNotFound("Unknown newsletter issue"))?;
+    let title = htmlescape::encode_minimal(&issue.title()?);
+    let html = issue.html()?;
+    let published_at = issue.published_at.format("%Y-%m-%d");
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p><small>Published on {published_at}</small></p>
+    {html}
+    <p><a href="/issues">&lt;- Archive</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+/*
+RSS 2.0 feed of the latest issues, the content of an issue is its escaped HTML.
+The links are absolute, the feed readers fetch it from anywhere.
+*/
+#[tracing::instrument(name = "Newsletter feed", skip(pool, base_url))]
+pub async fn feed(
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_published_issues(&pool, ISSUES_IN_FEED, 0)
+        .await
+        .map_err(e500)?;
+    let base_url = &base_url.0;
+    let mut items = String::new();
+    for issue in &issues {
+        let link = htmlescape::encode_minimal(&format!("{base_url}/issues/{}", issue.slug));
+        items.push_str(&format!(
+            r#"
+        <item>
+            <title>{}</title>
+            <link>{link}</link>
+            <guid isPermaLink="true">{link}</guid>
+            <pubDate>{}</pubDate>
+            <description>{}</description>
+        </item>"#,
+            htmlescape::encode_minimal(&issue.title()?),
+            issue.published_at.to_rfc2822(),
+            htmlescape::encode_minimal(&issue.html()?),
+        ));
+    }
+    let base_url = htmlescape::encode_minimal(base_url);
+    Ok(HttpResponse::Ok()
+        .content_type("application/rss+xml; charset=utf-8")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+    <channel>
+        <title>Newsletter</title>
+        <link>{base_url}/issues</link>
+        <description>The issues of the newsletter</description>
+        <atom:link href="{base_url}/feed.xml" rel="self" type="application/rss+xml"/>{items}
+    </channel>
+</rss>"#,
+        )))
+}
@@ -0,0 +1,16 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::metrics::Metrics;
+use crate::utils::e500;
+
+// scraped by Prometheus, in its text format
+pub async fn export_metrics(
+    metrics: web::Data<Metrics>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let body = metrics.export(&pool).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
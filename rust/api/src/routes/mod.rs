@@ -1,12 +1,28 @@
+mod admin;
 mod health_check;
+mod issues;
+mod login;
+mod metrics;
 mod newsletters;
+mod subscribers;
+mod subscribers_import;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod subscriptions_unsubscribe;
 
+pub use admin::*;
 pub use health_check::*;
+pub use issues::*;
+pub use login::*;
+pub use metrics::*;
 pub use newsletters::*;
+pub use subscribers::*;
+pub use subscribers_import::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_data::*;
+pub use subscriptions_unsubscribe::*;
 
 pub fn error_chain_fmt(
     e: &impl std::error::Error,
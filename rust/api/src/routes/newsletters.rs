@@ -1,40 +1,83 @@
 use std::fmt::Formatter;
 
 use actix_web::body::BoxBody;
-use actix_web::http::header::{HeaderMap, HeaderValue};
-use actix_web::http::{header, StatusCode};
+use actix_web::http::header::HeaderMap;
+use actix_web::http::StatusCode;
 use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
-use base64::Engine;
-use secrecy::ExposeSecret;
-use secrecy::Secret;
-use sqlx::PgPool;
-
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::{
+    authenticate_api_request, unauthorized_response, ApiAuthError, ApiScope, PasswordHashing,
+};
+use crate::clock::Clock;
+use crate::email_templates::{render_for_readers, validate_template};
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
 use crate::routes::error_chain_fmt;
+use crate::startup::IdempotencyTtl;
 
+/*
+An issue with a `publish_at` in the future is stored, and released by the scheduler at that
+time (see `issue_scheduler`).
+*/
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
     content: Content,
+    publish_at: Option<PublishAt>,
+}
+
+/*
+With an offset, `2023-07-24T08:00:00+02:00`, the issue is sent to everyone at that instant.
+Without, `2023-07-24T08:00:00`, every subscriber receives it at that time in their own time zone.
+*/
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(untagged)]
+pub enum PublishAt {
+    Instant(DateTime<FixedOffset>),
+    SubscriberLocal(NaiveDateTime),
 }
 
+impl PublishAt {
+    /// The first instant when the issue is due for a subscriber: a wall-clock time comes first in
+    /// the time zones at UTC+14:00, and last 26 hours later in those at UTC-12:00.
+    pub fn first_instant(&self) -> DateTime<Utc> {
+        match self {
+            PublishAt::Instant(instant) => instant.with_timezone(&Utc),
+            PublishAt::SubscriberLocal(local) => local.and_utc() - chrono::Duration::hours(14),
+        }
+    }
+
+    pub fn local(&self) -> Option<NaiveDateTime> {
+        match self {
+            PublishAt::Instant(_) => None,
+            PublishAt::SubscriberLocal(local) => Some(*local),
+        }
+    }
+}
+
+/*
+The content is a template, personalized for every subscriber (see `email_templates`).
+The text is generated from the HTML when missing.
+*/
 #[derive(serde::Deserialize)]
 pub struct Content {
     html: String,
-    text: String,
-}
-
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+    text: Option<String>,
 }
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("The API token does not have the scope of this request")]
+    MissingScope,
+    #[error("There is no scheduled issue with this id")]
+    UnknownScheduledIssue,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -48,173 +91,306 @@ impl std::fmt::Debug for PublishError {
 impl ResponseError for PublishError {
     fn error_response(&self) -> HttpResponse<BoxBody> {
         match self {
+            PublishError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            PublishError::MissingScope => HttpResponse::new(StatusCode::FORBIDDEN),
+            PublishError::UnknownScheduledIssue => HttpResponse::new(StatusCode::NOT_FOUND),
             PublishError::UnexpectedError(_) => {
                 HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
             }
-            PublishError::AuthError(_) => {
-                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
-                response
-                    .headers_mut()
-                    .insert(header::WWW_AUTHENTICATE, header_value);
-                response
-            }
+            PublishError::AuthError(_) => unauthorized_response("publish"),
         }
     }
 }
 
-struct Credentials {
-    username: String,
-    password: Secret<String>,
+impl From<ApiAuthError> for PublishError {
+    fn from(e: ApiAuthError) -> Self {
+        match e {
+            ApiAuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+            ApiAuthError::MissingScope => PublishError::MissingScope,
+            ApiAuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+        }
+    }
 }
 
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request),
+    skip(body, pool, password_hashing, idempotency_ttl, clock, request),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    password_hashing: web::Data<PasswordHashing>,
+    idempotency_ttl: web::Data<IdempotencyTtl>,
+    clock: web::Data<dyn Clock>,
     request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers())
-        // bubble up error
-        .map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    let user_id = validate_credentials(credentials, &pool).await?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        // lazy version of `context`, closure is only called in case of an error
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    error.cause_chain = ?error,
-                    "Skipping a confirmed subscriber. \
-                    Their stored contact details are invalid",
-                );
-            }
-        }
-    }
-    Ok(HttpResponse::Ok().finish())
-}
-
-async fn validate_credentials(
-    credentials: Credentials,
-    pool: &PgPool,
-) -> Result<uuid::Uuid, PublishError> {
-    // Argon2 hashes based on OWASP guidance:
-    // https://cheatsheetseries.owasp.org/cheatsheets/Password_Storage_Cheat_Sheet.html
-    /*let hasher = Argon2::new(
-        Algorithm::Argon2id,
-        Version::V0x13,
-        Params::new(19000, 2, 1, None)
-            .context("Failed to build Argon2 parameters")
-            .map_err(PublishError::UnexpectedError)?,
-    );*/
-
-    let user: Option<_> = sqlx::query!(
-        r#"
-        SELECT user_id, password_hash
-        FROM users
-        WHERE username = $1
-        "#,
-        credentials.username,
+    let user_id =
+        authenticate_api_request(&request, &pool, &password_hashing, ApiScope::Publish).await?;
+
+    validate_template(
+        &body.title,
+        &body.content.html,
+        body.content.text.as_deref(),
+    )
+    .map_err(|e| PublishError::ValidationError(format!("Invalid template: {e}")))?;
+    let idempotency_key =
+        idempotency_key(request.headers()).map_err(PublishError::ValidationError)?;
+    let mut transaction = match &idempotency_key {
+        Some(key) => match try_processing(&pool, key, user_id, idempotency_ttl.0)
+            .await
+            .context("Failed to look up the idempotency key")?
+        {
+            NextAction::StartProcessing(transaction) => transaction,
+            // a retry of a request that was already processed
+            NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+        },
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire Postgres connection from the pool")?,
+    };
+    // an instant that already passed publishes the issue right away, a wall-clock time is
+    // released by the scheduler as it comes in the time zone of each subscriber
+    let publish_at = body.publish_at.filter(|publish_at| match publish_at {
+        PublishAt::Instant(instant) => instant.with_timezone(&Utc) > clock.now(),
+        PublishAt::SubscriberLocal(_) => true,
+    });
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &body.title,
+        body.content.text.as_deref(),
+        &body.content.html,
+        publish_at,
+        user_id,
     )
-    .fetch_optional(pool)
     .await
-    .context("Failed to perform a query to retrieve stored credentials")
-    .map_err(PublishError::UnexpectedError)?;
-
-    let (expected_password_hash, user_id) = match user {
-        Some(user) => (user.password_hash, user.user_id),
+    .context("Failed to store newsletter issue details")?;
+    // the issue is delivered by the background worker, see `issue_delivery_worker`
+    let response = match publish_at {
+        Some(publish_at) => HttpResponse::Accepted().json(ScheduledIssue {
+            newsletter_issue_id: issue_id,
+            title: body.title.clone(),
+            publish_at: publish_at.first_instant(),
+            publish_at_local: publish_at.local(),
+        }),
         None => {
-            return Err(PublishError::AuthError(anyhow::anyhow!("Unknown username")));
+            enqueue_delivery_tasks(&mut transaction, issue_id)
+                .await
+                .context("Failed to enqueue delivery tasks")?;
+            HttpResponse::Accepted().finish()
         }
     };
+    let response = match &idempotency_key {
+        Some(key) => save_response(transaction, key, user_id, response)
+            .await
+            .context("Failed to save the response of the idempotent request")?,
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a newsletter issue")?;
+            response
+        }
+    };
+    Ok(response)
+}
 
-    let expected_password_hash = PasswordHash::new(&expected_password_hash)
-        .context("Failed to parse hash in PHC string format")
-        .map_err(PublishError::UnexpectedError)?;
+// the optional `Idempotency-Key` header
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<IdempotencyKey>, String> {
+    let Some(header_value) = headers.get("Idempotency-Key") else {
+        return Ok(None);
+    };
+    let key = header_value
+        .to_str()
+        .map_err(|_| "The 'Idempotency-Key' header was not a valid UTF8 string".to_string())?;
+    IdempotencyKey::try_from(key.to_string())
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
 
-    Argon2::default()
-        .verify_password(
-            credentials.password.expose_secret().as_bytes(),
-            &expected_password_hash,
+// published now, unless it is scheduled for later
+#[tracing::instrument(skip_all)]
+pub async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: Option<&str>,
+    html_content: &str,
+    publish_at: Option<PublishAt>,
+    author_user_id: Uuid,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            published_at,
+            publish_at,
+            publish_at_local,
+            slug,
+            author_user_id
         )
-        .context("Invalid password")
-        .map_err(PublishError::AuthError)?;
+        VALUES ($1, $2, $3, $4, CASE WHEN $5::timestamptz IS NULL THEN now() END, $5, $6, $7, $8)
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        publish_at.map(|publish_at| publish_at.first_instant()),
+        publish_at.and_then(|publish_at| publish_at.local()),
+        issue_slug(title, newsletter_issue_id),
+        author_user_id,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(newsletter_issue_id)
+}
 
-    Ok(user_id)
+/*
+The title as readers see it, in lowercase ASCII words separated by dashes, followed by the
+beginning of the id to keep the slugs of issues with the same title apart.
+*/
+fn issue_slug(title: &str, newsletter_issue_id: Uuid) -> String {
+    let title = render_for_readers(title, false).unwrap_or_else(|_| title.to_owned());
+    let words: Vec<String> = title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_ascii_lowercase())
+        .collect();
+    let id = newsletter_issue_id.simple().to_string();
+    if words.is_empty() {
+        format!("issue-{}", &id[..8])
+    } else {
+        format!("{}-{}", words.join("-"), &id[..8])
+    }
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    // The header value, fi present, must be a valid UTF8 string
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header was missing")?
-        .to_str()
-        .context("The 'Authorization' header was not a valid UTF8 string")?;
-    let base64encoded_credentials = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'")?;
-    let decoded_bytes = base64::engine::general_purpose::STANDARD
-        .decode(base64encoded_credentials)
-        .context("Failed to base64-decode 'Basic' credentials")?;
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credential string is not valid UTF8")?;
-
-    // split on ':' into segments
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth"))?
-        .to_string();
-    let password = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth"))?
-        .to_string();
-
-    Ok(Credentials {
-        username,
-        password: Secret::new(password),
-    })
-}
-
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
+// one delivery task per confirmed subscriber
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
         r#"
-        SELECT email
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, email
         FROM subscriptions
         WHERE status = 'confirmed'
         "#,
+        newsletter_issue_id,
     )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
-    Ok(confirmed_subscribers)
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+// `publish_at_local` is the wall-clock time of the subscribers, `publish_at` the first instant
+#[derive(serde::Serialize)]
+pub struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    publish_at: DateTime<Utc>,
+    publish_at_local: Option<NaiveDateTime>,
+}
+
+// the issues waiting for their `publish_at`, the next one first
+#[tracing::instrument(
+    name = "List the scheduled newsletter issues",
+    skip(pool, password_hashing, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn list_scheduled_issues(
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_api_request(&request, &pool, &password_hashing, ApiScope::Schedule).await?;
+    let issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT newsletter_issue_id, title, publish_at AS "publish_at!", publish_at_local
+        FROM newsletter_issues
+        WHERE published_at IS NULL
+        ORDER BY publish_at
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve the scheduled issues")?;
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleData {
+    publish_at: PublishAt,
+}
+
+// a time that already passed releases the issue on the next run of the scheduler
+#[tracing::instrument(
+    name = "Reschedule a newsletter issue",
+    skip(body, pool, password_hashing, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn reschedule_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<RescheduleData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_api_request(&request, &pool, &password_hashing, ApiScope::Schedule).await?;
+    let issue = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        UPDATE newsletter_issues
+        SET publish_at = $2, publish_at_local = $3
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        RETURNING newsletter_issue_id, title, publish_at AS "publish_at!", publish_at_local
+        "#,
+        *newsletter_issue_id,
+        body.publish_at.first_instant(),
+        body.publish_at.local(),
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to reschedule the issue")?
+    .ok_or(PublishError::UnknownScheduledIssue)?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+// nothing was enqueued for a scheduled issue, it is deleted
+#[tracing::instrument(
+    name = "Cancel a scheduled newsletter issue",
+    skip(pool, password_hashing, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn cancel_scheduled_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_api_request(&request, &pool, &password_hashing, ApiScope::Schedule).await?;
+    let n_deleted_rows = sqlx::query!(
+        r#"
+        DELETE FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        "#,
+        *newsletter_issue_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to cancel the issue")?
+    .rows_affected();
+    if n_deleted_rows == 0 {
+        return Err(PublishError::UnknownScheduledIssue);
+    }
+    Ok(HttpResponse::NoContent().finish())
 }
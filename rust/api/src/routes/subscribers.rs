@@ -0,0 +1,595 @@
+use std::fmt::Formatter;
+
+use actix_web::body::BoxBody;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{
+    authenticate_api_request, unauthorized_response, ApiAuthError, ApiScope, PasswordHashing,
+};
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::routes::error_chain_fmt;
+use crate::subscriber_data::{erase_subscriber, record_data_request, DataRequestAction};
+use crate::subscriber_events::{
+    get_subscriber_events, record_subscriber_event, RecordedEvent, SubscriberEvent,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+// subscribers per query of the CSV export
+const EXPORT_BATCH_SIZE: i64 = 500;
+
+#[derive(thiserror::Error)]
+pub enum SubscribersError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    AuthError(#[from] ApiAuthError),
+    #[error("There is no subscriber with this id")]
+    UnknownSubscriber,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscribersError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscribersError {
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        match self {
+            SubscribersError::ValidationError(_) => {
+                HttpResponse::BadRequest().body(self.to_string())
+            }
+            SubscribersError::UnknownSubscriber => HttpResponse::NotFound().body(self.to_string()),
+            SubscribersError::AuthError(ApiAuthError::InvalidCredentials(_)) => {
+                unauthorized_response("subscribers")
+            }
+            SubscribersError::AuthError(ApiAuthError::MissingScope) => {
+                HttpResponse::new(StatusCode::FORBIDDEN)
+            }
+            SubscribersError::AuthError(ApiAuthError::UnexpectedError(_))
+            | SubscribersError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    unsubscribed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+    Suppressed,
+}
+
+impl SubscriberStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SubscriberStatus::PendingConfirmation => "pending_confirmation",
+            SubscriberStatus::Confirmed => "confirmed",
+            SubscriberStatus::Unsubscribed => "unsubscribed",
+            SubscriberStatus::Suppressed => "suppressed",
+        }
+    }
+}
+
+/*
+The filters of the listing and of the export, the dates in the RFC 3339 format. The search is
+an exact email address, or a part of a name or of an address.
+*/
+#[derive(serde::Deserialize)]
+pub struct SubscribersQuery {
+    status: Option<SubscriberStatus>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    search: Option<String>,
+    // the listing only
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+#[derive(Clone)]
+struct Filters {
+    status: Option<&'static str>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    email: Option<String>,
+    // an `ILIKE` pattern, on the name and on the address
+    pattern: Option<String>,
+}
+
+impl SubscribersQuery {
+    fn filters(&self) -> Result<Filters, String> {
+        let search = self
+            .search
+            .as_deref()
+            .map(str::trim)
+            .filter(|s| !s.is_empty());
+        let (email, pattern) = match search {
+            None => (None, None),
+            Some(search) => match SubscriberEmail::parse(search.to_owned()) {
+                Ok(email) => (Some(email.as_ref().to_owned()), None),
+                Err(_) => {
+                    let part = SubscriberName::parse(search.to_owned()).map_err(|_| {
+                        format!("The search is neither an email address nor a name: {search}")
+                    })?;
+                    (None, Some(format!("%{}%", escape_like(part.as_ref()))))
+                }
+            },
+        };
+        Ok(Filters {
+            status: self.status.map(|status| status.as_str()),
+            subscribed_after: self.subscribed_after,
+            subscribed_before: self.subscribed_before,
+            email,
+            pattern,
+        })
+    }
+}
+
+// the wildcards of `LIKE` are matched literally
+fn escape_like(s: &str) -> String {
+    s.replace('\\', r"\\")
+        .replace('%', r"\%")
+        .replace('_', r"\_")
+}
+
+/*
+Where a page starts: after the last subscriber of the previous page, in the order of the
+listing. Unlike an offset, a cursor doesn't skip nor repeat subscribers when some sign up or
+are deleted in the meantime.
+*/
+#[derive(Debug, PartialEq)]
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn after(subscriber: &Subscriber) -> Self {
+        Self {
+            subscribed_at: subscriber.subscribed_at,
+            id: subscriber.id,
+        }
+    }
+
+    // opaque to the clients, Postgres keeps microseconds
+    fn encode(&self) -> String {
+        let cursor = format!("{}/{}", self.subscribed_at.timestamp_micros(), self.id);
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(cursor)
+    }
+
+    fn decode(cursor: &str) -> Result<Self, String> {
+        let invalid = || "Invalid cursor".to_owned();
+        let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(cursor)
+            .map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (micros, id) = decoded.split_once('/').ok_or_else(invalid)?;
+        let micros: i64 = micros.parse().map_err(|_| invalid())?;
+        Ok(Self {
+            subscribed_at: DateTime::from_timestamp_micros(micros).ok_or_else(invalid)?,
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+// the most recent sign ups first
+async fn get_subscribers(
+    pool: &PgPool,
+    filters: &Filters,
+    cursor: Option<&Cursor>,
+    limit: i64,
+) -> Result<Vec<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at, unsubscribed_at
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR status = $1)
+            AND ($2::timestamptz IS NULL OR subscribed_at >= $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at < $3)
+            AND ($4::text IS NULL OR email = $4)
+            AND ($5::text IS NULL OR email ILIKE $5 OR name ILIKE $5)
+            AND ($6::timestamptz IS NULL OR (subscribed_at, id) < ($6, $7::uuid))
+        ORDER BY subscribed_at DESC, id DESC
+        LIMIT $8
+        "#,
+        filters.status,
+        filters.subscribed_after,
+        filters.subscribed_before,
+        filters.email,
+        filters.pattern,
+        cursor.map(|c| c.subscribed_at),
+        cursor.map(|c| c.id),
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(serde::Serialize)]
+struct SubscribersPage {
+    subscribers: Vec<Subscriber>,
+    // `null` on the last page
+    next_cursor: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "List the subscribers",
+    skip(query, pool, password_hashing, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn list_subscribers(
+    query: web::Query<SubscribersQuery>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribersError> {
+    authenticate_api_request(
+        &request,
+        &pool,
+        &password_hashing,
+        ApiScope::ManageSubscribers,
+    )
+    .await?;
+    let filters = query.filters().map_err(SubscribersError::ValidationError)?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(SubscribersError::ValidationError(format!(
+            "The limit must be between 1 and {MAX_PAGE_SIZE}"
+        )));
+    }
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .map_err(SubscribersError::ValidationError)?;
+    // one more subscriber tells whether there is a next page
+    let mut subscribers = get_subscribers(&pool, &filters, cursor.as_ref(), limit + 1)
+        .await
+        .context("Failed to retrieve the subscribers")?;
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|last| Cursor::after(last).encode())
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(SubscribersPage {
+        subscribers,
+        next_cursor,
+    }))
+}
+
+enum ExportState {
+    Header,
+    Batch(Option<Cursor>),
+    Done,
+}
+
+/*
+The subscribers matching the filters as CSV, streamed batch by batch: the export doesn't hold
+every subscriber in memory, nor a connection for the whole download.
+*/
+#[tracing::instrument(
+    name = "Export the subscribers",
+    skip(query, pool, password_hashing, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn export_subscribers(
+    query: web::Query<SubscribersQuery>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribersError> {
+    authenticate_api_request(
+        &request,
+        &pool,
+        &password_hashing,
+        ApiScope::ManageSubscribers,
+    )
+    .await?;
+    let filters = query.filters().map_err(SubscribersError::ValidationError)?;
+    let pool = pool.get_ref().clone();
+    let rows = futures_util::stream::unfold(ExportState::Header, move |state| {
+        let pool = pool.clone();
+        let filters = filters.clone();
+        async move {
+            match state {
+                ExportState::Header => Some((
+                    Ok(Bytes::from_static(
+                        b"id,email,name,status,subscribed_at,unsubscribed_at\r\n",
+                    )),
+                    ExportState::Batch(None),
+                )),
+                ExportState::Batch(cursor) => {
+                    match get_subscribers(&pool, &filters, cursor.as_ref(), EXPORT_BATCH_SIZE).await
+                    {
+                        Ok(batch) if batch.is_empty() => None,
+                        Ok(batch) => {
+                            let next = if (batch.len() as i64) < EXPORT_BATCH_SIZE {
+                                ExportState::Done
+                            } else {
+                                ExportState::Batch(batch.last().map(Cursor::after))
+                            };
+                            let csv: String = batch.iter().map(csv_row).collect();
+                            Some((Ok(Bytes::from(csv)), next))
+                        }
+                        // the response is cut short, the client can't take it for a full export
+                        Err(e) => {
+                            tracing::error!(error.cause_chain = ?e, "Failed to export the subscribers");
+                            Some((Err(e), ExportState::Done))
+                        }
+                    }
+                }
+                ExportState::Done => None,
+            }
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscribers.csv".into())],
+        })
+        .streaming(rows))
+}
+
+fn csv_row(subscriber: &Subscriber) -> String {
+    let unsubscribed_at = subscriber
+        .unsubscribed_at
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_default();
+    format!(
+        "{},{},{},{},{},{}\r\n",
+        subscriber.id,
+        csv_field(&subscriber.email),
+        csv_field(&subscriber.name),
+        subscriber.status,
+        subscriber.subscribed_at.to_rfc3339(),
+        unsubscribed_at,
+    )
+}
+
+/*
+Quoted as RFC 4180 wants it. The names are typed in by anyone signing up: a leading quote keeps
+the spreadsheets opening the export from running one as a formula.
+*/
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{value}")
+    } else {
+        value.to_owned()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberWithHistory {
+    #[serde(flatten)]
+    subscriber: Subscriber,
+    // the oldest event first
+    history: Vec<RecordedEvent>,
+}
+
+async fn get_subscriber_by_id(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at, unsubscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(
+    name = "Get a subscriber",
+    skip(pool, password_hashing, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribersError> {
+    authenticate_api_request(
+        &request,
+        &pool,
+        &password_hashing,
+        ApiScope::ManageSubscribers,
+    )
+    .await?;
+    let subscriber = get_subscriber_by_id(&pool, *subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber")?
+        .ok_or(SubscribersError::UnknownSubscriber)?;
+    let history = get_subscriber_events(&pool, *subscriber_id)
+        .await
+        .context("Failed to retrieve the history of the subscriber")?;
+    Ok(HttpResponse::Ok().json(SubscriberWithHistory {
+        subscriber,
+        history,
+    }))
+}
+
+/*
+Suppressing is for the addresses that must not be emailed anymore (a complaint, a bounce): the
+subscriber gets no issue, and signing up again with the address does nothing. The subscriber is
+kept, unlike a deletion that forgets the address.
+*/
+#[tracing::instrument(
+    name = "Suppress a subscriber",
+    skip(pool, password_hashing, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn suppress_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribersError> {
+    authenticate_api_request(
+        &request,
+        &pool,
+        &password_hashing,
+        ApiScope::ManageSubscribers,
+    )
+    .await?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    let suppressed = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'suppressed'
+        WHERE id = $1 AND status <> 'suppressed'
+        "#,
+        *subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to suppress the subscriber")?
+    .rows_affected()
+        > 0;
+    // suppressing twice is fine, and recorded once
+    if suppressed {
+        record_subscriber_event(
+            &mut transaction,
+            *subscriber_id,
+            SubscriberEvent::Suppressed,
+        )
+        .await
+        .context("Failed to record the suppression")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the suppression")?;
+    let subscriber = get_subscriber_by_id(&pool, *subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber")?
+        .ok_or(SubscribersError::UnknownSubscriber)?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+// the subscriber and every row about it, as for an erasure asked by the subscriber
+#[tracing::instrument(
+    name = "Delete a subscriber",
+    skip(pool, password_hashing, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribersError> {
+    let user_id = authenticate_api_request(
+        &request,
+        &pool,
+        &password_hashing,
+        ApiScope::ManageSubscribers,
+    )
+    .await?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    if !erase_subscriber(&mut transaction, *subscriber_id)
+        .await
+        .context("Failed to delete the subscriber")?
+    {
+        return Err(SubscribersError::UnknownSubscriber);
+    }
+    record_data_request(
+        &mut transaction,
+        Some(*subscriber_id),
+        Some(user_id),
+        DataRequestAction::ErasureCompleted,
+    )
+    .await
+    .context("Failed to record the deletion of the subscriber")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the deletion of the subscriber")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::{csv_field, escape_like, Cursor};
+
+    #[test]
+    fn a_cursor_survives_a_round_trip() {
+        let cursor = Cursor {
+            subscribed_at: Utc.with_ymd_and_hms(2023, 8, 20, 9, 35, 12).unwrap()
+                + chrono::Duration::microseconds(123_456),
+            id: Uuid::new_v4(),
+        };
+
+        assert_eq!(Cursor::decode(&cursor.encode()), Ok(cursor));
+    }
+
+    #[test]
+    fn an_invalid_cursor_is_rejected() {
+        assert!(Cursor::decode("not a cursor").is_err());
+        assert!(Cursor::decode("MTIz").is_err());
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("ursula"), "ursula");
+        assert_eq!(csv_field("le guin, ursula"), r#""le guin, ursula""#);
+        assert_eq!(csv_field(r#"ursula "le" guin"#), r#""ursula ""le"" guin""#);
+    }
+
+    #[test]
+    fn csv_fields_starting_like_a_formula_are_neutralized() {
+        assert_eq!(csv_field("=HYPERLINK(1)"), "'=HYPERLINK(1)");
+        assert_eq!(csv_field("@sum,1"), r#""'@sum,1""#);
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like(r"100%_\"), r"100\%\_\\");
+    }
+}
@@ -0,0 +1,77 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::StreamExt;
+use sqlx::PgPool;
+
+use crate::authentication::{authenticate_api_request, ApiScope, PasswordHashing};
+use crate::configuration::SubscriptionSettings;
+use crate::routes::SubscribersError;
+use crate::subscriber_import::{ImportError, ImportMode, ImportReport, SubscriberImport};
+
+#[derive(serde::Deserialize)]
+pub struct ImportParameters {
+    // confirmed subscribers get no confirmation email
+    #[serde(default)]
+    confirmed: bool,
+}
+
+/*
+Imports the CSV file of the body, with a header naming its `email` and `name` columns. The file
+is read as it is uploaded, the answer reports what became of every row. A file that is not valid
+CSV is a 400 with the report of the rows before the error, which are imported.
+*/
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(parameters, body, pool, settings, password_hashing, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn import_subscribers(
+    parameters: web::Query<ImportParameters>,
+    body: web::Payload,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionSettings>,
+    password_hashing: web::Data<PasswordHashing>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribersError> {
+    authenticate_api_request(
+        &request,
+        &pool,
+        &password_hashing,
+        ApiScope::ManageSubscribers,
+    )
+    .await?;
+    let mode = if parameters.confirmed {
+        ImportMode::PreConfirmed
+    } else {
+        ImportMode::SendConfirmations
+    };
+    let import = SubscriberImport::new(&pool, &settings, mode);
+    let report = match import_body(import, body).await {
+        Ok(report) => report,
+        Err(ImportError::InvalidCsv(report)) => return Ok(HttpResponse::BadRequest().json(report)),
+        Err(ImportError::UnexpectedError(e)) => return Err(SubscribersError::UnexpectedError(e)),
+    };
+    tracing::info!(
+        accepted = report.accepted,
+        rejected = report.rejected,
+        duplicates = report.duplicates,
+        "Imported subscribers"
+    );
+    Ok(HttpResponse::Ok().json(report))
+}
+
+async fn import_body(
+    mut import: SubscriberImport<'_>,
+    mut body: web::Payload,
+) -> Result<ImportReport, ImportError> {
+    while let Some(chunk) = body.next().await {
+        match chunk {
+            Ok(chunk) => import.push(&chunk).await?,
+            Err(e) => {
+                return Err(import
+                    .stop(format!("Failed to read the uploaded file: {e}"))
+                    .await)
+            }
+        }
+    }
+    import.finish().await
+}
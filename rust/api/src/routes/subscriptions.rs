@@ -7,27 +7,53 @@ use anyhow::Context;
 use chrono::Utc;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
-use sqlx::{PgPool, Postgres, Transaction};
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::configuration::SubscriptionSettings;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberTimezone};
 use crate::email_client::EmailClient;
+use crate::email_templates::{
+    get_template, recipient_context, render_template, template_error_message, EmailTemplate,
+    RenderedEmail, CONFIRMATION_TEMPLATE, LAYOUT_TEMPLATE,
+};
+use crate::metrics::{FunnelStep, Metrics};
 use crate::routes;
 use crate::startup::ApplicationBaseUrl;
+use crate::subscriber_events::{record_subscriber_event, SubscriberEvent};
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     name: String,
     email: String,
+    // honeypot: hidden from people by the form, only filled in by bots
+    #[serde(default)]
+    website: String,
+    // IANA time zone, like `Europe/Paris`: the scheduled issues arrive at their local time
+    timezone: Option<String>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
     type Error = String;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { email, name })
+        let mut new_subscriber = NewSubscriber::parse(value.name, value.email)?;
+        // a form sends an empty field when the time zone is unknown
+        new_subscriber.timezone = value
+            .timezone
+            .filter(|timezone| !timezone.is_empty())
+            .map(SubscriberTimezone::parse)
+            .transpose()?;
+        Ok(new_subscriber)
+    }
+}
+
+// the address as stored: without its plus tag, when they are ignored
+pub fn stored_email(email: SubscriberEmail, settings: &SubscriptionSettings) -> SubscriberEmail {
+    if settings.strip_plus_tags {
+        email.without_plus_tag()
+    } else {
+        email
     }
 }
 
@@ -76,7 +102,7 @@ impl ResponseError for SubscribeError {
 // prefix with % = use the Display implementation for logging purposes
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, email_client, base_url, settings, metrics),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -87,10 +113,19 @@ pub async fn subscribe(
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+    metrics: web::Data<Metrics>,
 ) -> Result<HttpResponse, SubscribeError> {
+    // the bot gets the answer of a successful sign up, and nothing happens
+    if !form.website.is_empty() {
+        tracing::warn!("The honeypot field is filled in, ignoring the sign up");
+        return Ok(HttpResponse::Ok().finish());
+    }
     // `web::Form` = wrapper around `FormData`
     // `form.0` -> access to underlying `FormData`
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let mut new_subscriber: NewSubscriber =
+        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    new_subscriber.email = stored_email(new_subscriber.email, &settings);
 
     let mut transaction = pool
         .begin()
@@ -100,29 +135,159 @@ pub async fn subscribe(
         // anyhow implements the Context trait for Result
         .context("Failed to acquire Postgres connection from the pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    // signing up again is fine: the answer is the same whatever the state of the address
+    let mut subscribed = true;
+    let (subscriber_id, name) = match insert_subscriber(&mut transaction, &new_subscriber)
         .await
-        .context("Failed to insert new subscriber in database")?;
+        .context("Failed to insert new subscriber in database")?
+    {
+        Some(subscriber_id) => (subscriber_id, new_subscriber.name.as_ref().to_owned()),
+        None => {
+            let subscriber = get_subscriber_for_update(&mut transaction, &new_subscriber.email)
+                .await
+                .context("Failed to retrieve the existing subscriber")?
+                .context("The existing subscriber was deleted concurrently")?;
+            match subscriber.status.as_str() {
+                // a suppressed address doesn't get any email, not even a confirmation
+                "confirmed" | "suppressed" => return Ok(HttpResponse::Ok().finish()),
+                "unsubscribed" => resubscribe(&mut transaction, subscriber.id)
+                    .await
+                    .context("Failed to subscribe again an unsubscribed subscriber")?,
+                // pending: a new confirmation email
+                _ => subscribed = false,
+            }
+            (subscriber.id, subscriber.name)
+        }
+    };
+    if subscribed {
+        record_subscriber_event(&mut transaction, subscriber_id, SubscriberEvent::Subscribed)
+            .await
+            .context("Failed to record the subscription")?;
+    }
 
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    send_new_confirmation(
+        transaction,
+        subscriber_id,
+        &name,
+        &new_subscriber.email,
+        &email_client,
+        &base_url.0,
+        &settings,
+    )
+    .await?;
+    if subscribed {
+        metrics.record_funnel_step(FunnelStep::Subscribed);
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    email: String,
+}
+
+/*
+Send a new confirmation email to a pending subscriber. The answer doesn't tell whether the
+address is known, and at most one email goes out per `resend_interval`.
+*/
+#[tracing::instrument(
+    name = "Resend the confirmation email",
+    skip(form, pool, email_client, base_url, settings),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, SubscribeError> {
+    let email = SubscriberEmail::parse(form.0.email).map_err(SubscribeError::ValidationError)?;
+    let email = stored_email(email, &settings);
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    let subscriber = get_subscriber_for_update(&mut transaction, &email)
         .await
-        .context("Failed to store confirmation token for new subscriber")?;
+        .context("Failed to retrieve the subscriber")?;
+    if let Some(subscriber) = subscriber {
+        if subscriber.status == "pending_confirmation" {
+            send_new_confirmation(
+                transaction,
+                subscriber.id,
+                &subscriber.name,
+                &email,
+                &email_client,
+                &base_url.0,
+                &settings,
+            )
+            .await?;
+        }
+    }
+    Ok(HttpResponse::Ok().finish())
+}
 
-    transaction
-        .commit()
+// store a new token and send it, unless a confirmation email was sent recently: the changes made in
+// the transaction are committed either way
+async fn send_new_confirmation(
+    mut transaction: Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    name: &str,
+    email: &SubscriberEmail,
+    email_client: &EmailClient,
+    base_url: &str,
+    settings: &SubscriptionSettings,
+) -> Result<(), SubscribeError> {
+    if confirmation_recently_sent(&mut transaction, subscriber_id, settings.resend_interval())
         .await
-        .context("Failed to commit SQL transaction to store new subscriber")?;
+        .context("Failed to retrieve the last confirmation token")?
+    {
+        tracing::info!("A confirmation email was sent recently, not sending another one");
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to store new subscriber")?;
+        return Ok(());
+    }
 
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
+    let subscription_token = generate_subscription_token();
+    store_token(
+        &mut transaction,
+        subscriber_id,
         &subscription_token,
+        settings.confirmation_token_ttl(),
     )
     .await
-    .context("Failed to send confirmation email")?;
-    Ok(HttpResponse::Ok().finish())
+    .context("Failed to store confirmation token for new subscriber")?;
+    record_subscriber_event(
+        &mut transaction,
+        subscriber_id,
+        SubscriberEvent::ConfirmationSent,
+    )
+    .await
+    .context("Failed to record the confirmation email")?;
+
+    let confirmation_email =
+        confirmation_email(&mut transaction, name, email, base_url, &subscription_token)
+            .await
+            .context("Failed to render the confirmation email")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store new subscriber")?;
+
+    email_client
+        .send_email(
+            email,
+            &confirmation_email.subject,
+            &confirmation_email.html,
+            &confirmation_email.text,
+        )
+        .await
+        .context("Failed to send confirmation email")?;
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -133,12 +298,14 @@ pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
+    ttl: std::time::Duration,
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)"#,
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id, created_at, expires_at)
+        VALUES ($1, $2, now(), now() + make_interval(secs => $3))"#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        ttl.as_secs_f64()
     )
     .execute(transaction)
     .await
@@ -170,31 +337,58 @@ impl std::error::Error for StoreTokenError {
     }
 }
 
+// rendered from the latest `confirmation` template
 #[tracing::instrument(
-    name = "Send confirmation email to new subscriber",
-    skip(email_client, new_subscriber, base_url, subscription_token)
+    name = "Render confirmation email",
+    skip(connection, name, email, base_url, subscription_token)
 )]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+pub async fn confirmation_email(
+    connection: &mut PgConnection,
+    name: &str,
+    email: &SubscriberEmail,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
-    let confirmation_link =
-        format!("{base_url}/subscriptions/confirm?subscription_token={subscription_token}");
-    let html_body = format!(
-        "Welcome to our newsletter!<br />\
-                Click <a href=\"{confirmation_link}\">here</a> to confirm your subscription.",
-    );
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {confirmation_link} to confirm your subscription.",
-    );
-    email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+) -> Result<RenderedEmail, anyhow::Error> {
+    ConfirmationTemplates::get(connection)
+        .await?
+        .render(name, email, base_url, subscription_token)
+}
+
+// the latest `layout` and `confirmation` templates, to render many confirmation emails
+pub struct ConfirmationTemplates {
+    layout: EmailTemplate,
+    template: EmailTemplate,
+}
+
+impl ConfirmationTemplates {
+    pub async fn get(connection: &mut PgConnection) -> Result<Self, anyhow::Error> {
+        let layout = get_template(connection, LAYOUT_TEMPLATE, None)
+            .await?
+            .context("The email layout template is missing")?;
+        let template = get_template(connection, CONFIRMATION_TEMPLATE, None)
+            .await?
+            .context("The confirmation email template is missing")?;
+        Ok(Self { layout, template })
+    }
+
+    pub fn render(
+        &self,
+        name: &str,
+        email: &SubscriberEmail,
+        base_url: &str,
+        subscription_token: &str,
+    ) -> Result<RenderedEmail, anyhow::Error> {
+        let mut context = recipient_context(name, email.as_ref());
+        context.insert(
+            "confirmation_link",
+            &format!("{base_url}/subscriptions/confirm?subscription_token={subscription_token}"),
+        );
+        render_template(&self.layout, &self.template, &context)
+            .map_err(|e| anyhow::anyhow!(template_error_message(&e)))
+    }
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -202,6 +396,7 @@ fn generate_subscription_token() -> String {
         .collect()
 }
 
+// `None` when the email address is already known
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -209,19 +404,86 @@ fn generate_subscription_token() -> String {
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+    let inserted = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, timezone)
+        VALUES ($1, $2, $3, $4, 'pending_confirmation', $5)
+        ON CONFLICT (email) DO NOTHING
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        new_subscriber
+            .timezone
+            .as_ref()
+            .map(|timezone| timezone.as_ref()),
+    )
+    .execute(transaction)
+    .await? // error is propagated via `?`
+    .rows_affected()
+        > 0;
+    Ok(inserted.then_some(subscriber_id))
+}
+
+pub struct ExistingSubscriber {
+    pub id: Uuid,
+    pub name: String,
+    pub status: String,
+}
+
+// the row stays locked until the end of the transaction
+#[tracing::instrument(name = "Get subscriber by email", skip(transaction))]
+pub async fn get_subscriber_for_update(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"SELECT id, name, status FROM subscriptions WHERE email = $1 FOR UPDATE"#,
+        email.as_ref(),
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
+#[tracing::instrument(name = "Subscribe again an unsubscribed subscriber", skip(transaction))]
+async fn resubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'pending_confirmation', subscribed_at = now(), unsubscribed_at = NULL
+        WHERE id = $1
+        "#,
+        subscriber_id,
     )
     .execute(transaction)
-    .await?; // error is propagated via `?`
-    Ok(subscriber_id)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Check the last confirmation token", skip(transaction))]
+async fn confirmation_recently_sent(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    resend_interval: std::time::Duration,
+) -> Result<bool, sqlx::Error> {
+    let recent = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM subscription_tokens
+            WHERE subscriber_id = $1 AND created_at > now() - make_interval(secs => $2)
+        ) AS "recent!"
+        "#,
+        subscriber_id,
+        resend_interval.as_secs_f64(),
+    )
+    .fetch_one(transaction)
+    .await?;
+    Ok(recent.recent)
 }
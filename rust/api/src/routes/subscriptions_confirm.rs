@@ -1,3 +1,4 @@
+use crate::metrics::{FunnelStep, Metrics};
 use crate::routes::error_chain_fmt;
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse, ResponseError};
@@ -18,6 +19,8 @@ pub enum ConfirmationError {
     UnexpectedError(#[from] anyhow::Error),
     #[error("There is no subscriber associated with the provided token")]
     UnknownToken,
+    #[error("The confirmation link has expired, please ask for a new one")]
+    ExpiredToken,
 }
 
 impl std::fmt::Debug for ConfirmationError {
@@ -30,48 +33,68 @@ impl ResponseError for ConfirmationError {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
             Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-#[tracing::instrument(name = "Confirm pending subscriber", skip(parameters, pool))]
+#[tracing::instrument(name = "Confirm pending subscriber", skip(parameters, pool, metrics))]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
+    metrics: web::Data<Metrics>,
 ) -> Result<HttpResponse, ConfirmationError> {
-    let subscriber_id = get_subscriber_id_from_token(&pool, &parameters.subscription_token)
-        .await
-        .context("Failed to retrieve the subscriber id associated with the provided token")?
-        .ok_or(ConfirmationError::UnknownToken)?;
-    confirm_subscriber(&pool, subscriber_id)
+    let (subscriber_id, expired) =
+        get_subscriber_id_from_token(&pool, &parameters.subscription_token)
+            .await
+            .context("Failed to retrieve the subscriber id associated with the provided token")?
+            .ok_or(ConfirmationError::UnknownToken)?;
+    if expired {
+        return Err(ConfirmationError::ExpiredToken);
+    }
+    let confirmed = confirm_subscriber(&pool, subscriber_id)
         .await
         .context("Failed to update subscriber status to `confirmed`")?;
+    if confirmed {
+        metrics.record_funnel_step(FunnelStep::Confirmed);
+    }
     Ok(HttpResponse::Ok().finish())
 }
 
+// false when the subscriber was not pending anymore (following the link twice)
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        // an unsubscribed subscriber is not subscribed again by an old confirmation link
+        r#"
+        WITH confirmed AS (
+            UPDATE subscriptions SET status = 'confirmed'
+            WHERE id = $1 AND status = 'pending_confirmation'
+            RETURNING id
+        )
+        INSERT INTO subscription_events (subscriber_id, event)
+        SELECT id, 'confirmed' FROM confirmed
+        "#,
         subscriber_id,
     )
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
+// the subscriber id, and whether the token expired
 #[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
 pub async fn get_subscriber_id_from_token(
     pool: &PgPool,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
+) -> Result<Option<(Uuid, bool)>, sqlx::Error> {
     let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens \
-        WHERE subscription_token = $1",
+        r#"SELECT subscriber_id, expires_at <= now() AS "expired!" FROM subscription_tokens
+        WHERE subscription_token = $1"#,
         subscription_token
     )
     .fetch_optional(pool)
     .await?;
-    Ok(result.map(|res| res.subscriber_id))
+    Ok(result.map(|res| (res.subscriber_id, res.expired)))
 }
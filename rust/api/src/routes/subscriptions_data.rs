@@ -0,0 +1,334 @@
+use std::fmt::Formatter;
+
+use actix_web::http::header::{
+    CacheControl, CacheDirective, ContentDisposition, ContentType, DispositionParam,
+    DispositionType,
+};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::clock::Clock;
+use crate::configuration::SubscriptionSettings;
+use crate::domain::{DataRequest, DataRequestToken, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::email_templates::{
+    get_template, recipient_context, render_template, template_error_message, RenderedEmail,
+    DATA_ERASURE_TEMPLATE, DATA_EXPORT_TEMPLATE, LAYOUT_TEMPLATE,
+};
+use crate::routes::{error_chain_fmt, stored_email};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::subscriber_data::{
+    erase_subscriber, get_subscriber_data, record_data_request, DataRequestAction,
+};
+
+#[derive(thiserror::Error)]
+pub enum DataRequestError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error("There is no data about you anymore")]
+    UnknownSubscriber,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DataRequestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for DataRequestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::ValidationError(_) | Self::InvalidToken(_) => StatusCode::BAD_REQUEST,
+            Self::UnknownSubscriber => StatusCode::NOT_FOUND,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestFormData {
+    email: String,
+    // `export` or `erasure`
+    request: DataRequest,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataLinkParameters {
+    token: String,
+}
+
+/// Link to export or erase the data of a subscriber, working until `expires_at`.
+pub fn data_request_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    request: DataRequest,
+    expires_at: DateTime<Utc>,
+    secret: &Secret<String>,
+) -> String {
+    let token = DataRequestToken::sign(subscriber_id, request, expires_at, secret);
+    let path = match request {
+        DataRequest::Export => "export",
+        DataRequest::Erasure => "erase",
+    };
+    format!(
+        "{base_url}/subscriptions/data/{path}?token={}",
+        token.as_ref()
+    )
+}
+
+/*
+A subscriber asks for their data, or for its erasure: the link goes to their address, and the
+answer is the same whether the address is known or not. Either way the request is audited.
+*/
+#[tracing::instrument(
+    name = "Request the data of a subscriber",
+    skip(form, pool, email_client, base_url, hmac_secret, settings, clock),
+    fields(subscriber_email = %form.email, request = form.request.as_str())
+)]
+pub async fn request_subscriber_data(
+    form: web::Form<DataRequestFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    settings: web::Data<SubscriptionSettings>,
+    clock: web::Data<dyn Clock>,
+) -> Result<HttpResponse, DataRequestError> {
+    let request = form.request;
+    let email = SubscriberEmail::parse(form.0.email).map_err(DataRequestError::ValidationError)?;
+    let email = stored_email(email, &settings);
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    let subscriber = sqlx::query!(
+        "SELECT id, name FROM subscriptions WHERE email = $1",
+        email.as_ref(),
+    )
+    .fetch_optional(&mut connection)
+    .await
+    .context("Failed to retrieve the subscriber")?;
+    let action = match request {
+        DataRequest::Export => DataRequestAction::ExportRequested,
+        DataRequest::Erasure => DataRequestAction::ErasureRequested,
+    };
+    record_data_request(
+        &mut connection,
+        subscriber.as_ref().map(|s| s.id),
+        None,
+        action,
+    )
+    .await
+    .context("Failed to record the data request")?;
+
+    let Some(subscriber) = subscriber else {
+        tracing::info!("Nobody subscribed with this address, no email is sent");
+        return Ok(HttpResponse::Ok().finish());
+    };
+    let ttl = chrono::Duration::from_std(settings.data_request_link_ttl())
+        .context("The data request link TTL is out of range")?;
+    let link = data_request_link(
+        &base_url.0,
+        subscriber.id,
+        request,
+        clock.now() + ttl,
+        &hmac_secret.0,
+    );
+    let data_request_email =
+        data_request_email(&mut connection, &subscriber.name, &email, request, &link)
+            .await
+            .context("Failed to render the data request email")?;
+    email_client
+        .send_email(
+            &email,
+            &data_request_email.subject,
+            &data_request_email.html,
+            &data_request_email.text,
+        )
+        .await
+        .context("Failed to send the data request email")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// rendered from the latest `data_export` or `data_erasure` template
+async fn data_request_email(
+    connection: &mut PgConnection,
+    name: &str,
+    email: &SubscriberEmail,
+    request: DataRequest,
+    link: &str,
+) -> Result<RenderedEmail, anyhow::Error> {
+    let (template_name, link_variable) = match request {
+        DataRequest::Export => (DATA_EXPORT_TEMPLATE, "export_link"),
+        DataRequest::Erasure => (DATA_ERASURE_TEMPLATE, "erasure_link"),
+    };
+    let layout = get_template(connection, LAYOUT_TEMPLATE, None)
+        .await?
+        .context("The email layout template is missing")?;
+    let template = get_template(connection, template_name, None)
+        .await?
+        .with_context(|| format!("The {template_name} email template is missing"))?;
+    let mut context = recipient_context(name, email.as_ref());
+    context.insert(link_variable, link);
+    render_template(&layout, &template, &context)
+        .map_err(|e| anyhow::anyhow!(template_error_message(&e)))
+}
+
+/*
+Everything stored about the subscriber of the link, as a JSON download. Downloading changes
+nothing but the audit, the link works until it expires.
+*/
+#[tracing::instrument(
+    name = "Export the data of a subscriber",
+    skip(parameters, pool, hmac_secret, clock)
+)]
+pub async fn export_subscriber_data(
+    parameters: web::Query<DataLinkParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    clock: web::Data<dyn Clock>,
+) -> Result<HttpResponse, DataRequestError> {
+    let subscriber_id = DataRequestToken::verify(
+        &parameters.token,
+        DataRequest::Export,
+        clock.now(),
+        &hmac_secret.0,
+    )
+    .map_err(DataRequestError::InvalidToken)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+        .execute(&mut transaction)
+        .await
+        .context("Failed to set the isolation level of the export")?;
+    let data = get_subscriber_data(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to collect the data of the subscriber")?
+        .ok_or(DataRequestError::UnknownSubscriber)?;
+    record_data_request(
+        &mut transaction,
+        Some(subscriber_id),
+        None,
+        DataRequestAction::ExportDownloaded,
+    )
+    .await
+    .context("Failed to record the export")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the export")?;
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscriber-data.json".into())],
+        })
+        .json(data))
+}
+
+/*
+Confirmation page: nothing is erased on GET, link checkers of mailboxes follow the links.
+The form posts to the erasure endpoint.
+*/
+#[tracing::instrument(
+    name = "Erasure confirmation page",
+    skip(parameters, hmac_secret, clock)
+)]
+pub async fn erase_subscriber_data_form(
+    parameters: web::Query<DataLinkParameters>,
+    hmac_secret: web::Data<HmacSecret>,
+    clock: web::Data<dyn Clock>,
+) -> Result<HttpResponse, DataRequestError> {
+    DataRequestToken::verify(
+        &parameters.token,
+        DataRequest::Erasure,
+        clock.now(),
+        &hmac_secret.0,
+    )
+    .map_err(DataRequestError::InvalidToken)?;
+    let token = htmlescape::encode_attribute(&parameters.token);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Erase your data</title>
+</head>
+<body>
+    <p>Do you want us to erase everything we store about you? You won't receive our newsletter anymore.</p>
+    <form action="/subscriptions/data/erase?token={token}" method="post">
+        <button type="submit">Erase my data</button>
+    </form>
+</body>
+</html>"#
+        )))
+}
+
+/*
+Erases the subscriber of the link, in one transaction with its audit. Erasing twice is fine:
+the link was signed for a subscriber, if it is gone there is nothing left about them.
+*/
+#[tracing::instrument(
+    name = "Erase the data of a subscriber",
+    skip(parameters, pool, hmac_secret, clock)
+)]
+pub async fn erase_subscriber_data(
+    parameters: web::Query<DataLinkParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    clock: web::Data<dyn Clock>,
+) -> Result<HttpResponse, DataRequestError> {
+    let subscriber_id = DataRequestToken::verify(
+        &parameters.token,
+        DataRequest::Erasure,
+        clock.now(),
+        &hmac_secret.0,
+    )
+    .map_err(DataRequestError::InvalidToken)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    if erase_subscriber(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to erase the subscriber")?
+    {
+        record_data_request(
+            &mut transaction,
+            Some(subscriber_id),
+            None,
+            DataRequestAction::ErasureCompleted,
+        )
+        .await
+        .context("Failed to record the erasure")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the erasure")?;
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Data erased</title>
+</head>
+<body>
+    <p>Your data has been erased, you won't receive our newsletter anymore.</p>
+</body>
+</html>"#,
+    ))
+}
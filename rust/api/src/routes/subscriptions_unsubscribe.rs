@@ -0,0 +1,160 @@
+use crate::domain::UnsubscribeToken;
+use crate::metrics::{FunnelStep, Metrics};
+use crate::routes::error_chain_fmt;
+use crate::startup::HmacSecret;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::PgPool;
+use std::fmt::Formatter;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error("There is no subscriber associated with the provided token")]
+    UnknownSubscriber,
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidToken(_) => StatusCode::BAD_REQUEST,
+            Self::UnknownSubscriber => StatusCode::NOT_FOUND,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Link to unsubscribe, for the `List-Unsubscribe` header and the newsletter footers.
+pub fn unsubscribe_link(base_url: &str, subscriber_id: Uuid, secret: &Secret<String>) -> String {
+    let token = UnsubscribeToken::sign(subscriber_id, secret);
+    format!(
+        "{base_url}/subscriptions/unsubscribe?token={}",
+        token.as_ref()
+    )
+}
+
+/*
+Confirmation page: nothing changes on GET, link checkers of mailboxes follow the links.
+The form posts to the one-click endpoint.
+*/
+#[tracing::instrument(name = "Unsubscribe confirmation page", skip(parameters, hmac_secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    UnsubscribeToken::verify(&parameters.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    let token = htmlescape::encode_attribute(&parameters.token);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?token={token}" method="post">
+        <input type="hidden" name="List-Unsubscribe" value="One-Click">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#
+        )))
+}
+
+/*
+One-click unsubscribe (RFC 8058): the mailbox provider posts `List-Unsubscribe=One-Click` to the
+URL of the `List-Unsubscribe` header, without cookies nor redirects. The token is all that
+matters, the body (urlencoded or multipart) is not read.
+*/
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, pool, hmac_secret, metrics)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let subscriber_id = UnsubscribeToken::verify(&parameters.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    let previous_status = mark_subscriber_as_unsubscribed(&pool, subscriber_id)
+        .await
+        .context("Failed to update subscriber status to `unsubscribed`")?
+        .ok_or(UnsubscribeError::UnknownSubscriber)?;
+    if !matches!(previous_status.as_str(), "unsubscribed" | "suppressed") {
+        metrics.record_funnel_step(FunnelStep::Unsubscribed);
+    }
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed, you won't receive our newsletter anymore.</p>
+</body>
+</html>"#,
+    ))
+}
+
+/*
+Unsubscribing twice is fine, and a suppressed subscriber stays suppressed. Returns the status
+the subscriber had before, `None` for an unknown subscriber (the CTE reads the row as it was
+before the update).
+*/
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+pub async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let previous = sqlx::query!(
+        r#"
+        WITH previous AS (SELECT status FROM subscriptions WHERE id = $1),
+        updated AS (
+            UPDATE subscriptions
+            SET status = CASE previous.status
+                    WHEN 'suppressed' THEN 'suppressed'
+                    ELSE 'unsubscribed'
+                END,
+                unsubscribed_at = COALESCE(unsubscribed_at, now())
+            FROM previous
+            WHERE id = $1
+            RETURNING previous.status
+        ),
+        event AS (
+            INSERT INTO subscription_events (subscriber_id, event)
+            SELECT $1, 'unsubscribed' FROM updated
+            WHERE updated.status NOT IN ('unsubscribed', 'suppressed')
+        )
+        SELECT status AS "status!" FROM updated
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(previous.map(|previous| previous.status))
+}
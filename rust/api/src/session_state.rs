@@ -0,0 +1,118 @@
+use std::future::{ready, Ready};
+
+use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use uuid::Uuid;
+
+/*
+Typed interface over the session of the admin area: the logged in user and the flash messages
+shown once, on the next page.
+*/
+pub struct TypedSession(Session);
+
+impl TypedSession {
+    const USER_ID_KEY: &'static str = "user_id";
+    const FLASH_MESSAGES_KEY: &'static str = "flash_messages";
+
+    // new session key after a privilege change, against session fixation
+    pub fn renew(&self) {
+        self.0.renew();
+    }
+
+    pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::USER_ID_KEY, user_id)
+    }
+
+    pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::USER_ID_KEY)
+    }
+
+    // forget the user, the session only lives on for the flash messages
+    pub fn log_out(&self) {
+        self.0.clear();
+        self.0.renew();
+    }
+
+    pub fn add_flash_message(&self, message: FlashMessage) -> Result<(), SessionInsertError> {
+        let mut messages = self
+            .0
+            .get::<Vec<FlashMessage>>(Self::FLASH_MESSAGES_KEY)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        messages.push(message);
+        self.0.insert(Self::FLASH_MESSAGES_KEY, messages)
+    }
+
+    // the messages are removed from the session once read
+    pub fn take_flash_messages(&self) -> Vec<FlashMessage> {
+        if !self.0.entries().contains_key(Self::FLASH_MESSAGES_KEY) {
+            // leave the session unchanged
+            return vec![];
+        }
+        self.0
+            .remove_as(Self::FLASH_MESSAGES_KEY)
+            .and_then(Result::ok)
+            .unwrap_or_default()
+    }
+
+    // the flash messages, ready to be put at the top of a page
+    pub fn take_flash_messages_html(&self) -> String {
+        self.take_flash_messages()
+            .iter()
+            .map(FlashMessage::to_html)
+            .collect()
+    }
+}
+
+impl FromRequest for TypedSession {
+    // same error as the `FromRequest` implementation of `Session`
+    type Error = <Session as FromRequest>::Error;
+    type Future = Ready<Result<TypedSession, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(TypedSession(req.get_session())))
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashLevel {
+    Info,
+    Error,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct FlashMessage {
+    pub level: FlashLevel,
+    pub content: String,
+}
+
+impl FlashMessage {
+    pub fn info(content: impl Into<String>) -> Self {
+        Self {
+            level: FlashLevel::Info,
+            content: content.into(),
+        }
+    }
+
+    pub fn error(content: impl Into<String>) -> Self {
+        Self {
+            level: FlashLevel::Error,
+            content: content.into(),
+        }
+    }
+
+    // `<p>` element, the content is escaped
+    pub fn to_html(&self) -> String {
+        let class = match self.level {
+            FlashLevel::Info => "info",
+            FlashLevel::Error => "error",
+        };
+        format!(
+            r#"<p class="{class}"><i>{}</i></p>"#,
+            htmlescape::encode_minimal(&self.content)
+        )
+    }
+}
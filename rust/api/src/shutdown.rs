@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+/*
+Tells the background tasks that the application is stopping: they finish the work at hand and
+return, instead of being aborted in the middle of a delivery.
+*/
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+// the other end of `Shutdown`, kept by the application
+pub struct ShutdownTrigger(watch::Sender<bool>);
+
+pub fn shutdown_channel() -> (ShutdownTrigger, Shutdown) {
+    let (sender, receiver) = watch::channel(false);
+    (ShutdownTrigger(sender), Shutdown(receiver))
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+}
+
+impl Shutdown {
+    // a dropped trigger means the application is gone
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow() || self.0.has_changed().is_err()
+    }
+
+    // sleep between two runs, cut short by the shutdown
+    pub async fn sleep(&mut self, duration: Duration) {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => {}
+            _ = self.0.wait_for(|requested| *requested) => {}
+        }
+    }
+}
+
+// SIGTERM (sent by the orchestrators) or Ctrl-C
+pub async fn termination_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
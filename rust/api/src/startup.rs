@@ -1,51 +1,138 @@
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::authentication::{reject_anonymous_users, PasswordHashing};
+use crate::clock::{Clock, SystemClock};
+use crate::configuration::{
+    DatabaseSettings, EmailClientSettings, RateLimitSettings, Settings, SubscriptionSettings,
+};
 use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::run_worker_until_stopped;
+use crate::issue_scheduler::run_scheduler_until_stopped;
+use crate::metrics::{record_http_metrics, Metrics};
+use crate::rate_limit::limit_sign_up_requests;
 use crate::routes::confirm;
-use crate::routes::{health_check, publish_newsletter, subscribe};
+use crate::routes::{
+    admin_dashboard, admin_home, api_tokens, cancel_scheduled_issue, change_password,
+    change_password_form, create_api_token_form, delete_subscriber, edit_email_template_form,
+    email_templates, erase_subscriber_data, erase_subscriber_data_form, export_metrics,
+    export_subscriber_data, export_subscribers, feed, get_subscriber, health_check,
+    import_subscribers, issue_page, issues_archive, list_scheduled_issues, list_subscribers, live,
+    log_out, login, login_form, preview_email_template, publish_newsletter,
+    publish_newsletter_form, publish_newsletter_issue, ready, request_subscriber_data,
+    reschedule_issue, resend_confirmation, revoke_api_token_form, save_email_template, subscribe,
+    suppress_subscriber, unsubscribe, unsubscribe_form,
+};
+use crate::session_store::PgSessionStore;
+use crate::shutdown::{shutdown_channel, termination_signal, ShutdownTrigger};
+use crate::subscription_cleanup::run_cleanup_until_stopped;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::web::Data;
 use actix_web::{web, App, HttpServer};
+use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
+use std::future::Future;
 use std::io::Error;
 use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
     port: u16,
     server: Server,
+    shutdown: ShutdownTrigger,
+    shutdown_timeout: Duration,
+    worker: JoinHandle<()>,
+    cleanup: JoinHandle<()>,
+    scheduler: JoinHandle<()>,
 }
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+        Self::build_with_clock(configuration, Arc::new(SystemClock)).await
+    }
+
+    // the clock decides when the scheduled issues are due
+    pub async fn build_with_clock(
+        configuration: Settings,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
 
-        let sender_email = configuration
+        let metrics = Metrics::new().map_err(std::io::Error::other)?;
+        let email_client = configuration
             .email_client
-            .sender()
-            .expect("Invalid sender email address");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+            .clone()
+            .client()
+            .map_err(std::io::Error::other)?
+            .with_metrics(metrics.clone());
+        let password_hashing =
+            PasswordHashing::new(&configuration.password_hashing).map_err(std::io::Error::other)?;
+        let (shutdown_trigger, shutdown) = shutdown_channel();
+
+        // deliver the newsletter issues in the background, next to the HTTP server
+        let worker = tokio::spawn(run_worker_until_stopped(
+            connection_pool.clone(),
+            configuration
+                .email_client
+                .clone()
+                .client()
+                .map_err(std::io::Error::other)?
+                .with_metrics(metrics.clone()),
+            configuration.delivery.clone(),
+            ApplicationBaseUrl(configuration.application.base_url.clone()),
+            HmacSecret(configuration.application.hmac_secret.clone()),
+            shutdown.clone(),
+        ));
+
+        let scheduler = tokio::spawn(run_scheduler_until_stopped(
+            connection_pool.clone(),
+            clock.clone(),
+            configuration.delivery,
+            shutdown.clone(),
+        ));
+
+        let cleanup = tokio::spawn(run_cleanup_until_stopped(
+            connection_pool.clone(),
+            configuration.subscriptions.clone(),
+            shutdown,
+        ));
 
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
         );
         let listener = TcpListener::bind(address)?;
+        let shutdown_timeout = configuration.application.shutdown_timeout();
         let port = listener.local_addr().unwrap().port();
         let server = run(
             listener,
             connection_pool,
             email_client,
-            configuration.application.base_url,
+            configuration.email_client,
+            configuration.application.base_url.clone(),
+            configuration.application.idempotency_ttl(),
+            configuration.application.hmac_secret,
+            configuration.subscriptions,
+            clock,
+            password_hashing,
+            configuration.rate_limits,
+            metrics,
+            shutdown_timeout,
         )?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            shutdown: shutdown_trigger,
+            shutdown_timeout,
+            worker,
+            cleanup,
+            scheduler,
+        })
     }
 
     pub fn port(&self) -> u16 {
@@ -53,7 +140,58 @@ impl Application {
     }
 
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        self.run_until(termination_signal()).await
+    }
+
+    /*
+    Serve until `signal`, then shut down gracefully: the server stops accepting connections and
+    finishes the requests in flight, the background tasks finish their work at hand. Whatever
+    still runs at the end of the shutdown timeout is dropped.
+    */
+    pub async fn run_until(self, signal: impl Future<Output = ()>) -> Result<(), std::io::Error> {
+        let Self {
+            server,
+            shutdown,
+            shutdown_timeout,
+            mut worker,
+            mut cleanup,
+            mut scheduler,
+            ..
+        } = self;
+        let server_handle = server.handle();
+        // the server is a future driving its own workers, it keeps running while we wait
+        let mut server = tokio::spawn(server);
+        let stopped_on_its_own = tokio::select! {
+            // the server only stops on its own when it fails
+            outcome = &mut server => Some(outcome),
+            _ = signal => {
+                tracing::info!("Shutting down");
+                None
+            }
+        };
+        let deadline = tokio::time::Instant::now() + shutdown_timeout;
+        shutdown.trigger();
+        // the server finishes the requests in flight within the same timeout
+        server_handle.stop(true).await;
+        let background_tasks = async {
+            let _ = (&mut worker).await;
+            let _ = (&mut cleanup).await;
+            let _ = (&mut scheduler).await;
+        };
+        if tokio::time::timeout_at(deadline, background_tasks)
+            .await
+            .is_err()
+        {
+            tracing::warn!("The background tasks did not stop before the shutdown timeout");
+        }
+        worker.abort();
+        cleanup.abort();
+        scheduler.abort();
+        let outcome = match stopped_on_its_own {
+            Some(outcome) => outcome,
+            None => server.await,
+        };
+        outcome.map_err(std::io::Error::other)?
     }
 }
 
@@ -63,31 +201,165 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
         .connect_lazy_with(configuration.with_db())
 }
 
+#[derive(Clone)]
 pub struct ApplicationBaseUrl(pub String);
 
+pub struct IdempotencyTtl(pub std::time::Duration);
+
+#[derive(Clone)]
+pub struct HmacSecret(pub Secret<String>);
+
+// every argument is shared with the handlers as application data
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
+    email_client_settings: EmailClientSettings,
     base_url: String,
+    idempotency_ttl: std::time::Duration,
+    hmac_secret: Secret<String>,
+    subscription_settings: SubscriptionSettings,
+    clock: Arc<dyn Clock>,
+    password_hashing: PasswordHashing,
+    rate_limits: RateLimitSettings,
+    metrics: Metrics,
+    shutdown_timeout: Duration,
 ) -> Result<Server, Error> {
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let session_store = PgSessionStore::new(db_pool.clone());
+    let hmac_secret = Data::new(HmacSecret(hmac_secret));
     // wrap to an Arc smart pointer
     let db_pool = web::Data::new(db_pool);
     let email_client = Data::new(email_client);
+    let email_client_settings = Data::new(email_client_settings);
     let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let idempotency_ttl = Data::new(IdempotencyTtl(idempotency_ttl));
+    let subscription_settings = Data::new(subscription_settings);
+    let clock: Data<dyn Clock> = Data::from(clock);
+    let password_hashing = Data::new(password_hashing);
+    let rate_limits = Data::new(rate_limits);
+    let metrics = Data::new(metrics);
     let server = HttpServer::new(move || {
         App::new()
             // add middleware => 'wrap' method on 'App'
+            .wrap(SessionMiddleware::new(
+                session_store.clone(),
+                secret_key.clone(),
+            ))
+            .wrap(from_fn(record_http_metrics))
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
+            .route("/health/live", web::get().to(live))
+            .route("/health/ready", web::get().to(ready))
+            .route("/metrics", web::get().to(export_metrics))
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("", web::get().to(admin_home))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/newsletters", web::get().to(publish_newsletter_form))
+                    .route("/newsletters", web::post().to(publish_newsletter_issue))
+                    .route("/templates", web::get().to(email_templates))
+                    .route("/templates/{name}", web::get().to(edit_email_template_form))
+                    .route("/templates/{name}", web::post().to(save_email_template))
+                    .route(
+                        "/templates/{name}/preview",
+                        web::get().to(preview_email_template),
+                    )
+                    .route("/tokens", web::get().to(api_tokens))
+                    .route("/tokens", web::post().to(create_api_token_form))
+                    .route(
+                        "/tokens/{token_id}/revoke",
+                        web::post().to(revoke_api_token_form),
+                    )
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/logout", web::post().to(log_out)),
+            )
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(from_fn(limit_sign_up_requests))
+                    .route(web::post().to(subscribe)),
+            )
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/subscribers", web::get().to(list_subscribers))
+            // before `{subscriber_id}`, which would match it
+            .route("/subscribers/export.csv", web::get().to(export_subscribers))
+            .route("/subscribers/import", web::post().to(import_subscribers))
+            .route(
+                "/subscribers/{subscriber_id}",
+                web::get().to(get_subscriber),
+            )
+            .route(
+                "/subscribers/{subscriber_id}",
+                web::delete().to(delete_subscriber),
+            )
+            .route(
+                "/subscribers/{subscriber_id}/suppress",
+                web::post().to(suppress_subscriber),
+            )
+            .service(
+                web::resource("/subscriptions/resend_confirmation")
+                    .wrap(from_fn(limit_sign_up_requests))
+                    .route(web::post().to(resend_confirmation)),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .service(
+                web::resource("/subscriptions/data_request")
+                    .wrap(from_fn(limit_sign_up_requests))
+                    .route(web::post().to(request_subscriber_data)),
+            )
+            .route(
+                "/subscriptions/data/export",
+                web::get().to(export_subscriber_data),
+            )
+            .route(
+                "/subscriptions/data/erase",
+                web::get().to(erase_subscriber_data_form),
+            )
+            .route(
+                "/subscriptions/data/erase",
+                web::post().to(erase_subscriber_data),
+            )
+            .route("/issues", web::get().to(issues_archive))
+            .route("/issues/{slug}", web::get().to(issue_page))
+            .route("/feed.xml", web::get().to(feed))
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route(
+                "/newsletters/scheduled",
+                web::get().to(list_scheduled_issues),
+            )
+            .route(
+                "/newsletters/scheduled/{newsletter_issue_id}",
+                web::put().to(reschedule_issue),
+            )
+            .route(
+                "/newsletters/scheduled/{newsletter_issue_id}",
+                web::delete().to(cancel_scheduled_issue),
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(email_client_settings.clone())
             .app_data(base_url.clone())
+            .app_data(idempotency_ttl.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(clock.clone())
+            .app_data(password_hashing.clone())
+            .app_data(rate_limits.clone())
+            .app_data(metrics.clone())
     })
     .listen(listener)?
+    // the application handles the signals, to stop the background tasks along with the server
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
     .run();
     Ok(server)
 }
@@ -0,0 +1,251 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::subscriber_events::RecordedEvent;
+
+/*
+The audit of the data requests (GDPR). An entry holds no address: it outlives the erasure of the
+subscriber, whose id means nothing anymore.
+*/
+#[derive(Debug, Clone, Copy)]
+pub enum DataRequestAction {
+    ExportRequested,
+    ExportDownloaded,
+    ErasureRequested,
+    ErasureCompleted,
+}
+
+impl DataRequestAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataRequestAction::ExportRequested => "export_requested",
+            DataRequestAction::ExportDownloaded => "export_downloaded",
+            DataRequestAction::ErasureRequested => "erasure_requested",
+            DataRequestAction::ErasureCompleted => "erasure_completed",
+        }
+    }
+}
+
+// `user_id` is the admin acting for the subscriber
+#[tracing::instrument(name = "Record a data request", skip(connection))]
+pub async fn record_data_request(
+    connection: &mut PgConnection,
+    subscriber_id: Option<Uuid>,
+    user_id: Option<Uuid>,
+    action: DataRequestAction,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO data_request_audit (subscriber_id, user_id, action)
+        VALUES ($1, $2, $3)
+        "#,
+        subscriber_id,
+        user_id,
+        action.as_str(),
+    )
+    .execute(connection)
+    .await?;
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberData {
+    pub subscription: SubscriptionData,
+    // the oldest first
+    pub history: Vec<RecordedEvent>,
+    pub subscription_tokens: Vec<TokenData>,
+    // the issues not delivered yet
+    pub pending_deliveries: Vec<DeliveryData>,
+    // the issues sent, the oldest first
+    pub deliveries: Vec<SentIssueData>,
+    pub data_requests: Vec<DataRequestData>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriptionData {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub timezone: Option<String>,
+    pub subscribed_at: DateTime<Utc>,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct TokenData {
+    pub subscription_token: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryData {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub n_retries: i32,
+    pub execute_after: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SentIssueData {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DataRequestData {
+    pub action: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/*
+Everything stored about a subscriber, `None` for an unknown subscriber. The queries run in a
+`REPEATABLE READ` transaction, they see the same snapshot.
+*/
+#[tracing::instrument(name = "Collect the data of a subscriber", skip(transaction))]
+pub async fn get_subscriber_data(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberData>, sqlx::Error> {
+    let Some(subscription) = sqlx::query_as!(
+        SubscriptionData,
+        r#"
+        SELECT id, email, name, status, timezone, subscribed_at, unsubscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let history = sqlx::query_as!(
+        RecordedEvent,
+        r#"
+        SELECT event, occurred_at
+        FROM subscription_events
+        WHERE subscriber_id = $1
+        ORDER BY occurred_at, id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let subscription_tokens = sqlx::query_as!(
+        TokenData,
+        r#"
+        SELECT subscription_token, created_at, expires_at
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let pending_deliveries = sqlx::query_as!(
+        DeliveryData,
+        r#"
+        SELECT q.newsletter_issue_id, i.title, q.n_retries, q.execute_after
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE q.subscriber_email = $1
+        ORDER BY q.execute_after
+        "#,
+        subscription.email
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let deliveries = sqlx::query_as!(
+        SentIssueData,
+        r#"
+        SELECT d.newsletter_issue_id, i.title, d.sent_at
+        FROM issue_deliveries d
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        WHERE d.subscriber_id = $1
+        ORDER BY d.sent_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let data_requests = sqlx::query_as!(
+        DataRequestData,
+        r#"
+        SELECT action, occurred_at
+        FROM data_request_audit
+        WHERE subscriber_id = $1
+        ORDER BY id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    Ok(Some(SubscriberData {
+        subscription,
+        history,
+        subscription_tokens,
+        pending_deliveries,
+        deliveries,
+        data_requests,
+    }))
+}
+
+/*
+Deletes every row about a subscriber: the subscription and its history, the tokens, the pending
+and the past deliveries and the rate limit counter of the address. Returns `false` for an unknown subscriber.
+The rows go in the transaction of the caller, all at once or not at all.
+*/
+#[tracing::instrument(name = "Erase a subscriber", skip(transaction))]
+pub async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let Some(subscriber) = sqlx::query!(
+        "SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    else {
+        return Ok(false);
+    };
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE subscriber_email = $1",
+        subscriber.email,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM issue_deliveries WHERE subscriber_id = $1",
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // the addresses are counted without their plus tag
+    if let Ok(email) = SubscriberEmail::parse(subscriber.email) {
+        sqlx::query!(
+            "DELETE FROM rate_limits WHERE key = $1",
+            format!("email:{}", email.without_plus_tag().as_ref()),
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+    // the history goes along (`ON DELETE CASCADE`)
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+        .execute(&mut *transaction)
+        .await?;
+    Ok(true)
+}
@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+/*
+The history of a subscriber, for the admins. The events are recorded along with the change they
+describe, in the same transaction or statement.
+*/
+#[derive(Debug, Clone, Copy)]
+pub enum SubscriberEvent {
+    // signing up, or again after unsubscribing
+    Subscribed,
+    // from a CSV file, by an admin
+    Imported,
+    ConfirmationSent,
+    Confirmed,
+    Unsubscribed,
+    // by an admin
+    Suppressed,
+}
+
+impl SubscriberEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriberEvent::Subscribed => "subscribed",
+            SubscriberEvent::Imported => "imported",
+            SubscriberEvent::ConfirmationSent => "confirmation_sent",
+            SubscriberEvent::Confirmed => "confirmed",
+            SubscriberEvent::Unsubscribed => "unsubscribed",
+            SubscriberEvent::Suppressed => "suppressed",
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct RecordedEvent {
+    pub event: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Record a subscriber event", skip(connection))]
+pub async fn record_subscriber_event(
+    connection: &mut PgConnection,
+    subscriber_id: Uuid,
+    event: SubscriberEvent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO subscription_events (subscriber_id, event) VALUES ($1, $2)",
+        subscriber_id,
+        event.as_str(),
+    )
+    .execute(connection)
+    .await?;
+    Ok(())
+}
+
+// the oldest first
+pub async fn get_subscriber_events(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<RecordedEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        RecordedEvent,
+        r#"
+        SELECT event, occurred_at
+        FROM subscription_events
+        WHERE subscriber_id = $1
+        ORDER BY occurred_at, id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,588 @@
+use std::collections::HashSet;
+use std::fmt::Formatter;
+use std::path::Path;
+use std::str::Utf8Error;
+
+use anyhow::Context;
+use csv_core::ReadRecordResult;
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::configuration::{Settings, SubscriptionSettings};
+use crate::domain::{NewSubscriber, SubscriberTimezone};
+use crate::routes::{error_chain_fmt, generate_subscription_token, stored_email};
+use crate::startup::get_connection_pool;
+use crate::subscriber_events::SubscriberEvent;
+
+// subscribers inserted per transaction
+const BATCH_SIZE: usize = 1000;
+// a quote left open doesn't read the rest of the file into a single row
+const MAX_ROW_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    // the subscribers already agreed to get the newsletter elsewhere
+    PreConfirmed,
+    // pending, until they follow the link of their confirmation email, sent by the delivery workers
+    SendConfirmations,
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    // the rows before the error are imported, the report says what became of them
+    #[error("{}", .0.error.as_deref().unwrap_or_default())]
+    InvalidCsv(ImportReport),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ImportReport {
+    pub accepted: usize,
+    pub rejected: usize,
+    pub duplicates: usize,
+    // in the order of the file
+    pub rows: Vec<RowReport>,
+    // why the import stopped before the end of the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RowReport {
+    // as numbered by a spreadsheet: the header is row 1, blank lines don't count
+    pub row: u64,
+    // as written in the file
+    pub email: String,
+    #[serde(flatten)]
+    pub outcome: RowOutcome,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RowOutcome {
+    Accepted,
+    Rejected { reason: String },
+    // already a subscriber, whatever their status, or earlier in the file
+    Duplicate,
+}
+
+impl ImportReport {
+    fn add(&mut self, row: u64, email: String, outcome: RowOutcome) {
+        match outcome {
+            RowOutcome::Accepted => self.accepted += 1,
+            RowOutcome::Rejected { .. } => self.rejected += 1,
+            RowOutcome::Duplicate => self.duplicates += 1,
+        }
+        self.rows.push(RowReport {
+            row,
+            email,
+            outcome,
+        });
+    }
+}
+
+type Record = Result<Vec<String>, Utf8Error>;
+
+// splits the chunks of a CSV file into records, wherever the chunks end
+struct CsvRecords {
+    reader: csv_core::Reader,
+    // the record being read, and where its fields end
+    output: Vec<u8>,
+    output_len: usize,
+    ends: Vec<usize>,
+    ends_len: usize,
+}
+
+impl CsvRecords {
+    fn new() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            output: vec![0; 1024],
+            output_len: 0,
+            ends: vec![0; 16],
+            ends_len: 0,
+        }
+    }
+
+    // an empty input is the end of the file, the records before an error are still read
+    fn read(&mut self, mut input: &[u8], records: &mut Vec<Record>) -> Result<(), String> {
+        loop {
+            let (result, read, written, ended) = self.reader.read_record(
+                input,
+                &mut self.output[self.output_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            input = &input[read..];
+            self.output_len += written;
+            self.ends_len += ended;
+            match result {
+                ReadRecordResult::InputEmpty | ReadRecordResult::End => return Ok(()),
+                ReadRecordResult::OutputFull => {
+                    if self.output.len() >= MAX_ROW_BYTES {
+                        return Err(format!(
+                            "A row is longer than {} KiB, is a quote left open?",
+                            MAX_ROW_BYTES / 1024
+                        ));
+                    }
+                    self.output.resize(self.output.len() * 2, 0);
+                }
+                ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+                ReadRecordResult::Record => {
+                    records.push(self.record());
+                    self.output_len = 0;
+                    self.ends_len = 0;
+                }
+            }
+        }
+    }
+
+    fn record(&self) -> Record {
+        let mut start = 0;
+        self.ends[..self.ends_len]
+            .iter()
+            .map(|&end| {
+                let field = std::str::from_utf8(&self.output[start..end]).map(str::to_owned);
+                start = end;
+                field
+            })
+            .collect()
+    }
+}
+
+struct Columns {
+    email: usize,
+    name: usize,
+    // optional, the default time zone of the settings applies when missing or empty
+    timezone: Option<usize>,
+}
+
+impl Columns {
+    // named in any case, in any order, among other columns
+    fn from_header(header: Record) -> Result<Self, String> {
+        let header = header.map_err(|_| "The header is not valid UTF-8".to_owned())?;
+        let position = |column: &str| {
+            header.iter().position(|name| {
+                name.trim_start_matches('\u{feff}')
+                    .trim()
+                    .eq_ignore_ascii_case(column)
+            })
+        };
+        match (position("email"), position("name")) {
+            (Some(email), Some(name)) => Ok(Self {
+                email,
+                name,
+                timezone: position("timezone"),
+            }),
+            _ => Err("The header must name an `email` and a `name` column".to_owned()),
+        }
+    }
+}
+
+struct ValidRow {
+    row: u64,
+    email: String,
+    id: Uuid,
+    subscriber: NewSubscriber,
+}
+
+/*
+The import of a CSV file of subscribers, fed chunk by chunk: every row is validated like a sign
+up, then inserted by batches with a `COPY`. The addresses already known are left untouched and
+reported as duplicates, the rows that are not valid are reported with the reason.
+A file that is not valid CSV stops the import where the error is: the rows before are imported.
+*/
+pub struct SubscriberImport<'a> {
+    pool: &'a PgPool,
+    settings: &'a SubscriptionSettings,
+    mode: ImportMode,
+    records: CsvRecords,
+    // known once the header is read
+    columns: Option<Columns>,
+    row: u64,
+    // the addresses of the file so far
+    seen: HashSet<String>,
+    batch: Vec<ValidRow>,
+    report: ImportReport,
+}
+
+impl<'a> SubscriberImport<'a> {
+    pub fn new(pool: &'a PgPool, settings: &'a SubscriptionSettings, mode: ImportMode) -> Self {
+        Self {
+            pool,
+            settings,
+            mode,
+            records: CsvRecords::new(),
+            columns: None,
+            row: 0,
+            seen: HashSet::new(),
+            batch: Vec::new(),
+            report: ImportReport::default(),
+        }
+    }
+
+    pub async fn push(&mut self, chunk: &[u8]) -> Result<(), ImportError> {
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        self.read(chunk).await
+    }
+
+    // imports the rows left, the batches before were imported already
+    pub async fn finish(mut self) -> Result<ImportReport, ImportError> {
+        self.read(&[]).await?;
+        if self.columns.is_none() {
+            return Err(self.stop("The file is empty".into()).await);
+        }
+        self.import_batch().await?;
+        self.report.rows.sort_by_key(|row| row.row);
+        Ok(self.report)
+    }
+
+    // imports the rows read so far, the error and their report make an `InvalidCsv`
+    pub async fn stop(&mut self, error: String) -> ImportError {
+        if let Err(e) = self.import_batch().await {
+            return e;
+        }
+        let mut report = std::mem::take(&mut self.report);
+        report.rows.sort_by_key(|row| row.row);
+        report.error = Some(error);
+        ImportError::InvalidCsv(report)
+    }
+
+    async fn read(&mut self, chunk: &[u8]) -> Result<(), ImportError> {
+        let mut records = Vec::new();
+        let read = self.records.read(chunk, &mut records);
+        self.add_records(records).await?;
+        match read {
+            Ok(()) => Ok(()),
+            Err(error) => Err(self.stop(error).await),
+        }
+    }
+
+    async fn add_records(&mut self, records: Vec<Record>) -> Result<(), ImportError> {
+        for record in records {
+            if let Err(error) = self.add_record(record) {
+                return Err(self.stop(error).await);
+            }
+            if self.batch.len() >= BATCH_SIZE {
+                self.import_batch().await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_record(&mut self, record: Record) -> Result<(), String> {
+        self.row += 1;
+        let Some(columns) = &self.columns else {
+            self.columns = Some(Columns::from_header(record)?);
+            return Ok(());
+        };
+        let Ok(fields) = record else {
+            self.report.add(
+                self.row,
+                String::new(),
+                RowOutcome::Rejected {
+                    reason: "The row is not valid UTF-8".into(),
+                },
+            );
+            return Ok(());
+        };
+        // a row of empty cells, as left by spreadsheets
+        if fields.iter().all(|field| field.trim().is_empty()) {
+            return Ok(());
+        }
+        let field = |index: usize| fields.get(index).cloned().unwrap_or_default();
+        let (email, name) = (field(columns.email), field(columns.name));
+        let timezone = columns
+            .timezone
+            .map(field)
+            .filter(|timezone| !timezone.trim().is_empty());
+        let parsed = NewSubscriber::parse(name, email.clone()).and_then(|mut subscriber| {
+            subscriber.timezone = timezone.map(SubscriberTimezone::parse).transpose()?;
+            Ok(subscriber)
+        });
+        let mut subscriber = match parsed {
+            Ok(subscriber) => subscriber,
+            Err(reason) => {
+                self.report
+                    .add(self.row, email, RowOutcome::Rejected { reason });
+                return Ok(());
+            }
+        };
+        subscriber.email = stored_email(subscriber.email, self.settings);
+        if !self.seen.insert(subscriber.email.as_ref().to_owned()) {
+            self.report.add(self.row, email, RowOutcome::Duplicate);
+            return Ok(());
+        }
+        self.batch.push(ValidRow {
+            row: self.row,
+            email,
+            id: Uuid::new_v4(),
+            subscriber,
+        });
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "Import a batch of subscribers",
+        skip(self),
+        fields(rows = self.batch.len())
+    )]
+    async fn import_batch(&mut self) -> Result<(), ImportError> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch);
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to acquire Postgres connection from the pool")?;
+        let inserted = insert_subscribers(&mut transaction, &batch, self.mode)
+            .await
+            .context("Failed to insert the imported subscribers")?;
+        if self.mode == ImportMode::SendConfirmations {
+            let ids: Vec<Uuid> = batch
+                .iter()
+                .map(|row| row.id)
+                .filter(|id| inserted.contains(id))
+                .collect();
+            let tokens: Vec<String> = ids.iter().map(|_| generate_subscription_token()).collect();
+            store_tokens(
+                &mut transaction,
+                &ids,
+                &tokens,
+                self.settings.confirmation_token_ttl(),
+            )
+            .await
+            .context("Failed to store the confirmation tokens of the imported subscribers")?;
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the imported subscribers")?;
+
+        for row in batch {
+            let outcome = if inserted.contains(&row.id) {
+                RowOutcome::Accepted
+            } else {
+                RowOutcome::Duplicate
+            };
+            self.report.add(row.row, row.email, outcome);
+        }
+        Ok(())
+    }
+}
+
+// `api import-subscribers`: the import of `POST /subscribers/import`, from a file
+pub async fn import_subscribers_file(
+    configuration: &Settings,
+    path: &Path,
+    mode: ImportMode,
+) -> Result<ImportReport, ImportError> {
+    let pool = get_connection_pool(&configuration.database);
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut import = SubscriberImport::new(&pool, &configuration.subscriptions, mode);
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        import.push(&buffer[..read]).await?;
+    }
+    import.finish().await
+}
+
+// the ids of the new subscribers, the others are known already
+#[tracing::instrument(name = "Insert the imported subscribers", skip(transaction, batch))]
+async fn insert_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    batch: &[ValidRow],
+    mode: ImportMode,
+) -> Result<HashSet<Uuid>, sqlx::Error> {
+    // the temporary table only exists at runtime, `query!` can't check these queries
+    sqlx::query(
+        r#"
+        CREATE TEMPORARY TABLE imported_subscribers
+        (
+            id       uuid NOT NULL,
+            email    TEXT NOT NULL,
+            name     TEXT NOT NULL,
+            timezone TEXT NULL
+        ) ON COMMIT DROP
+        "#,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    let mut copy = transaction
+        .copy_in_raw(
+            "COPY imported_subscribers (id, email, name, timezone) FROM STDIN (FORMAT csv)",
+        )
+        .await?;
+    copy.send(copy_rows(batch)).await?;
+    copy.finish().await?;
+
+    let status = match mode {
+        ImportMode::PreConfirmed => "confirmed",
+        ImportMode::SendConfirmations => "pending_confirmation",
+    };
+    let ids = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        WITH inserted AS (
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status, timezone)
+            SELECT id, email, name, now(), $1, timezone FROM imported_subscribers
+            ON CONFLICT (email) DO NOTHING
+            RETURNING id
+        ), events AS (
+            INSERT INTO subscription_events (subscriber_id, event)
+            SELECT id, $2 FROM inserted
+        )
+        SELECT id FROM inserted
+        "#,
+    )
+    .bind(status)
+    .bind(SubscriberEvent::Imported.as_str())
+    .fetch_all(&mut *transaction)
+    .await?;
+    Ok(ids.into_iter().collect())
+}
+
+// in the CSV format of `COPY`, every text quoted: an unquoted empty field is NULL
+fn copy_rows(batch: &[ValidRow]) -> Vec<u8> {
+    let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+    batch
+        .iter()
+        .map(|row| {
+            format!(
+                "{},{},{},{}\n",
+                row.id,
+                quote(row.subscriber.email.as_ref()),
+                quote(row.subscriber.name.as_ref()),
+                row.subscriber
+                    .timezone
+                    .as_ref()
+                    .map(|timezone| quote(timezone.as_ref()))
+                    .unwrap_or_default()
+            )
+        })
+        .collect::<String>()
+        .into_bytes()
+}
+
+// the confirmation emails are queued with their token, for the delivery workers
+#[tracing::instrument(name = "Store the confirmation tokens", skip_all)]
+async fn store_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_ids: &[Uuid],
+    subscription_tokens: &[String],
+    ttl: std::time::Duration,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH tokens AS (
+            INSERT INTO subscription_tokens
+                (subscription_token, subscriber_id, created_at, expires_at)
+            SELECT token, subscriber_id, now(), now() + make_interval(secs => $3)
+            FROM UNNEST($1::text[], $2::uuid[]) AS t (token, subscriber_id)
+            RETURNING subscription_token, subscriber_id
+        ), queued AS (
+            INSERT INTO confirmation_email_queue (subscription_token)
+            SELECT subscription_token FROM tokens
+        )
+        INSERT INTO subscription_events (subscriber_id, event)
+        SELECT subscriber_id, $4 FROM tokens
+        "#,
+        subscription_tokens,
+        subscriber_ids,
+        ttl.as_secs_f64(),
+        SubscriberEvent::ConfirmationSent.as_str(),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Columns, CsvRecords, Record};
+
+    fn fields(record: &Record) -> Vec<&str> {
+        record
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(String::as_str)
+            .collect()
+    }
+
+    #[test]
+    fn records_are_read_whatever_the_chunks() {
+        let csv = "email,name\r\nursula@example.com,\"Le Guin, Ursula\"\nle@guin.com,\"Ursula \"\"K\"\"\"";
+        for size in [1, 2, 7, csv.len()] {
+            let mut reader = CsvRecords::new();
+            let mut records = Vec::new();
+            for chunk in csv.as_bytes().chunks(size) {
+                reader.read(chunk, &mut records).unwrap();
+            }
+            reader.read(&[], &mut records).unwrap();
+            assert_eq!(records.len(), 3, "chunks of {size} bytes");
+            assert_eq!(fields(&records[0]), ["email", "name"]);
+            assert_eq!(
+                fields(&records[1]),
+                ["ursula@example.com", "Le Guin, Ursula"]
+            );
+            assert_eq!(fields(&records[2]), ["le@guin.com", "Ursula \"K\""]);
+        }
+    }
+
+    #[test]
+    fn a_quote_left_open_is_rejected() {
+        let mut reader = CsvRecords::new();
+        let mut records = Vec::new();
+        let row = format!("email,name\n\"{}", "a".repeat(100 * 1024));
+        assert!(reader.read(row.as_bytes(), &mut records).is_err());
+        // the header before is read
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn fields_that_are_not_utf8_are_reported() {
+        let mut reader = CsvRecords::new();
+        let mut records = Vec::new();
+        reader.read(b"a,\xff\xfe\n", &mut records).unwrap();
+        assert!(records[0].is_err());
+    }
+
+    #[test]
+    fn columns_are_found_in_any_case_and_order() {
+        let header = Ok(vec!["\u{feff}Id".into(), "Name ".into(), "EMAIL".into()]);
+        let columns = Columns::from_header(header).unwrap();
+        assert_eq!(
+            (columns.email, columns.name, columns.timezone),
+            (2, 1, None)
+        );
+        let header = Ok(vec!["email".into(), "name".into(), "TimeZone".into()]);
+        let columns = Columns::from_header(header).unwrap();
+        assert_eq!(columns.timezone, Some(2));
+    }
+
+    #[test]
+    fn a_header_without_email_is_rejected() {
+        let header = Ok(vec!["name".into(), "address".into()]);
+        assert!(Columns::from_header(header).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use sqlx::PgPool;
+
+use crate::configuration::SubscriptionSettings;
+use crate::rate_limit::delete_expired_rate_limits;
+use crate::shutdown::Shutdown;
+
+/*
+Delete the expired confirmation tokens, the pending subscribers left without a token and the
+ended rate limit windows, every `cleanup_interval` until the application stops.
+*/
+pub async fn run_cleanup_until_stopped(
+    pool: PgPool,
+    settings: SubscriptionSettings,
+    mut shutdown: Shutdown,
+) {
+    while !shutdown.is_requested() {
+        // the error is logged by the span, the next run tries again
+        let _ = delete_stale_subscriptions(&pool).await;
+        let _ = delete_expired_rate_limits(&pool).await;
+        shutdown.sleep(settings.cleanup_interval()).await;
+    }
+}
+
+/*
+A pending subscriber whose tokens all expired can't confirm anymore: the row is deleted and
+signing up again starts from scratch. A new token is stored in the same transaction as a
+pending subscriber, so pending rows are never seen without one in the meantime.
+*/
+#[tracing::instrument(skip_all, err)]
+pub async fn delete_stale_subscriptions(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let n_deleted_tokens =
+        sqlx::query!("DELETE FROM subscription_tokens WHERE expires_at <= now()")
+            .execute(&mut transaction)
+            .await?
+            .rows_affected();
+    let n_deleted_subscribers = sqlx::query!(
+        r#"
+        DELETE FROM subscriptions
+        WHERE status = 'pending_confirmation' AND NOT EXISTS (
+            SELECT 1 FROM subscription_tokens WHERE subscriber_id = subscriptions.id
+        )
+        "#
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    transaction.commit().await?;
+    tracing::info!(
+        n_deleted_tokens,
+        n_deleted_subscribers,
+        "Deleted stale subscriptions"
+    );
+    Ok(())
+}
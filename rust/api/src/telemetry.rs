@@ -1,11 +1,23 @@
+use opentelemetry::propagation::Injector;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self as sdktrace, TracerProvider};
+use opentelemetry::sdk::{runtime, Resource};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use tokio::task::JoinHandle;
 use tracing::subscriber::set_global_default;
 use tracing::Subscriber;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::{EnvFilter, Registry};
 
+use crate::configuration::TelemetrySettings;
+
 /*
 Compose multiple layers into a `tracing`'s subscriber
 Use `impl Subscriber` as return type to avoid having to spell out
@@ -15,6 +27,7 @@ pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    settings: &TelemetrySettings,
 ) -> impl Subscriber + Send + Sync
 where
     // higher-ranked trait bound (HRTB)
@@ -25,15 +38,54 @@ where
     // default log level = info (used if RUST_LOG environment variable is not set)
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    // the spans are OpenTelemetry spans too, part of the trace of the caller
+    let tracer = get_tracer(&name, settings).expect("Failed to build the OTLP exporter");
+    let opentelemetry_layer = tracing_opentelemetry::layer().with_tracer(tracer);
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
     // The `with` method is provided by `SubscriberExt`, an extension
     // trait for `Subscriber` exposed by `tracing_subscriber`
     Registry::default()
         .with(env_filter)
+        .with(opentelemetry_layer)
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
 
+/*
+The tracer of the application, registered as the global tracer provider. The spans are exported
+to the OTLP collector when one is configured, in batches sent from a thread of their own (timed
+by the runtime calling this function, it must outlive the exporter).
+Without a collector they are not exported, the trace context is still propagated.
+*/
+fn get_tracer(
+    name: &str,
+    settings: &TelemetrySettings,
+) -> Result<sdktrace::Tracer, opentelemetry::trace::TraceError> {
+    let config = sdktrace::config().with_resource(Resource::new([KeyValue::new(
+        "service.name",
+        name.to_owned(),
+    )]));
+    match &settings.otlp_endpoint {
+        Some(endpoint) => opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+                    .with_timeout(settings.export_timeout()),
+            )
+            .with_trace_config(config)
+            .install_batch(runtime::TokioCurrentThread),
+        None => {
+            let provider = TracerProvider::builder().with_config(config).build();
+            let tracer = provider.tracer(name.to_owned());
+            // the tracer only keeps a weak reference to its provider
+            global::set_tracer_provider(provider);
+            Ok(tracer)
+        }
+    }
+}
+
 /*
 Register subscriber as global default to pocess span data
 */
@@ -42,4 +94,44 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     // use `set_global_default` to specify which subscriber should be used to process spans
     set_global_default(subscriber).expect("Failed to set subscriber");
+    // W3C trace context: the `traceparent` header of the requests, read and sent
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+// the spans left in the batch are exported before the application exits
+pub fn shutdown_tracer_provider() {
+    global::shutdown_tracer_provider();
+}
+
+// the `traceparent` of the current span, for the services called to continue its trace
+pub fn trace_context_headers() -> HeaderMap {
+    let context = tracing::Span::current().context();
+    let mut headers = HeaderMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+// `spawn_blocking`, inside the current span
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
 }
@@ -0,0 +1,43 @@
+use crate::helpers::{assert_is_redirect_to, spawn_api_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+    let app = spawn_api_app().await;
+
+    let response = app.get_admin_dashboard().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    let app = spawn_api_app().await;
+
+    app.test_user.login(&app).await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(r#"<p class="info"><i>You have successfully logged out.</i></p>"#));
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_admin_root_redirects_to_the_dashboard() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .api_client
+        .get(&format!("{}/admin", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
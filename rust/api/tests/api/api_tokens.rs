@@ -0,0 +1,188 @@
+use reqwest::StatusCode;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_api_app, TestApp, TestUser,
+};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Release notes",
+        "content": {
+            "text": "Release notes as plain text",
+            "html": "<p>Release notes as HTML</p>",
+        }
+    })
+}
+
+async fn token_id(app: &TestApp, name: &str) -> uuid::Uuid {
+    sqlx::query!("SELECT id FROM api_tokens WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+#[tokio::test]
+async fn a_token_with_the_publish_scope_publishes_a_newsletter() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token("CI", &["newsletters:publish"]).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+    let saved = sqlx::query!("SELECT author_user_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.author_user_id, Some(app.test_user.user_id));
+}
+
+#[tokio::test]
+async fn tokens_are_stored_hashed_and_their_use_is_recorded() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token("CI", &["newsletters:publish"]).await;
+
+    app.post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+
+    let saved = sqlx::query!("SELECT token_hash, last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(token.starts_with("nlt_"));
+    assert_ne!(saved.token_hash, token);
+    assert!(saved.last_used_at.is_some());
+    let html = app.get_api_tokens_html().await;
+    assert!(html.contains("CI"));
+    assert!(!html.contains(&token));
+}
+
+#[tokio::test]
+async fn a_token_without_the_scope_is_forbidden() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+    let token = app
+        .create_api_token("Scheduler", &["newsletters:schedule"])
+        .await;
+
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn an_unknown_token_is_rejected() {
+    let app = spawn_api_app().await;
+
+    let response = app
+        .post_newsletters_with_token("nlt_unknown", newsletter_request_body())
+        .await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let challenges: Vec<_> = response
+        .headers()
+        .get_all("WWW-Authenticate")
+        .iter()
+        .map(|value| value.to_str().unwrap().to_owned())
+        .collect();
+    assert!(challenges.contains(&r#"Bearer realm="publish""#.to_owned()));
+}
+
+#[tokio::test]
+async fn a_revoked_token_is_rejected() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token("CI", &["newsletters:publish"]).await;
+
+    let response = app.post_revoke_api_token(token_id(&app, "CI").await).await;
+    assert_is_redirect_to(&response, "/admin/tokens");
+    let html = app.get_api_tokens_html().await;
+    assert!(html.contains("The token has been revoked."));
+    assert!(html.contains("revoked on"));
+
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn an_expired_token_is_rejected() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token("CI", &["newsletters:publish"]).await;
+    sqlx::query!("UPDATE api_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn a_token_needs_a_name_and_a_scope() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_api_token(&[("name", ""), ("newsletters:publish", "on")])
+        .await;
+    assert_is_redirect_to(&response, "/admin/tokens");
+    let html = app.get_api_tokens_html().await;
+    assert!(html.contains("The token needs a name."));
+
+    let response = app.post_api_token(&[("name", "CI")]).await;
+    assert_is_redirect_to(&response, "/admin/tokens");
+    let html = app.get_api_tokens_html().await;
+    assert!(html.contains("The token needs at least one scope."));
+}
+
+#[tokio::test]
+async fn only_the_owner_can_revoke_a_token() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+    app.create_api_token("CI", &["newsletters:publish"]).await;
+    let token_id = token_id(&app, "CI").await;
+    // the token now belongs to another user
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+    sqlx::query!("UPDATE api_tokens SET user_id = $1", other_user.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_revoke_api_token(token_id).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_tokens() {
+    let app = spawn_api_app().await;
+
+    let response = app
+        .post_api_token(&[("name", "CI"), ("newsletters:publish", "on")])
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,136 @@
+use std::sync::{Arc, Mutex};
+
+use api::configuration::{get_configuration, EmailBackend, FileSettings, SmtpSettings, SmtpTls};
+use api::startup::Application;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+use crate::helpers::spawn_api_app_with;
+
+// local SMTP server keeping the messages it receives, in the DATA format
+struct SmtpSink {
+    port: u16,
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl SmtpSink {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let messages = Arc::new(Mutex::new(vec![]));
+        let sink_messages = messages.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve_smtp_session(stream, sink_messages.clone()));
+            }
+        });
+        Self { port, messages }
+    }
+
+    fn messages(&self) -> Vec<String> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+async fn serve_smtp_session(stream: tokio::net::TcpStream, messages: Arc<Mutex<Vec<String>>>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer.write_all(b"220 smtp-sink ESMTP\r\n").await.unwrap();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let command = line.to_uppercase();
+        let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+            b"250 smtp-sink\r\n"
+        } else if command.starts_with("DATA") {
+            writer
+                .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                .await
+                .unwrap();
+            let mut message = String::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line == "." {
+                    break;
+                }
+                message.push_str(&line);
+                message.push('\n');
+            }
+            messages.lock().unwrap().push(message);
+            b"250 OK\r\n"
+        } else if command.starts_with("QUIT") {
+            writer.write_all(b"221 Bye\r\n").await.unwrap();
+            return;
+        } else {
+            b"250 OK\r\n"
+        };
+        writer.write_all(reply).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn the_smtp_backend_sends_the_confirmation_email_to_the_smtp_server() {
+    let smtp_sink = SmtpSink::start().await;
+    let port = smtp_sink.port;
+    let app = spawn_api_app_with(|c| {
+        c.email_client.backend = EmailBackend::Smtp;
+        c.email_client.smtp = Some(SmtpSettings {
+            host: "127.0.0.1".into(),
+            port: Some(port),
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+        });
+    })
+    .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let messages = smtp_sink.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].contains("To: ursula_le_guin@gmail.com"));
+    assert!(messages[0].contains("Subject: Welcome!"));
+    assert!(messages[0].contains("multipart/alternative"));
+    assert!(messages[0].contains("/subscriptions/confirm?subscription_token="));
+}
+
+#[tokio::test]
+async fn the_file_backend_writes_the_confirmation_email_to_the_outbox() {
+    let directory = std::env::temp_dir().join(format!("outbox-{}", Uuid::new_v4()));
+    let outbox = directory.to_str().unwrap().to_owned();
+    let app = spawn_api_app_with(|c| {
+        c.email_client.backend = EmailBackend::File;
+        c.email_client.file = Some(FileSettings { directory: outbox });
+    })
+    .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let files: Vec<_> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].extension().unwrap(), "eml");
+    let message = std::fs::read_to_string(&files[0]).unwrap();
+    assert!(message.contains("To: ursula_le_guin@gmail.com"));
+    assert!(message.contains("Subject: Welcome!"));
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn the_application_does_not_start_without_the_settings_of_its_backend() {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.application.port = 0;
+    configuration.email_client.backend = EmailBackend::Smtp;
+    configuration.email_client.smtp = None;
+
+    let application = Application::build(configuration).await;
+
+    assert!(application.is_err());
+}
@@ -0,0 +1,205 @@
+use api::email_templates::save_template_version;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_api_app};
+
+#[tokio::test]
+async fn the_confirmation_email_is_personalized() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Welcome!");
+    assert!(body["HtmlBody"].as_str().unwrap().contains("le guin"));
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<!DOCTYPE html>"));
+    assert!(body["TextBody"].as_str().unwrap().contains("le guin"));
+}
+
+#[tokio::test]
+async fn newsletter_issues_are_personalized_and_get_a_generated_text() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "News for {{ name }}",
+            "content": {
+                "html": "<h1>Hello {{ name }}</h1><p>The latest news.</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "News for le guin");
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("<h1>Hello le guin</h1>"));
+    assert!(html.contains("/subscriptions/unsubscribe?token="));
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.contains("Hello le guin"));
+    assert!(text.contains("The latest news."));
+    assert!(!text.contains("<p>"));
+    assert!(text.contains("Unsubscribe: http://127.0.0.1"));
+}
+
+#[tokio::test]
+async fn newsletter_issues_with_an_invalid_template_are_rejected() {
+    let app = spawn_api_app().await;
+
+    for html in ["<p>Hello {{ name </p>", "<p>Hello {{ unknown }}</p>"] {
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "content": {"html": html, "text": "Hello"}
+            }))
+            .await;
+
+        assert_eq!(response.status().as_u16(), 400, "{html}");
+    }
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_the_templates() {
+    let app = spawn_api_app().await;
+
+    let response = app.get_email_template("confirmation").await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_email_template_preview("confirmation").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_preview_renders_the_template_for_a_sample_subscriber() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_email_template_preview("confirmation").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Welcome!"));
+    // escaped in the `srcdoc` attribute of the frame
+    assert!(html_page.contains("Ursula Le Guin"));
+    assert!(html_page.contains("subscription_token=sample"));
+}
+
+#[tokio::test]
+async fn saving_a_template_creates_a_new_version_used_for_the_next_emails() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_email_template(
+            "confirmation",
+            &serde_json::json!({
+                "subject": "Please confirm, {{ name }}",
+                "html_content": r#"<p>Confirm <a href="{{ confirmation_link | safe }}">here</a></p>"#,
+                "text_content": "",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/templates/confirmation");
+    let html_page = app
+        .get_email_template("confirmation")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("saved as version 2."));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Please confirm, le guin");
+    // the text is generated from the HTML, with the link
+    let links = app.get_confirmation_links(email_request);
+    assert_eq!(links.html, links.plain_text);
+}
+
+#[tokio::test]
+async fn concurrent_saves_of_a_template_get_different_versions() {
+    let app = spawn_api_app().await;
+
+    let saves = (0..4).map(|i| {
+        let content = format!("<p>Version {i} {{{{ content | safe }}}}</p>");
+        let pool = app.db_pool.clone();
+        async move { save_template_version(&pool, "layout", "", &content, None).await }
+    });
+    let mut versions: Vec<i32> = futures_util::future::join_all(saves)
+        .await
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+
+    versions.sort();
+    assert_eq!(versions, vec![2, 3, 4, 5]);
+}
+
+#[tokio::test]
+async fn invalid_templates_are_not_saved() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_email_template(
+            "confirmation",
+            &serde_json::json!({
+                "subject": "Welcome",
+                "html_content": "<p>{% if name %}</p>",
+                "text_content": "",
+            }),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/templates/confirmation");
+    let html_page = app
+        .get_email_template("confirmation")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(r#"<p class="error"><i>Invalid template"#));
+    assert!(html_page.contains("version 1."));
+}
+
+#[tokio::test]
+async fn unknown_templates_are_not_found() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.get_email_template("unknown").await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
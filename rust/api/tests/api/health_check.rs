@@ -1,4 +1,12 @@
-use crate::helpers::spawn_api_app;
+use std::time::{Duration, Instant};
+
+use api::configuration::get_configuration;
+use secrecy::Secret;
+use sqlx::{Connection, Executor, PgConnection};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_api_app, spawn_api_app_with};
 
 #[tokio::test]
 async fn health_check_should_return_ok() {
@@ -17,3 +25,194 @@ async fn health_check_should_return_ok() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn liveness_probe_returns_200() {
+    // Arrange
+    let app = spawn_api_app().await;
+
+    // Act
+    let response = app.get_health("live").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["live"], true);
+}
+
+#[tokio::test]
+async fn readiness_probe_reports_every_check() {
+    // Arrange
+    let app = spawn_api_app().await;
+
+    // Act
+    let response = app.get_health("ready").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["ready"], true);
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+    assert_eq!(body["checks"]["migrations"]["status"], "ok");
+    assert_eq!(
+        body["checks"]["migrations"]["pending"],
+        serde_json::json!([])
+    );
+    assert_eq!(body["checks"]["email_backend"]["status"], "ok");
+    assert_eq!(body["checks"]["email_backend"]["backend"], "postmark");
+}
+
+#[tokio::test]
+async fn readiness_probe_fails_when_the_database_is_unreachable() {
+    // Arrange
+    let app = spawn_api_app().await;
+    let database_name: String = sqlx::query_scalar("SELECT current_database()")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    // refuse the new connections and close the open ones
+    let configuration = get_configuration().unwrap();
+    let mut connection = PgConnection::connect_with(&configuration.database.without_db())
+        .await
+        .unwrap();
+    connection
+        .execute(format!(r#"ALTER DATABASE "{database_name}" ALLOW_CONNECTIONS false"#).as_str())
+        .await
+        .unwrap();
+    sqlx::query("SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = $1")
+        .bind(&database_name)
+        .execute(&mut connection)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.get_health("ready").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["checks"]["database"]["status"], "failed");
+    // the details are in the logs only
+    assert_eq!(
+        body["checks"]["database"]["error"],
+        "The database is unreachable"
+    );
+    // the liveness probe doesn't depend on the database
+    assert_eq!(app.get_health("live").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn readiness_probe_fails_with_pending_migrations() {
+    // Arrange
+    let app = spawn_api_app().await;
+    let last_version: i64 = sqlx::query_scalar("SELECT max(version) FROM _sqlx_migrations")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query("DELETE FROM _sqlx_migrations WHERE version = $1")
+        .bind(last_version)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.get_health("ready").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+    assert_eq!(body["checks"]["migrations"]["status"], "failed");
+    assert_eq!(
+        body["checks"]["migrations"]["pending"],
+        serde_json::json!([last_version])
+    );
+}
+
+#[tokio::test]
+async fn readiness_probe_fails_when_the_email_backend_is_misconfigured() {
+    // Arrange
+    let app = spawn_api_app_with(|config| {
+        config.email_client.authorization_token = Secret::new(String::new());
+    })
+    .await;
+
+    // Act
+    let response = app.get_health("ready").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["email_backend"]["status"], "failed");
+    assert_eq!(
+        body["checks"]["email_backend"]["error"],
+        "Missing Postmark authorization token"
+    );
+}
+
+#[tokio::test]
+async fn shutdown_finishes_the_requests_in_flight() {
+    // Arrange
+    let mut app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let subscription = tokio::spawn(
+        reqwest::Client::new()
+            .post(&format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+            .send(),
+    );
+    // the confirmation email is being sent
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Act
+    let start = Instant::now();
+    app.shut_down().await.unwrap();
+
+    // Assert
+    let response = subscription.await.unwrap().unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    // the idle background tasks don't wait for their next run
+    assert!(start.elapsed() < Duration::from_secs(5));
+    // new connections are refused
+    assert!(reqwest::get(&format!("{}/health/live", &app.address))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn shutdown_gives_up_on_the_requests_after_the_timeout() {
+    // Arrange
+    let mut app = spawn_api_app_with(|config| {
+        config.application.shutdown_timeout_seconds = 1;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(30)))
+        .mount(&app.email_server)
+        .await;
+    let subscription = tokio::spawn(
+        reqwest::Client::new()
+            .post(&format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+            .send(),
+    );
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Act
+    let start = Instant::now();
+    app.shut_down().await.unwrap();
+
+    // Assert
+    assert!(start.elapsed() < Duration::from_secs(5));
+    // the connection is closed without a response
+    assert!(subscription.await.unwrap().is_err());
+}
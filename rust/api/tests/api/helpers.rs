@@ -1,34 +1,120 @@
-use api::configuration::{get_configuration, DatabaseSettings};
-use api::startup::{get_connection_pool, Application};
+use std::sync::{Arc, Mutex};
+
+use api::clock::Clock;
+use api::configuration::{
+    get_configuration, DatabaseSettings, DeliverySettings, EmailBackend, Settings,
+    TelemetrySettings,
+};
+use api::email_client::EmailClient;
+use api::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use api::issue_scheduler::release_due_issues;
+use api::startup::{get_connection_pool, Application, ApplicationBaseUrl, HmacSecret};
 use api::telemetry::{get_subscriber, init_subscriber};
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHasher};
+use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/*
+Runtime of the telemetry shared by the tests: the batches of spans are exported with timers of the
+runtime building the exporter, and the collector stand-in is started on it. Never shut down,
+unlike the runtime of a test.
+*/
+static TELEMETRY_RUNTIME: Lazy<tokio::runtime::Runtime> =
+    Lazy::new(|| tokio::runtime::Runtime::new().unwrap());
+
+// stand-in for an OTLP collector, receiving the traces of every test
+pub static OTLP_COLLECTOR: Lazy<MockServer> = Lazy::new(|| {
+    // `block_on` can't be called from the runtime of a test
+    std::thread::spawn(|| {
+        TELEMETRY_RUNTIME.block_on(async {
+            let collector = MockServer::builder().start().await;
+            Mock::given(path("/v1/traces"))
+                .and(method("POST"))
+                .respond_with(ResponseTemplate::new(200))
+                .mount(&collector)
+                .await;
+            collector
+        })
+    })
+    .join()
+    .unwrap()
+});
 
 pub static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
+    let telemetry = TelemetrySettings {
+        otlp_endpoint: Some(OTLP_COLLECTOR.uri()),
+        export_timeout_milliseconds: 10_000,
+    };
+    // the batches of spans are exported every 100ms, instead of 5s
+    std::env::set_var("OTEL_BSP_SCHEDULE_DELAY", "100");
+    let _runtime = TELEMETRY_RUNTIME.enter();
     // cannot assign output of `get_subscriber` to a variable based on value TEST_LOG
     // since the sink is part of the type returned by `get_subscriber` (and therefore
     // not the same type).
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            &telemetry,
+        );
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::sink,
+            &telemetry,
+        );
         init_subscriber(subscriber);
     }
 });
 
+// the time of the application, moved forward by the tests
+pub struct MockClock(Mutex<DateTime<Utc>>);
+
+impl MockClock {
+    pub fn advance(&self, duration: chrono::Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
 pub struct TestApp {
     pub address: String,
     pub port: u16,
     pub db_pool: PgPool,
+    pub database_name: String,
     pub email_server: MockServer,
     pub test_user: TestUser,
+    pub email_client: EmailClient,
+    pub delivery: DeliverySettings,
+    // keeps the session cookie, doesn't follow redirects
+    pub api_client: reqwest::Client,
+    pub base_url: ApplicationBaseUrl,
+    pub hmac_secret: HmacSecret,
+    pub clock: Arc<MockClock>,
+    // stands in for SIGTERM
+    stop_signal: Option<oneshot::Sender<()>>,
+    running: Option<JoinHandle<Result<(), std::io::Error>>>,
 }
 
 pub struct ConfirmationLinks {
@@ -53,10 +139,117 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    // from the client `ip`, as seen through a proxy
+    pub async fn post_subscriptions_from(&self, body: String, ip: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(&format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", ip)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_resend_confirmation(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(&format!(
+                "{}/subscriptions/resend_confirmation",
+                &self.address
+            ))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_data_request(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(&format!("{}/subscriptions/data_request", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
-        //let (username, password) = self.test_user().await;
+        self.test_user
+            .post_newsletters(&self.address, body, None)
+            .await
+    }
+
+    // stop the application as SIGTERM does, returns once it has shut down
+    pub async fn shut_down(&mut self) -> Result<(), std::io::Error> {
+        let _ = self
+            .stop_signal
+            .take()
+            .expect("The application was already stopped")
+            .send(());
+        self.running
+            .take()
+            .unwrap()
+            .await
+            .expect("The application panicked")
+    }
+
+    pub async fn get_health(&self, probe: &str) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/health/{probe}", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // run the delivery worker in the foreground, until the queue is empty
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.delivery,
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    // run the scheduler in the foreground, at the time of the mock clock
+    pub async fn release_scheduled_issues(&self) {
+        release_due_issues(
+            &self.db_pool,
+            self.clock.as_ref(),
+            self.delivery.default_timezone,
+        )
+        .await
+        .unwrap();
+    }
+
+    pub async fn get_scheduled_issues(&self) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .get(&format!("{}/newsletters/scheduled", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn put_scheduled_issue(
+        &self,
+        newsletter_issue_id: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(&format!(
+                "{}/newsletters/scheduled/{newsletter_issue_id}",
+                &self.address
+            ))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
@@ -64,6 +257,292 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn delete_scheduled_issue(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(&format!(
+                "{}/newsletters/scheduled/{newsletter_issue_id}",
+                &self.address
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.test_user
+            .post_newsletters(&self.address, body, Some(idempotency_key))
+            .await
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.get(&format!("{}/login", &self.address))
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.get(&format!("{}/admin/dashboard", &self.address))
+            .await
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.get(&format!("{}/admin/password", &self.address)).await
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_publish_newsletter(&self) -> reqwest::Response {
+        self.get(&format!("{}/admin/newsletters", &self.address))
+            .await
+    }
+
+    pub async fn get_publish_newsletter_html(&self) -> String {
+        self.get_publish_newsletter().await.text().await.unwrap()
+    }
+
+    pub async fn post_publish_newsletter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/newsletters", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_email_template(&self, name: &str) -> reqwest::Response {
+        self.get(&format!("{}/admin/templates/{name}", &self.address))
+            .await
+    }
+
+    pub async fn post_email_template<Body>(&self, name: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/templates/{name}", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_email_template_preview(&self, name: &str) -> reqwest::Response {
+        self.get(&format!("{}/admin/templates/{name}/preview", &self.address))
+            .await
+    }
+
+    pub async fn get_api_tokens_html(&self) -> String {
+        self.get(&format!("{}/admin/tokens", &self.address))
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_api_token<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/tokens", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // creates a token through the admin area, returns it
+    pub async fn create_api_token(&self, name: &str, scopes: &[&str]) -> String {
+        let mut form: Vec<(&str, &str)> = vec![("name", name), ("expires_in_days", "")];
+        form.extend(scopes.iter().map(|scope| (*scope, "on")));
+        let html = self.post_api_token(&form).await.text().await.unwrap();
+        let start = html.find(r#"<pre id="api-token">"#).unwrap() + r#"<pre id="api-token">"#.len();
+        let end = start + html[start..].find("</pre>").unwrap();
+        html[start..end].to_owned()
+    }
+
+    pub async fn post_revoke_api_token(&self, token_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/admin/tokens/{token_id}/revoke", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_newsletters_with_token(
+        &self,
+        token: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(&format!("{}/newsletters", &self.address))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // the admin API of the subscribers, with the credentials of the test user
+    pub async fn get_subscribers(&self, query: &[(&str, &str)]) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(&format!("{}/subscribers", &self.address))
+            .query(query)
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscribers_export(&self, query: &[(&str, &str)]) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(&format!("{}/subscribers/export.csv", &self.address))
+            .query(query)
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscriber(&self, subscriber_id: Uuid) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(&format!("{}/subscribers/{subscriber_id}", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn delete_subscriber(&self, subscriber_id: Uuid) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(&format!("{}/subscribers/{subscriber_id}", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_subscribers_import(&self, csv: &str, confirmed: bool) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(&format!("{}/subscribers/import", &self.address))
+            .query(&[("confirmed", confirmed)])
+            .header("Content-Type", "text/csv")
+            .body(csv.to_owned())
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_suppress_subscriber(&self, subscriber_id: Uuid) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(&format!(
+                "{}/subscribers/{subscriber_id}/suppress",
+                &self.address
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_issues_archive(&self, page: Option<i64>) -> reqwest::Response {
+        let url = match page {
+            Some(page) => format!("{}/issues?page={page}", &self.address),
+            None => format!("{}/issues", &self.address),
+        };
+        self.get(&url).await
+    }
+
+    pub async fn get_issue_page(&self, slug: &str) -> reqwest::Response {
+        self.get(&format!("{}/issues/{slug}", &self.address)).await
+    }
+
+    pub async fn get_feed(&self) -> reqwest::Response {
+        self.get(&format!("{}/feed.xml", &self.address)).await
+    }
+
+    pub async fn get_metrics(&self) -> reqwest::Response {
+        self.get(&format!("{}/metrics", &self.address)).await
+    }
+
+    pub async fn get_metrics_text(&self) -> String {
+        self.get_metrics().await.text().await.unwrap()
+    }
+
+    async fn get(&self, url: &str) -> reqwest::Response {
+        self.api_client
+            .get(url)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // the URL of the `List-Unsubscribe` header of a newsletter, on the port of the test app
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let header = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .expect("No List-Unsubscribe header");
+        let value = header["Value"].as_str().unwrap();
+        let raw_link = value.strip_prefix('<').unwrap().strip_suffix('>').unwrap();
+        let mut unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -96,7 +575,31 @@ impl TestUser {
         }
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn post_newsletters(
+        &self,
+        address: &str,
+        body: serde_json::Value,
+        idempotency_key: Option<&str>,
+    ) -> reqwest::Response {
+        let mut request = reqwest::Client::new()
+            .post(&format!("{}/newsletters", address))
+            .basic_auth(&self.username, Some(&self.password))
+            .json(&body);
+        if let Some(key) = idempotency_key {
+            request = request.header("Idempotency-Key", key);
+        }
+        request.send().await.expect("Failed to execute request")
+    }
+
+    pub async fn login(&self, app: &TestApp) {
+        app.post_login(&serde_json::json!({
+            "username": &self.username,
+            "password": &self.password
+        }))
+        .await;
+    }
+
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::default()
             .hash_password(self.password.as_bytes(), &salt)
@@ -116,6 +619,11 @@ impl TestUser {
 }
 
 pub async fn spawn_api_app() -> TestApp {
+    spawn_api_app_with(|_| {}).await
+}
+
+// spawn the application after adjusting its configuration
+pub async fn spawn_api_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING); // execute code in `TRACING` on first invocation, skip on other invocations
 
     // mock server as stand in for the Postmark API
@@ -126,7 +634,21 @@ pub async fn spawn_api_app() -> TestApp {
         let mut config = get_configuration().expect("Failed to read configuration");
         config.database.database_name = Uuid::new_v4().to_string(); // different database for each test
         config.application.port = 0; // random OS port
+        config.email_client.backend = EmailBackend::Postmark;
         config.email_client.base_url = email_server.uri();
+        // the tests dispatch the deliveries themselves: no backoff, and the
+        // background worker stays idle after its first look at the queue
+        config.delivery.backoff_base_milliseconds = 0;
+        config.delivery.poll_interval_milliseconds = 3_600_000;
+        // cheap password hashes, the parameters of production are slow in debug builds
+        config.password_hashing.memory_kib = 1024;
+        config.password_hashing.iterations = 1;
+        // the scheduled issues are released by the tests too
+        config.delivery.scheduler_interval_milliseconds = 3_600_000;
+        // every test signs up from the same address, the rate limits have tests of their own
+        config.rate_limits.per_ip.max_requests = 1000;
+        config.rate_limits.per_recipient.max_requests = 1000;
+        customise(&mut config);
         config
     };
 
@@ -134,19 +656,39 @@ pub async fn spawn_api_app() -> TestApp {
     configure_database(&configuration.database).await;
 
     // launch application as background task
-    let application = Application::build(configuration.clone())
+    let clock = Arc::new(MockClock(Mutex::new(Utc::now())));
+    let application = Application::build_with_clock(configuration.clone(), clock.clone())
         .await
         .expect("Failed to build application");
     // get port before spawning application
     let application_port = application.port();
-    let _ = tokio::spawn(application.run_until_stopped());
+    let (stop_signal, stopped) = oneshot::channel::<()>();
+    let running = tokio::spawn(application.run_until(async {
+        let _ = stopped.await;
+    }));
 
     let test_app = TestApp {
         address: format!("http://localhost:{}", application_port),
         port: application_port,
         db_pool: get_connection_pool(&configuration.database),
+        database_name: configuration.database.database_name.clone(),
         email_server,
         test_user: TestUser::generate(),
+        email_client: configuration
+            .email_client
+            .client()
+            .expect("Failed to build the email client"),
+        delivery: configuration.delivery,
+        base_url: ApplicationBaseUrl(configuration.application.base_url.clone()),
+        hmac_secret: HmacSecret(configuration.application.hmac_secret.clone()),
+        clock,
+        stop_signal: Some(stop_signal),
+        running: Some(running),
+        api_client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .build()
+            .unwrap(),
     };
     test_app.test_user.store(&test_app.db_pool).await;
     test_app
@@ -173,3 +715,46 @@ pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
 
     connection_pool
 }
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        // scoped mock -> `MockGuard` : stops specified mock behaviour when going out of scope
+        // stops returning http 200 at end of this function
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // inspect requests received by mock Postmark server in order to retrieve the
+    // confirmation link
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
@@ -0,0 +1,182 @@
+use api::clock::Clock;
+use chrono::Duration;
+use reqwest::StatusCode;
+
+use crate::helpers::{spawn_api_app, TestApp};
+
+fn newsletter_request_body(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "html": "<p>Dear {{ name }}, here is the <b>news</b>.</p>",
+        }
+    })
+}
+
+async fn publish_issue(app: &TestApp, title: &str) {
+    let response = app.post_newsletters(newsletter_request_body(title)).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+}
+
+async fn slug_of(app: &TestApp, title: &str) -> String {
+    sqlx::query!("SELECT slug FROM newsletter_issues WHERE title = $1", title)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .slug
+}
+
+#[tokio::test]
+async fn published_issues_are_stored_with_their_author() {
+    let app = spawn_api_app().await;
+
+    publish_issue(&app, "Newsletter title").await;
+
+    let saved = sqlx::query!(
+        "SELECT title, html_content, text_content, published_at, author_user_id \
+        FROM newsletter_issues"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.title, "Newsletter title");
+    assert!(saved.published_at.is_some());
+    assert_eq!(saved.author_user_id, Some(app.test_user.user_id));
+}
+
+#[tokio::test]
+async fn the_archive_lists_the_published_issues_with_links_to_their_page() {
+    let app = spawn_api_app().await;
+    publish_issue(&app, "First issue").await;
+    publish_issue(&app, "Second issue").await;
+
+    let html = app.get_issues_archive(None).await.text().await.unwrap();
+
+    let first_slug = slug_of(&app, "First issue").await;
+    let second_slug = slug_of(&app, "Second issue").await;
+    assert!(first_slug.starts_with("first-issue-"));
+    // the most recent first
+    let first_position = html.find(&format!("/issues/{first_slug}")).unwrap();
+    let second_position = html.find(&format!("/issues/{second_slug}")).unwrap();
+    assert!(second_position < first_position);
+}
+
+#[tokio::test]
+async fn an_issue_page_shows_the_issue_to_an_anonymous_reader() {
+    let app = spawn_api_app().await;
+    publish_issue(&app, "Hello {{ name }}").await;
+    let slug = slug_of(&app, "Hello {{ name }}").await;
+
+    let response = app.get_issue_page(&slug).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<h1>Hello reader</h1>"));
+    assert!(html.contains("<p>Dear reader, here is the <b>news</b>.</p>"));
+    assert!(slug.starts_with("hello-reader-"));
+}
+
+#[tokio::test]
+async fn an_unknown_issue_is_not_found() {
+    let app = spawn_api_app().await;
+
+    let response = app.get_issue_page("unknown-issue").await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_public_until_they_are_released() {
+    let app = spawn_api_app().await;
+    let mut body = newsletter_request_body("Next issue");
+    body["publish_at"] = (app.clock.now() + Duration::days(1)).to_rfc3339().into();
+    let response = app.post_newsletters(body).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let slug = slug_of(&app, "Next issue").await;
+
+    assert_eq!(
+        app.get_issue_page(&slug).await.status(),
+        StatusCode::NOT_FOUND
+    );
+    let html = app.get_issues_archive(None).await.text().await.unwrap();
+    assert!(!html.contains(&slug));
+    let feed = app.get_feed().await.text().await.unwrap();
+    assert!(!feed.contains(&slug));
+
+    app.clock.advance(Duration::days(2));
+    app.release_scheduled_issues().await;
+
+    assert_eq!(app.get_issue_page(&slug).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn the_archive_is_paginated() {
+    let app = spawn_api_app().await;
+    for i in 0..11 {
+        publish_issue(&app, &format!("Issue {i}")).await;
+    }
+
+    let first_page = app.get_issues_archive(None).await.text().await.unwrap();
+    let second_page = app.get_issues_archive(Some(2)).await.text().await.unwrap();
+
+    assert_eq!(first_page.matches("<li>").count(), 10);
+    assert!(first_page.contains(r#"<a href="/issues?page=2">Older issues</a>"#));
+    assert!(!first_page.contains("Newer issues"));
+    // the oldest issue is alone on the second page
+    assert_eq!(second_page.matches("<li>").count(), 1);
+    assert!(second_page.contains("Issue 0"));
+    assert!(second_page.contains(r#"<a href="/issues?page=1">Newer issues</a>"#));
+    assert!(!second_page.contains("Older issues"));
+}
+
+#[tokio::test]
+async fn an_invalid_page_is_rejected() {
+    let app = spawn_api_app().await;
+
+    for page in [0, -1, i64::MAX] {
+        let response = app.get_issues_archive(Some(page)).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "page {page}");
+    }
+}
+
+#[tokio::test]
+async fn the_feed_has_an_item_per_published_issue() {
+    let app = spawn_api_app().await;
+    publish_issue(&app, "Feed & news").await;
+    let slug = slug_of(&app, "Feed & news").await;
+
+    let response = app.get_feed().await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/rss+xml; charset=utf-8"
+    );
+    let feed = response.text().await.unwrap();
+    assert!(feed.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?>"#));
+    assert_eq!(feed.matches("<item>").count(), 1);
+    assert!(feed.contains("<title>Feed &amp; news</title>"));
+    assert!(feed.contains(&format!("<link>http://127.0.0.1/issues/{slug}</link>")));
+    // the HTML of the issue is escaped in the description
+    assert!(feed.contains("&lt;p&gt;Dear reader"));
+}
+
+#[tokio::test]
+async fn slugs_of_issues_with_the_same_title_are_different() {
+    let app = spawn_api_app().await;
+    publish_issue(&app, "Same title").await;
+    publish_issue(&app, "Same title").await;
+
+    let slugs: Vec<String> = sqlx::query!("SELECT slug FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.slug)
+        .collect();
+
+    assert_eq!(slugs.len(), 2);
+    assert_ne!(slugs[0], slugs[1]);
+    assert!(slugs.iter().all(|slug| slug.starts_with("same-title-")));
+}
@@ -0,0 +1,121 @@
+use crate::helpers::{assert_is_redirect_to, spawn_api_app};
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    let app = spawn_api_app().await;
+
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // the flash message is displayed once
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(r#"<p class="error"><i>Authentication failed</i></p>"#));
+
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed"));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    let app = spawn_api_app().await;
+
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn the_session_key_changes_on_login() {
+    let app = spawn_api_app().await;
+
+    // a session exists before the login, for the flash message
+    app.post_login(&serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    }))
+    .await;
+    let sessions_before: Vec<String> = sqlx::query_scalar!("SELECT session_key FROM sessions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sessions_before.len(), 1);
+
+    app.test_user.login(&app).await;
+
+    let sessions_after: Vec<String> = sqlx::query_scalar!("SELECT session_key FROM sessions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sessions_after.len(), 1);
+    assert_ne!(sessions_before, sessions_after);
+}
+
+#[tokio::test]
+async fn an_outdated_password_hash_is_replaced_on_login() {
+    let app = spawn_api_app().await;
+    let stored_hash = |pool: sqlx::PgPool, user_id: uuid::Uuid| async move {
+        sqlx::query!(
+            "SELECT password_hash FROM users WHERE user_id = $1",
+            user_id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .password_hash
+    };
+    // the test user is stored with the default parameters of the argon2 crate
+    let previous_hash = stored_hash(app.db_pool.clone(), app.test_user.user_id).await;
+    assert!(!previous_hash.contains("m=1024,t=1,p=1"));
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let new_hash = stored_hash(app.db_pool.clone(), app.test_user.user_id).await;
+    // the parameters of the test configuration
+    assert!(new_hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+    // the new hash holds the same password
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn an_unknown_username_is_rejected_like_a_wrong_password() {
+    let app = spawn_api_app().await;
+
+    let unknown_user = app
+        .post_login(&serde_json::json!({
+            "username": "unknown-username",
+            "password": "random-password"
+        }))
+        .await;
+    let wrong_password = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "random-password"
+        }))
+        .await;
+
+    assert_is_redirect_to(&unknown_user, "/login");
+    assert_is_redirect_to(&wrong_password, "/login");
+}
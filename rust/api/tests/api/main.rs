@@ -1,5 +1,21 @@
+mod admin_dashboard;
+mod admin_newsletters;
+mod api_tokens;
+mod change_password;
+mod email_backends;
+mod email_templates;
 mod health_check;
 mod helpers;
+mod issues;
+mod login;
+mod metrics;
 mod newsletter;
+mod rate_limits;
+mod scheduled_newsletters;
+mod subscribers;
+mod subscribers_import;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod subscriptions_unsubscribe;
+mod trace_context;
@@ -0,0 +1,163 @@
+use api::domain::UnsubscribeToken;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_api_app};
+
+// the value of a sample, like `http_requests_total{method="GET"} 1`
+fn sample(metrics: &str, series: &str) -> Option<f64> {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .map(|value| value.parse().unwrap())
+}
+
+#[tokio::test]
+async fn metrics_are_exported_in_the_prometheus_text_format() {
+    let app = spawn_api_app().await;
+
+    let response = app.get_metrics().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/plain; version=0.0.4"
+    );
+    let metrics = response.text().await.unwrap();
+    assert!(metrics.contains("# TYPE delivery_queue_depth gauge"));
+    assert!(metrics.contains("# TYPE db_pool_connections gauge"));
+}
+
+#[tokio::test]
+async fn requests_are_counted_and_timed_per_route() {
+    let app = spawn_api_app().await;
+
+    for _ in 0..2 {
+        app.get_issue_page("unknown-issue").await;
+    }
+    reqwest::get(&format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+    reqwest::get(&format!("{}/not/a/route", &app.address))
+        .await
+        .unwrap();
+
+    let metrics = app.get_metrics_text().await;
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"http_requests_total{method="GET",route="/issues/{slug}",status="404"}"#
+        ),
+        Some(2.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"http_requests_total{method="GET",route="/health_check",status="200"}"#
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"http_requests_total{method="GET",route="unmatched",status="404"}"#
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"http_request_duration_seconds_count{method="GET",route="/issues/{slug}"}"#
+        ),
+        Some(2.0)
+    );
+}
+
+#[tokio::test]
+async fn the_subscription_funnel_and_the_emails_are_counted() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let token = UnsubscribeToken::sign(subscriber_id, &app.hmac_secret.0);
+    let unsubscribe_link = format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        app.address,
+        token.as_ref()
+    );
+    // unsubscribing twice counts once
+    for _ in 0..2 {
+        reqwest::Client::new()
+            .post(&unsubscribe_link)
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    let metrics = app.get_metrics_text().await;
+    for step in ["subscribed", "confirmed", "unsubscribed"] {
+        let series = format!(r#"subscription_funnel_total{{step="{step}"}}"#);
+        assert_eq!(sample(&metrics, &series), Some(1.0), "{series}");
+    }
+    assert_eq!(
+        sample(&metrics, r#"emails_sent_total{backend="postmark"}"#),
+        Some(1.0)
+    );
+}
+
+#[tokio::test]
+async fn emails_the_backend_fails_to_send_are_counted() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let metrics = app.get_metrics_text().await;
+    assert_eq!(
+        sample(&metrics, r#"emails_failed_total{backend="postmark"}"#),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(&metrics, r#"emails_sent_total{backend="postmark"}"#),
+        None
+    );
+}
+
+#[tokio::test]
+async fn the_delivery_queue_depth_is_sampled_on_scrape() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let metrics = app.get_metrics_text().await;
+    assert_eq!(sample(&metrics, "delivery_queue_depth"), Some(1.0));
+
+    app.dispatch_all_pending_emails().await;
+    let metrics = app.get_metrics_text().await;
+    assert_eq!(sample(&metrics, "delivery_queue_depth"), Some(0.0));
+}
@@ -1,5 +1,9 @@
-use crate::helpers::{spawn_api_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_api_app, spawn_api_app_with,
+    TestApp, TestUser,
+};
 use reqwest::StatusCode;
+use std::time::Duration;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -23,7 +27,8 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     });
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -47,7 +52,295 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     });
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+             "text": "Newsletter body as plain text",
+             "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn publishing_stores_the_issue_and_returns_before_delivery() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let _mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0) // nothing is sent while handling the request
+        .named("Delivery during the request")
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let issue = sqlx::query!("SELECT newsletter_issue_id, title FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the stored issue");
+    assert_eq!(issue.title, "Newsletter title");
+    let queued = sqlx::query!(
+        "SELECT subscriber_email, n_retries FROM issue_delivery_queue \
+        WHERE newsletter_issue_id = $1",
+        issue.newsletter_issue_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].subscriber_email, "ursula_le_guin@gmail.com");
+    assert_eq!(queued[0].n_retries, 0);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+
+    assert_queue_is_empty(&app).await;
+}
+
+#[tokio::test]
+async fn deliveries_are_given_up_after_the_maximum_number_of_attempts() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(app.delivery.max_attempts as u64)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    assert_queue_is_empty(&app).await;
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_postponed_with_backoff() {
+    let app = spawn_api_app_with(|config| {
+        config.delivery.backoff_base_milliseconds = 60_000;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    // the second attempt is not due yet
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!(
+        "SELECT n_retries, execute_after > now() + interval '50 seconds' AS \"postponed!\" \
+        FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 1);
+    assert!(task.postponed);
+}
+
+#[tokio::test]
+async fn the_background_worker_delivers_newsletters() {
+    let app = spawn_api_app_with(|config| {
+        config.delivery.poll_interval_milliseconds = 50;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    for _ in 0..100 {
+        let pending = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+        if pending == 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("The background worker did not deliver the newsletter issue");
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    // submit the same issue again
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that the issue was sent once
+}
+
+#[tokio::test]
+async fn concurrent_submissions_are_handled_gracefully() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    let response1 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key);
+    let response2 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_scoped_per_user() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await
+        .error_for_status()
+        .unwrap();
+    other_user
+        .post_newsletters(
+            &app.address,
+            newsletter_request_body(),
+            Some(&idempotency_key),
+        )
+        .await
+        .error_for_status()
+        .unwrap();
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn expired_idempotency_keys_can_be_reused() {
+    let app = spawn_api_app_with(|config| {
+        config.application.idempotency_ttl_seconds = 0;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    for _ in 0..2 {
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+            .await
+            .error_for_status()
+            .unwrap();
+        app.dispatch_all_pending_emails().await;
+    }
+}
+
+#[tokio::test]
+async fn invalid_idempotency_keys_are_rejected() {
+    let app = spawn_api_app().await;
+
+    for key in ["", &"a".repeat(50)] {
+        let response = app
+            .post_newsletters_with_idempotency_key(newsletter_request_body(), key)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+async fn assert_queue_is_empty(app: &TestApp) {
+    let pending = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(pending, 0);
 }
 
 #[tokio::test]
@@ -103,41 +396,3 @@ async fn requests_missing_authorization_should_be_rejected() {
         response.headers()["WWW-Authenticate"]
     );
 }
-
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
-        .expect(1)
-        // scoped mock -> `MockGuard` : stops specified mock behaviour when going out of scope
-        // stops returning http 200 at end of this function
-        .mount_as_scoped(&app.email_server)
-        .await;
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
-
-    // inspect requests received by mock Postmark server in order to retrieve the
-    // confirmation link
-    let email_request = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
-    app.get_confirmation_links(&email_request)
-}
-
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
-}
@@ -0,0 +1,155 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use api::rate_limit::delete_expired_rate_limits;
+
+use crate::helpers::{spawn_api_app_with, TestApp};
+
+async fn accept_emails(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+fn sign_up(n: usize) -> String {
+    format!("name=le%20guin&email=ursula{n}%40example.com")
+}
+
+#[tokio::test]
+async fn too_many_sign_ups_from_an_ip_are_rejected_with_a_429() {
+    let app = spawn_api_app_with(|config| {
+        config.rate_limits.per_ip.max_requests = 2;
+        config.rate_limits.per_ip.window_seconds = 600;
+    })
+    .await;
+    accept_emails(&app).await;
+
+    for n in 0..2 {
+        let response = app.post_subscriptions(sign_up(n)).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = app.post_subscriptions(sign_up(2)).await;
+
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=600).contains(&retry_after));
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 2);
+}
+
+#[tokio::test]
+async fn too_many_sign_ups_for_an_address_are_rejected_whatever_its_case_and_plus_tag() {
+    let app = spawn_api_app_with(|config| {
+        config.rate_limits.per_recipient.max_requests = 2;
+    })
+    .await;
+    accept_emails(&app).await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+    app.post_subscriptions("name=le%20guin&email=Ursula%40Example.com".into())
+        .await;
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%2Bnews%40example.com".into())
+        .await;
+    let resend = app
+        .post_resend_confirmation("email=ursula%40example.com".into())
+        .await;
+    let other_address = app.post_subscriptions(sign_up(1)).await;
+
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+    assert_eq!(resend.status().as_u16(), 429);
+    assert_eq!(other_address.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_client_address_is_taken_from_the_proxy_headers_when_trusted() {
+    let app = spawn_api_app_with(|config| {
+        config.rate_limits.per_ip.max_requests = 1;
+        config.rate_limits.trusted_proxies = 1;
+    })
+    .await;
+    accept_emails(&app).await;
+
+    let first = app.post_subscriptions_from(sign_up(0), "203.0.113.1").await;
+    let second = app.post_subscriptions_from(sign_up(1), "203.0.113.1").await;
+    let other_client = app.post_subscriptions_from(sign_up(2), "203.0.113.2").await;
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+    assert_eq!(other_client.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_addresses_sent_by_the_client_before_the_trusted_proxies_are_ignored() {
+    let app = spawn_api_app_with(|config| {
+        config.rate_limits.per_ip.max_requests = 1;
+        config.rate_limits.trusted_proxies = 2;
+    })
+    .await;
+    accept_emails(&app).await;
+
+    // <sent by the client>, <client address>, <address of the outer proxy>
+    let first = app
+        .post_subscriptions_from(sign_up(0), "198.51.100.1, 203.0.113.1, 10.0.0.1")
+        .await;
+    let spoofed = app
+        .post_subscriptions_from(sign_up(1), "198.51.100.2, 203.0.113.1, 10.0.0.1")
+        .await;
+    let other_client = app
+        .post_subscriptions_from(sign_up(2), "203.0.113.2, 10.0.0.1")
+        .await;
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(spoofed.status().as_u16(), 429);
+    assert_eq!(other_client.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_proxy_headers_are_ignored_by_default() {
+    let app = spawn_api_app_with(|config| {
+        config.rate_limits.per_ip.max_requests = 1;
+    })
+    .await;
+    accept_emails(&app).await;
+
+    app.post_subscriptions_from(sign_up(0), "203.0.113.1").await;
+    let response = app.post_subscriptions_from(sign_up(1), "203.0.113.2").await;
+
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn a_new_window_starts_once_the_previous_one_has_expired() {
+    let app = spawn_api_app_with(|config| {
+        config.rate_limits.per_ip.max_requests = 1;
+    })
+    .await;
+    accept_emails(&app).await;
+    app.post_subscriptions(sign_up(0)).await;
+    sqlx::query!("UPDATE rate_limits SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscriptions(sign_up(1)).await;
+    sqlx::query!("UPDATE rate_limits SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let n_deleted = delete_expired_rate_limits(&app.db_pool).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_deleted, 3);
+}
@@ -0,0 +1,288 @@
+use api::clock::Clock;
+use chrono::{DateTime, Duration, Utc};
+use reqwest::StatusCode;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_api_app, TestApp};
+
+fn scheduled_request_body(publish_at: DateTime<Utc>) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "publish_at": publish_at.to_rfc3339(),
+    })
+}
+
+// returns the id of the scheduled issue
+async fn schedule_issue(app: &TestApp, publish_at: DateTime<Utc>) -> String {
+    let response = app
+        .post_newsletters(scheduled_request_body(publish_at))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+fn utc(s: &str) -> DateTime<Utc> {
+    s.parse().unwrap()
+}
+
+// signs up and confirms a subscriber, in `timezone` when given
+async fn create_confirmed_subscriber_in(app: &TestApp, email: &str, timezone: Option<&str>) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let mut body = format!("name=le%20guin&email={}", email.replace('@', "%40"));
+    if let Some(timezone) = timezone {
+        body.push_str(&format!("&timezone={}", timezone.replace('/', "%2F")));
+    }
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let confirmation_links = app.get_confirmation_links(email_requests.last().unwrap());
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+// the addresses with a delivery in the queue, in alphabetical order
+async fn enqueued_recipients(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue ORDER BY subscriber_email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.subscriber_email)
+        .collect()
+}
+
+async fn scheduled_issues(app: &TestApp) -> Vec<serde_json::Value> {
+    let response = app.get_scheduled_issues().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn a_scheduled_issue_is_delivered_once_its_time_has_come() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    schedule_issue(&app, app.clock.now() + Duration::days(3)).await;
+
+    let mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .named("Delivery before the scheduled time")
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.clock.advance(Duration::days(2));
+    app.release_scheduled_issues().await;
+    app.dispatch_all_pending_emails().await;
+    drop(mock_guard);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.clock.advance(Duration::days(1));
+    app.release_scheduled_issues().await;
+    app.dispatch_all_pending_emails().await;
+    assert!(scheduled_issues(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_publish_at_in_the_past_publishes_the_issue_right_away() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(scheduled_request_body(app.clock.now() - Duration::hours(1)))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+    assert!(scheduled_issues(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_listed_next_one_first() {
+    let app = spawn_api_app().await;
+    let now = app.clock.now();
+    let later_id = schedule_issue(&app, now + Duration::days(2)).await;
+    let sooner_id = schedule_issue(&app, now + Duration::days(1)).await;
+
+    let issues = scheduled_issues(&app).await;
+
+    assert_eq!(issues.len(), 2);
+    assert_eq!(issues[0]["newsletter_issue_id"], sooner_id.as_str());
+    assert_eq!(issues[1]["newsletter_issue_id"], later_id.as_str());
+    assert_eq!(issues[0]["title"], "Newsletter title");
+}
+
+#[tokio::test]
+async fn a_publish_at_without_offset_reaches_every_subscriber_at_that_time_in_their_time_zone() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber_in(&app, "tokyo@example.com", Some("Asia/Tokyo")).await;
+    create_confirmed_subscriber_in(&app, "new_york@example.com", Some("America/New_York")).await;
+    // in the default time zone of the settings, UTC
+    create_confirmed_subscriber_in(&app, "unknown@example.com", None).await;
+    app.clock.set(utc("2099-07-26T12:00:00Z"));
+    let mut body = scheduled_request_body(app.clock.now());
+    body["publish_at"] = "2099-07-27T08:00:00".into();
+
+    let response = app.post_newsletters(body).await;
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let issues = scheduled_issues(&app).await;
+    assert_eq!(issues[0]["publish_at_local"], "2099-07-27T08:00:00");
+    // 08:00 comes first at UTC+14:00
+    assert_eq!(
+        issues[0]["publish_at"]
+            .as_str()
+            .unwrap()
+            .parse::<DateTime<Utc>>()
+            .unwrap(),
+        utc("2099-07-26T18:00:00Z")
+    );
+    // 08:00 in Tokyo (UTC+09:00), then in UTC, then in New York (UTC-04:00 in summer)
+    for (now, recipients) in [
+        ("2099-07-26T22:59:00Z", vec![]),
+        ("2099-07-26T23:00:00Z", vec!["tokyo@example.com"]),
+        ("2099-07-27T07:59:00Z", vec!["tokyo@example.com"]),
+        (
+            "2099-07-27T08:00:00Z",
+            vec!["tokyo@example.com", "unknown@example.com"],
+        ),
+        (
+            "2099-07-27T12:00:00Z",
+            vec![
+                "new_york@example.com",
+                "tokyo@example.com",
+                "unknown@example.com",
+            ],
+        ),
+        // past the last time zone, nobody is enqueued twice
+        (
+            "2099-07-29T00:00:00Z",
+            vec![
+                "new_york@example.com",
+                "tokyo@example.com",
+                "unknown@example.com",
+            ],
+        ),
+    ] {
+        app.clock.set(utc(now));
+        app.release_scheduled_issues().await;
+        assert_eq!(enqueued_recipients(&app).await, recipients, "at {now}");
+    }
+    assert!(scheduled_issues(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_rescheduled_issue_is_delivered_at_its_new_time() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    let now = app.clock.now();
+    let issue_id = schedule_issue(&app, now + Duration::days(1)).await;
+
+    let response = app
+        .put_scheduled_issue(
+            &issue_id,
+            serde_json::json!({ "publish_at": (now + Duration::days(7)).to_rfc3339() }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .named("Delivery at the previous time")
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.clock.advance(Duration::days(2));
+    app.release_scheduled_issues().await;
+    app.dispatch_all_pending_emails().await;
+    drop(mock_guard);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.clock.advance(Duration::days(5));
+    app.release_scheduled_issues().await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_cancelled_issue_is_never_delivered() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_issue(&app, app.clock.now() + Duration::days(1)).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.delete_scheduled_issue(&issue_id).await;
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(scheduled_issues(&app).await.is_empty());
+    app.clock.advance(Duration::days(2));
+    app.release_scheduled_issues().await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_rescheduled_or_cancelled() {
+    let app = spawn_api_app().await;
+    let issue_id = schedule_issue(&app, app.clock.now() + Duration::days(1)).await;
+    app.clock.advance(Duration::days(2));
+    app.release_scheduled_issues().await;
+
+    let reschedule_response = app
+        .put_scheduled_issue(
+            &issue_id,
+            serde_json::json!({ "publish_at": (app.clock.now() + Duration::days(1)).to_rfc3339() }),
+        )
+        .await;
+    let cancel_response = app.delete_scheduled_issue(&issue_id).await;
+
+    assert_eq!(reschedule_response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(cancel_response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn the_scheduled_issues_require_authentication() {
+    let app = spawn_api_app().await;
+
+    let response = reqwest::Client::new()
+        .get(&format!("{}/newsletters/scheduled", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
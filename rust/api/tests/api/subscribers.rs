@@ -0,0 +1,435 @@
+use api::domain::UnsubscribeToken;
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::StatusCode;
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_api_app, TestApp};
+
+fn signed_up_on(day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 8, day, 12, 0, 0).unwrap()
+}
+
+async fn insert_subscriber(
+    app: &TestApp,
+    email: &str,
+    name: &str,
+    status: &str,
+    subscribed_at: DateTime<Utc>,
+) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, $2, $3, $4, $5)",
+        id,
+        email,
+        name,
+        subscribed_at,
+        status,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    id
+}
+
+async fn listed_emails(response: reqwest::Response) -> (Vec<String>, Option<String>) {
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let emails = body["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap().to_owned())
+        .collect();
+    (emails, body["next_cursor"].as_str().map(str::to_owned))
+}
+
+async fn confirmed_subscriber_id(app: &TestApp) -> Uuid {
+    create_confirmed_subscriber(app).await;
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = 'ursula_le_guin@gmail.com'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+#[tokio::test]
+async fn requests_without_credentials_are_rejected() {
+    let app = spawn_api_app().await;
+
+    let response = reqwest::get(&format!("{}/subscribers", &app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let challenges: Vec<_> = response
+        .headers()
+        .get_all("WWW-Authenticate")
+        .iter()
+        .map(|h| h.to_str().unwrap().to_owned())
+        .collect();
+    assert!(challenges.contains(&r#"Basic realm="subscribers""#.to_owned()));
+    assert!(challenges.contains(&r#"Bearer realm="subscribers""#.to_owned()));
+}
+
+#[tokio::test]
+async fn a_token_needs_the_subscribers_scope() {
+    let app = spawn_api_app().await;
+    app.test_user.login(&app).await;
+    let publish_token = app
+        .create_api_token("publish", &["newsletters:publish"])
+        .await;
+    let manage_token = app
+        .create_api_token("manage", &["subscribers:manage"])
+        .await;
+    let url = format!("{}/subscribers", &app.address);
+
+    let forbidden = reqwest::Client::new()
+        .get(&url)
+        .bearer_auth(publish_token)
+        .send()
+        .await
+        .unwrap();
+    let allowed = reqwest::Client::new()
+        .get(&url)
+        .bearer_auth(manage_token)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(forbidden.status(), StatusCode::FORBIDDEN);
+    assert_eq!(allowed.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_page_by_page_the_most_recent_first() {
+    let app = spawn_api_app().await;
+    for day in 1..=5 {
+        let email = format!("reader{day}@example.com");
+        insert_subscriber(&app, &email, "reader", "confirmed", signed_up_on(day)).await;
+    }
+
+    let mut emails = Vec::new();
+    let mut cursor: Option<String> = None;
+    let mut n_pages = 0;
+    loop {
+        let mut query = vec![("limit", "2")];
+        if let Some(cursor) = &cursor {
+            query.push(("cursor", cursor));
+        }
+        let (page, next_cursor) = listed_emails(app.get_subscribers(&query).await).await;
+        emails.extend(page);
+        n_pages += 1;
+        match next_cursor {
+            Some(next_cursor) => cursor = Some(next_cursor),
+            None => break,
+        }
+    }
+
+    assert_eq!(n_pages, 3);
+    assert_eq!(
+        emails,
+        [5, 4, 3, 2, 1].map(|day| format!("reader{day}@example.com"))
+    );
+}
+
+#[tokio::test]
+async fn subscribers_are_filtered_by_status_and_sign_up_date() {
+    let app = spawn_api_app().await;
+    insert_subscriber(&app, "a@example.com", "a", "confirmed", signed_up_on(1)).await;
+    insert_subscriber(&app, "b@example.com", "b", "confirmed", signed_up_on(10)).await;
+    insert_subscriber(&app, "c@example.com", "c", "unsubscribed", signed_up_on(10)).await;
+    insert_subscriber(&app, "d@example.com", "d", "confirmed", signed_up_on(20)).await;
+    let after = signed_up_on(5).to_rfc3339();
+    let before = signed_up_on(15).to_rfc3339();
+
+    let (emails, next_cursor) = listed_emails(
+        app.get_subscribers(&[
+            ("status", "confirmed"),
+            ("subscribed_after", &after),
+            ("subscribed_before", &before),
+        ])
+        .await,
+    )
+    .await;
+
+    assert_eq!(emails, ["b@example.com"]);
+    assert_eq!(next_cursor, None);
+}
+
+#[tokio::test]
+async fn subscribers_are_searched_by_email_or_name() {
+    let app = spawn_api_app().await;
+    let now = Utc::now();
+    insert_subscriber(
+        &app,
+        "ursula@example.com",
+        "Ursula Le Guin",
+        "confirmed",
+        now,
+    )
+    .await;
+    insert_subscriber(&app, "leguin.fan@example.com", "A fan", "confirmed", now).await;
+    insert_subscriber(&app, "ursula.k@example.com", "Someone", "confirmed", now).await;
+
+    // an address is looked up as is, once normalized
+    let (exact, _) = listed_emails(
+        app.get_subscribers(&[("search", "Ursula@Example.com")])
+            .await,
+    )
+    .await;
+    // anything else is a part of the name or of the address
+    let (mut partial, _) = listed_emails(app.get_subscribers(&[("search", "guin")]).await).await;
+    partial.sort();
+    // the wildcards are not
+    let (wildcard, _) = listed_emails(app.get_subscribers(&[("search", "%")]).await).await;
+
+    assert_eq!(exact, ["ursula@example.com"]);
+    assert_eq!(partial, ["leguin.fan@example.com", "ursula@example.com"]);
+    assert!(wildcard.is_empty());
+}
+
+#[tokio::test]
+async fn invalid_listing_parameters_are_rejected() {
+    let app = spawn_api_app().await;
+    let test_cases = [
+        (vec![("limit", "0")], "a limit of 0"),
+        (vec![("limit", "501")], "a limit above the maximum"),
+        (vec![("cursor", "not-a-cursor")], "an invalid cursor"),
+        (vec![("search", "<script>")], "a search that is no name"),
+        (vec![("status", "deleted")], "an unknown status"),
+        (vec![("subscribed_after", "yesterday")], "an invalid date"),
+    ];
+
+    for (query, description) in test_cases {
+        let response = app.get_subscribers(&query).await;
+
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "The API did not reject {description}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_history_of_a_subscriber_is_recorded() {
+    let app = spawn_api_app().await;
+    let subscriber_id = confirmed_subscriber_id(&app).await;
+
+    let response = app.get_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(body["status"], "confirmed");
+    let events: Vec<_> = body["history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["event"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(events, ["subscribed", "confirmation_sent", "confirmed"]);
+}
+
+#[tokio::test]
+async fn unknown_subscribers_are_not_found() {
+    let app = spawn_api_app().await;
+    let subscriber_id = Uuid::new_v4();
+
+    assert_eq!(
+        app.get_subscriber(subscriber_id).await.status(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        app.delete_subscriber(subscriber_id).await.status(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        app.post_suppress_subscriber(subscriber_id).await.status(),
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn a_deleted_subscriber_is_gone_with_its_tokens_and_history() {
+    let app = spawn_api_app().await;
+    let subscriber_id = confirmed_subscriber_id(&app).await;
+
+    let response = app.delete_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        app.get_subscriber(subscriber_id).await.status(),
+        StatusCode::NOT_FOUND
+    );
+    let remaining = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT count(*) FROM subscription_tokens WHERE subscriber_id = $1) AS "tokens!",
+            (SELECT count(*) FROM subscription_events WHERE subscriber_id = $1) AS "events!"
+        "#,
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.tokens, 0);
+    assert_eq!(remaining.events, 0);
+}
+
+#[tokio::test]
+async fn a_suppressed_subscriber_gets_no_email_even_signing_up_again() {
+    let app = spawn_api_app().await;
+    let subscriber_id = confirmed_subscriber_id(&app).await;
+
+    let response = app.post_suppress_subscriber(subscriber_id).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "suppressed");
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let newsletter = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {"html": "<p>Newsletter body as HTML</p>"}
+    });
+    assert_eq!(
+        app.post_newsletters(newsletter).await.status(),
+        StatusCode::ACCEPTED
+    );
+    app.dispatch_all_pending_emails().await;
+    let sign_up = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(sign_up.status(), StatusCode::OK);
+    // suppressing again is recorded once
+    app.post_suppress_subscriber(subscriber_id).await;
+    let body: serde_json::Value = app
+        .get_subscriber(subscriber_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["status"], "suppressed");
+    let last_events: Vec<_> = body["history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["event"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(last_events.last().unwrap(), "suppressed");
+    assert_eq!(last_events.iter().filter(|e| *e == "suppressed").count(), 1);
+}
+
+#[tokio::test]
+async fn a_suppressed_subscriber_stays_suppressed_when_unsubscribing() {
+    let app = spawn_api_app().await;
+    let subscriber_id = confirmed_subscriber_id(&app).await;
+    app.post_suppress_subscriber(subscriber_id).await;
+    let token = UnsubscribeToken::sign(subscriber_id, &app.hmac_secret.0);
+
+    let response = reqwest::Client::new()
+        .post(&format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            &app.address,
+            token.as_ref()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = app
+        .get_subscriber(subscriber_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["status"], "suppressed");
+    let last_event = body["history"].as_array().unwrap().last().unwrap().clone();
+    assert_eq!(last_event["event"], "suppressed");
+}
+
+#[tokio::test]
+async fn the_export_is_a_csv_of_the_filtered_subscribers() {
+    let app = spawn_api_app().await;
+    insert_subscriber(
+        &app,
+        "a@example.com",
+        "Le Guin, Ursula",
+        "confirmed",
+        signed_up_on(2),
+    )
+    .await;
+    insert_subscriber(
+        &app,
+        "b@example.com",
+        "=HYPERLINK(\"x\")",
+        "confirmed",
+        signed_up_on(1),
+    )
+    .await;
+    insert_subscriber(&app, "c@example.com", "c", "unsubscribed", signed_up_on(3)).await;
+
+    let response = app.get_subscribers_export(&[("status", "confirmed")]).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/csv; charset=utf-8"
+    );
+    assert!(response
+        .headers()
+        .get("Content-Disposition")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(
+        lines[0],
+        "id,email,name,status,subscribed_at,unsubscribed_at"
+    );
+    assert!(lines[1].contains(r#",a@example.com,"Le Guin, Ursula",confirmed,"#));
+    assert!(lines[2].contains(r#",b@example.com,"'=HYPERLINK(""x"")",confirmed,"#));
+}
+
+#[tokio::test]
+async fn the_export_streams_every_subscriber_batch_by_batch() {
+    let app = spawn_api_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT gen_random_uuid(), 'reader' || i || '@example.com', 'reader', now() - i * interval '1 minute', 'confirmed'
+        FROM generate_series(1, 1200) AS i
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let csv = app.get_subscribers_export(&[]).await.text().await.unwrap();
+
+    let mut emails: Vec<_> = csv
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').nth(1).unwrap().to_owned())
+        .collect();
+    assert_eq!(emails.len(), 1200);
+    emails.sort();
+    emails.dedup();
+    assert_eq!(emails.len(), 1200);
+    // the oldest last
+    assert!(csv
+        .lines()
+        .last()
+        .unwrap()
+        .contains(",reader1200@example.com,"));
+}
@@ -0,0 +1,347 @@
+use reqwest::StatusCode;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_api_app, TestApp};
+
+async fn statuses(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|s| (s.email, s.status))
+        .collect()
+}
+
+async fn events(app: &TestApp, email: &str) -> Vec<String> {
+    sqlx::query!(
+        r#"
+        SELECT event FROM subscription_events
+        WHERE subscriber_id = (SELECT id FROM subscriptions WHERE email = $1)
+        ORDER BY id
+        "#,
+        email
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|e| e.event)
+    .collect()
+}
+
+#[tokio::test]
+async fn imports_without_credentials_are_rejected() {
+    let app = spawn_api_app().await;
+
+    let response = reqwest::Client::new()
+        .post(&format!("{}/subscribers/import", &app.address))
+        .body("email,name\nursula@example.com,Ursula\n")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(statuses(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn imported_subscribers_are_sent_a_confirmation_email() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscribers_import(
+            "email,name\nursula@example.com,Ursula\n\"le,guin@example.com\",Le Guin\nle_guin@example.com,\"Le Guin, Ursula\"\n",
+            false,
+        )
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 2);
+    assert_eq!(report["rejected"], 1);
+    assert_eq!(
+        statuses(&app).await,
+        [
+            ("le_guin@example.com".into(), "pending_confirmation".into()),
+            ("ursula@example.com".into(), "pending_confirmation".into()),
+        ]
+    );
+    assert_eq!(
+        events(&app, "ursula@example.com").await,
+        ["imported", "confirmation_sent"]
+    );
+
+    // the emails are queued, the delivery workers send them
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+    app.dispatch_all_pending_emails().await;
+
+    // the link of the email confirms the subscriber
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    let confirmed = reqwest::get(confirmation_link).await.unwrap();
+    assert_eq!(confirmed.status(), StatusCode::OK);
+    let statuses = statuses(&app).await;
+    assert!(statuses.iter().any(|(_, status)| status == "confirmed"));
+}
+
+#[tokio::test]
+async fn pre_confirmed_subscribers_are_not_emailed() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscribers_import("Name;Email\nUrsula,ursula@example.com\n", true)
+        .await;
+
+    // separated by semicolons, the header has neither column
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .post_subscribers_import("\u{feff}Name,Email\r\nUrsula,ursula@example.com\r\n", true)
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        statuses(&app).await,
+        [("ursula@example.com".into(), "confirmed".into())]
+    );
+    assert_eq!(events(&app, "ursula@example.com").await, ["imported"]);
+}
+
+#[tokio::test]
+async fn every_row_is_reported() {
+    let app = spawn_api_app().await;
+    // ursula_le_guin@gmail.com
+    create_confirmed_subscriber(&app).await;
+    let csv = "\
+email,name,city
+ursula@example.com,Ursula,Berkeley
+not-an-email,Ursula,Portland
+
+ursula_le_guin@gmail.com,Ursula K.,Berkeley
+URSULA@example.com,Ursula,Berkeley
+le_guin@example.com,,Portland
+le_guin@mailinator.com,Le Guin,Portland
+le_guin@example.com
+";
+
+    let response = app.post_subscribers_import(csv, true).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 1);
+    assert_eq!(report["rejected"], 4);
+    assert_eq!(report["duplicates"], 2);
+    let rows: Vec<(u64, &str, &str)> = report["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| {
+            (
+                row["row"].as_u64().unwrap(),
+                row["email"].as_str().unwrap(),
+                row["status"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            (2, "ursula@example.com", "accepted"),
+            (3, "not-an-email", "rejected"),
+            (4, "ursula_le_guin@gmail.com", "duplicate"),
+            (5, "URSULA@example.com", "duplicate"),
+            (6, "le_guin@example.com", "rejected"),
+            (7, "le_guin@mailinator.com", "rejected"),
+            (8, "le_guin@example.com", "rejected"),
+        ]
+    );
+    assert!(report["rows"][5]["reason"]
+        .as_str()
+        .unwrap()
+        .contains("mailinator.com"));
+    // the subscriber already known is left as it was
+    assert_eq!(
+        statuses(&app).await,
+        [
+            ("ursula@example.com".into(), "confirmed".into()),
+            ("ursula_le_guin@gmail.com".into(), "confirmed".into()),
+        ]
+    );
+    assert_eq!(
+        events(&app, "ursula_le_guin@gmail.com").await,
+        ["subscribed", "confirmation_sent", "confirmed"]
+    );
+}
+
+#[tokio::test]
+async fn a_timezone_column_sets_the_time_zone_of_the_subscribers() {
+    let app = spawn_api_app().await;
+    let csv = "\
+email,name,timezone
+tokyo@example.com,Ursula,Asia/Tokyo
+unknown@example.com,Ursula,
+atlantis@example.com,Ursula,Europe/Atlantis
+";
+
+    let response = app.post_subscribers_import(csv, true).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 2);
+    assert_eq!(report["rows"][2]["status"], "rejected");
+    let timezones: Vec<(String, Option<String>)> =
+        sqlx::query!("SELECT email, timezone FROM subscriptions ORDER BY email")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.email, s.timezone))
+            .collect();
+    assert_eq!(
+        timezones,
+        [
+            ("tokyo@example.com".into(), Some("Asia/Tokyo".into())),
+            ("unknown@example.com".into(), None),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn a_file_without_email_and_name_columns_is_rejected() {
+    let app = spawn_api_app().await;
+
+    for csv in ["", "email,first_name\nursula@example.com,Ursula\n"] {
+        let response = app.post_subscribers_import(csv, true).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{csv:?}");
+    }
+    assert!(statuses(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn large_files_are_imported_in_batches() {
+    let app = spawn_api_app().await;
+    let mut csv = String::from("email,name\n");
+    for i in 0..2500 {
+        csv.push_str(&format!("reader{i}@example.com,Reader {i}\n"));
+    }
+
+    let response = app.post_subscribers_import(&csv, true).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 2500);
+    assert_eq!(report["rows"][2499]["row"], 2501);
+    let count = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(count, 2500);
+}
+
+#[tokio::test]
+async fn failed_confirmation_emails_are_retried() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscribers_import("email,name\nursula@example.com,Ursula\n", false)
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 1);
+    assert_eq!(report["rows"][0]["status"], "accepted");
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn the_rows_before_an_invalid_csv_error_are_imported_and_reported() {
+    let app = spawn_api_app().await;
+    let mut csv = String::from("email,name\n");
+    for i in 0..1500 {
+        csv.push_str(&format!("reader{i}@example.com,Reader {i}\n"));
+    }
+    // a quote left open, longer than a row can be
+    csv.push_str(&format!("\"{}", "a".repeat(100 * 1024)));
+
+    let response = app.post_subscribers_import(&csv, true).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert!(report["error"].as_str().unwrap().contains("quote"));
+    assert_eq!(report["accepted"], 1500);
+    assert_eq!(report["rows"].as_array().unwrap().len(), 1500);
+    let count = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(count, 1500);
+}
+
+#[tokio::test]
+async fn subscribers_are_imported_from_the_command_line() {
+    let app = spawn_api_app().await;
+    let file = std::env::temp_dir().join(format!("{}.csv", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &file,
+        "email,name\nursula@example.com,Ursula\nnot-an-email,Le Guin\n",
+    )
+    .unwrap();
+
+    let database_name = app.database_name.clone();
+    let email_server = app.email_server.uri();
+    let output = tokio::task::spawn_blocking(move || {
+        std::process::Command::new(env!("CARGO_BIN_EXE_api"))
+            .args(["import-subscribers", "--confirmed"])
+            .arg(&file)
+            .env("APP_DATABASE__DATABASE_NAME", database_name)
+            .env("APP_EMAIL_CLIENT__BASE_URL", email_server)
+            .output()
+            .unwrap()
+    })
+    .await
+    .unwrap();
+
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["accepted"], 1);
+    assert_eq!(report["rejected"], 1);
+    assert_eq!(
+        statuses(&app).await,
+        [("ursula@example.com".into(), "confirmed".into())]
+    );
+}
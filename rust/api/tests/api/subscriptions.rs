@@ -2,7 +2,11 @@ use reqwest::StatusCode;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-use crate::helpers::spawn_api_app;
+use api::subscription_cleanup::delete_stale_subscriptions;
+
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_api_app, spawn_api_app_with,
+};
 
 #[tokio::test]
 async fn subscribe_should_return_ok_for_valid_form_data() {
@@ -80,6 +84,10 @@ async fn subscribe_should_return_400_when_fields_are_present_but_invalid() {
         ("name=&email=ursula_le_guin%40gmail.com", "empty name"),
         ("name=Ursula&email=", "empty email"),
         ("name=Ursula&email=definitely-not-an-email", "invalid email"),
+        (
+            "name=Ursula&email=ursula_le_guin%40gmail.com&timezone=Europe%2FAtlantis",
+            "invalid time zone",
+        ),
     ];
 
     for (body, description) in test_cases {
@@ -143,3 +151,353 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
 
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_twice_resends_the_confirmation_email_to_a_pending_subscriber() {
+    let app = spawn_api_app_with(|c| c.subscriptions.resend_interval_seconds = 0).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let first = app.post_subscriptions(body.into()).await;
+    let second = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+
+    // both links work
+    let requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&requests[0]);
+    let second_link = app.get_confirmation_links(&requests[1]);
+    assert_ne!(first_link.html, second_link.html);
+    for link in [first_link.html, second_link.html] {
+        assert_eq!(reqwest::get(link).await.unwrap().status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn addresses_differing_by_case_are_the_same_subscriber() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40Gmail.com".into())
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn plus_tags_are_dropped_when_configured() {
+    let app = spawn_api_app_with(|c| c.subscriptions.strip_plus_tags = true).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula%2Bnews%40gmail.com".into())
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula%2Bother%40gmail.com".into())
+        .await;
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "ursula@gmail.com");
+}
+
+#[tokio::test]
+async fn plus_tags_are_kept_by_default() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula%2Bnews%40gmail.com".into())
+        .await;
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula+news@gmail.com");
+}
+
+#[tokio::test]
+async fn disposable_email_addresses_are_rejected() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40Mailinator.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn a_filled_in_honeypot_is_answered_like_a_sign_up_but_ignored() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula%40gmail.com&website=https%3A%2F%2Fspam.example.com".into(),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_throttled() {
+    let app = spawn_api_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let response = app.post_subscriptions(body.into()).await;
+    let resend = app
+        .post_resend_confirmation("email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(resend.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribing_again_when_confirmed_returns_200_without_email() {
+    let app = spawn_api_app_with(|c| c.subscriptions.resend_interval_seconds = 0).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn an_unsubscribed_subscriber_can_subscribe_again() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now(), \
+        subscribed_at = now() - interval '1 day'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // the confirmation email of the first signup was not sent recently
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '1 day'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+    assert!(saved.unsubscribed_at.is_none());
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_right_after_unsubscribing_is_kept_without_a_new_email() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now(), \
+        subscribed_at = now() - interval '1 day'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // the confirmation email of the first signup was sent less than `resend_interval` ago
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+    assert!(saved.unsubscribed_at.is_none());
+    let subscribed_events = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM subscription_events WHERE event = 'subscribed'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(subscribed_events, 2);
+}
+
+#[tokio::test]
+async fn resend_confirmation_sends_a_new_link_to_a_pending_subscriber() {
+    let app = spawn_api_app_with(|c| c.subscriptions.resend_interval_seconds = 0).await;
+    create_unconfirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_resend_confirmation("email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn resend_confirmation_does_not_reveal_unknown_or_confirmed_addresses() {
+    let app = spawn_api_app_with(|c| c.subscriptions.resend_interval_seconds = 0).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for email in ["ursula_le_guin%40gmail.com", "someone_else%40gmail.com"] {
+        let response = app.post_resend_confirmation(format!("email={email}")).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = app
+        .post_resend_confirmation("email=not-an-email".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn cleanup_deletes_expired_tokens_and_stale_pending_subscribers() {
+    let app = spawn_api_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    delete_stale_subscriptions(&app.db_pool).await.unwrap();
+
+    let n_tokens = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+    assert_eq!(n_subscribers, 0);
+}
+
+#[tokio::test]
+async fn cleanup_keeps_confirmed_subscribers_and_valid_tokens() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let body = "name=tolkien&email=tolkien%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+
+    delete_stale_subscriptions(&app.db_pool).await.unwrap();
+
+    let saved = sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[0].status, "pending_confirmation");
+    assert_eq!(saved[1].status, "confirmed");
+}
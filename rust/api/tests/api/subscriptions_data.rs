@@ -0,0 +1,280 @@
+use reqwest::StatusCode;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_api_app, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+// the link of the data request email, the last one sent
+async fn request_link(app: &TestApp, request: &str) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_data_request(format!(
+            "email=ursula_le_guin%40gmail.com&request={request}"
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    app.get_confirmation_links(email_requests.last().unwrap())
+        .html
+}
+
+async fn audit(app: &TestApp) -> Vec<(Option<Uuid>, String, Option<Uuid>)> {
+    sqlx::query!("SELECT subscriber_id, action, user_id FROM data_request_audit ORDER BY id")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|a| (a.subscriber_id, a.action, a.user_id))
+        .collect()
+}
+
+async fn subscriber_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", EMAIL)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+#[tokio::test]
+async fn the_export_link_downloads_the_data_of_the_subscriber() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    let id = subscriber_id(&app).await;
+
+    let link = request_link(&app, "export").await;
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response
+        .headers()
+        .get("Content-Disposition")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscription"]["email"], EMAIL);
+    assert_eq!(data["subscription"]["status"], "confirmed");
+    let history: Vec<_> = data["history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(history, ["subscribed", "confirmation_sent", "confirmed"]);
+    assert_eq!(data["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(data["data_requests"][0]["action"], "export_requested");
+    assert_eq!(
+        audit(&app).await,
+        [
+            (Some(id), "export_requested".into(), None),
+            (Some(id), "export_downloaded".into(), None),
+        ]
+    );
+}
+
+// published and sent to the confirmed subscribers
+async fn deliver_an_issue(app: &TestApp, title: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": title,
+            "content": {"text": "text", "html": "<p>html</p>"}
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn the_export_lists_the_issues_sent_to_the_subscriber() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    deliver_an_issue(&app, "Sent newsletter title").await;
+
+    let link = request_link(&app, "export").await;
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let data: serde_json::Value = response.json().await.unwrap();
+    let deliveries = data["deliveries"].as_array().unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["title"], "Sent newsletter title");
+    assert!(deliveries[0]["sent_at"].is_string());
+    assert!(data["pending_deliveries"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn unknown_addresses_get_the_same_answer_without_email() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_data_request("email=nobody%40example.com&request=erasure".into())
+        .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        audit(&app).await,
+        [(None, "erasure_requested".into(), None)]
+    );
+}
+
+#[tokio::test]
+async fn invalid_data_requests_are_rejected() {
+    let app = spawn_api_app().await;
+
+    for body in [
+        "email=not-an-email&request=export",
+        "email=ursula%40example.com&request=everything",
+        "email=ursula%40example.com",
+    ] {
+        let response = app.post_data_request(body.into()).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{body}");
+    }
+    assert!(audit(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn the_erasure_link_deletes_every_row_about_the_subscriber() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    let id = subscriber_id(&app).await;
+    // a delivery sent already
+    deliver_an_issue(&app, "Sent newsletter title").await;
+    // a delivery still in the queue
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "text", "html": "<p>html</p>"}
+        }))
+        .await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let queued = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(queued, 1);
+
+    let link = request_link(&app, "erasure").await;
+    // following the link only asks for a confirmation
+    let form = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(form.status(), StatusCode::OK);
+    assert!(form.text().await.unwrap().contains("Erase my data"));
+    assert_eq!(subscriber_id(&app).await, id);
+
+    let response = reqwest::Client::new()
+        .post(link.clone())
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let remaining = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT count(*) FROM subscriptions) AS "subscriptions!",
+            (SELECT count(*) FROM subscription_tokens) AS "tokens!",
+            (SELECT count(*) FROM subscription_events) AS "events!",
+            (SELECT count(*) FROM issue_delivery_queue) AS "deliveries!",
+            (SELECT count(*) FROM issue_deliveries) AS "sent!",
+            (SELECT count(*) FROM rate_limits WHERE key LIKE 'email:%') AS "rate_limits!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        (
+            remaining.subscriptions,
+            remaining.tokens,
+            remaining.events,
+            remaining.deliveries,
+            remaining.sent,
+            remaining.rate_limits
+        ),
+        (0, 0, 0, 0, 0, 0)
+    );
+
+    // erasing twice is fine, and audited once
+    let again = reqwest::Client::new().post(link).send().await.unwrap();
+    assert_eq!(again.status(), StatusCode::OK);
+    assert_eq!(
+        audit(&app).await,
+        [
+            (Some(id), "erasure_requested".into(), None),
+            (Some(id), "erasure_completed".into(), None),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn a_link_only_works_for_its_request_until_it_expires() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    let export_link = request_link(&app, "export").await;
+
+    // an export link can't erase
+    let mut erasure_link = export_link.clone();
+    erasure_link.set_path("/subscriptions/data/erase");
+    let response = reqwest::Client::new()
+        .post(erasure_link)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    app.clock.advance(chrono::Duration::hours(25));
+    let response = reqwest::get(export_link).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.unwrap().contains("expired"));
+    // still subscribed
+    subscriber_id(&app).await;
+}
+
+#[tokio::test]
+async fn the_data_of_an_erased_subscriber_is_not_found() {
+    let app = spawn_api_app().await;
+    create_confirmed_subscriber(&app).await;
+    let id = subscriber_id(&app).await;
+    let export_link = request_link(&app, "export").await;
+
+    let response = app.delete_subscriber(id).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = reqwest::get(export_link).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    // the deletion by an admin is audited too
+    assert_eq!(
+        audit(&app).await,
+        [
+            (Some(id), "export_requested".into(), None),
+            (
+                Some(id),
+                "erasure_completed".into(),
+                Some(app.test_user.user_id)
+            ),
+        ]
+    );
+}
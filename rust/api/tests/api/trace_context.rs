@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_api_app, OTLP_COLLECTOR};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_SPAN_ID: &str = "00f067aa0ba902b7";
+
+// the trace id of a `traceparent` header: `00-<trace id>-<span id>-<flags>`
+fn trace_id(traceparent: &str) -> &str {
+    traceparent.split('-').nth(1).unwrap()
+}
+
+#[tokio::test]
+async fn the_trace_of_a_request_continues_in_the_call_to_the_email_provider() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(&format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("traceparent", format!("00-{TRACE_ID}-{PARENT_SPAN_ID}-01"))
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = email_request.headers.get(&"traceparent".into()).unwrap()[0].as_str();
+    assert_eq!(trace_id(traceparent), TRACE_ID);
+    // the provider is called from a span of the application, not from the caller's
+    assert!(!traceparent.contains(PARENT_SPAN_ID));
+}
+
+#[tokio::test]
+async fn a_request_without_traceparent_starts_a_new_trace() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    for email in ["ursula%40gmail.com", "tolkien%40gmail.com"] {
+        app.post_subscriptions(format!("name=le%20guin&email={email}"))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let trace_ids: Vec<String> = requests
+        .iter()
+        .map(|request| {
+            let traceparent = request.headers.get(&"traceparent".into()).unwrap()[0].as_str();
+            trace_id(traceparent).to_owned()
+        })
+        .collect();
+    assert_eq!(trace_ids.len(), 2);
+    assert_ne!(trace_ids[0], trace_ids[1]);
+    assert_ne!(trace_ids[0], "0".repeat(32));
+}
+
+#[tokio::test]
+async fn the_spans_are_exported_to_the_otlp_collector() {
+    let app = spawn_api_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let trace_id = "0af7651916cd43dd8448eb211c80319c";
+
+    reqwest::Client::new()
+        .post(&format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("traceparent", format!("00-{trace_id}-{PARENT_SPAN_ID}-01"))
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // the spans are protobuf encoded, with the trace id as 16 raw bytes
+    let trace_id = hex::decode(trace_id).unwrap();
+    for _ in 0..50 {
+        let exported = OTLP_COLLECTOR
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .any(|request| {
+                request
+                    .body
+                    .windows(trace_id.len())
+                    .any(|bytes| bytes == trace_id.as_slice())
+            });
+        if exported {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The spans of the request were not exported");
+}
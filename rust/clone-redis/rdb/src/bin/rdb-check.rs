@@ -0,0 +1,196 @@
+use rdb::commands;
+use rdb::db::{now_ms, Db, Object};
+use rdb::resp;
+use rdb::snapshot::{self, Snapshot};
+use std::collections::BTreeMap;
+use std::process::ExitCode;
+
+/*
+Check a snapshot written by SAVE (or by Redis), or an append only file of Redis commands:
+
+cargo run --bin rdb-check -- dump.rdb
+cargo run --bin rdb-check -- appendonly.aof
+
+The kind of file is detected from its content. The commands of an AOF are replayed in memory to count
+the keys, the commands rdb doesn't support are reported. Exits with 1 when the file is not valid.
+*/
+
+// commands that act outside of the keyspace (files, other servers, the role of the server):
+// an AOF replayed to be checked must not run them
+const NOT_REPLAYED: &[&str] = &[
+    "save",
+    "bgsave",
+    "bgrewriteaof",
+    "migrate",
+    "replicaof",
+    "slaveof",
+    "failover",
+    "shutdown",
+    "config",
+    "debug",
+];
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, path] = args.as_slice() else {
+        eprintln!("usage: rdb-check <dump.rdb | appendonly.aof>");
+        return ExitCode::from(2);
+    };
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("error: can't read {path}: {e}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let valid = if bytes.starts_with(b"REDIS") {
+        check_rdb(path, &bytes)
+    } else {
+        check_aof(path, &bytes)
+    };
+    if valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn check_rdb(path: &str, bytes: &[u8]) -> bool {
+    println!("[offset 0] Checking RDB file {path}");
+    let snapshot = match snapshot::load(bytes) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            println!("--- RDB ERROR DETECTED ---");
+            println!("[offset {}] {}", e.offset, e.message);
+            return false;
+        }
+    };
+
+    let Snapshot {
+        version,
+        aux,
+        entries,
+    } = snapshot;
+    println!("[offset 9] RDB version {version}");
+    for (key, value) in &aux {
+        println!(
+            "[info] AUX FIELD {} = '{}'",
+            String::from_utf8_lossy(key),
+            String::from_utf8_lossy(value)
+        );
+    }
+    println!("[offset {}] Checksum OK", bytes.len());
+    println!("[offset {}] \\o/ RDB looks OK! \\o/", bytes.len());
+
+    let now = now_ms();
+    let expired = entries
+        .iter()
+        .filter(|entry| entry.expire_at.is_some_and(|at| at <= now))
+        .count();
+    println!("[info] {} keys read", entries.len());
+    println!(
+        "[info] {} expires",
+        entries.iter().filter(|e| e.expire_at.is_some()).count()
+    );
+    println!("[info] {expired} already expired");
+    print_stats(entries.iter().map(|entry| (&entry.object, entry.expire_at)));
+    true
+}
+
+fn check_aof(path: &str, bytes: &[u8]) -> bool {
+    let mut db = Db::new();
+    let mut input = bytes;
+    let mut commands = 0;
+    // name -> number of calls
+    let mut unsupported: BTreeMap<String, usize> = BTreeMap::new();
+    let mut not_replayed: BTreeMap<String, usize> = BTreeMap::new();
+    let mut ok_up_to = 0;
+    let mut error = None;
+    loop {
+        match resp::read_command(&mut input) {
+            Ok(Some(args)) => {
+                ok_up_to = bytes.len() - input.len();
+                let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+                if NOT_REPLAYED.contains(&name.as_str()) {
+                    *not_replayed.entry(name).or_default() += 1;
+                    continue;
+                }
+                commands += 1;
+                if let resp::Value::Error(message) = commands::execute(&mut db, &args) {
+                    if message.starts_with("ERR unknown command") {
+                        *unsupported.entry(name).or_default() += 1;
+                    }
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        }
+    }
+
+    let ok_up_to_line = bytes[..ok_up_to].iter().filter(|&&b| b == b'\n').count();
+    println!(
+        "AOF analyzed: filename={path}, size={}, ok_up_to={ok_up_to}, \
+        ok_up_to_line={ok_up_to_line}, diff={}",
+        bytes.len(),
+        bytes.len() - ok_up_to
+    );
+    if let Some(e) = error {
+        println!("Bad file format reading the append only file {path}: {e}");
+        println!(
+            "AOF {path} is not valid, the last {} bytes can't be read",
+            bytes.len() - ok_up_to
+        );
+        return false;
+    }
+    println!("AOF {path} is valid");
+
+    println!("[info] {commands} commands replayed");
+    for (name, calls) in &unsupported {
+        println!("[warning] {calls} '{name}' commands not supported by rdb, skipped");
+    }
+    for (name, calls) in &not_replayed {
+        println!("[warning] {calls} '{name}' commands with side effects, skipped");
+    }
+    // expired keys are only removed when accessed, they are not counted
+    println!("[info] {} keys", db.iter().count());
+    print_stats(db.iter().map(|(_, object, expire_at)| (object, expire_at)));
+    true
+}
+
+#[derive(Default)]
+struct TypeStats {
+    keys: usize,
+    expires: usize,
+    // members of the collections, 1 for a string
+    elements: usize,
+    // size of the DUMP payloads
+    bytes: usize,
+}
+
+fn print_stats<'a>(keys: impl Iterator<Item = (&'a Object, Option<u64>)>) {
+    let mut stats: BTreeMap<&str, TypeStats> = BTreeMap::new();
+    for (object, expire_at) in keys {
+        let type_stats = stats.entry(snapshot::type_name(object)).or_default();
+        type_stats.keys += 1;
+        type_stats.expires += expire_at.is_some() as usize;
+        type_stats.elements += match object {
+            Object::SortedSet(set) => set.len(),
+            _ => 1,
+        };
+        type_stats.bytes += snapshot::dump(object).len();
+    }
+
+    println!(
+        "{:<10}{:>10}{:>10}{:>12}{:>14}",
+        "type", "keys", "expires", "elements", "dump bytes"
+    );
+    for (name, s) in stats {
+        println!(
+            "{name:<10}{:>10}{:>10}{:>12}{:>14}",
+            s.keys, s.expires, s.elements, s.bytes
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use rdb::conformance::{self, Golden, Script, Server, Target};
+use rdb::db::Db;
+use rdb::server;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/*
+Replay the scripts (a directory = all its .redis files) against rdb and compare the replies with the
+expected ones, the .golden file next to each script:
+
+cargo run --bin rdb-conformance -- golden
+
+or with the replies of a live Redis (scripts/redis-docker.sh), and write them as the new golden files:
+
+cargo run --bin rdb-conformance -- --reference 127.0.0.1:6379 golden
+cargo run --bin rdb-conformance -- --reference 127.0.0.1:6379 --record golden
+
+rdb runs in-process unless --rdb <host:port> is given. The servers are flushed before each script.
+*/
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let mut rdb_addr = None;
+    let mut reference_addr = None;
+    let mut record = false;
+    let mut scripts = vec![];
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--rdb" | "--reference" => {
+                let Some(addr) = args.get(i + 1).cloned() else {
+                    return usage();
+                };
+                if args[i] == "--rdb" {
+                    rdb_addr = Some(addr);
+                } else {
+                    reference_addr = Some(addr);
+                }
+                i += 1;
+            }
+            "--record" => record = true,
+            arg if arg.starts_with("--") => return usage(),
+            path => match script_paths(Path::new(path)) {
+                Ok(paths) => scripts.extend(paths),
+                Err(e) => {
+                    eprintln!("error: {path}: {e}");
+                    return ExitCode::from(2);
+                }
+            },
+        }
+        i += 1;
+    }
+    if scripts.is_empty() || (record && reference_addr.is_none()) {
+        return usage();
+    }
+
+    let result = if record {
+        record_golden_files(reference_addr.as_deref().unwrap_or_default(), &scripts)
+    } else {
+        check(rdb_addr, reference_addr.as_deref(), &scripts)
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn script_paths(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "redis") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn record_golden_files(reference_addr: &str, scripts: &[PathBuf]) -> std::io::Result<bool> {
+    let mut reference = Server::connect(reference_addr)?;
+    for path in scripts {
+        let script = Script::load(path)?;
+        reference.reset()?;
+        let golden = Golden::record(&script, &mut reference)?;
+        let golden_path = path.with_extension("golden");
+        std::fs::write(&golden_path, golden)?;
+        println!(
+            "recorded {} replies to {}",
+            script.commands.len(),
+            golden_path.display()
+        );
+    }
+    Ok(true)
+}
+
+fn check(
+    rdb_addr: Option<String>,
+    reference_addr: Option<&str>,
+    scripts: &[PathBuf],
+) -> std::io::Result<bool> {
+    let rdb_addr = match rdb_addr {
+        Some(addr) => addr,
+        None => start_rdb()?,
+    };
+    let mut rdb = Server::connect(rdb_addr)?;
+    let mut reference = reference_addr.map(Server::connect).transpose()?;
+
+    let mut passed = 0;
+    for path in scripts {
+        let script = Script::load(path)?;
+        rdb.reset()?;
+        let result = match &mut reference {
+            Some(reference) => {
+                reference.reset()?;
+                conformance::replay(&script, &mut rdb, reference)
+            }
+            None => replay_golden_file(&script, path, &mut rdb)?,
+        };
+        match result {
+            Ok(commands) => {
+                passed += 1;
+                println!("ok   {} ({commands} commands)", script.name);
+            }
+            Err(e) => println!("FAIL {e}"),
+        }
+    }
+    println!("{passed}/{} scripts passed", scripts.len());
+    Ok(passed == scripts.len())
+}
+
+fn replay_golden_file(
+    script: &Script,
+    path: &Path,
+    rdb: &mut dyn Target,
+) -> std::io::Result<Result<usize, conformance::ReplayError>> {
+    let mut golden = Golden::load(&path.with_extension("golden"))?;
+    let result = conformance::replay(script, rdb, &mut golden);
+    if result.is_ok() && golden.remaining() > 0 {
+        eprintln!(
+            "warning: {} more replies than commands in the golden file of {}, write it again",
+            golden.remaining(),
+            script.name
+        );
+    }
+    Ok(result)
+}
+
+// an empty rdb on a free port
+fn start_rdb() -> std::io::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?.to_string();
+    thread::spawn(move || server::serve(listener, Arc::new(Mutex::new(Db::new()))));
+    Ok(addr)
+}
+
+fn usage() -> ExitCode {
+    eprintln!(
+        "usage: rdb-conformance [--rdb <host:port>] [--reference <host:port> [--record]] \
+        <script.redis | directory>..."
+    );
+    ExitCode::from(2)
+}
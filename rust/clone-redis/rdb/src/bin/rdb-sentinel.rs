@@ -0,0 +1,73 @@
+use rdb::sentinel::{self, Config};
+use rdb::server;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+/*
+Monitor the primary `mymaster` with 3 sentinels, 2 of them must agree it is down:
+
+cargo run --bin rdb-sentinel -- --port 26379 --monitor mymaster 127.0.0.1 6379 2 \
+    --replica 127.0.0.1:6380 --sentinel 127.0.0.1:26380 --sentinel 127.0.0.1:26381
+
+then ask any of them for the current primary:
+
+redis-cli -p 26379 SENTINEL get-master-addr-by-name mymaster
+*/
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let mut port = "26379".to_string();
+    let mut monitor = None;
+    let mut replicas = vec![];
+    let mut sentinels = vec![];
+    let mut down_after = Duration::from_millis(5000);
+
+    let mut i = 1;
+    while i < args.len() {
+        let value = |n: usize| args.get(i + n).cloned().unwrap_or_else(|| usage());
+        match args[i].as_str() {
+            "--port" => port = value(1),
+            "--monitor" => {
+                let quorum = value(4).parse().unwrap_or_else(|_| usage());
+                monitor = Some((value(1), format!("{}:{}", value(2), value(3)), quorum));
+                i += 3;
+            }
+            "--replica" => replicas.push(value(1)),
+            "--sentinel" => sentinels.push(value(1)),
+            "--down-after-ms" => {
+                down_after = Duration::from_millis(value(1).parse().unwrap_or_else(|_| usage()))
+            }
+            _ => usage(),
+        }
+        i += 2;
+    }
+    let Some((name, primary, quorum)) = monitor else {
+        usage()
+    };
+
+    let sentinel = sentinel::spawn(Config {
+        name,
+        primary,
+        quorum,
+        replicas,
+        sentinels,
+        down_after,
+    });
+
+    let listener = TcpListener::bind(format!("127.0.0.1:{port}"))?;
+    eprintln!(
+        "info: rdb-sentinel {} listening on {}",
+        sentinel.id(),
+        listener.local_addr()?
+    );
+    let handler = Arc::clone(&sentinel);
+    server::serve_with(listener, move |args| handler.handle(args))
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: rdb-sentinel [--port <port>] --monitor <name> <host> <port> <quorum> \
+        [--replica <host:port>]... [--sentinel <host:port>]... [--down-after-ms <ms>]"
+    );
+    std::process::exit(2)
+}
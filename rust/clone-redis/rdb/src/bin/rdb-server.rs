@@ -0,0 +1,54 @@
+use rdb::db::Db;
+use rdb::server;
+use rdb::snapshot;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/*
+Start the server (default port = 6379):
+
+cargo run --bin rdb-server -- --port 6380
+
+and talk to it with redis-cli, or the rdb client.
+
+SAVE writes the keys to dump.rdb, or to --dbfilename <path>, that is loaded when the server starts.
+*/
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let mut port = "6379".to_string();
+    let mut dbfilename = None;
+
+    let mut i = 1;
+    while i < args.len() {
+        let value = args.get(i + 1).cloned().unwrap_or_else(|| usage());
+        match args[i].as_str() {
+            "--port" => port = value,
+            "--dbfilename" => dbfilename = Some(PathBuf::from(value)),
+            _ => usage(),
+        }
+        i += 2;
+    }
+
+    let path = dbfilename
+        .clone()
+        .unwrap_or_else(|| snapshot::DEFAULT_DBFILENAME.into());
+    let mut db = if path.exists() {
+        let db = snapshot::load_file(&path)?;
+        eprintln!("info: {} keys loaded from {}", db.len(), path.display());
+        db
+    } else {
+        Db::new()
+    };
+    db.dbfilename = dbfilename;
+
+    let listener = TcpListener::bind(format!("127.0.0.1:{port}"))?;
+    eprintln!("info: rdb listening on {}", listener.local_addr()?);
+
+    server::serve(listener, Arc::new(Mutex::new(db)))
+}
+
+fn usage() -> ! {
+    eprintln!("usage: rdb-server [--port <port>] [--dbfilename <path>]");
+    std::process::exit(2)
+}
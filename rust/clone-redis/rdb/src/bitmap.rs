@@ -0,0 +1,262 @@
+// Bit operations on string values = https://redis.io/docs/data-types/bitmaps/
+// Bit 0 is the most significant bit of the first byte, bytes beyond the end of the string read as zero.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+/// Integer type of a BITFIELD operation, like `i5` or `u8`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+impl FieldType {
+    pub fn parse(s: &[u8]) -> Option<FieldType> {
+        let signed = match s.first()?.to_ascii_lowercase() {
+            b'i' => true,
+            b'u' => false,
+            _ => return None,
+        };
+        let bits: u32 = std::str::from_utf8(&s[1..]).ok()?.parse().ok()?;
+        let max_bits = if signed { 64 } else { 63 };
+        (1..=max_bits)
+            .contains(&bits)
+            .then_some(FieldType { signed, bits })
+    }
+
+    fn range(&self) -> (i128, i128) {
+        if self.signed {
+            (-(1 << (self.bits - 1)), (1 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1 << self.bits) - 1)
+        }
+    }
+
+    /// Fits `value` in the type according to the overflow policy, `None` means the operation fails.
+    pub fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        let (min, max) = self.range();
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            Overflow::Fail => None,
+            Overflow::Sat => Some(value.clamp(min, max) as i64),
+            Overflow::Wrap => {
+                let wrapped = value.rem_euclid(1 << self.bits);
+                if self.signed && wrapped > max {
+                    Some((wrapped - (1 << self.bits)) as i64)
+                } else {
+                    Some(wrapped as i64)
+                }
+            }
+        }
+    }
+}
+
+pub fn get_bit(bytes: &[u8], offset: u64) -> u8 {
+    let byte = (offset >> 3) as usize;
+    match bytes.get(byte) {
+        Some(b) => (b >> (7 - (offset & 7))) & 1,
+        None => 0,
+    }
+}
+
+/// Sets the bit at `offset`, growing the string when needed, returns the previous bit.
+pub fn set_bit(bytes: &mut Vec<u8>, offset: u64, bit: u8) -> u8 {
+    let byte = (offset >> 3) as usize;
+    if byte >= bytes.len() {
+        bytes.resize(byte + 1, 0);
+    }
+    let shift = 7 - (offset & 7);
+    let previous = (bytes[byte] >> shift) & 1;
+    if bit == 1 {
+        bytes[byte] |= 1 << shift;
+    } else {
+        bytes[byte] &= !(1 << shift);
+    }
+    previous
+}
+
+/// Converts a (possibly negative) `start`..=`end` range into absolute positions,
+/// `None` when the range is empty. `len` is expressed in the same unit as the range.
+pub fn normalize_range(start: i64, end: i64, len: i64) -> Option<(u64, u64)> {
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { (len + end).max(0) } else { end };
+    let end = end.min(len - 1);
+    (start <= end).then_some((start as u64, end as u64))
+}
+
+/// Number of set bits between bit positions `start` and `end` (inclusive).
+pub fn count(bytes: &[u8], start: u64, end: u64) -> u64 {
+    let (first, last) = ((start >> 3) as usize, (end >> 3) as usize);
+    let mut total: u64 = bytes[first..=last]
+        .iter()
+        .map(|b| b.count_ones() as u64)
+        .sum();
+    // exclude the bits of the first and last byte that are out of range
+    let head_mask = !(0xffu8 >> (start & 7));
+    let tail_mask = (0xffu16 >> ((end & 7) + 1)) as u8;
+    total -= (bytes[first] & head_mask).count_ones() as u64;
+    total -= (bytes[last] & tail_mask).count_ones() as u64;
+    total
+}
+
+/// Position of the first bit set to `bit` between bit positions `start` and `end` (inclusive).
+pub fn position(bytes: &[u8], bit: u8, start: u64, end: u64) -> Option<u64> {
+    let skip = if bit == 1 { 0x00 } else { 0xff };
+    let mut offset = start;
+    while offset <= end {
+        // skip whole bytes that can't contain the bit we're looking for
+        if offset & 7 == 0 && offset + 7 <= end {
+            let byte = *bytes.get((offset >> 3) as usize).unwrap_or(&0);
+            if byte == skip {
+                offset += 8;
+                continue;
+            }
+        }
+        if get_bit(bytes, offset) == bit {
+            return Some(offset);
+        }
+        offset += 1;
+    }
+    None
+}
+
+pub fn bitop(op: BitOp, sources: &[&[u8]]) -> Vec<u8> {
+    let len = sources.iter().map(|s| s.len()).max().unwrap_or(0);
+    (0..len)
+        .map(|i| {
+            let mut bytes = sources.iter().map(|s| *s.get(i).unwrap_or(&0));
+            let first = bytes.next().unwrap_or(0);
+            match op {
+                BitOp::And => bytes.fold(first, |acc, b| acc & b),
+                BitOp::Or => bytes.fold(first, |acc, b| acc | b),
+                BitOp::Xor => bytes.fold(first, |acc, b| acc ^ b),
+                BitOp::Not => !first,
+            }
+        })
+        .collect()
+}
+
+/// Reads the integer of type `field` stored at bit `offset`.
+pub fn get_field(bytes: &[u8], field: FieldType, offset: u64) -> i64 {
+    let mut value: u64 = 0;
+    for i in 0..field.bits as u64 {
+        value = (value << 1) | get_bit(bytes, offset + i) as u64;
+    }
+    if field.signed && field.bits < 64 && value & (1 << (field.bits - 1)) != 0 {
+        // sign extension
+        value |= u64::MAX << field.bits;
+    }
+    value as i64
+}
+
+/// Writes the low `field.bits` bits of `value` at bit `offset`, growing the string when needed.
+pub fn set_field(bytes: &mut Vec<u8>, field: FieldType, offset: u64, value: i64) {
+    let value = value as u64;
+    for i in 0..field.bits as u64 {
+        let bit = (value >> (field.bits as u64 - 1 - i)) & 1;
+        set_bit(bytes, offset + i, bit as u8);
+    }
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::{
+        bitop, count, get_field, normalize_range, position, set_bit, set_field, BitOp, FieldType,
+        Overflow,
+    };
+
+    #[test]
+    fn test_set_bit_grows_string() {
+        let mut bytes = vec![];
+
+        assert_eq!(0, set_bit(&mut bytes, 7, 1));
+        assert_eq!(1, set_bit(&mut bytes, 7, 1));
+        set_bit(&mut bytes, 9, 1);
+
+        assert_eq!(vec![0b0000_0001, 0b0100_0000], bytes);
+    }
+
+    #[test]
+    fn test_count_with_bit_range() {
+        let bytes = b"foobar";
+
+        assert_eq!(26, count(bytes, 0, 47));
+        assert_eq!(6, count(bytes, 8, 15));
+        // BITCOUNT key 5 30 BIT
+        assert_eq!(17, count(bytes, 5, 30));
+    }
+
+    #[test]
+    fn test_normalize_negative_range() {
+        assert_eq!(Some((1, 5)), normalize_range(1, -1, 6));
+        assert_eq!(Some((0, 5)), normalize_range(-100, 100, 6));
+        assert_eq!(None, normalize_range(4, 2, 6));
+        assert_eq!(None, normalize_range(0, -1, 0));
+    }
+
+    #[test]
+    fn test_position() {
+        let bytes = [0xff, 0xf0, 0x00];
+
+        assert_eq!(Some(12), position(&bytes, 0, 0, 23));
+        assert_eq!(None, position(&bytes, 1, 16, 23));
+    }
+
+    #[test]
+    fn test_bitop() {
+        let a: &[u8] = b"foobar";
+        let b: &[u8] = b"abcdef";
+
+        assert_eq!(b"`bc`ab".to_vec(), bitop(BitOp::And, &[a, b]));
+        assert_eq!(vec![0x99, 0x90], bitop(BitOp::Not, &[&[0x66, 0x6f]]));
+        assert_eq!(
+            vec![0x07, 0x0d, 0x0c, 0x06, 0x04, 0x14],
+            bitop(BitOp::Xor, &[a, b])
+        );
+    }
+
+    #[test]
+    fn test_bitfield_fit() {
+        let u8_field = FieldType::parse(b"u8").unwrap();
+        let i8_field = FieldType::parse(b"i8").unwrap();
+
+        assert_eq!(Some(44), u8_field.fit(300, Overflow::Wrap));
+        assert_eq!(Some(255), u8_field.fit(300, Overflow::Sat));
+        assert_eq!(None, u8_field.fit(300, Overflow::Fail));
+        assert_eq!(Some(-128), i8_field.fit(128, Overflow::Wrap));
+        assert_eq!(Some(-128), i8_field.fit(-1000, Overflow::Sat));
+        assert_eq!(None, FieldType::parse(b"u64"));
+    }
+
+    #[test]
+    fn test_bitfield_get_set() {
+        let mut bytes = vec![];
+        let i5 = FieldType::parse(b"i5").unwrap();
+
+        set_field(&mut bytes, i5, 3, -3);
+
+        assert_eq!(-3, get_field(&bytes, i5, 3));
+        assert_eq!(0b0001_1101, bytes[0]);
+        assert_eq!(29, get_field(&bytes, FieldType::parse(b"u5").unwrap(), 3));
+    }
+}
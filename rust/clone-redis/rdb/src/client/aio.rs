@@ -0,0 +1,336 @@
+// Async client and pool on top of tokio, same API as the blocking ones
+
+use std::future::Future;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::client::{
+    connection_closed, encode_command, into_result, parse_message, FromValue, Message, Result,
+};
+use crate::resp::{self, Header, Value};
+
+pub struct AsyncClient {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    broken: bool,
+}
+
+impl AsyncClient {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Ok(AsyncClient {
+            reader: BufReader::new(reader),
+            writer,
+            broken: false,
+        })
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    pub async fn query<T: FromValue>(&mut self, args: &[&[u8]]) -> Result<T> {
+        // a query dropped between the write and the end of its reply (a timeout, a cancelled
+        // task) would leave that reply for the next query: the connection is broken until then
+        self.broken = true;
+        self.send(args).await?;
+        let reply = self.read_reply().await?;
+        self.broken = false;
+        T::from_value(into_result(reply)?)
+    }
+
+    async fn send(&mut self, args: &[&[u8]]) -> Result<()> {
+        self.writer.write_all(&encode_command(args)).await?;
+        Ok(())
+    }
+
+    async fn read_reply(&mut self) -> Result<Value> {
+        read_value(&mut self.reader)
+            .await?
+            .ok_or_else(connection_closed)
+    }
+
+    pub async fn ping(&mut self) -> Result<String> {
+        self.query(&[b"PING"]).await
+    }
+
+    pub async fn get<T: FromValue>(&mut self, key: impl AsRef<[u8]>) -> Result<T> {
+        self.query(&[b"GET", key.as_ref()]).await
+    }
+
+    pub async fn set(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<()> {
+        self.query(&[b"SET", key.as_ref(), value.as_ref()]).await
+    }
+
+    pub async fn set_ex(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+        seconds: u64,
+    ) -> Result<()> {
+        let seconds = seconds.to_string();
+        self.query(&[
+            b"SET",
+            key.as_ref(),
+            value.as_ref(),
+            b"EX",
+            seconds.as_bytes(),
+        ])
+        .await
+    }
+
+    pub async fn del(&mut self, key: impl AsRef<[u8]>) -> Result<i64> {
+        self.query(&[b"DEL", key.as_ref()]).await
+    }
+
+    pub async fn incr(&mut self, key: impl AsRef<[u8]>) -> Result<i64> {
+        self.query(&[b"INCR", key.as_ref()]).await
+    }
+
+    pub async fn hgetall<T: FromValue>(&mut self, key: impl AsRef<[u8]>) -> Result<T> {
+        self.query(&[b"HGETALL", key.as_ref()]).await
+    }
+
+    pub async fn lpush<V: AsRef<[u8]>>(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        values: &[V],
+    ) -> Result<i64> {
+        let mut args = vec![&b"LPUSH"[..], key.as_ref()];
+        args.extend(values.iter().map(|value| value.as_ref()));
+        self.query(&args).await
+    }
+
+    pub async fn subscribe(mut self, channels: &[&str]) -> Result<AsyncSubscription> {
+        let mut args = vec![&b"SUBSCRIBE"[..]];
+        args.extend(channels.iter().map(|channel| channel.as_bytes()));
+        self.send(&args).await?;
+        Ok(AsyncSubscription { client: self })
+    }
+}
+
+pub struct AsyncSubscription {
+    client: AsyncClient,
+}
+
+impl AsyncSubscription {
+    pub async fn next_message(&mut self) -> Result<Message> {
+        loop {
+            let reply = self.client.read_reply().await?;
+            if let Some(message) = parse_message(reply)? {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+// the async twin of `resp::read_value`, boxed because it is recursive
+fn read_value<'a, R>(
+    reader: &'a mut R,
+) -> Pin<Box<dyn Future<Output = io::Result<Option<Value>>> + Send + 'a>>
+where
+    R: AsyncBufRead + Unpin + Send,
+{
+    Box::pin(async move {
+        let mut line = Vec::new();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(None);
+        }
+        if !line.ends_with(b"\r\n") {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        line.truncate(line.len() - 2);
+
+        let value = match resp::parse_header(&line)? {
+            Header::Value(value) => value,
+            Header::Bulk(len) => {
+                let mut bytes = Vec::with_capacity((len + 2).min(resp::INITIAL_CAPACITY));
+                (&mut *reader)
+                    .take(len as u64 + 2)
+                    .read_to_end(&mut bytes)
+                    .await?;
+                resp::bulk_from_crlf_terminated(len, bytes)?
+            }
+            Header::Array(len) => {
+                let mut items = Vec::with_capacity(len.min(resp::INITIAL_CAPACITY));
+                for _ in 0..len {
+                    let item = read_value(reader)
+                        .await?
+                        .ok_or_else(|| resp::protocol_error("unexpected end of array"))?;
+                    items.push(item);
+                }
+                Value::Array(items)
+            }
+        };
+        Ok(Some(value))
+    })
+}
+
+pub struct AsyncPool {
+    addr: String,
+    idle: Mutex<Vec<AsyncClient>>,
+    // one permit per connection that can be open
+    permits: Semaphore,
+}
+
+impl AsyncPool {
+    pub fn new(addr: impl Into<String>, max_size: usize) -> Self {
+        AsyncPool {
+            addr: addr.into(),
+            idle: Mutex::new(vec![]),
+            permits: Semaphore::new(max_size.max(1)),
+        }
+    }
+
+    pub async fn get(&self) -> Result<AsyncPooledClient<'_>> {
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
+        let idle = self.idle.lock().unwrap().pop();
+        let client = match idle {
+            Some(client) => client,
+            None => AsyncClient::connect(self.addr.as_str()).await?,
+        };
+        Ok(AsyncPooledClient {
+            pool: self,
+            client: Some(client),
+            _permit: permit,
+        })
+    }
+}
+
+pub struct AsyncPooledClient<'a> {
+    pool: &'a AsyncPool,
+    client: Option<AsyncClient>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl Deref for AsyncPooledClient<'_> {
+    type Target = AsyncClient;
+
+    fn deref(&self) -> &AsyncClient {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for AsyncPooledClient<'_> {
+    fn deref_mut(&mut self) -> &mut AsyncClient {
+        self.client.as_mut().unwrap()
+    }
+}
+
+impl Drop for AsyncPooledClient<'_> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take().filter(|client| !client.is_broken()) {
+            self.pool.idle.lock().unwrap().push(client);
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::{read_value, AsyncPool};
+    use crate::client::RdbError;
+    use crate::db::Db;
+    use crate::resp::{self, Value};
+    use crate::server;
+    use std::io::{BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    fn start_rdb_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let db = Arc::new(Mutex::new(Db::new()));
+        thread::spawn(move || server::serve(listener, db));
+        addr
+    }
+
+    // replies to every command with its last argument, after a delay
+    fn start_slow_echo_server(delay: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(&stream);
+                    while let Ok(Some(args)) = resp::read_command(&mut reader) {
+                        thread::sleep(delay);
+                        let reply = Value::bulk(args.last().cloned().unwrap_or_default());
+                        if (&stream).write_all(&reply.encode()).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_read_value() {
+        let mut input = &b"*2\r\n$3\r\nrdb\r\n:42\r\n"[..];
+
+        let value = read_value(&mut input).await.unwrap();
+
+        assert_eq!(
+            Some(Value::Array(vec![Value::bulk("rdb"), Value::Integer(42)])),
+            value
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pooled_async_clients() {
+        let pool = AsyncPool::new(start_rdb_server(), 2);
+
+        let mut client = pool.get().await.unwrap();
+        client.set("greeting", "hello").await.unwrap();
+        assert_eq!(
+            Some("hello".to_string()),
+            client.get::<Option<String>>("greeting").await.unwrap()
+        );
+        drop(client);
+
+        let mut client = pool.get().await.unwrap();
+        assert_eq!(None, client.get::<Option<String>>("missing").await.unwrap());
+        assert!(matches!(
+            client.query::<Value>(&[b"NOPE"]).await,
+            Err(RdbError::Server(msg)) if msg.starts_with("ERR unknown command 'NOPE'")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_a_cancelled_query_does_not_leak_its_reply_to_the_next_borrower() {
+        let pool = AsyncPool::new(start_slow_echo_server(Duration::from_millis(200)), 1);
+
+        let mut client = pool.get().await.unwrap();
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(50),
+            client.get::<Option<String>>("first"),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        assert!(client.is_broken());
+        drop(client);
+
+        let mut client = pool.get().await.unwrap();
+        assert_eq!(
+            Some("second".to_string()),
+            client.get::<Option<String>>("second").await.unwrap()
+        );
+        assert!(!client.is_broken());
+    }
+}
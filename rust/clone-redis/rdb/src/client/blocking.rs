@@ -0,0 +1,213 @@
+// Blocking client, one TCP connection
+
+use std::io::{BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use crate::client::{
+    connection_closed, encode_command, into_result, parse_message, FromValue, Message, RdbError,
+    Result,
+};
+use crate::resp::{self, Value};
+use crate::sentinel;
+
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    // an I/O error may leave a reply unread: the connection can't be reused
+    broken: bool,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let writer = TcpStream::connect(addr)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Client {
+            reader,
+            writer,
+            broken: false,
+        })
+    }
+
+    /// Connects to the current primary `name`, as known by the sentinels (`host:port`).
+    pub fn connect_via_sentinels(sentinels: &[&str], name: &str) -> Result<Self> {
+        Client::connect(sentinel::discover_primary(sentinels, name)?)
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Sends any command and converts its reply, error replies are returned as `RdbError::Server`.
+    pub fn query<T: FromValue>(&mut self, args: &[&[u8]]) -> Result<T> {
+        let reply = self.send(args).and_then(|_| self.read_reply());
+        if let Err(RdbError::Io(_)) = reply {
+            self.broken = true;
+        }
+        T::from_value(into_result(reply?)?)
+    }
+
+    fn send(&mut self, args: &[&[u8]]) -> Result<()> {
+        self.writer.write_all(&encode_command(args))?;
+        Ok(())
+    }
+
+    fn read_reply(&mut self) -> Result<Value> {
+        resp::read_value(&mut self.reader)?.ok_or_else(connection_closed)
+    }
+
+    pub fn ping(&mut self) -> Result<String> {
+        self.query(&[b"PING"])
+    }
+
+    pub fn get<T: FromValue>(&mut self, key: impl AsRef<[u8]>) -> Result<T> {
+        self.query(&[b"GET", key.as_ref()])
+    }
+
+    pub fn set(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<()> {
+        self.query(&[b"SET", key.as_ref(), value.as_ref()])
+    }
+
+    /// SET with an expiration in seconds.
+    pub fn set_ex(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+        seconds: u64,
+    ) -> Result<()> {
+        let seconds = seconds.to_string();
+        self.query(&[
+            b"SET",
+            key.as_ref(),
+            value.as_ref(),
+            b"EX",
+            seconds.as_bytes(),
+        ])
+    }
+
+    pub fn del(&mut self, key: impl AsRef<[u8]>) -> Result<i64> {
+        self.query(&[b"DEL", key.as_ref()])
+    }
+
+    pub fn incr(&mut self, key: impl AsRef<[u8]>) -> Result<i64> {
+        self.query(&[b"INCR", key.as_ref()])
+    }
+
+    pub fn hgetall<T: FromValue>(&mut self, key: impl AsRef<[u8]>) -> Result<T> {
+        self.query(&[b"HGETALL", key.as_ref()])
+    }
+
+    /// Returns the length of the list after the push.
+    pub fn lpush<V: AsRef<[u8]>>(&mut self, key: impl AsRef<[u8]>, values: &[V]) -> Result<i64> {
+        let mut args = vec![&b"LPUSH"[..], key.as_ref()];
+        args.extend(values.iter().map(|value| value.as_ref()));
+        self.query(&args)
+    }
+
+    /// Turns the connection into a subscription, only pub/sub commands can be sent afterwards.
+    pub fn subscribe(mut self, channels: &[&str]) -> Result<Subscription> {
+        let mut args = vec![&b"SUBSCRIBE"[..]];
+        args.extend(channels.iter().map(|channel| channel.as_bytes()));
+        self.send(&args)?;
+        Ok(Subscription { client: self })
+    }
+}
+
+pub struct Subscription {
+    client: Client,
+}
+
+impl Subscription {
+    /// Blocks until the next message is published on one of the channels.
+    pub fn next_message(&mut self) -> Result<Message> {
+        loop {
+            let reply = self.client.read_reply()?;
+            if let Some(message) = parse_message(reply)? {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::Client;
+    use crate::client::{Message, RdbError};
+    use crate::db::Db;
+    use crate::resp::{self, Value};
+    use crate::server;
+    use std::io::{BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn start_rdb_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let db = Arc::new(Mutex::new(Db::new()));
+        thread::spawn(move || server::serve(listener, db));
+        addr
+    }
+
+    #[test]
+    fn test_typed_replies() {
+        let mut client = Client::connect(start_rdb_server()).unwrap();
+
+        assert_eq!("PONG", client.ping().unwrap());
+        client.set("greeting", "hello").unwrap();
+        assert_eq!("hello", client.get::<String>("greeting").unwrap());
+        assert_eq!(None, client.get::<Option<Vec<u8>>>("missing").unwrap());
+        assert_eq!(1, client.del("greeting").unwrap());
+        assert!(matches!(
+            client.get::<String>("greeting"),
+            Err(RdbError::Type {
+                expected: "a string",
+                got: Value::Null
+            })
+        ));
+        assert!(matches!(
+            client.query::<()>(&[b"GET"]),
+            Err(RdbError::Server(msg)) if msg == "ERR wrong number of arguments for 'get' command"
+        ));
+        // the connection is still usable after an error reply
+        assert_eq!("PONG", client.ping().unwrap());
+    }
+
+    #[test]
+    fn test_subscription() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let command = resp::read_command(&mut BufReader::new(&stream)).unwrap();
+            assert_eq!(Some(vec![b"SUBSCRIBE".to_vec(), b"news".to_vec()]), command);
+            let replies = [
+                Value::Array(vec![
+                    Value::bulk("subscribe"),
+                    Value::bulk("news"),
+                    Value::Integer(1),
+                ]),
+                Value::Array(vec![
+                    Value::bulk("message"),
+                    Value::bulk("news"),
+                    Value::bulk("hello"),
+                ]),
+            ];
+            for reply in replies {
+                (&stream).write_all(&reply.encode()).unwrap();
+            }
+        });
+
+        let mut subscription = Client::connect(addr).unwrap().subscribe(&["news"]).unwrap();
+
+        let message = subscription.next_message().unwrap();
+        assert_eq!(
+            Message {
+                channel: "news".into(),
+                payload: b"hello".to_vec()
+            },
+            message
+        );
+        assert_eq!("hello", message.payload::<String>().unwrap());
+    }
+}
@@ -0,0 +1,181 @@
+// Conversions of the RESP replies to Rust types
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::client::{RdbError, Result};
+use crate::resp::Value;
+
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self>;
+}
+
+fn type_error<T>(expected: &'static str, got: Value) -> Result<T> {
+    Err(RdbError::Type { expected, got })
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self> {
+        Ok(value)
+    }
+}
+
+// any reply will do, like the +OK of SET
+impl FromValue for () {
+    fn from_value(_value: Value) -> Result<Self> {
+        Ok(())
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::BulkString(bytes) => Ok(bytes),
+            Value::SimpleString(s) => Ok(s.into_bytes()),
+            value => type_error("a string", value),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::BulkString(bytes) => String::from_utf8(bytes)
+                .or_else(|e| type_error("a UTF-8 string", Value::BulkString(e.into_bytes()))),
+            Value::SimpleString(s) => Ok(s),
+            Value::Integer(n) => Ok(n.to_string()),
+            value => type_error("a string", value),
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Integer(n) => Ok(n),
+            Value::BulkString(ref bytes) => {
+                match std::str::from_utf8(bytes).ok().and_then(|s| s.parse().ok()) {
+                    Some(n) => Ok(n),
+                    None => type_error("an integer", value),
+                }
+            }
+            value => type_error("an integer", value),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Integer(n) => Ok(n as f64),
+            Value::BulkString(ref bytes) => {
+                match std::str::from_utf8(bytes).ok().and_then(|s| s.parse().ok()) {
+                    Some(n) => Ok(n),
+                    None => type_error("a float", value),
+                }
+            }
+            value => type_error("a float", value),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Integer(n) => Ok(n != 0),
+            Value::SimpleString(ref s) if s == "OK" => Ok(true),
+            Value::Null | Value::NullArray => Ok(false),
+            value => type_error("a boolean", value),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Null | Value::NullArray => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Array(items) => items.into_iter().map(T::from_value).collect(),
+            Value::NullArray => Ok(vec![]),
+            value => type_error("an array", value),
+        }
+    }
+}
+
+// flat array of field, value, field, value... like the reply of HGETALL
+impl<K: FromValue + Eq + Hash, V: FromValue> FromValue for HashMap<K, V> {
+    fn from_value(value: Value) -> Result<Self> {
+        let items = match value {
+            Value::Array(items) if items.len() % 2 == 0 => items,
+            Value::NullArray => vec![],
+            value => return type_error("an array of field value pairs", value),
+        };
+        let mut map = HashMap::with_capacity(items.len() / 2);
+        let mut items = items.into_iter();
+        while let (Some(field), Some(value)) = (items.next(), items.next()) {
+            map.insert(K::from_value(field)?, V::from_value(value)?);
+        }
+        Ok(map)
+    }
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::FromValue;
+    use crate::client::RdbError;
+    use crate::resp::Value;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_scalars() {
+        assert_eq!(42, i64::from_value(Value::Integer(42)).unwrap());
+        assert_eq!(42, i64::from_value(Value::bulk("42")).unwrap());
+        assert_eq!(1.5, f64::from_value(Value::bulk("1.5")).unwrap());
+        assert_eq!("OK", String::from_value(Value::ok()).unwrap());
+        assert!(bool::from_value(Value::Integer(1)).unwrap());
+        assert!(matches!(
+            i64::from_value(Value::bulk("abc")),
+            Err(RdbError::Type {
+                expected: "an integer",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_option_and_vec() {
+        assert_eq!(None, Option::<String>::from_value(Value::Null).unwrap());
+        assert_eq!(
+            vec![Some("a".to_string()), None],
+            Vec::<Option<String>>::from_value(Value::Array(vec![Value::bulk("a"), Value::Null]))
+                .unwrap()
+        );
+        assert_eq!(
+            b"raw".to_vec(),
+            Vec::<u8>::from_value(Value::bulk("raw")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hashmap_from_flat_array() {
+        let value = Value::Array(vec![
+            Value::bulk("name"),
+            Value::bulk("rdb"),
+            Value::bulk("stars"),
+            Value::bulk("42"),
+        ]);
+
+        let map = HashMap::<String, String>::from_value(value).unwrap();
+
+        assert_eq!(Some(&"rdb".to_string()), map.get("name"));
+        assert_eq!(Some(&"42".to_string()), map.get("stars"));
+    }
+}
@@ -0,0 +1,114 @@
+// Typed client: the commands are sent as RESP arrays, the replies converted with `FromValue`
+//
+// - `Client`: blocking, one connection
+// - `Pool`: a bounded pool of blocking clients, shareable between threads
+// - `AsyncClient` / `AsyncPool`: the same on top of tokio (feature `async`)
+
+mod blocking;
+mod from_value;
+mod pool;
+
+#[cfg(feature = "async")]
+mod aio;
+
+use std::fmt;
+use std::io;
+
+use crate::resp::Value;
+
+pub use blocking::{Client, Subscription};
+pub use from_value::FromValue;
+pub use pool::{Pool, PooledClient};
+
+#[cfg(feature = "async")]
+pub use aio::{AsyncClient, AsyncPool, AsyncPooledClient, AsyncSubscription};
+
+#[derive(Debug)]
+pub enum RdbError {
+    Io(io::Error),
+    /// An error reply sent by the server, like `ERR syntax error`.
+    Server(String),
+    /// The reply could not be converted to the requested type.
+    Type {
+        expected: &'static str,
+        got: Value,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, RdbError>;
+
+impl fmt::Display for RdbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RdbError::Io(e) => write!(f, "{e}"),
+            RdbError::Server(msg) => write!(f, "{msg}"),
+            RdbError::Type { expected, got } => {
+                write!(f, "expected {expected}, the server replied {got:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RdbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RdbError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RdbError {
+    fn from(e: io::Error) -> Self {
+        RdbError::Io(e)
+    }
+}
+
+/// A message received on a channel the client subscribed to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub channel: String,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    pub fn payload<T: FromValue>(&self) -> Result<T> {
+        T::from_value(Value::BulkString(self.payload.clone()))
+    }
+}
+
+fn encode_command(args: &[&[u8]]) -> Vec<u8> {
+    Value::Array(args.iter().map(|arg| Value::bulk(*arg)).collect()).encode()
+}
+
+fn into_result(value: Value) -> Result<Value> {
+    match value {
+        Value::Error(msg) => Err(RdbError::Server(msg)),
+        value => Ok(value),
+    }
+}
+
+// ["message", channel, payload], the other pub/sub replies (like the subscribe confirmations) are skipped
+fn parse_message(value: Value) -> Result<Option<Message>> {
+    let Value::Array(items) = into_result(value)? else {
+        return Ok(None);
+    };
+    match <[Value; 3]>::try_from(items) {
+        Ok([Value::BulkString(kind), channel, Value::BulkString(payload)])
+            if kind == b"message" =>
+        {
+            Ok(Some(Message {
+                channel: String::from_value(channel)?,
+                payload,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn connection_closed() -> RdbError {
+    RdbError::Io(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "connection closed by the server",
+    ))
+}
@@ -0,0 +1,136 @@
+// A bounded pool of blocking clients: `get` waits when all the connections are in use
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+
+use crate::client::{Client, Result};
+
+pub struct Pool {
+    addr: String,
+    max_size: usize,
+    state: Mutex<PoolState>,
+    released: Condvar,
+}
+
+struct PoolState {
+    idle: Vec<Client>,
+    // idle + in use
+    open: usize,
+}
+
+impl Pool {
+    pub fn new(addr: impl Into<String>, max_size: usize) -> Self {
+        Pool {
+            addr: addr.into(),
+            max_size: max_size.max(1),
+            state: Mutex::new(PoolState {
+                idle: vec![],
+                open: 0,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn get(&self) -> Result<PooledClient<'_>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(client) = state.idle.pop() {
+                return Ok(self.pooled(client));
+            }
+            if state.open < self.max_size {
+                break;
+            }
+            state = self.released.wait(state).unwrap();
+        }
+        state.open += 1;
+        drop(state);
+
+        // connect without holding the lock
+        match Client::connect(self.addr.as_str()) {
+            Ok(client) => Ok(self.pooled(client)),
+            Err(e) => {
+                self.release(None);
+                Err(e)
+            }
+        }
+    }
+
+    fn pooled(&self, client: Client) -> PooledClient<'_> {
+        PooledClient {
+            pool: self,
+            client: Some(client),
+        }
+    }
+
+    fn release(&self, client: Option<Client>) {
+        let mut state = self.state.lock().unwrap();
+        match client {
+            Some(client) if !client.is_broken() => state.idle.push(client),
+            _ => state.open -= 1,
+        }
+        self.released.notify_one();
+    }
+}
+
+/// A client borrowed from the pool, given back when dropped.
+pub struct PooledClient<'a> {
+    pool: &'a Pool,
+    client: Option<Client>,
+}
+
+impl Deref for PooledClient<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledClient<'_> {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledClient<'_> {
+    fn drop(&mut self) {
+        self.pool.release(self.client.take());
+    }
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::Pool;
+    use crate::db::Db;
+    use crate::server;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn test_connections_are_reused_and_bounded() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || server::serve(listener, Arc::new(Mutex::new(Db::new()))));
+        let pool = Arc::new(Pool::new(addr, 2));
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let pool = Arc::clone(&pool);
+                thread::spawn(move || {
+                    let mut client = pool.get().unwrap();
+                    client.set(format!("key{i}"), i.to_string()).unwrap();
+                    client.get::<String>(format!("key{i}")).unwrap()
+                })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(i.to_string(), handle.join().unwrap());
+        }
+
+        let state = pool.state.lock().unwrap();
+        assert!(state.open <= 2);
+        assert_eq!(state.open, state.idle.len());
+    }
+}
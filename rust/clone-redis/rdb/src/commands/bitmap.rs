@@ -0,0 +1,304 @@
+// SETBIT / GETBIT / BITCOUNT / BITPOS / BITOP / BITFIELD
+
+use crate::bitmap::{self, BitOp, FieldType, Overflow};
+use crate::commands::{parse_i64, Reply, SYNTAX_ERR};
+use crate::db::{Db, Object};
+use crate::resp::Value;
+
+const BIT_OFFSET_ERR: &str = "ERR bit offset is not an integer or out of range";
+const BIT_VALUE_ERR: &str = "ERR bit is not an integer or out of range";
+const BITFIELD_TYPE_ERR: &str = "ERR Invalid bitfield type. Use something like i16 u8. \
+    Note that u64 is not supported but i64 is.";
+
+// strings are limited to 512MB
+const MAX_BYTES: u64 = 512 * 1024 * 1024;
+
+// `#N` offsets (BITFIELD only) are multiplied by the width of the field type
+fn parse_bit_offset(arg: &[u8], field: Option<FieldType>) -> Result<u64, String> {
+    let (arg, multiplier) = match (arg.strip_prefix(b"#"), field) {
+        (Some(index), Some(field)) => (index, field.bits as i64),
+        _ => (arg, 1),
+    };
+    let offset = parse_i64(arg)
+        .ok()
+        .and_then(|offset| offset.checked_mul(multiplier))
+        .ok_or(BIT_OFFSET_ERR)?;
+    if offset < 0 || (offset as u64 >> 3) >= MAX_BYTES {
+        return Err(BIT_OFFSET_ERR.into());
+    }
+    Ok(offset as u64)
+}
+
+// BYTE (default) or BIT: the unit of the range of BITCOUNT and BITPOS
+fn parse_is_bit(arg: &[u8]) -> Result<bool, String> {
+    match arg.to_ascii_lowercase().as_slice() {
+        b"byte" => Ok(false),
+        b"bit" => Ok(true),
+        _ => Err(SYNTAX_ERR.into()),
+    }
+}
+
+pub fn setbit(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let offset = parse_bit_offset(&args[2], None)?;
+    let bit = match parse_i64(&args[3]) {
+        Ok(bit @ (0 | 1)) => bit as u8,
+        _ => return Err(BIT_VALUE_ERR.into()),
+    };
+    let bytes = db.get_string_or_insert(&args[1])?;
+    Ok(Value::Integer(bitmap::set_bit(bytes, offset, bit) as i64))
+}
+
+pub fn getbit(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let offset = parse_bit_offset(&args[2], None)?;
+    let bit = match db.get_string(&args[1])? {
+        Some(bytes) => bitmap::get_bit(&bytes, offset),
+        None => 0,
+    };
+    Ok(Value::Integer(bit as i64))
+}
+
+pub fn bitcount(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let range = match args.len() {
+        2 => None,
+        4 | 5 => {
+            let start = parse_i64(&args[2])?;
+            let end = parse_i64(&args[3])?;
+            let is_bit = match args.get(4) {
+                Some(unit) => parse_is_bit(unit)?,
+                None => false,
+            };
+            Some((start, end, is_bit))
+        }
+        _ => return Err(SYNTAX_ERR.into()),
+    };
+
+    let Some(bytes) = db.get_string(&args[1])? else {
+        return Ok(Value::Integer(0));
+    };
+    let len = bytes.len() as i64;
+    let bit_range = match range {
+        None => bitmap::normalize_range(0, -1, len).map(|(start, end)| (start * 8, end * 8 + 7)),
+        Some((start, end, true)) => bitmap::normalize_range(start, end, len * 8),
+        Some((start, end, false)) => {
+            bitmap::normalize_range(start, end, len).map(|(start, end)| (start * 8, end * 8 + 7))
+        }
+    };
+    let count = match bit_range {
+        Some((start, end)) => bitmap::count(&bytes, start, end),
+        None => 0,
+    };
+    Ok(Value::Integer(count as i64))
+}
+
+pub fn bitpos(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let bit = match parse_i64(&args[2])? {
+        bit @ (0 | 1) => bit as u8,
+        _ => return Err("ERR The bit argument must be 1 or 0.".into()),
+    };
+
+    // a missing key is an infinite string of zeroes
+    let Some(bytes) = db.get_string(&args[1])? else {
+        return Ok(Value::Integer(if bit == 1 { -1 } else { 0 }));
+    };
+    let len = bytes.len() as i64;
+
+    if args.len() > 6 {
+        return Err(SYNTAX_ERR.into());
+    }
+    let start = match args.get(3) {
+        Some(start) => parse_i64(start)?,
+        None => 0,
+    };
+    let is_bit = match args.get(5) {
+        Some(unit) => parse_is_bit(unit)?,
+        None => false,
+    };
+    let end = match args.get(4) {
+        Some(end) => Some(parse_i64(end)?),
+        None => None,
+    };
+    let unit_len = if is_bit { len * 8 } else { len };
+    let Some((start, end)) = bitmap::normalize_range(start, end.unwrap_or(unit_len - 1), unit_len)
+    else {
+        // an empty range contains neither ones nor zeroes
+        return Ok(Value::Integer(-1));
+    };
+    let (start, last) = if is_bit {
+        (start, end)
+    } else {
+        (start * 8, end * 8 + 7)
+    };
+
+    let position = match bitmap::position(&bytes, bit, start, last) {
+        Some(position) => position as i64,
+        // without an explicit end, the string is considered to be padded with zeroes on the right
+        None if bit == 0 && args.len() < 5 => last as i64 + 1,
+        None => -1,
+    };
+    Ok(Value::Integer(position))
+}
+
+pub fn bitop(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let op = match args[1].to_ascii_lowercase().as_slice() {
+        b"and" => BitOp::And,
+        b"or" => BitOp::Or,
+        b"xor" => BitOp::Xor,
+        b"not" => BitOp::Not,
+        _ => return Err(SYNTAX_ERR.into()),
+    };
+    if op == BitOp::Not && args.len() != 4 {
+        return Err("ERR BITOP NOT must be called with a single source key.".into());
+    }
+
+    let sources = args[3..]
+        .iter()
+        .map(|key| {
+            Ok(db
+                .get_string(key)?
+                .map(|s| s.into_owned())
+                .unwrap_or_default())
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let sources: Vec<&[u8]> = sources.iter().map(|s| s.as_slice()).collect();
+    let result = bitmap::bitop(op, &sources);
+
+    let len = result.len();
+    if result.is_empty() {
+        db.remove(&args[2]);
+    } else {
+        db.set(args[2].clone(), Object::String(result));
+    }
+    Ok(Value::Integer(len as i64))
+}
+
+enum FieldOp {
+    Get,
+    Set(i64),
+    IncrBy(i64),
+}
+
+struct BitfieldOp {
+    op: FieldOp,
+    field: FieldType,
+    offset: u64,
+    overflow: Overflow,
+}
+
+pub fn bitfield(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    run_bitfield(db, args, false)
+}
+
+pub fn bitfield_ro(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    run_bitfield(db, args, true)
+}
+
+fn run_bitfield(db: &mut Db, args: &[Vec<u8>], read_only: bool) -> Reply {
+    let ops = parse_bitfield_ops(&args[2..], read_only)?;
+
+    let highest_write = ops
+        .iter()
+        .filter(|op| !matches!(op.op, FieldOp::Get))
+        .map(|op| op.offset + op.field.bits as u64 - 1)
+        .max();
+    let Some(highest_write) = highest_write else {
+        // only GET operations: don't create the key
+        let bytes = db.get_string(&args[1])?.unwrap_or_default();
+        let replies = ops
+            .iter()
+            .map(|op| Value::Integer(bitmap::get_field(&bytes, op.field, op.offset)))
+            .collect();
+        return Ok(Value::Array(replies));
+    };
+
+    let bytes = db.get_string_or_insert(&args[1])?;
+    let min_len = (highest_write >> 3) as usize + 1;
+    if bytes.len() < min_len {
+        bytes.resize(min_len, 0);
+    }
+
+    let mut replies = vec![];
+    for BitfieldOp {
+        op,
+        field,
+        offset,
+        overflow,
+    } in ops
+    {
+        let current = bitmap::get_field(bytes, field, offset);
+        let current_value = if field.signed {
+            current as i128
+        } else {
+            current as u64 as i128
+        };
+        let reply = match op {
+            FieldOp::Get => Value::Integer(current),
+            FieldOp::Set(value) => {
+                let value = if field.signed {
+                    value as i128
+                } else {
+                    value as u64 as i128
+                };
+                match field.fit(value, overflow) {
+                    Some(value) => {
+                        bitmap::set_field(bytes, field, offset, value);
+                        Value::Integer(current)
+                    }
+                    None => Value::Null,
+                }
+            }
+            FieldOp::IncrBy(increment) => {
+                match field.fit(current_value + increment as i128, overflow) {
+                    Some(value) => {
+                        bitmap::set_field(bytes, field, offset, value);
+                        Value::Integer(value)
+                    }
+                    None => Value::Null,
+                }
+            }
+        };
+        replies.push(reply);
+    }
+    Ok(Value::Array(replies))
+}
+
+fn parse_bitfield_ops(args: &[Vec<u8>], read_only: bool) -> Result<Vec<BitfieldOp>, String> {
+    let mut ops = vec![];
+    let mut overflow = Overflow::Wrap;
+    let mut i = 0;
+    while i < args.len() {
+        let subcommand = args[i].to_ascii_lowercase();
+        let remaining = args.len() - i - 1;
+        match subcommand.as_slice() {
+            b"overflow" if remaining >= 1 => {
+                overflow = match args[i + 1].to_ascii_lowercase().as_slice() {
+                    b"wrap" => Overflow::Wrap,
+                    b"sat" => Overflow::Sat,
+                    b"fail" => Overflow::Fail,
+                    _ => return Err("ERR Invalid OVERFLOW type specified".into()),
+                };
+                i += 2;
+                continue;
+            }
+            b"get" if remaining >= 2 => {}
+            b"set" | b"incrby" if remaining >= 3 => {}
+            _ => return Err(SYNTAX_ERR.into()),
+        }
+
+        let field = FieldType::parse(&args[i + 1]).ok_or(BITFIELD_TYPE_ERR)?;
+        let offset = parse_bit_offset(&args[i + 2], Some(field))?;
+        let op = match subcommand.as_slice() {
+            b"get" => FieldOp::Get,
+            _ if read_only => return Err("ERR BITFIELD_RO only supports the GET subcommand".into()),
+            b"set" => FieldOp::Set(parse_i64(&args[i + 3])?),
+            _ => FieldOp::IncrBy(parse_i64(&args[i + 3])?),
+        };
+        i += if matches!(op, FieldOp::Get) { 3 } else { 4 };
+        ops.push(BitfieldOp {
+            op,
+            field,
+            offset,
+            overflow,
+        });
+    }
+    Ok(ops)
+}
@@ -0,0 +1,303 @@
+// GEOADD / GEOPOS / GEODIST / GEOHASH / GEOSEARCH
+//
+// A geo index is a plain sorted set: the score of each member is the 52 bit geohash of its position.
+
+use crate::commands::sorted_set::{add_members, AddFlags, XX_NX_ERR};
+use crate::commands::{parse_f64, parse_i64, Reply, SYNTAX_ERR};
+use crate::db::Db;
+use crate::geohash;
+use crate::resp::Value;
+
+const UNIT_ERR: &str = "ERR unsupported unit provided. please use M, KM, FT, MI";
+
+/// Conversion factor to meters.
+fn parse_unit(arg: &[u8]) -> Result<f64, String> {
+    match arg.to_ascii_lowercase().as_slice() {
+        b"m" => Ok(1.0),
+        b"km" => Ok(1000.0),
+        b"ft" => Ok(0.3048),
+        b"mi" => Ok(1609.34),
+        _ => Err(UNIT_ERR.into()),
+    }
+}
+
+fn parse_position(longitude: &[u8], latitude: &[u8]) -> Result<(f64, f64), String> {
+    let (longitude, latitude) = (parse_f64(longitude)?, parse_f64(latitude)?);
+    if !geohash::is_valid(longitude, latitude) {
+        return Err(format!(
+            "ERR invalid longitude,latitude pair {longitude:.6},{latitude:.6}"
+        ));
+    }
+    Ok((longitude, latitude))
+}
+
+// like Redis' "%.17Lf" without the trailing zeroes
+fn format_coordinate(value: f64) -> String {
+    let formatted = format!("{value:.17}");
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+fn format_distance(meters: f64, unit: f64) -> String {
+    format!("{:.4}", meters / unit)
+}
+
+fn member_position(db: &Db, key: &[u8], member: &[u8]) -> Result<Option<(f64, f64)>, String> {
+    let score = db.get_sorted_set(key)?.and_then(|set| set.score(member));
+    Ok(score.map(|score| geohash::decode(score as u64)))
+}
+
+pub fn geoadd(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let mut flags = AddFlags::default();
+    let mut i = 2;
+    while let Some(arg) = args.get(i) {
+        match arg.to_ascii_lowercase().as_slice() {
+            b"nx" => flags.nx = true,
+            b"xx" => flags.xx = true,
+            b"ch" => flags.ch = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let triples = &args[i..];
+    if triples.is_empty() || !triples.len().is_multiple_of(3) {
+        return Err(
+            "ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ".into(),
+        );
+    }
+    if flags.nx && flags.xx {
+        return Err(XX_NX_ERR.into());
+    }
+    let members = triples
+        .chunks(3)
+        .map(|triple| {
+            let (longitude, latitude) = parse_position(&triple[0], &triple[1])?;
+            let bits = geohash::encode(longitude, latitude).ok_or(SYNTAX_ERR)?;
+            Ok((bits as f64, triple[2].clone()))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(Value::Integer(add_members(db, &args[1], flags, members)?))
+}
+
+pub fn geopos(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let positions = args[2..]
+        .iter()
+        .map(|member| {
+            Ok(match member_position(db, &args[1], member)? {
+                Some((longitude, latitude)) => Value::Array(vec![
+                    Value::bulk(format_coordinate(longitude)),
+                    Value::bulk(format_coordinate(latitude)),
+                ]),
+                None => Value::NullArray,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(Value::Array(positions))
+}
+
+pub fn geodist(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let unit = match args.len() {
+        4 => 1.0,
+        5 => parse_unit(&args[4])?,
+        _ => return Err(SYNTAX_ERR.into()),
+    };
+    let from = member_position(db, &args[1], &args[2])?;
+    let to = member_position(db, &args[1], &args[3])?;
+    match (from, to) {
+        (Some((lon1, lat1)), Some((lon2, lat2))) => {
+            let meters = geohash::distance(lon1, lat1, lon2, lat2);
+            Ok(Value::bulk(format_distance(meters, unit)))
+        }
+        _ => Ok(Value::Null),
+    }
+}
+
+pub fn geohash(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let set = db.get_sorted_set(&args[1])?;
+    let hashes = args[2..]
+        .iter()
+        .map(|member| match set.and_then(|set| set.score(member)) {
+            Some(score) => Value::bulk(geohash::to_geohash_string(score as u64)),
+            None => Value::Null,
+        })
+        .collect();
+    Ok(Value::Array(hashes))
+}
+
+enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+#[derive(PartialEq)]
+enum Sort {
+    None,
+    Asc,
+    Desc,
+}
+
+struct Found<'a> {
+    member: &'a [u8],
+    distance: f64,
+    score: f64,
+}
+
+pub fn geosearch(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let Some(set) = db.get_sorted_set(&args[1])? else {
+        return Ok(Value::Array(vec![]));
+    };
+
+    let mut from_member = None;
+    let mut from_position = None;
+    let mut shape = None;
+    let mut unit = 1.0;
+    let (mut by_radius, mut by_box) = (false, false);
+    let (mut with_dist, mut with_hash, mut with_coord) = (false, false, false);
+    let mut any = false;
+    let mut sort = Sort::None;
+    let mut count = None;
+
+    let mut i = 2;
+    while i < args.len() {
+        let remaining = args.len() - i - 1;
+        match args[i].to_ascii_lowercase().as_slice() {
+            b"withdist" => with_dist = true,
+            b"withhash" => with_hash = true,
+            b"withcoord" => with_coord = true,
+            b"any" => any = true,
+            b"asc" => sort = Sort::Asc,
+            b"desc" => sort = Sort::Desc,
+            b"count" if remaining >= 1 => {
+                let n = parse_i64(&args[i + 1])?;
+                if n <= 0 {
+                    return Err("ERR COUNT must be > 0".into());
+                }
+                count = Some(n as usize);
+                i += 1;
+            }
+            b"frommember" if remaining >= 1 && from_member.is_none() => {
+                from_member = Some(&args[i + 1]);
+                i += 1;
+            }
+            b"fromlonlat" if remaining >= 2 && from_position.is_none() => {
+                from_position = Some(parse_position(&args[i + 1], &args[i + 2])?);
+                i += 2;
+            }
+            b"byradius" if remaining >= 2 && !by_radius => {
+                let radius = parse_f64(&args[i + 1]).map_err(|_| "ERR need numeric radius")?;
+                if radius < 0.0 {
+                    return Err("ERR radius cannot be negative".into());
+                }
+                unit = parse_unit(&args[i + 2])?;
+                shape = Some(Shape::Radius(radius * unit));
+                by_radius = true;
+                i += 2;
+            }
+            b"bybox" if remaining >= 3 && !by_box => {
+                let width = parse_f64(&args[i + 1]).map_err(|_| "ERR need numeric width")?;
+                let height = parse_f64(&args[i + 2]).map_err(|_| "ERR need numeric height")?;
+                if width < 0.0 || height < 0.0 {
+                    return Err("ERR height or width cannot be negative".into());
+                }
+                unit = parse_unit(&args[i + 3])?;
+                shape = Some(Shape::Box {
+                    width: width * unit,
+                    height: height * unit,
+                });
+                by_box = true;
+                i += 3;
+            }
+            _ => return Err(SYNTAX_ERR.into()),
+        }
+        i += 1;
+    }
+
+    let command = String::from_utf8_lossy(&args[0]);
+    if from_member.is_some() == from_position.is_some() {
+        return Err(format!(
+            "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for {command}"
+        ));
+    }
+    let Some(shape) = shape.filter(|_| by_radius != by_box) else {
+        return Err(format!(
+            "ERR exactly one of BYRADIUS and BYBOX can be specified for {command}"
+        ));
+    };
+    if any && count.is_none() {
+        return Err("ERR the ANY argument requires COUNT argument".into());
+    }
+    // the closest N members are wanted: COUNT implies sorting unless ANY N members will do
+    if count.is_some() && sort == Sort::None && !any {
+        sort = Sort::Asc;
+    }
+
+    let center = match from_position {
+        Some(position) => position,
+        None => match from_member.and_then(|member| set.score(member)) {
+            Some(score) => geohash::decode(score as u64),
+            None => return Err("ERR could not decode requested zset member".into()),
+        },
+    };
+
+    let mut found = vec![];
+    for (member, score) in set.iter() {
+        let position = geohash::decode(score as u64);
+        let distance = match shape {
+            Shape::Radius(radius) => {
+                let distance = geohash::distance(center.0, center.1, position.0, position.1);
+                (distance <= radius).then_some(distance)
+            }
+            Shape::Box { width, height } => {
+                geohash::distance_if_in_box(width, height, center, position)
+            }
+        };
+        if let Some(distance) = distance {
+            found.push(Found {
+                member,
+                distance,
+                score,
+            });
+            if any && Some(found.len()) == count {
+                break;
+            }
+        }
+    }
+
+    match sort {
+        Sort::None => {}
+        Sort::Asc => found.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+        Sort::Desc => found.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+    }
+    if let Some(count) = count {
+        found.truncate(count);
+    }
+
+    let replies = found
+        .into_iter()
+        .map(|found| {
+            if !(with_dist || with_hash || with_coord) {
+                return Value::bulk(found.member);
+            }
+            let mut item = vec![Value::bulk(found.member)];
+            if with_dist {
+                item.push(Value::bulk(format_distance(found.distance, unit)));
+            }
+            if with_hash {
+                item.push(Value::Integer(found.score as i64));
+            }
+            if with_coord {
+                let (longitude, latitude) = geohash::decode(found.score as u64);
+                item.push(Value::Array(vec![
+                    Value::bulk(format_coordinate(longitude)),
+                    Value::bulk(format_coordinate(latitude)),
+                ]));
+            }
+            Value::Array(item)
+        })
+        .collect();
+    Ok(Value::Array(replies))
+}
@@ -0,0 +1,64 @@
+// PFADD / PFCOUNT / PFMERGE
+
+use crate::commands::Reply;
+use crate::db::{Db, Object};
+use crate::hyperloglog::HyperLogLog;
+use crate::resp::Value;
+
+pub fn pfadd(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let key = &args[1];
+    let (mut hll, mut updated) = match db.get_string(key)? {
+        Some(bytes) => (HyperLogLog::from_bytes(&bytes)?, false),
+        None => (HyperLogLog::new(), true),
+    };
+    for element in &args[2..] {
+        updated |= hll.add(element);
+    }
+    if updated {
+        db.overwrite(key.clone(), Object::String(hll.to_bytes()));
+    }
+    Ok(Value::Integer(updated as i64))
+}
+
+pub fn pfcount(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    if let [_, key] = args {
+        let Some(bytes) = db.get_string(key)? else {
+            return Ok(Value::Integer(0));
+        };
+        let mut hll = HyperLogLog::from_bytes(&bytes)?;
+        let had_valid_cache = hll.has_valid_cache();
+        let count = hll.count();
+        if !had_valid_cache {
+            // store the cardinality so the next PFCOUNT doesn't need to compute it again
+            db.overwrite(key.clone(), Object::String(hll.to_bytes()));
+        }
+        return Ok(Value::Integer(count as i64));
+    }
+
+    // the union of multiple HyperLogLogs is never cached
+    let mut union = HyperLogLog::new();
+    for key in &args[1..] {
+        if let Some(bytes) = db.get_string(key)? {
+            union.merge(&HyperLogLog::from_bytes(&bytes)?);
+        }
+    }
+    Ok(Value::Integer(union.count() as i64))
+}
+
+pub fn pfmerge(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let destination = &args[1];
+    // the destination (when it exists) is part of the union as well
+    let mut merged = match db.get_string(destination)? {
+        Some(bytes) => HyperLogLog::from_bytes(&bytes)?,
+        None => HyperLogLog::new(),
+    };
+    for key in &args[2..] {
+        if let Some(bytes) = db.get_string(key)? {
+            merged.merge(&HyperLogLog::from_bytes(&bytes)?);
+        }
+    }
+    merged.to_dense();
+    merged.invalidate_cache();
+    db.overwrite(destination.clone(), Object::String(merged.to_bytes()));
+    Ok(Value::ok())
+}
@@ -0,0 +1,183 @@
+// Generic commands, working on keys of any type
+
+use crate::commands::{parse_i64, Reply, SYNTAX_ERR};
+use crate::db::{now_ms, Db, Object};
+use crate::resp::Value;
+
+const SAME_OBJECT_ERR: &str = "ERR source and destination objects are the same";
+
+pub fn del(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let deleted = args[1..]
+        .iter()
+        .filter(|key| db.remove(key).is_some())
+        .count();
+    Ok(Value::Integer(deleted as i64))
+}
+
+pub fn exists(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let found = args[1..].iter().filter(|key| db.contains(key)).count();
+    Ok(Value::Integer(found as i64))
+}
+
+// FLUSHALL / FLUSHDB [ASYNC | SYNC], there is a single database
+pub fn flushall(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    match &args[1..] {
+        [] => {}
+        [mode] if mode.eq_ignore_ascii_case(b"async") || mode.eq_ignore_ascii_case(b"sync") => {}
+        _ => return Err(SYNTAX_ERR.into()),
+    }
+    db.clear();
+    Ok(Value::ok())
+}
+
+pub fn ttl(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let ms = remaining_ttl_ms(db, &args[1]);
+    // rounded to the closest second
+    Ok(Value::Integer(if ms < 0 { ms } else { (ms + 500) / 1000 }))
+}
+
+pub fn pttl(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    Ok(Value::Integer(remaining_ttl_ms(db, &args[1])))
+}
+
+// -2 = missing key, -1 = no expiration
+fn remaining_ttl_ms(db: &Db, key: &[u8]) -> i64 {
+    if !db.contains(key) {
+        return -2;
+    }
+    match db.expire_at(key) {
+        Some(at) => at.saturating_sub(now_ms()) as i64,
+        None => -1,
+    }
+}
+
+// COPY source destination [DB destination-db] [REPLACE]
+pub fn copy(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let (source, destination) = (&args[1], &args[2]);
+    let mut replace = false;
+    let mut i = 3;
+    while i < args.len() {
+        match args[i].to_ascii_lowercase().as_slice() {
+            b"replace" => replace = true,
+            b"db" if i + 1 < args.len() => {
+                // a single database
+                if parse_i64(&args[i + 1])? != 0 {
+                    return Err("ERR DB index is out of range".into());
+                }
+                i += 1;
+            }
+            _ => return Err(SYNTAX_ERR.into()),
+        }
+        i += 1;
+    }
+    if source == destination {
+        return Err(SAME_OBJECT_ERR.into());
+    }
+
+    let Some(object) = db.get(source).cloned() else {
+        return Ok(Value::Integer(0));
+    };
+    if db.contains(destination) && !replace {
+        return Ok(Value::Integer(0));
+    }
+    let expire_at = db.expire_at(source);
+    db.set(destination.clone(), object);
+    if let Some(at) = expire_at {
+        db.set_expire_at(destination, at);
+    }
+    Ok(Value::Integer(1))
+}
+
+pub fn rename(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    rename_key(db, &args[1], &args[2], false)
+}
+
+pub fn renamenx(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    rename_key(db, &args[1], &args[2], true)
+}
+
+fn rename_key(db: &mut Db, source: &[u8], destination: &[u8], nx: bool) -> Reply {
+    let done = if nx { Value::Integer(1) } else { Value::ok() };
+    if !db.contains(source) {
+        return Err("ERR no such key".into());
+    }
+    if source == destination {
+        return Ok(if nx { Value::Integer(0) } else { Value::ok() });
+    }
+    if nx && db.contains(destination) {
+        return Ok(Value::Integer(0));
+    }
+
+    let expire_at = db.expire_at(source);
+    let object = db.remove(source).expect("the source key exists");
+    db.set(destination.to_vec(), object);
+    if let Some(at) = expire_at {
+        db.set_expire_at(destination, at);
+    }
+    Ok(done)
+}
+
+// OBJECT ENCODING | FREQ | IDLETIME key, without counting as an access to the key
+//
+// Both the idle time and the access frequency are tracked, whatever the eviction policy.
+pub fn object(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let subcommand = args[1].to_ascii_lowercase();
+    if subcommand == b"help" {
+        let help = [
+            "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "ENCODING <key>",
+            "    Return the kind of internal representation used in order to store the value",
+            "    associated with a <key>.",
+            "FREQ <key>",
+            "    Return the access frequency index of the <key>. The returned integer is",
+            "    proportional to the logarithm of the recent access frequency of the key.",
+            "IDLETIME <key>",
+            "    Return the idle time of the <key>, that is the approximated number of",
+            "    seconds elapsed since the last access to the key.",
+            "HELP",
+            "    Print this help.",
+        ];
+        return Ok(Value::Array(
+            help.iter()
+                .map(|line| Value::SimpleString(line.to_string()))
+                .collect(),
+        ));
+    }
+    let known = [&b"encoding"[..], b"freq", b"idletime"].contains(&subcommand.as_slice());
+    if !known {
+        return Err(format!(
+            "ERR unknown subcommand '{}'. Try OBJECT HELP.",
+            String::from_utf8_lossy(&args[1])
+        ));
+    }
+    if args.len() != 3 {
+        return Err(format!(
+            "ERR wrong number of arguments for 'object|{}' command",
+            String::from_utf8_lossy(&subcommand)
+        ));
+    }
+
+    let key = &args[2];
+    let Some(object) = db.peek(key) else {
+        return Ok(Value::Null);
+    };
+    let reply = match subcommand.as_slice() {
+        b"encoding" => Value::bulk(encoding(object)),
+        b"freq" => Value::Integer(db.access_frequency(key).unwrap_or(0) as i64),
+        _ => Value::Integer((db.idle_time_ms(key).unwrap_or(0) / 1000) as i64),
+    };
+    Ok(reply)
+}
+
+// the names of the encodings Redis would use for the value
+fn encoding(object: &Object) -> &'static str {
+    match object {
+        Object::Int(_) => "int",
+        Object::String(bytes) if bytes.len() <= 44 => "embstr",
+        Object::String(_) => "raw",
+        Object::SortedSet(set) if set.len() <= 128 && set.iter().all(|(m, _)| m.len() <= 64) => {
+            "listpack"
+        }
+        Object::SortedSet(_) => "skiplist",
+    }
+}
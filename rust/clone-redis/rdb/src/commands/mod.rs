@@ -0,0 +1,936 @@
+// Command table + dispatching of the commands sent by clients
+
+mod bitmap;
+mod geo;
+mod hyperloglog;
+mod keys;
+mod replication;
+mod serialization;
+mod sorted_set;
+mod strings;
+
+use crate::db::Db;
+use crate::resp::Value;
+
+pub const SYNTAX_ERR: &str = "ERR syntax error";
+pub const NOT_AN_INTEGER_ERR: &str = "ERR value is not an integer or out of range";
+pub const NOT_A_FLOAT_ERR: &str = "ERR value is not a valid float";
+
+// the error is the message of the error reply (prefixed by the error code, like `ERR`)
+pub type Reply = Result<Value, String>;
+
+type Handler = fn(&mut Db, &[Vec<u8>]) -> Reply;
+
+// name, arity, handler
+// like Redis: the arity is the number of arguments including the command name, negative = at least N
+const COMMANDS: &[(&str, i32, Handler)] = &[
+    ("ping", -1, ping),
+    ("echo", 2, echo),
+    ("command", -1, command),
+    ("del", -2, keys::del),
+    ("exists", -2, keys::exists),
+    ("flushall", -1, keys::flushall),
+    ("flushdb", -1, keys::flushall),
+    ("ttl", 2, keys::ttl),
+    ("pttl", 2, keys::pttl),
+    ("copy", -3, keys::copy),
+    ("rename", 3, keys::rename),
+    ("renamenx", 3, keys::renamenx),
+    ("object", -2, keys::object),
+    ("dump", 2, serialization::dump),
+    ("restore", -4, serialization::restore),
+    ("migrate", -6, serialization::migrate),
+    ("save", 1, serialization::save),
+    ("get", 2, strings::get),
+    ("set", -3, strings::set),
+    ("getdel", 2, strings::getdel),
+    ("getex", -2, strings::getex),
+    ("mget", -2, strings::mget),
+    ("mset", -3, strings::mset),
+    ("msetnx", -3, strings::msetnx),
+    ("append", 3, strings::append),
+    ("strlen", 2, strings::strlen),
+    ("getrange", 4, strings::getrange),
+    ("setrange", 4, strings::setrange),
+    ("incr", 2, strings::incr),
+    ("decr", 2, strings::decr),
+    ("incrby", 3, strings::incrby),
+    ("decrby", 3, strings::decrby),
+    ("incrbyfloat", 3, strings::incrbyfloat),
+    ("pfadd", -2, hyperloglog::pfadd),
+    ("pfcount", -2, hyperloglog::pfcount),
+    ("pfmerge", -2, hyperloglog::pfmerge),
+    ("setbit", 4, bitmap::setbit),
+    ("getbit", 3, bitmap::getbit),
+    ("bitcount", -2, bitmap::bitcount),
+    ("bitpos", -3, bitmap::bitpos),
+    ("bitop", -4, bitmap::bitop),
+    ("bitfield", -2, bitmap::bitfield),
+    ("bitfield_ro", -2, bitmap::bitfield_ro),
+    ("zadd", -4, sorted_set::zadd),
+    ("zscore", 3, sorted_set::zscore),
+    ("zrem", -3, sorted_set::zrem),
+    ("zcard", 2, sorted_set::zcard),
+    ("zrange", -4, sorted_set::zrange),
+    ("geoadd", -5, geo::geoadd),
+    ("geopos", -2, geo::geopos),
+    ("geodist", -4, geo::geodist),
+    ("geohash", -2, geo::geohash),
+    ("geosearch", -7, geo::geosearch),
+    ("replicaof", 3, replication::replicaof),
+    ("slaveof", 3, replication::replicaof),
+    ("role", 1, replication::role),
+];
+
+/// Executes a command (name + arguments) against the keyspace and returns the reply.
+pub fn execute(db: &mut Db, args: &[Vec<u8>]) -> Value {
+    let Some(name) = args.first() else {
+        return Value::error("ERR empty command");
+    };
+    let lowercase_name = String::from_utf8_lossy(name).to_lowercase();
+
+    let Some(&(name, arity, handler)) = COMMANDS.iter().find(|c| c.0 == lowercase_name) else {
+        let args_beginning: String = args[1..]
+            .iter()
+            .map(|arg| format!("'{}' ", String::from_utf8_lossy(arg)))
+            .collect();
+        return Value::error(format!(
+            "ERR unknown command '{}', with args beginning with: {args_beginning}",
+            String::from_utf8_lossy(name)
+        ));
+    };
+
+    let argc = args.len() as i32;
+    if (arity > 0 && argc != arity) || argc < -arity {
+        return Value::error(format!(
+            "ERR wrong number of arguments for '{name}' command"
+        ));
+    }
+
+    handler(db, args).unwrap_or_else(Value::Error)
+}
+
+/// Parses an integer argument as strictly as Redis does (no spaces, no `+`, no leading zeroes).
+pub fn parse_i64(arg: &[u8]) -> Result<i64, String> {
+    let digits = arg.strip_prefix(b"-").unwrap_or(arg);
+    let well_formed = match digits {
+        [b'0'] => arg.len() == 1,
+        [b'1'..=b'9', rest @ ..] => rest.iter().all(u8::is_ascii_digit),
+        _ => false,
+    };
+    well_formed
+        .then(|| std::str::from_utf8(arg).ok()?.parse().ok())
+        .flatten()
+        .ok_or_else(|| NOT_AN_INTEGER_ERR.to_string())
+}
+
+/// Parses a float argument, like Redis `inf` and `-inf` are accepted but NaN is not.
+pub fn parse_f64(arg: &[u8]) -> Result<f64, String> {
+    std::str::from_utf8(arg)
+        .ok()
+        .filter(|s| !s.starts_with(|c: char| c.is_ascii_whitespace()))
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|value| !value.is_nan())
+        .ok_or_else(|| NOT_A_FLOAT_ERR.to_string())
+}
+
+fn ping(_db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    match args.len() {
+        1 => Ok(Value::SimpleString("PONG".into())),
+        2 => Ok(Value::bulk(args[1].clone())),
+        _ => Err("ERR wrong number of arguments for 'ping' command".into()),
+    }
+}
+
+fn echo(_db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    Ok(Value::bulk(args[1].clone()))
+}
+
+// clients like redis-cli ask for the command docs when connecting
+fn command(_db: &mut Db, _args: &[Vec<u8>]) -> Reply {
+    Ok(Value::Array(vec![]))
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::{execute, parse_f64, parse_i64};
+    use crate::db::{Db, Object};
+    use crate::resp::Value;
+    use crate::server;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn run(db: &mut Db, command: &str) -> Value {
+        let args: Vec<Vec<u8>> = command
+            .split_whitespace()
+            .map(|arg| arg.as_bytes().to_vec())
+            .collect();
+        execute(db, &args)
+    }
+
+    #[test]
+    fn test_parse_i64_is_strict() {
+        assert_eq!(Ok(-42), parse_i64(b"-42"));
+        assert_eq!(Ok(0), parse_i64(b"0"));
+        for invalid in [
+            "",
+            "-",
+            "+1",
+            "01",
+            "-0",
+            " 1",
+            "1.0",
+            "9223372036854775808",
+        ] {
+            assert!(parse_i64(invalid.as_bytes()).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_parse_f64() {
+        assert_eq!(Ok(1.5), parse_f64(b"1.5"));
+        assert_eq!(Ok(f64::NEG_INFINITY), parse_f64(b"-inf"));
+        for invalid in ["", "nan", " 1", "1 ", "abc"] {
+            assert!(parse_f64(invalid.as_bytes()).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let mut db = Db::new();
+
+        assert_eq!(
+            Value::error("ERR unknown command 'FOO', with args beginning with: 'bar' "),
+            run(&mut db, "FOO bar")
+        );
+    }
+
+    #[test]
+    fn test_wrong_number_of_arguments() {
+        let mut db = Db::new();
+
+        assert_eq!(
+            Value::error("ERR wrong number of arguments for 'get' command"),
+            run(&mut db, "GET")
+        );
+    }
+
+    #[test]
+    fn test_hyperloglog_commands() {
+        let mut db = Db::new();
+
+        assert_eq!(Value::Integer(1), run(&mut db, "PFADD hll a b c d e f g"));
+        assert_eq!(Value::Integer(0), run(&mut db, "PFADD hll a b"));
+        assert_eq!(Value::Integer(7), run(&mut db, "PFCOUNT hll"));
+        run(&mut db, "PFADD other g h i");
+        assert_eq!(Value::Integer(9), run(&mut db, "PFCOUNT hll other"));
+        assert_eq!(Value::ok(), run(&mut db, "PFMERGE merged hll other"));
+        assert_eq!(Value::Integer(9), run(&mut db, "PFCOUNT merged"));
+
+        run(&mut db, "SET greeting hello");
+        assert_eq!(
+            Value::error("WRONGTYPE Key is not a valid HyperLogLog string value."),
+            run(&mut db, "PFADD greeting a")
+        );
+    }
+
+    #[test]
+    fn test_bitmap_commands() {
+        let mut db = Db::new();
+
+        assert_eq!(Value::Integer(0), run(&mut db, "SETBIT flags 7 1"));
+        assert_eq!(Value::Integer(1), run(&mut db, "GETBIT flags 7"));
+        assert_eq!(Value::Integer(0), run(&mut db, "GETBIT flags 100"));
+        assert_eq!(
+            Value::error("ERR bit is not an integer or out of range"),
+            run(&mut db, "SETBIT flags 7 2")
+        );
+
+        run(&mut db, "SET key foobar");
+        assert_eq!(Value::Integer(26), run(&mut db, "BITCOUNT key"));
+        assert_eq!(Value::Integer(6), run(&mut db, "BITCOUNT key 1 1"));
+        assert_eq!(Value::Integer(17), run(&mut db, "BITCOUNT key 5 30 BIT"));
+        assert_eq!(
+            Value::error("ERR syntax error"),
+            run(&mut db, "BITCOUNT key 1")
+        );
+
+        assert_eq!(Value::Integer(1), run(&mut db, "BITPOS key 1"));
+        assert_eq!(Value::Integer(0), run(&mut db, "BITPOS missing 0"));
+        assert_eq!(Value::Integer(-1), run(&mut db, "BITPOS missing 1"));
+
+        assert_eq!(Value::Integer(6), run(&mut db, "BITOP AND dest key key"));
+        assert_eq!(Value::bulk("foobar"), run(&mut db, "GET dest"));
+        assert_eq!(
+            Value::error("ERR BITOP NOT must be called with a single source key."),
+            run(&mut db, "BITOP NOT dest key key")
+        );
+    }
+
+    #[test]
+    fn test_bitpos_ranges() {
+        let mut db = Db::new();
+        db.set(b"key".to_vec(), Object::String(vec![0xff, 0xf0, 0x00]));
+
+        assert_eq!(Value::Integer(12), run(&mut db, "BITPOS key 0"));
+        assert_eq!(Value::Integer(8), run(&mut db, "BITPOS key 1 1"));
+        assert_eq!(Value::Integer(-1), run(&mut db, "BITPOS key 1 2 -1"));
+        assert_eq!(Value::Integer(7), run(&mut db, "BITPOS key 1 7 15 BIT"));
+
+        db.set(b"ones".to_vec(), Object::String(vec![0xff]));
+        // without an explicit end the string is considered padded with zeroes
+        assert_eq!(Value::Integer(8), run(&mut db, "BITPOS ones 0"));
+        assert_eq!(Value::Integer(-1), run(&mut db, "BITPOS ones 0 0 -1"));
+    }
+
+    #[test]
+    fn test_bitfield() {
+        let mut db = Db::new();
+
+        assert_eq!(
+            Value::Array(vec![Value::Integer(0), Value::Integer(100)]),
+            run(&mut db, "BITFIELD key SET i8 0 100 GET i8 0")
+        );
+        assert_eq!(
+            Value::Array(vec![Value::Integer(100), Value::Integer(-56)]),
+            run(&mut db, "BITFIELD key GET i8 #0 INCRBY i8 0 100")
+        );
+        assert_eq!(
+            Value::Array(vec![Value::Integer(127), Value::Null]),
+            run(
+                &mut db,
+                "BITFIELD key OVERFLOW SAT INCRBY i8 0 300 OVERFLOW FAIL INCRBY i8 0 1"
+            )
+        );
+        assert_eq!(
+            Value::error("ERR BITFIELD_RO only supports the GET subcommand"),
+            run(&mut db, "BITFIELD_RO key SET i8 0 1")
+        );
+        assert_eq!(
+            Value::error(
+                "ERR Invalid bitfield type. Use something like i16 u8. \
+                Note that u64 is not supported but i64 is."
+            ),
+            run(&mut db, "BITFIELD key GET u64 0")
+        );
+    }
+
+    #[test]
+    fn test_sorted_set_commands() {
+        let mut db = Db::new();
+
+        assert_eq!(
+            Value::Integer(3),
+            run(&mut db, "ZADD z 1 one 2 two 3 three")
+        );
+        assert_eq!(Value::Integer(0), run(&mut db, "ZADD z NX 10 one"));
+        assert_eq!(Value::Integer(1), run(&mut db, "ZADD z XX CH 1.5 one"));
+        assert_eq!(Value::Integer(0), run(&mut db, "ZADD z GT 0 two"));
+        assert_eq!(Value::bulk("1.5"), run(&mut db, "ZSCORE z one"));
+        assert_eq!(Value::bulk("4"), run(&mut db, "ZADD z INCR 2 two"));
+        assert_eq!(
+            Value::Array(vec![
+                Value::bulk("three"),
+                Value::bulk("3"),
+                Value::bulk("two"),
+                Value::bulk("4")
+            ]),
+            run(&mut db, "ZRANGE z 1 -1 WITHSCORES")
+        );
+        assert_eq!(Value::Integer(1), run(&mut db, "ZREM z one missing"));
+        assert_eq!(Value::Integer(2), run(&mut db, "ZCARD z"));
+
+        assert_eq!(
+            Value::error("ERR XX and NX options at the same time are not compatible"),
+            run(&mut db, "ZADD z NX XX 1 one")
+        );
+        assert_eq!(
+            Value::error("ERR value is not a valid float"),
+            run(&mut db, "ZADD z abc one")
+        );
+        run(&mut db, "SET greeting hello");
+        assert_eq!(
+            Value::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            run(&mut db, "ZADD greeting 1 one")
+        );
+        assert_eq!(
+            Value::error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            run(&mut db, "GET z")
+        );
+    }
+
+    // the examples of the Redis documentation
+    fn sicily() -> Db {
+        let mut db = Db::new();
+        assert_eq!(
+            Value::Integer(2),
+            run(
+                &mut db,
+                "GEOADD Sicily 13.361389 38.115556 Palermo 15.087269 37.502669 Catania"
+            )
+        );
+        db
+    }
+
+    fn position(longitude: &str, latitude: &str) -> Value {
+        Value::Array(vec![Value::bulk(longitude), Value::bulk(latitude)])
+    }
+
+    #[test]
+    fn test_geoadd_geopos_geohash() {
+        let mut db = sicily();
+
+        assert_eq!(
+            Value::Array(vec![
+                position("13.36138933897018433", "38.11555639549629859"),
+                position("15.08726745843887329", "37.50266842333162032"),
+                Value::NullArray,
+            ]),
+            run(&mut db, "GEOPOS Sicily Palermo Catania NonExisting")
+        );
+        assert_eq!(
+            Value::Array(vec![Value::bulk("sqc8b49rny0"), Value::bulk("sqdtr74hyu0")]),
+            run(&mut db, "GEOHASH Sicily Palermo Catania")
+        );
+        assert_eq!(
+            Value::Array(vec![
+                Value::bulk("Palermo"),
+                Value::bulk("3479099956230698"),
+                Value::bulk("Catania"),
+                Value::bulk("3479447370796909"),
+            ]),
+            run(&mut db, "ZRANGE Sicily 0 -1 WITHSCORES")
+        );
+
+        assert_eq!(
+            Value::error("ERR invalid longitude,latitude pair 200.000000,100.000000"),
+            run(&mut db, "GEOADD Sicily 200 100 Nowhere")
+        );
+        assert_eq!(
+            Value::error(
+                "ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... "
+            ),
+            run(&mut db, "GEOADD Sicily 13.361389 38.115556 Palermo 15")
+        );
+        assert_eq!(
+            Value::Integer(0),
+            run(&mut db, "GEOADD Sicily XX 13.361389 38.115556 Palermo")
+        );
+    }
+
+    #[test]
+    fn test_geodist() {
+        let mut db = sicily();
+
+        assert_eq!(
+            Value::bulk("166274.1516"),
+            run(&mut db, "GEODIST Sicily Palermo Catania")
+        );
+        assert_eq!(
+            Value::bulk("166.2742"),
+            run(&mut db, "GEODIST Sicily Palermo Catania km")
+        );
+        assert_eq!(
+            Value::bulk("103.3182"),
+            run(&mut db, "GEODIST Sicily Palermo Catania MI")
+        );
+        assert_eq!(Value::Null, run(&mut db, "GEODIST Sicily Foo Bar"));
+        assert_eq!(
+            Value::error("ERR unsupported unit provided. please use M, KM, FT, MI"),
+            run(&mut db, "GEODIST Sicily Palermo Catania yd")
+        );
+    }
+
+    #[test]
+    fn test_geosearch() {
+        let mut db = sicily();
+        run(
+            &mut db,
+            "GEOADD Sicily 12.758489 38.788135 edge1 17.241510 38.788135 edge2",
+        );
+
+        assert_eq!(
+            Value::Array(vec![Value::bulk("Catania"), Value::bulk("Palermo")]),
+            run(
+                &mut db,
+                "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 200 km ASC"
+            )
+        );
+        let item = |member: &str, dist: &str, longitude: &str, latitude: &str| {
+            Value::Array(vec![
+                Value::bulk(member),
+                Value::bulk(dist),
+                position(longitude, latitude),
+            ])
+        };
+        assert_eq!(
+            Value::Array(vec![
+                item(
+                    "Catania",
+                    "56.4413",
+                    "15.08726745843887329",
+                    "37.50266842333162032"
+                ),
+                item(
+                    "Palermo",
+                    "190.4424",
+                    "13.36138933897018433",
+                    "38.11555639549629859"
+                ),
+                item(
+                    "edge2",
+                    "279.7403",
+                    "17.24151045083999634",
+                    "38.78813451624225195"
+                ),
+                item(
+                    "edge1",
+                    "279.7405",
+                    "12.7584877610206604",
+                    "38.78813451624225195"
+                ),
+            ]),
+            run(
+                &mut db,
+                "GEOSEARCH Sicily FROMLONLAT 15 37 BYBOX 400 400 km ASC WITHCOORD WITHDIST"
+            )
+        );
+        assert_eq!(
+            Value::Array(vec![Value::Array(vec![
+                Value::bulk("Palermo"),
+                Value::bulk("0.0000"),
+                Value::Integer(3479099956230698),
+            ])]),
+            run(
+                &mut db,
+                "GEOSEARCH Sicily FROMMEMBER Palermo BYRADIUS 100 km COUNT 1 WITHDIST WITHHASH"
+            )
+        );
+        assert_eq!(
+            Value::Array(vec![]),
+            run(
+                &mut db,
+                "GEOSEARCH missing FROMLONLAT 15 37 BYRADIUS 200 km"
+            )
+        );
+    }
+
+    #[test]
+    fn test_geosearch_errors() {
+        let mut db = sicily();
+
+        for (command, error) in [
+            (
+                "GEOSEARCH Sicily BYRADIUS 200 km ASC WITHDIST",
+                "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH",
+            ),
+            (
+                "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 200 km BYBOX 1 1 km",
+                "ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH",
+            ),
+            (
+                "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 200 km ANY",
+                "ERR the ANY argument requires COUNT argument",
+            ),
+            (
+                "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 200 km COUNT 0",
+                "ERR COUNT must be > 0",
+            ),
+            (
+                "GEOSEARCH Sicily FROMMEMBER Rome BYRADIUS 200 km",
+                "ERR could not decode requested zset member",
+            ),
+            (
+                "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS -1 km",
+                "ERR radius cannot be negative",
+            ),
+            (
+                "GEOSEARCH Sicily FROMLONLAT 15 37 BYBOX wide 1 km",
+                "ERR need numeric width",
+            ),
+        ] {
+            assert_eq!(Value::error(error), run(&mut db, command), "{command}");
+        }
+    }
+
+    #[test]
+    fn test_replicaof_and_role() {
+        let mut db = Db::new();
+
+        assert_eq!(Value::bulk("master"), role(&mut db)[0]);
+        assert_eq!(Value::ok(), run(&mut db, "REPLICAOF 127.0.0.1 6380"));
+        assert_eq!(
+            vec![
+                Value::bulk("slave"),
+                Value::bulk("127.0.0.1"),
+                Value::Integer(6380),
+                Value::bulk("connected"),
+                Value::Integer(0),
+            ],
+            role(&mut db)
+        );
+        assert_eq!(
+            Value::error("ERR Invalid master port"),
+            run(&mut db, "REPLICAOF 127.0.0.1 70000")
+        );
+        assert_eq!(Value::ok(), run(&mut db, "REPLICAOF NO ONE"));
+        assert_eq!(Value::bulk("master"), role(&mut db)[0]);
+    }
+
+    fn role(db: &mut Db) -> Vec<Value> {
+        match run(db, "ROLE") {
+            Value::Array(items) => items,
+            other => panic!("unexpected reply {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut db = Db::new();
+
+        assert_eq!(Value::Integer(1), run(&mut db, "INCR counter"));
+        assert_eq!(Value::Integer(11), run(&mut db, "INCRBY counter 10"));
+        assert_eq!(Value::Integer(10), run(&mut db, "DECR counter"));
+        assert_eq!(Value::Integer(-5), run(&mut db, "DECRBY counter 15"));
+        assert_eq!(Some(&Object::Int(-5)), db.get(b"counter"));
+
+        run(&mut db, "SET max 9223372036854775807");
+        assert_eq!(
+            Value::error("ERR increment or decrement would overflow"),
+            run(&mut db, "INCR max")
+        );
+        assert_eq!(
+            Value::error("ERR decrement would overflow"),
+            run(&mut db, "DECRBY counter -9223372036854775808")
+        );
+        run(&mut db, "SET padded 007");
+        assert_eq!(
+            Value::error("ERR value is not an integer or out of range"),
+            run(&mut db, "INCR padded")
+        );
+        assert_eq!(
+            Value::error("ERR value is not an integer or out of range"),
+            run(&mut db, "INCRBY counter 1.5")
+        );
+    }
+
+    #[test]
+    fn test_incrbyfloat() {
+        let mut db = Db::new();
+
+        run(&mut db, "SET mykey 10.50");
+        assert_eq!(Value::bulk("10.6"), run(&mut db, "INCRBYFLOAT mykey 0.1"));
+        assert_eq!(Value::bulk("5.6"), run(&mut db, "INCRBYFLOAT mykey -5"));
+        run(&mut db, "SET mykey 5.0e3");
+        assert_eq!(Value::bulk("5200"), run(&mut db, "INCRBYFLOAT mykey 2.0e2"));
+        assert_eq!(
+            Value::error("ERR increment would produce NaN or Infinity"),
+            run(&mut db, "INCRBYFLOAT mykey inf")
+        );
+        run(&mut db, "SET text hello");
+        assert_eq!(
+            Value::error("ERR value is not a valid float"),
+            run(&mut db, "INCRBYFLOAT text 1")
+        );
+    }
+
+    #[test]
+    fn test_string_commands() {
+        let mut db = Db::new();
+
+        assert_eq!(Value::Integer(5), run(&mut db, "APPEND greeting Hello"));
+        assert_eq!(Value::Integer(11), run(&mut db, "APPEND greeting _World"));
+        assert_eq!(Value::Integer(11), run(&mut db, "STRLEN greeting"));
+        assert_eq!(Value::bulk("Hello"), run(&mut db, "GETRANGE greeting 0 4"));
+        assert_eq!(
+            Value::bulk("World"),
+            run(&mut db, "GETRANGE greeting -5 -1")
+        );
+        assert_eq!(Value::bulk(""), run(&mut db, "GETRANGE greeting -1 -5"));
+        assert_eq!(
+            Value::bulk("Hello_World"),
+            run(&mut db, "GETRANGE greeting 0 100")
+        );
+        assert_eq!(
+            Value::Integer(11),
+            run(&mut db, "SETRANGE greeting 6 Redis")
+        );
+        assert_eq!(Value::bulk("Hello_Redis"), run(&mut db, "GET greeting"));
+        assert_eq!(Value::Integer(3), run(&mut db, "SETRANGE padded 1 ab"));
+        assert_eq!(Value::bulk("\0ab"), run(&mut db, "GET padded"));
+        assert_eq!(
+            Value::error("ERR offset is out of range"),
+            run(&mut db, "SETRANGE padded -1 ab")
+        );
+
+        // integer encoded values behave like strings
+        run(&mut db, "SET number 42");
+        assert_eq!(Value::Integer(2), run(&mut db, "STRLEN number"));
+        assert_eq!(Value::Integer(3), run(&mut db, "APPEND number 0"));
+        assert_eq!(Some(&Object::String(b"420".to_vec())), db.get(b"number"));
+    }
+
+    #[test]
+    fn test_multiple_keys() {
+        let mut db = Db::new();
+
+        assert_eq!(Value::ok(), run(&mut db, "MSET a 1 b 2"));
+        run(&mut db, "ZADD z 1 one");
+        assert_eq!(
+            Value::Array(vec![Value::bulk("1"), Value::Null, Value::Null]),
+            run(&mut db, "MGET a z missing")
+        );
+        assert_eq!(Value::Integer(0), run(&mut db, "MSETNX b 3 c 4"));
+        assert_eq!(Value::Integer(1), run(&mut db, "MSETNX c 3 d 4"));
+        assert_eq!(
+            Value::error("ERR wrong number of arguments for 'mset' command"),
+            run(&mut db, "MSET a 1 b")
+        );
+        assert_eq!(Value::bulk("3"), run(&mut db, "GETDEL c"));
+        assert_eq!(Value::Integer(0), run(&mut db, "EXISTS c"));
+    }
+
+    #[test]
+    fn test_set_options_and_getex() {
+        let mut db = Db::new();
+
+        assert_eq!(Value::ok(), run(&mut db, "SET session abc EX 100"));
+        assert_eq!(Value::Integer(100), run(&mut db, "TTL session"));
+        assert_eq!(Value::Null, run(&mut db, "SET session def NX"));
+        assert_eq!(
+            Value::bulk("abc"),
+            run(&mut db, "SET session def XX GET KEEPTTL")
+        );
+        assert_eq!(Value::Integer(100), run(&mut db, "TTL session"));
+        assert_eq!(Value::bulk("def"), run(&mut db, "GETEX session PERSIST"));
+        assert_eq!(Value::Integer(-1), run(&mut db, "TTL session"));
+        assert_eq!(Value::bulk("def"), run(&mut db, "GETEX session PX 5000"));
+        assert!(matches!(run(&mut db, "PTTL session"), Value::Integer(ms) if ms > 4000));
+        assert_eq!(Value::bulk("def"), run(&mut db, "GETEX session PXAT 1"));
+        assert_eq!(Value::Null, run(&mut db, "GET session"));
+        assert_eq!(Value::Integer(-2), run(&mut db, "TTL session"));
+
+        assert_eq!(
+            Value::error("ERR invalid expire time in 'set' command"),
+            run(&mut db, "SET key value EX 0")
+        );
+        assert_eq!(
+            Value::error("ERR invalid expire time in 'getex' command"),
+            run(&mut db, "GETEX key EX -1")
+        );
+        assert_eq!(
+            Value::error("ERR syntax error"),
+            run(&mut db, "SET key value NX XX")
+        );
+        assert_eq!(
+            Value::error("ERR syntax error"),
+            run(&mut db, "SET key value EX 10 KEEPTTL")
+        );
+    }
+
+    #[test]
+    fn test_copy_and_rename() {
+        let mut db = Db::new();
+        run(&mut db, "SET source hello EX 100");
+
+        assert_eq!(Value::Integer(1), run(&mut db, "COPY source copy"));
+        assert_eq!(Value::bulk("hello"), run(&mut db, "GET copy"));
+        assert_eq!(Value::Integer(100), run(&mut db, "TTL copy"));
+        assert_eq!(Value::Integer(0), run(&mut db, "COPY source copy"));
+        assert_eq!(
+            Value::Integer(1),
+            run(&mut db, "COPY source copy DB 0 REPLACE")
+        );
+        assert_eq!(
+            Value::error("ERR source and destination objects are the same"),
+            run(&mut db, "COPY source source")
+        );
+        assert_eq!(
+            Value::error("ERR DB index is out of range"),
+            run(&mut db, "COPY source other DB 1")
+        );
+
+        assert_eq!(Value::ok(), run(&mut db, "RENAME source renamed"));
+        assert_eq!(Value::Integer(0), run(&mut db, "EXISTS source"));
+        assert_eq!(Value::Integer(100), run(&mut db, "TTL renamed"));
+        assert_eq!(Value::Integer(0), run(&mut db, "RENAMENX renamed copy"));
+        assert_eq!(Value::Integer(1), run(&mut db, "RENAMENX renamed fresh"));
+        assert_eq!(
+            Value::error("ERR no such key"),
+            run(&mut db, "RENAME missing other")
+        );
+
+        assert_eq!(
+            Value::error("ERR syntax error"),
+            run(&mut db, "FLUSHALL NOW")
+        );
+        assert_eq!(Value::ok(), run(&mut db, "FLUSHDB ASYNC"));
+        assert_eq!(Value::Integer(0), run(&mut db, "EXISTS copy fresh"));
+    }
+
+    fn dump(db: &mut Db, key: &str) -> Vec<u8> {
+        match run(db, &format!("DUMP {key}")) {
+            Value::BulkString(payload) => payload,
+            reply => panic!("unexpected DUMP reply {reply:?}"),
+        }
+    }
+
+    fn restore(db: &mut Db, key: &str, ttl: &str, payload: &[u8], options: &[&str]) -> Value {
+        let mut args = vec![
+            b"RESTORE".to_vec(),
+            key.into(),
+            ttl.into(),
+            payload.to_vec(),
+        ];
+        args.extend(options.iter().map(|option| option.as_bytes().to_vec()));
+        execute(db, &args)
+    }
+
+    #[test]
+    fn test_dump_restore() {
+        let mut db = Db::new();
+        run(&mut db, "ZADD z 1 one 2 two");
+        run(&mut db, "SET greeting hello");
+        let payload = dump(&mut db, "z");
+        assert_eq!(Value::Null, run(&mut db, "DUMP missing"));
+
+        assert_eq!(Value::ok(), restore(&mut db, "copy", "5000", &payload, &[]));
+        assert_eq!(Value::Integer(2), run(&mut db, "ZCARD copy"));
+        assert_eq!(Value::Integer(5), run(&mut db, "TTL copy"));
+        assert_eq!(
+            Value::error("BUSYKEY Target key name already exists."),
+            restore(&mut db, "greeting", "0", &payload, &[])
+        );
+        assert_eq!(
+            Value::ok(),
+            restore(&mut db, "greeting", "0", &payload, &["REPLACE"])
+        );
+        assert_eq!(Value::bulk("2"), run(&mut db, "ZSCORE greeting two"));
+        // an absolute expiration in the past: the key is deleted
+        assert_eq!(
+            Value::ok(),
+            restore(&mut db, "greeting", "1", &payload, &["REPLACE", "ABSTTL"])
+        );
+        assert_eq!(Value::Integer(0), run(&mut db, "EXISTS greeting"));
+
+        let mut corrupted = payload.clone();
+        corrupted[2] ^= 0xff;
+        assert_eq!(
+            Value::error("ERR DUMP payload version or checksum are wrong"),
+            restore(&mut db, "other", "0", &corrupted, &[])
+        );
+        assert_eq!(
+            Value::error("ERR Invalid TTL value, must be >= 0"),
+            restore(&mut db, "other", "-1", &payload, &[])
+        );
+        assert_eq!(
+            Value::error("ERR syntax error"),
+            restore(
+                &mut db,
+                "other",
+                "0",
+                &payload,
+                &["IDLETIME", "1", "FREQ", "1"]
+            )
+        );
+        assert_eq!(
+            Value::error("ERR Invalid FREQ value, must be >= 0 and <= 255"),
+            restore(&mut db, "other", "0", &payload, &["FREQ", "256"])
+        );
+    }
+
+    #[test]
+    fn test_object() {
+        let mut db = Db::new();
+        run(&mut db, "SET counter 42");
+        run(&mut db, "SET greeting hello");
+        run(&mut db, &format!("SET long {}", "x".repeat(45)));
+        run(&mut db, "ZADD z 1 one");
+
+        assert_eq!(Value::bulk("int"), run(&mut db, "OBJECT ENCODING counter"));
+        assert_eq!(
+            Value::bulk("embstr"),
+            run(&mut db, "OBJECT encoding greeting")
+        );
+        assert_eq!(Value::bulk("raw"), run(&mut db, "OBJECT ENCODING long"));
+        assert_eq!(Value::bulk("listpack"), run(&mut db, "OBJECT ENCODING z"));
+        assert_eq!(Value::Null, run(&mut db, "OBJECT ENCODING missing"));
+
+        let payload = dump(&mut db, "greeting");
+        restore(&mut db, "idle", "0", &payload, &["IDLETIME", "100"]);
+        assert_eq!(Value::Integer(100), run(&mut db, "OBJECT IDLETIME idle"));
+        // OBJECT doesn't count as an access
+        assert_eq!(Value::Integer(100), run(&mut db, "OBJECT IDLETIME idle"));
+        run(&mut db, "GET idle");
+        assert_eq!(Value::Integer(0), run(&mut db, "OBJECT IDLETIME idle"));
+
+        restore(&mut db, "hot", "0", &payload, &["FREQ", "200"]);
+        assert_eq!(Value::Integer(200), run(&mut db, "OBJECT FREQ hot"));
+
+        assert_eq!(
+            Value::error("ERR unknown subcommand 'nope'. Try OBJECT HELP."),
+            run(&mut db, "OBJECT nope key")
+        );
+        assert_eq!(
+            Value::error("ERR wrong number of arguments for 'object|freq' command"),
+            run(&mut db, "OBJECT FREQ")
+        );
+    }
+
+    #[test]
+    fn test_migrate() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let target = Arc::new(Mutex::new(Db::new()));
+        let target_db = Arc::clone(&target);
+        thread::spawn(move || server::serve(listener, target_db));
+
+        let mut db = Db::new();
+        run(&mut db, "SET greeting hello EX 100");
+        run(&mut db, "SET counter 1");
+        run(&mut db, "SET other 2");
+
+        assert_eq!(
+            Value::ok(),
+            run(
+                &mut db,
+                &format!("MIGRATE 127.0.0.1 {port} greeting 0 1000")
+            )
+        );
+        assert_eq!(Value::Integer(0), run(&mut db, "EXISTS greeting"));
+        let mut target = target.lock().unwrap();
+        assert_eq!(Value::bulk("hello"), run(&mut target, "GET greeting"));
+        assert!(matches!(
+            run(&mut target, "TTL greeting"),
+            Value::Integer(99..=100)
+        ));
+        drop(target);
+
+        let keys = vec![
+            b"MIGRATE".to_vec(),
+            b"127.0.0.1".to_vec(),
+            port.to_string().into_bytes(),
+            vec![],
+            b"0".to_vec(),
+            b"1000".to_vec(),
+            b"COPY".to_vec(),
+            b"KEYS".to_vec(),
+            b"counter".to_vec(),
+            b"missing".to_vec(),
+        ];
+        assert_eq!(Value::ok(), execute(&mut db, &keys));
+        assert_eq!(Value::Integer(1), run(&mut db, "EXISTS counter"));
+        assert_eq!(
+            Value::error(
+                "ERR Target instance replied with error: BUSYKEY Target key name already exists."
+            ),
+            execute(&mut db, &keys)
+        );
+        assert_eq!(
+            Value::SimpleString("NOKEY".into()),
+            run(&mut db, &format!("MIGRATE 127.0.0.1 {port} missing 0 1000"))
+        );
+    }
+}
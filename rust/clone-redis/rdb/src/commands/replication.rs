@@ -0,0 +1,46 @@
+// REPLICAOF / ROLE
+//
+// Only the role of the server is tracked (enough for rdb-sentinel to fail over), the keyspace is not
+// streamed from the primary to its replicas.
+
+use crate::commands::{parse_i64, Reply};
+use crate::db::Db;
+use crate::resp::Value;
+
+pub fn replicaof(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let (host, port) = (&args[1], &args[2]);
+    if host.eq_ignore_ascii_case(b"no") && port.eq_ignore_ascii_case(b"one") {
+        db.replica_of = None;
+        return Ok(Value::ok());
+    }
+    let port = match parse_i64(port) {
+        Ok(port @ 0..=65535) => port as u16,
+        _ => return Err("ERR Invalid master port".into()),
+    };
+    let primary = (String::from_utf8_lossy(host).into_owned(), port);
+    if db.replica_of.as_ref() == Some(&primary) {
+        return Ok(Value::SimpleString(
+            "OK Already connected to specified master".into(),
+        ));
+    }
+    db.replica_of = Some(primary);
+    Ok(Value::ok())
+}
+
+pub fn role(db: &mut Db, _args: &[Vec<u8>]) -> Reply {
+    let reply = match &db.replica_of {
+        None => vec![
+            Value::bulk("master"),
+            Value::Integer(0),
+            Value::Array(vec![]),
+        ],
+        Some((host, port)) => vec![
+            Value::bulk("slave"),
+            Value::bulk(host.as_str()),
+            Value::Integer(*port as i64),
+            Value::bulk("connected"),
+            Value::Integer(0),
+        ],
+    };
+    Ok(Value::Array(reply))
+}
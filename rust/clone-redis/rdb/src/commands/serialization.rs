@@ -0,0 +1,224 @@
+// DUMP / RESTORE / MIGRATE, moving keys between servers, and SAVE
+
+use std::io::{self, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::commands::{parse_i64, Reply, SYNTAX_ERR};
+use crate::db::{now_ms, Db};
+use crate::resp::{self, Value};
+use crate::snapshot::{self, RestoreError, DEFAULT_DBFILENAME};
+
+pub fn dump(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    match db.get(&args[1]) {
+        Some(object) => Ok(Value::bulk(snapshot::dump(object))),
+        None => Ok(Value::Null),
+    }
+}
+
+// RESTORE key ttl serialized-value [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ frequency]
+pub fn restore(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let (key, payload) = (&args[1], &args[3]);
+    let (mut replace, mut absttl) = (false, false);
+    let (mut idle_time, mut frequency) = (None, None);
+    let mut i = 4;
+    while i < args.len() {
+        match args[i].to_ascii_lowercase().as_slice() {
+            b"replace" => replace = true,
+            b"absttl" => absttl = true,
+            b"idletime" if frequency.is_none() && i + 1 < args.len() => {
+                let seconds = parse_i64(&args[i + 1])?;
+                if seconds < 0 {
+                    return Err("ERR Invalid IDLETIME value, must be >= 0".into());
+                }
+                idle_time = Some((seconds as u64).saturating_mul(1000));
+                i += 1;
+            }
+            b"freq" if idle_time.is_none() && i + 1 < args.len() => {
+                match parse_i64(&args[i + 1])? {
+                    freq @ 0..=255 => frequency = Some(freq as u8),
+                    _ => return Err("ERR Invalid FREQ value, must be >= 0 and <= 255".into()),
+                }
+                i += 1;
+            }
+            _ => return Err(SYNTAX_ERR.into()),
+        }
+        i += 1;
+    }
+
+    let ttl = parse_i64(&args[2])?;
+    if ttl < 0 {
+        return Err("ERR Invalid TTL value, must be >= 0".into());
+    }
+    if !replace && db.contains(key) {
+        return Err("BUSYKEY Target key name already exists.".into());
+    }
+    let object = snapshot::restore(payload).map_err(|e| match e {
+        RestoreError::VersionOrChecksum => "ERR DUMP payload version or checksum are wrong",
+        RestoreError::BadFormat(_) => "ERR Bad data format",
+    })?;
+
+    let expire_at = match ttl {
+        0 => None,
+        ttl if absttl => Some(ttl as u64),
+        ttl => Some(now_ms().saturating_add(ttl as u64)),
+    };
+    if expire_at.is_some_and(|at| at <= now_ms()) {
+        // already expired: only the old value goes away
+        db.remove(key);
+        return Ok(Value::ok());
+    }
+    db.set(key.clone(), object);
+    if let Some(at) = expire_at {
+        db.set_expire_at(key, at);
+    }
+    db.set_access(key, idle_time, frequency);
+    Ok(Value::ok())
+}
+
+// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE] [AUTH password]
+//     [AUTH2 username password] [KEYS key [key ...]]
+//
+// The keys are sent with RESTORE on a new connection, the server is blocked until the target replied.
+pub fn migrate(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let (mut copy, mut replace) = (false, false);
+    let mut auth: Vec<&[u8]> = vec![];
+    let mut keys = vec![&args[3]];
+    let mut i = 6;
+    while i < args.len() {
+        match args[i].to_ascii_lowercase().as_slice() {
+            b"copy" => copy = true,
+            b"replace" => replace = true,
+            b"auth" if i + 1 < args.len() => {
+                auth = vec![&args[i + 1]];
+                i += 1;
+            }
+            b"auth2" if i + 2 < args.len() => {
+                auth = vec![&args[i + 1], &args[i + 2]];
+                i += 2;
+            }
+            b"keys" => {
+                if !args[3].is_empty() {
+                    return Err(
+                        "ERR When using MIGRATE KEYS option, the key argument must be \
+                        set to the empty string"
+                            .into(),
+                    );
+                }
+                keys = args[i + 1..].iter().collect();
+                break;
+            }
+            _ => return Err(SYNTAX_ERR.into()),
+        }
+        i += 1;
+    }
+    let destination_db = parse_i64(&args[4])?;
+    let timeout = match parse_i64(&args[5])? {
+        ms if ms <= 0 => Duration::from_secs(1),
+        ms => Duration::from_millis(ms as u64),
+    };
+
+    let keys: Vec<_> = keys.into_iter().filter(|key| db.contains(key)).collect();
+    if keys.is_empty() {
+        return Ok(Value::SimpleString("NOKEY".into()));
+    }
+
+    // all the commands are pipelined
+    let mut commands = vec![];
+    if !auth.is_empty() {
+        commands.push(
+            [&b"AUTH"[..]]
+                .iter()
+                .chain(&auth)
+                .map(|arg| arg.to_vec())
+                .collect(),
+        );
+    }
+    if destination_db != 0 {
+        commands.push(vec![b"SELECT".to_vec(), args[4].clone()]);
+    }
+    let commands_before_keys = commands.len();
+    for key in &keys {
+        let ttl = match db.expire_at(key) {
+            Some(at) => at.saturating_sub(now_ms()).max(1),
+            None => 0,
+        };
+        let payload = db.get(key).map(snapshot::dump).expect("the key exists");
+        let mut restore = vec![
+            b"RESTORE".to_vec(),
+            key.to_vec(),
+            ttl.to_string().into_bytes(),
+        ];
+        restore.push(payload);
+        if replace {
+            restore.push(b"REPLACE".to_vec());
+        }
+        commands.push(restore);
+    }
+
+    let host = String::from_utf8_lossy(&args[1]);
+    let port = String::from_utf8_lossy(&args[2]);
+    let stream = connect(&format!("{host}:{port}"), timeout)
+        .map_err(|_| "IOERR error or timeout connecting to the client")?;
+    let replies = send_all(&stream, &commands)
+        .map_err(|_| "IOERR error or timeout writing to target instance")?;
+
+    let mut error = None;
+    for (n, reply) in replies.enumerate() {
+        let reply = reply.map_err(|_| "IOERR error or timeout reading to target instance")?;
+        match reply {
+            Value::Error(message) => {
+                error.get_or_insert(message);
+            }
+            // keys that were restored are not ours anymore
+            _ if n >= commands_before_keys && !copy => {
+                db.remove(keys[n - commands_before_keys]);
+            }
+            _ => {}
+        }
+    }
+    match error {
+        Some(message) => Err(format!("ERR Target instance replied with error: {message}")),
+        None => Ok(Value::ok()),
+    }
+}
+
+fn connect(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let socket_addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid address"))?;
+    let stream = TcpStream::connect_timeout(&socket_addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+// the replies are read lazily, in the order of the commands
+fn send_all<'a>(
+    stream: &'a TcpStream,
+    commands: &[Vec<Vec<u8>>],
+) -> io::Result<impl Iterator<Item = io::Result<Value>> + 'a> {
+    let mut buffer = vec![];
+    for command in commands {
+        let command = Value::Array(command.iter().map(|arg| Value::bulk(arg.clone())).collect());
+        buffer.extend_from_slice(&command.encode());
+    }
+    (&*stream).write_all(&buffer)?;
+
+    let mut reader = BufReader::new(stream);
+    Ok((0..commands.len()).map(move |_| {
+        resp::read_value(&mut reader)?.ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+    }))
+}
+
+pub fn save(db: &mut Db, _args: &[Vec<u8>]) -> Reply {
+    let path = db
+        .dbfilename
+        .clone()
+        .unwrap_or_else(|| DEFAULT_DBFILENAME.into());
+    snapshot::save_to_file(db, Path::new(&path))
+        .map_err(|e| format!("ERR error saving the snapshot to {}: {e}", path.display()))?;
+    Ok(Value::ok())
+}
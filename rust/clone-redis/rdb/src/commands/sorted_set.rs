@@ -0,0 +1,203 @@
+// ZADD / ZSCORE / ZREM / ZCARD / ZRANGE
+
+use crate::commands::{parse_f64, parse_i64, Reply, SYNTAX_ERR};
+use crate::db::Db;
+use crate::resp::Value;
+
+pub const XX_NX_ERR: &str = "ERR XX and NX options at the same time are not compatible";
+
+/// The update flags shared by ZADD and GEOADD.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AddFlags {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    pub ch: bool,
+}
+
+/// Adds or updates the members, returns the number of added (or changed with CH) members.
+pub fn add_members(
+    db: &mut Db,
+    key: &[u8],
+    flags: AddFlags,
+    members: Vec<(f64, Vec<u8>)>,
+) -> Result<i64, String> {
+    // XX never creates the key
+    if db.get_sorted_set(key)?.is_none() && flags.xx {
+        return Ok(0);
+    }
+    let set = db.get_sorted_set_or_insert(key)?;
+    let mut added = 0;
+    let mut updated = 0;
+    for (score, member) in members {
+        // GT / LT only update when the new score is greater / less than the current one
+        let blocked = |current: f64| {
+            flags.nx || (flags.gt && score <= current) || (flags.lt && score >= current)
+        };
+        match set.score(&member) {
+            None if !flags.xx => {
+                set.insert(member, score);
+                added += 1;
+            }
+            Some(current) if current != score && !blocked(current) => {
+                set.insert(member, score);
+                updated += 1;
+            }
+            _ => {}
+        }
+    }
+    if set.is_empty() {
+        db.remove(key);
+    }
+    Ok(if flags.ch { added + updated } else { added })
+}
+
+/// Formats a score like Redis replies with doubles.
+pub fn format_score(score: f64) -> String {
+    match score {
+        f64::INFINITY => "inf".into(),
+        f64::NEG_INFINITY => "-inf".into(),
+        _ => score.to_string(),
+    }
+}
+
+pub fn zadd(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let mut flags = AddFlags::default();
+    let mut incr = false;
+    let mut i = 2;
+    while let Some(arg) = args.get(i) {
+        match arg.to_ascii_lowercase().as_slice() {
+            b"nx" => flags.nx = true,
+            b"xx" => flags.xx = true,
+            b"gt" => flags.gt = true,
+            b"lt" => flags.lt = true,
+            b"ch" => flags.ch = true,
+            b"incr" => incr = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let pairs = &args[i..];
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return Err(SYNTAX_ERR.into());
+    }
+    if flags.nx && flags.xx {
+        return Err(XX_NX_ERR.into());
+    }
+    if [flags.nx, flags.gt, flags.lt]
+        .iter()
+        .filter(|&&f| f)
+        .count()
+        > 1
+    {
+        return Err("ERR GT, LT, and/or NX options at the same time are not compatible".into());
+    }
+    if incr && pairs.len() > 2 {
+        return Err("ERR INCR option supports a single increment-element pair".into());
+    }
+    let members = pairs
+        .chunks(2)
+        .map(|pair| Ok((parse_f64(&pair[0])?, pair[1].clone())))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if incr {
+        let (increment, member) = members.into_iter().next().unwrap_or_default();
+        let current = db
+            .get_sorted_set(&args[1])?
+            .and_then(|set| set.score(&member));
+        if (flags.nx && current.is_some()) || (flags.xx && current.is_none()) {
+            return Ok(Value::Null);
+        }
+        let score = current.unwrap_or(0.0) + increment;
+        if score.is_nan() {
+            return Err("ERR resulting score is not a number (NaN)".into());
+        }
+        let flags = AddFlags { ch: true, ..flags };
+        let changed = add_members(db, &args[1], flags, vec![(score, member)])?;
+        return Ok(match current {
+            // GT / LT prevented the update
+            Some(current) if changed == 0 && current != score => Value::Null,
+            _ => Value::bulk(format_score(score)),
+        });
+    }
+
+    Ok(Value::Integer(add_members(db, &args[1], flags, members)?))
+}
+
+pub fn zscore(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    match db
+        .get_sorted_set(&args[1])?
+        .and_then(|set| set.score(&args[2]))
+    {
+        Some(score) => Ok(Value::bulk(format_score(score))),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn zrem(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let Some(set) = db.get_sorted_set_mut(&args[1])? else {
+        return Ok(Value::Integer(0));
+    };
+    let removed = args[2..].iter().filter(|member| set.remove(member)).count();
+    if set.is_empty() {
+        db.remove(&args[1]);
+    }
+    Ok(Value::Integer(removed as i64))
+}
+
+pub fn zcard(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let len = db.get_sorted_set(&args[1])?.map_or(0, |set| set.len());
+    Ok(Value::Integer(len as i64))
+}
+
+// only the rank based form: ZRANGE key start stop [REV] [WITHSCORES]
+pub fn zrange(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let start = parse_i64(&args[2])?;
+    let stop = parse_i64(&args[3])?;
+    let mut rev = false;
+    let mut with_scores = false;
+    for arg in &args[4..] {
+        match arg.to_ascii_lowercase().as_slice() {
+            b"rev" => rev = true,
+            b"withscores" => with_scores = true,
+            _ => return Err(SYNTAX_ERR.into()),
+        }
+    }
+
+    let Some(set) = db.get_sorted_set(&args[1])? else {
+        return Ok(Value::Array(vec![]));
+    };
+    let len = set.len() as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        return Ok(Value::Array(vec![]));
+    }
+
+    let members: Box<dyn Iterator<Item = (&[u8], f64)>> = if rev {
+        Box::new(set.iter().rev())
+    } else {
+        Box::new(set.iter())
+    };
+    let mut reply = vec![];
+    for (member, score) in members
+        .skip(start as usize)
+        .take((stop - start + 1) as usize)
+    {
+        reply.push(Value::bulk(member));
+        if with_scores {
+            reply.push(Value::bulk(format_score(score)));
+        }
+    }
+    Ok(Value::Array(reply))
+}
@@ -0,0 +1,293 @@
+// Commands working on string values
+
+use crate::commands::{parse_f64, parse_i64, Reply, NOT_AN_INTEGER_ERR, SYNTAX_ERR};
+use crate::db::{now_ms, Db, Object, WRONGTYPE};
+use crate::resp::Value;
+
+// strings are limited to 512MB
+const MAX_STRING_LEN: u64 = 512 * 1024 * 1024;
+const STRING_TOO_LONG_ERR: &str = "ERR string exceeds maximum allowed size (proto-max-bulk-len)";
+
+/// The expiration options of SET and GETEX.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expiration {
+    // Unix time in ms
+    At(u64),
+    KeepTtl,
+    Persist,
+}
+
+// EX / PX are relative, EXAT / PXAT absolute, in seconds or milliseconds
+fn parse_expiration(option: &[u8], arg: &[u8], command: &str) -> Result<Expiration, String> {
+    let invalid = || format!("ERR invalid expire time in '{command}' command");
+    let time = parse_i64(arg)?;
+    let (unit_ms, relative) = match option {
+        b"ex" => (1000, true),
+        b"px" => (1, true),
+        b"exat" => (1000, false),
+        _ => (1, false),
+    };
+    if time <= 0 {
+        return Err(invalid());
+    }
+    let base = if relative { now_ms() as i64 } else { 0 };
+    time.checked_mul(unit_ms)
+        .and_then(|ms| ms.checked_add(base))
+        .map(|at| Expiration::At(at as u64))
+        .ok_or_else(invalid)
+}
+
+fn apply_expiration(db: &mut Db, key: &[u8], expiration: Option<Expiration>) {
+    match expiration {
+        Some(Expiration::At(at)) => {
+            db.set_expire_at(key, at);
+        }
+        Some(Expiration::Persist) => {
+            db.persist(key);
+        }
+        Some(Expiration::KeepTtl) | None => {}
+    }
+}
+
+fn string_reply(db: &Db, key: &[u8]) -> Reply {
+    match db.get_string(key)? {
+        Some(bytes) => Ok(Value::bulk(bytes.into_owned())),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn get(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    string_reply(db, &args[1])
+}
+
+// SET key value [NX | XX] [GET] [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+//     PXAT unix-time-milliseconds | KEEPTTL]
+pub fn set(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let (key, value) = (&args[1], &args[2]);
+    let (mut nx, mut xx, mut get) = (false, false, false);
+    let mut expiration = None;
+    let mut i = 3;
+    while i < args.len() {
+        let option = args[i].to_ascii_lowercase();
+        match option.as_slice() {
+            b"nx" if !xx => nx = true,
+            b"xx" if !nx => xx = true,
+            b"get" => get = true,
+            b"keepttl" if expiration.is_none() => expiration = Some(Expiration::KeepTtl),
+            b"ex" | b"px" | b"exat" | b"pxat" if expiration.is_none() && i + 1 < args.len() => {
+                expiration = Some(parse_expiration(&option, &args[i + 1], "set")?);
+                i += 1;
+            }
+            _ => return Err(SYNTAX_ERR.into()),
+        }
+        i += 1;
+    }
+
+    let old_value = if get {
+        Some(string_reply(db, key)?)
+    } else {
+        None
+    };
+    let found = db.contains(key);
+    if (nx && found) || (xx && !found) {
+        return Ok(old_value.unwrap_or(Value::Null));
+    }
+
+    let object = Object::string(value.clone());
+    if expiration == Some(Expiration::KeepTtl) {
+        db.overwrite(key.clone(), object);
+    } else {
+        db.set(key.clone(), object);
+    }
+    apply_expiration(db, key, expiration);
+    Ok(old_value.unwrap_or_else(Value::ok))
+}
+
+pub fn getdel(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let reply = string_reply(db, &args[1])?;
+    db.remove(&args[1]);
+    Ok(reply)
+}
+
+// GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds | PXAT unix-time-milliseconds | PERSIST]
+pub fn getex(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let mut expiration = None;
+    let mut i = 2;
+    while i < args.len() {
+        let option = args[i].to_ascii_lowercase();
+        match option.as_slice() {
+            b"persist" if expiration.is_none() => expiration = Some(Expiration::Persist),
+            b"ex" | b"px" | b"exat" | b"pxat" if expiration.is_none() && i + 1 < args.len() => {
+                expiration = Some(parse_expiration(&option, &args[i + 1], "getex")?);
+                i += 1;
+            }
+            _ => return Err(SYNTAX_ERR.into()),
+        }
+        i += 1;
+    }
+
+    let reply = string_reply(db, &args[1])?;
+    if reply != Value::Null {
+        apply_expiration(db, &args[1], expiration);
+    }
+    Ok(reply)
+}
+
+pub fn mget(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    // keys that don't hold a string are reported as missing, not as an error
+    let values = args[1..]
+        .iter()
+        .map(|key| match db.get_string(key) {
+            Ok(Some(bytes)) => Value::bulk(bytes.into_owned()),
+            _ => Value::Null,
+        })
+        .collect();
+    Ok(Value::Array(values))
+}
+
+fn check_key_value_pairs(args: &[Vec<u8>]) -> Result<(), String> {
+    if args.len().is_multiple_of(2) {
+        return Err(format!(
+            "ERR wrong number of arguments for '{}' command",
+            String::from_utf8_lossy(&args[0]).to_lowercase()
+        ));
+    }
+    Ok(())
+}
+
+pub fn mset(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    check_key_value_pairs(args)?;
+    for pair in args[1..].chunks(2) {
+        db.set(pair[0].clone(), Object::string(pair[1].clone()));
+    }
+    Ok(Value::ok())
+}
+
+pub fn msetnx(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    check_key_value_pairs(args)?;
+    if args[1..].chunks(2).any(|pair| db.contains(&pair[0])) {
+        return Ok(Value::Integer(0));
+    }
+    mset(db, args)?;
+    Ok(Value::Integer(1))
+}
+
+pub fn append(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let len = db.get_string(&args[1])?.map_or(0, |bytes| bytes.len());
+    if len as u64 + args[2].len() as u64 > MAX_STRING_LEN {
+        return Err(STRING_TOO_LONG_ERR.into());
+    }
+    let bytes = db.get_string_or_insert(&args[1])?;
+    bytes.extend_from_slice(&args[2]);
+    Ok(Value::Integer(bytes.len() as i64))
+}
+
+pub fn strlen(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let len = match db.get(&args[1]) {
+        None => 0,
+        Some(Object::String(bytes)) => bytes.len(),
+        Some(Object::Int(n)) => n.to_string().len(),
+        Some(_) => return Err(WRONGTYPE.into()),
+    };
+    Ok(Value::Integer(len as i64))
+}
+
+pub fn getrange(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let start = parse_i64(&args[2])?;
+    let end = parse_i64(&args[3])?;
+    let Some(bytes) = db.get_string(&args[1])? else {
+        return Ok(Value::bulk(""));
+    };
+
+    let len = bytes.len() as i64;
+    if start < 0 && end < 0 && start > end {
+        return Ok(Value::bulk(""));
+    }
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let end = if end < 0 { len + end } else { end }.max(0).min(len - 1);
+    if start > end || len == 0 {
+        return Ok(Value::bulk(""));
+    }
+    Ok(Value::bulk(&bytes[start as usize..=end as usize]))
+}
+
+pub fn setrange(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let offset = parse_i64(&args[2])?;
+    if offset < 0 {
+        return Err("ERR offset is out of range".into());
+    }
+    let value = &args[3];
+    if value.is_empty() {
+        // nothing to write: don't create the key
+        return strlen(db, args);
+    }
+    // WRONGTYPE takes precedence over the size check
+    db.get_string(&args[1])?;
+    if offset as u64 + value.len() as u64 > MAX_STRING_LEN {
+        return Err(STRING_TOO_LONG_ERR.into());
+    }
+
+    let bytes = db.get_string_or_insert(&args[1])?;
+    let (offset, end) = (offset as usize, offset as usize + value.len());
+    if bytes.len() < end {
+        bytes.resize(end, 0);
+    }
+    bytes[offset..end].copy_from_slice(value);
+    Ok(Value::Integer(bytes.len() as i64))
+}
+
+fn get_integer(db: &Db, key: &[u8]) -> Result<i64, String> {
+    match db.get(key) {
+        None => Ok(0),
+        Some(Object::Int(n)) => Ok(*n),
+        Some(Object::String(bytes)) => parse_i64(bytes).map_err(|_| NOT_AN_INTEGER_ERR.into()),
+        Some(_) => Err(WRONGTYPE.into()),
+    }
+}
+
+fn incr_by(db: &mut Db, key: &[u8], increment: i64) -> Reply {
+    let value = get_integer(db, key)?
+        .checked_add(increment)
+        .ok_or("ERR increment or decrement would overflow")?;
+    db.overwrite(key.to_vec(), Object::Int(value));
+    Ok(Value::Integer(value))
+}
+
+pub fn incr(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    incr_by(db, &args[1], 1)
+}
+
+pub fn decr(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    incr_by(db, &args[1], -1)
+}
+
+pub fn incrby(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    incr_by(db, &args[1], parse_i64(&args[2])?)
+}
+
+pub fn decrby(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let decrement = parse_i64(&args[2])?;
+    if decrement == i64::MIN {
+        return Err("ERR decrement would overflow".into());
+    }
+    incr_by(db, &args[1], -decrement)
+}
+
+pub fn incrbyfloat(db: &mut Db, args: &[Vec<u8>]) -> Reply {
+    let current = match db.get_string(&args[1])? {
+        Some(bytes) => parse_f64(&bytes)?,
+        None => 0.0,
+    };
+    let increment = parse_f64(&args[2])?;
+    let value = current + increment;
+    if !value.is_finite() {
+        return Err("ERR increment would produce NaN or Infinity".into());
+    }
+    // the shortest representation that reads back as the same value, never in exponent form
+    let formatted = value.to_string();
+    db.overwrite(
+        args[1].clone(),
+        Object::String(formatted.clone().into_bytes()),
+    );
+    Ok(Value::bulk(formatted))
+}
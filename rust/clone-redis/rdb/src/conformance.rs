@@ -0,0 +1,396 @@
+// Differential testing: replays scripts of commands against rdb and a reference, a live Redis server or
+// a golden file of expected replies, and reports the first command whose replies differ
+//
+// script = one command per line, arguments split like redis-cli does (quotes, \x escapes), # comments
+// golden file = the RESP replies expected for the commands of a script, as received on the wire
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+
+use crate::resp::{self, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptCommand {
+    /// Line number in the script, from 1.
+    pub line: usize,
+    pub args: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    pub name: String,
+    pub commands: Vec<ScriptCommand>,
+}
+
+impl Script {
+    pub fn parse(name: impl Into<String>, text: &str) -> Result<Script, String> {
+        let name = name.into();
+        let mut commands = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let args = split_args(line).map_err(|e| format!("{name}:{}: {e}", n + 1))?;
+            commands.push(ScriptCommand { line: n + 1, args });
+        }
+        Ok(Script { name, commands })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Script> {
+        let text = fs::read_to_string(path)?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        Script::parse(name, &text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Splits a line into arguments like redis-cli: "double quotes" with C escapes, 'single quotes'.
+pub fn split_args(line: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(args);
+        };
+        let mut arg = vec![];
+        if first == '"' || first == '\'' {
+            chars.next();
+            loop {
+                match (chars.next(), first) {
+                    (None, _) => return Err("unbalanced quotes".into()),
+                    (Some(c), quote) if c == quote => break,
+                    (Some('\\'), '"') => match chars.next() {
+                        Some('x') => {
+                            let hex: String = chars.by_ref().take(2).collect();
+                            let byte = u8::from_str_radix(&hex, 16)
+                                .map_err(|_| format!("invalid escape \\x{hex}"))?;
+                            arg.push(byte);
+                        }
+                        Some('n') => arg.push(b'\n'),
+                        Some('r') => arg.push(b'\r'),
+                        Some('t') => arg.push(b'\t'),
+                        Some(c) => push_char(&mut arg, c),
+                        None => return Err("unbalanced quotes".into()),
+                    },
+                    (Some('\\'), _) if chars.peek() == Some(&'\'') => {
+                        chars.next();
+                        arg.push(b'\'');
+                    }
+                    (Some(c), _) => push_char(&mut arg, c),
+                }
+            }
+            // "a"b is ambiguous
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return Err("closing quote must be followed by a space".into());
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                push_char(&mut arg, c);
+            }
+        }
+        args.push(arg);
+    }
+}
+
+fn push_char(arg: &mut Vec<u8>, c: char) {
+    arg.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+/// Something that replies to commands: a live server or a golden file.
+pub trait Target {
+    fn reply(&mut self, args: &[Vec<u8>]) -> io::Result<Value>;
+}
+
+/// A live server, rdb or Redis.
+pub struct Server {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Server {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Server> {
+        let writer = TcpStream::connect(addr)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Server { reader, writer })
+    }
+
+    /// Removes all the keys, so that every script starts from an empty server.
+    pub fn reset(&mut self) -> io::Result<()> {
+        match self.reply(&[b"FLUSHALL".to_vec()])? {
+            Value::Error(message) => Err(io::Error::other(format!("FLUSHALL failed: {message}"))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Target for Server {
+    fn reply(&mut self, args: &[Vec<u8>]) -> io::Result<Value> {
+        self.writer.write_all(&encode_command(args))?;
+        resp::read_value(&mut self.reader)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"))
+    }
+}
+
+/// The replies expected for the commands of a script, in order.
+pub struct Golden {
+    replies: VecDeque<Value>,
+}
+
+impl Golden {
+    pub fn parse(mut bytes: &[u8]) -> io::Result<Golden> {
+        let mut replies = VecDeque::new();
+        while let Some(reply) = resp::read_value(&mut bytes)? {
+            replies.push_back(reply);
+        }
+        Ok(Golden { replies })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Golden> {
+        Golden::parse(&fs::read(path)?)
+    }
+
+    /// The replies not consumed yet, left over when the golden file was written for another script.
+    pub fn remaining(&self) -> usize {
+        self.replies.len()
+    }
+
+    /// Replays the script against the reference and returns its replies as a golden file.
+    pub fn record(script: &Script, reference: &mut dyn Target) -> io::Result<Vec<u8>> {
+        let mut golden = vec![];
+        for command in &script.commands {
+            golden.extend_from_slice(&reference.reply(&command.args)?.encode());
+        }
+        Ok(golden)
+    }
+}
+
+impl Target for Golden {
+    fn reply(&mut self, _args: &[Vec<u8>]) -> io::Result<Value> {
+        self.replies.pop_front().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the golden file has fewer replies than the script has commands",
+            )
+        })
+    }
+}
+
+fn encode_command(args: &[Vec<u8>]) -> Vec<u8> {
+    Value::Array(args.iter().map(|arg| Value::bulk(arg.clone())).collect()).encode()
+}
+
+/// The first command of a script that rdb and the reference reply differently to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub script: String,
+    pub line: usize,
+    pub command: Vec<Vec<u8>>,
+    pub rdb: Value,
+    pub reference: Value,
+}
+
+// the wire trace of ping-redis
+fn tx_info(f: &mut fmt::Formatter<'_>, header: &str, msg: &[u8]) -> fmt::Result {
+    writeln!(f, "{header}")?;
+    let text = String::from_utf8_lossy(msg);
+    for line in text.split("\r\n") {
+        writeln!(f, "| {line}")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}:{}: the replies of rdb and the reference differ",
+            self.script, self.line
+        )?;
+        let request = encode_command(&self.command);
+        tx_info(
+            f,
+            &format!("> {} bytes transmitted", request.len()),
+            &request,
+        )?;
+        let rdb = self.rdb.encode();
+        tx_info(
+            f,
+            &format!("< {} bytes transmitted by rdb", rdb.len()),
+            &rdb,
+        )?;
+        let reference = self.reference.encode();
+        tx_info(
+            f,
+            &format!("< {} bytes transmitted by the reference", reference.len()),
+            &reference,
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Divergence(Divergence),
+    /// rdb or the reference couldn't be reached, or the golden file is too short.
+    Io {
+        script: String,
+        line: usize,
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Divergence(divergence) => divergence.fmt(f),
+            ReplayError::Io {
+                script,
+                line,
+                source,
+            } => write!(f, "{script}:{line}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Sends every command of the script to both targets, stops at the first different reply.
+/// Returns the number of commands replayed.
+pub fn replay(
+    script: &Script,
+    rdb: &mut dyn Target,
+    reference: &mut dyn Target,
+) -> Result<usize, ReplayError> {
+    for command in &script.commands {
+        let io_error = |source| ReplayError::Io {
+            script: script.name.clone(),
+            line: command.line,
+            source,
+        };
+        let rdb_reply = rdb.reply(&command.args).map_err(io_error)?;
+        let reference_reply = reference.reply(&command.args).map_err(io_error)?;
+        if rdb_reply != reference_reply {
+            return Err(ReplayError::Divergence(Divergence {
+                script: script.name.clone(),
+                line: command.line,
+                command: command.args.clone(),
+                rdb: rdb_reply,
+                reference: reference_reply,
+            }));
+        }
+    }
+    Ok(script.commands.len())
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::{replay, split_args, Golden, ReplayError, Script, Server};
+    use crate::db::Db;
+    use crate::resp::Value;
+    use crate::server;
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::{fs, thread};
+
+    fn start_rdb_server() -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || server::serve(listener, Arc::new(Mutex::new(Db::new()))));
+        Server::connect(addr).unwrap()
+    }
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            args(&["SET", "key", "value"]),
+            split_args("SET  key value ").unwrap()
+        );
+        assert_eq!(
+            vec![b"SET".to_vec(), b"a key".to_vec(), b"\x00\r\n\"".to_vec()],
+            split_args(r#"SET "a key" "\x00\r\n\"""#).unwrap()
+        );
+        assert_eq!(
+            args(&["GET", "it's", ""]),
+            split_args(r"GET 'it\'s' ''").unwrap()
+        );
+        assert!(split_args(r#"GET "key"#).is_err());
+        assert!(split_args(r#"GET "a"b"#).is_err());
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let script = Script::parse(
+            "test.redis",
+            "# greeting\nSET greeting hello\n\nGET greeting\n",
+        )
+        .unwrap();
+        let golden = [Value::ok(), Value::bulk("hi")]
+            .iter()
+            .flat_map(Value::encode)
+            .collect::<Vec<u8>>();
+        let mut golden = Golden::parse(&golden).unwrap();
+
+        let Err(ReplayError::Divergence(divergence)) =
+            replay(&script, &mut start_rdb_server(), &mut golden)
+        else {
+            panic!("the replies of GET differ");
+        };
+
+        assert_eq!(4, divergence.line);
+        assert_eq!(
+            "test.redis:4: the replies of rdb and the reference differ\n\
+            > 27 bytes transmitted\n\
+            | *2\n| $3\n| GET\n| $8\n| greeting\n| \n\
+            < 11 bytes transmitted by rdb\n\
+            | $5\n| hello\n| \n\
+            < 8 bytes transmitted by the reference\n\
+            | $2\n| hi\n| \n",
+            divergence.to_string()
+        );
+    }
+
+    #[test]
+    fn test_golden_file_too_short() {
+        let script = Script::parse("test.redis", "PING\nPING").unwrap();
+        let mut golden = Golden::parse(b"+PONG\r\n").unwrap();
+
+        let result = replay(&script, &mut start_rdb_server(), &mut golden);
+
+        assert!(matches!(result, Err(ReplayError::Io { line: 2, .. })));
+    }
+
+    // the scripts of the golden directory against their expected replies
+    #[test]
+    fn test_golden_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden");
+        let mut scripts: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "redis"))
+            .collect();
+        scripts.sort();
+        assert!(!scripts.is_empty());
+
+        let mut rdb = start_rdb_server();
+        for path in scripts {
+            let script = Script::load(&path).unwrap();
+            let mut golden = Golden::load(&path.with_extension("golden")).unwrap();
+            rdb.reset().unwrap();
+
+            if let Err(e) = replay(&script, &mut rdb, &mut golden) {
+                panic!("{e}");
+            }
+            assert_eq!(0, golden.remaining(), "{} has unused replies", script.name);
+        }
+    }
+}
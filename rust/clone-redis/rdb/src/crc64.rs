@@ -0,0 +1,45 @@
+// CRC-64 with the Jones polynomial, the checksum of Redis DUMP payloads and RDB files
+// (reflected, initial value 0, no final xor)
+
+// 0xad93d23594c935a9 bit-reversed
+const POLY: u64 = 0x95ac9329ac4bc9b5;
+
+const TABLE: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continues the checksum `crc` with `bytes`, start with 0.
+pub fn crc64(crc: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(crc, |crc, &byte| {
+        TABLE[((crc ^ byte as u64) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::crc64;
+
+    #[test]
+    fn test_check_value() {
+        // the test vector of the Redis sources
+        assert_eq!(0xe9c6d914c4b8d9ca, crc64(0, b"123456789"));
+        assert_eq!(crc64(0, b"123456789"), crc64(crc64(0, b"1234"), b"56789"));
+    }
+}
@@ -0,0 +1,366 @@
+// The keyspace: every key maps to an object of one of the supported data types
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::hash_map::{self, HashMap, RandomState};
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::commands::parse_i64;
+use crate::sorted_set::SortedSet;
+
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    // binary-safe string, also used to store bitmaps and HyperLogLogs
+    String(Vec<u8>),
+    // a string that is the representation of a 64 bit integer, stored compactly
+    Int(i64),
+    SortedSet(SortedSet),
+}
+
+impl Object {
+    /// A string object, integer encoded when the bytes are the canonical form of an integer.
+    pub fn string(bytes: Vec<u8>) -> Object {
+        // like Redis, at most 20 chars = the length of i64::MIN
+        match parse_i64(&bytes) {
+            Ok(n) if bytes.len() <= 20 => Object::Int(n),
+            _ => Object::String(bytes),
+        }
+    }
+}
+
+/// Current Unix time in milliseconds, the unit of the expiration times.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// the LFU counter of Redis: logarithmic, starts at 5 so that new keys are not evicted right away
+// and decremented once per minute without access
+const LFU_INIT_VAL: u8 = 5;
+const LFU_LOG_FACTOR: f64 = 10.0;
+const LFU_DECAY_TIME_MS: u64 = 60 * 1000;
+
+// when a key was last read or written and how often, reported by OBJECT IDLETIME / FREQ
+#[derive(Debug, Clone, Copy)]
+struct Access {
+    last_ms: u64,
+    lfu_counter: u8,
+}
+
+impl Access {
+    fn new() -> Self {
+        Access {
+            last_ms: now_ms(),
+            lfu_counter: LFU_INIT_VAL,
+        }
+    }
+
+    fn decayed_counter(&self, now: u64) -> u8 {
+        let periods = now.saturating_sub(self.last_ms) / LFU_DECAY_TIME_MS;
+        self.lfu_counter.saturating_sub(periods.min(255) as u8)
+    }
+
+    fn touched(&self) -> Self {
+        let now = now_ms();
+        let mut counter = self.decayed_counter(now);
+        // the more accesses, the less likely the counter is to be incremented
+        let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+        if counter < 255 && random_f64() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+            counter += 1;
+        }
+        Access {
+            last_ms: now,
+            lfu_counter: counter,
+        }
+    }
+}
+
+fn random_f64() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[derive(Debug)]
+struct Entry {
+    object: Object,
+    // updated by lookups, which only borrow the keyspace
+    access: Cell<Access>,
+}
+
+impl Entry {
+    fn new(object: Object) -> Self {
+        Entry {
+            object,
+            access: Cell::new(Access::new()),
+        }
+    }
+
+    fn touch(&self) {
+        self.access.set(self.access.get().touched());
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Db {
+    entries: HashMap<Vec<u8>, Entry>,
+    // Unix time in ms when the key expires, expired keys are removed lazily when accessed
+    expires: HashMap<Vec<u8>, u64>,
+    // (host, port) of the primary when this server is a replica, set by REPLICAOF
+    pub replica_of: Option<(String, u16)>,
+    // the snapshot file written by SAVE
+    pub dbfilename: Option<PathBuf>,
+}
+
+impl Db {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn is_expired(&self, key: &[u8]) -> bool {
+        matches!(self.expires.get(key), Some(&at) if at <= now_ms())
+    }
+
+    fn remove_if_expired(&mut self, key: &[u8]) {
+        if self.is_expired(key) {
+            self.remove(key);
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&Object> {
+        let entry = self.peek_entry(key)?;
+        entry.touch();
+        Some(&entry.object)
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut Object> {
+        self.remove_if_expired(key);
+        let entry = self.entries.get_mut(key)?;
+        entry.touch();
+        Some(&mut entry.object)
+    }
+
+    fn peek_entry(&self, key: &[u8]) -> Option<&Entry> {
+        if self.is_expired(key) {
+            return None;
+        }
+        self.entries.get(key)
+    }
+
+    /// Looks up a key without counting it as an access, like OBJECT does.
+    pub fn peek(&self, key: &[u8]) -> Option<&Object> {
+        self.peek_entry(key).map(|entry| &entry.object)
+    }
+
+    /// Milliseconds since the key was last accessed.
+    pub fn idle_time_ms(&self, key: &[u8]) -> Option<u64> {
+        let access = self.peek_entry(key)?.access.get();
+        Some(now_ms().saturating_sub(access.last_ms))
+    }
+
+    /// The logarithmic access frequency counter of the key (0-255).
+    pub fn access_frequency(&self, key: &[u8]) -> Option<u8> {
+        let access = self.peek_entry(key)?.access.get();
+        Some(access.decayed_counter(now_ms()))
+    }
+
+    /// Sets the access metadata of a key, when it's restored from elsewhere.
+    pub fn set_access(&mut self, key: &[u8], idle_time_ms: Option<u64>, frequency: Option<u8>) {
+        if let Some(entry) = self.entries.get(key) {
+            let mut access = entry.access.get();
+            if let Some(idle) = idle_time_ms {
+                access.last_ms = now_ms().saturating_sub(idle);
+            }
+            if let Some(frequency) = frequency {
+                access.lfu_counter = frequency;
+            }
+            entry.access.set(access);
+        }
+    }
+
+    /// The keys that are not expired, with their object and expiration, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &Object, Option<u64>)> {
+        let now = now_ms();
+        self.entries.iter().filter_map(move |(key, entry)| {
+            let expire_at = self.expires.get(key).copied();
+            match expire_at {
+                Some(at) if at <= now => None,
+                _ => Some((key.as_slice(), &entry.object, expire_at)),
+            }
+        })
+    }
+
+    /// Sets the object of a key, discarding its time to live.
+    pub fn set(&mut self, key: Vec<u8>, object: Object) {
+        self.expires.remove(&key);
+        self.entries.insert(key, Entry::new(object));
+    }
+
+    /// Replaces the object of a key, keeping its time to live.
+    pub fn overwrite(&mut self, key: Vec<u8>, object: Object) {
+        self.remove_if_expired(&key);
+        self.entries.insert(key, Entry::new(object));
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<Object> {
+        self.expires.remove(key);
+        self.entries.remove(key).map(|entry| entry.object)
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        !self.is_expired(key) && self.entries.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all the keys.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.expires.clear();
+    }
+
+    /// Unix time in ms when the key expires, `None` for a missing key or a key without expiration.
+    pub fn expire_at(&self, key: &[u8]) -> Option<u64> {
+        if !self.contains(key) {
+            return None;
+        }
+        self.expires.get(key).copied()
+    }
+
+    /// Sets the expiration of an existing key, returns false when the key does not exist.
+    pub fn set_expire_at(&mut self, key: &[u8], at_ms: u64) -> bool {
+        if !self.contains(key) {
+            return false;
+        }
+        self.expires.insert(key.to_vec(), at_ms);
+        true
+    }
+
+    /// Removes the expiration of a key, returns true when the key had one.
+    pub fn persist(&mut self, key: &[u8]) -> bool {
+        self.contains(key) && self.expires.remove(key).is_some()
+    }
+
+    /// Looks up a string value, errors with WRONGTYPE when the key holds another type.
+    pub fn get_string(&self, key: &[u8]) -> Result<Option<Cow<'_, [u8]>>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Object::String(bytes)) => Ok(Some(Cow::Borrowed(bytes))),
+            Some(Object::Int(n)) => Ok(Some(Cow::Owned(n.to_string().into_bytes()))),
+            Some(_) => Err(WRONGTYPE.into()),
+        }
+    }
+
+    /// Returns the string value stored at `key`, creating an empty one if the key does not exist.
+    /// An integer encoded value is converted to a plain string, as it is about to be modified.
+    pub fn get_string_or_insert(&mut self, key: &[u8]) -> Result<&mut Vec<u8>, String> {
+        self.remove_if_expired(key);
+        let object = self.entry_or_insert(key, || Object::String(vec![]));
+        if let Object::Int(n) = object {
+            *object = Object::String(n.to_string().into_bytes());
+        }
+        match object {
+            Object::String(bytes) => Ok(bytes),
+            _ => Err(WRONGTYPE.into()),
+        }
+    }
+
+    pub fn get_sorted_set(&self, key: &[u8]) -> Result<Option<&SortedSet>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Object::SortedSet(set)) => Ok(Some(set)),
+            Some(_) => Err(WRONGTYPE.into()),
+        }
+    }
+
+    pub fn get_sorted_set_mut(&mut self, key: &[u8]) -> Result<Option<&mut SortedSet>, String> {
+        match self.get_mut(key) {
+            None => Ok(None),
+            Some(Object::SortedSet(set)) => Ok(Some(set)),
+            Some(_) => Err(WRONGTYPE.into()),
+        }
+    }
+
+    pub fn get_sorted_set_or_insert(&mut self, key: &[u8]) -> Result<&mut SortedSet, String> {
+        self.remove_if_expired(key);
+        let object = self.entry_or_insert(key, || Object::SortedSet(SortedSet::new()));
+        match object {
+            Object::SortedSet(set) => Ok(set),
+            _ => Err(WRONGTYPE.into()),
+        }
+    }
+
+    fn entry_or_insert(&mut self, key: &[u8], new_object: impl FnOnce() -> Object) -> &mut Object {
+        let entry = match self.entries.entry(key.to_vec()) {
+            hash_map::Entry::Occupied(entry) => {
+                entry.get().touch();
+                entry.into_mut()
+            }
+            hash_map::Entry::Vacant(entry) => entry.insert(Entry::new(new_object())),
+        };
+        &mut entry.object
+    }
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::{now_ms, Db, Object};
+
+    #[test]
+    fn test_integer_encoding() {
+        assert_eq!(Object::Int(-42), Object::string(b"-42".to_vec()));
+        assert_eq!(
+            Object::String(b"042".to_vec()),
+            Object::string(b"042".to_vec())
+        );
+        assert_eq!(
+            Object::String(b"1.5".to_vec()),
+            Object::string(b"1.5".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_expired_keys_are_gone() {
+        let mut db = Db::new();
+        db.set(b"key".to_vec(), Object::string(b"value".to_vec()));
+
+        assert!(db.set_expire_at(b"key", now_ms() - 1));
+
+        assert!(!db.contains(b"key"));
+        assert_eq!(None, db.get_string(b"key").unwrap());
+        assert!(!db.set_expire_at(b"missing", now_ms() + 1000));
+    }
+
+    #[test]
+    fn test_access_metadata() {
+        let mut db = Db::new();
+        db.set(b"key".to_vec(), Object::Int(1));
+        db.set_access(b"key", Some(120_000), Some(10));
+
+        assert!(db.idle_time_ms(b"key").unwrap() >= 120_000);
+        // decremented once per idle minute
+        assert_eq!(Some(8), db.access_frequency(b"key"));
+
+        db.get(b"key");
+        assert!(db.idle_time_ms(b"key").unwrap() < 1000);
+        assert_eq!(None, db.idle_time_ms(b"missing"));
+    }
+}
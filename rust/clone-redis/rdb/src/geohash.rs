@@ -0,0 +1,195 @@
+// 52 bit geohashes, the scores of the members of a geo sorted set = https://redis.io/docs/data-types/geospatial/
+//
+// Like Redis the latitude is limited to the range of the Web Mercator projection (EPSG:3857),
+// the interleaved bits of the longitude and latitude are stored as the score of a sorted set member.
+
+pub const GEO_STEP_MAX: u32 = 26;
+pub const GEO_LAT_MIN: f64 = -85.05112878;
+pub const GEO_LAT_MAX: f64 = 85.05112878;
+pub const GEO_LONG_MIN: f64 = -180.0;
+pub const GEO_LONG_MAX: f64 = 180.0;
+
+// Earth's quatratic mean radius for WGS-84
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+
+const GEO_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Range {
+    min: f64,
+    max: f64,
+}
+
+const LONG_RANGE: Range = Range {
+    min: GEO_LONG_MIN,
+    max: GEO_LONG_MAX,
+};
+const LAT_RANGE: Range = Range {
+    min: GEO_LAT_MIN,
+    max: GEO_LAT_MAX,
+};
+
+pub fn is_valid(longitude: f64, latitude: f64) -> bool {
+    (GEO_LONG_MIN..=GEO_LONG_MAX).contains(&longitude)
+        && (GEO_LAT_MIN..=GEO_LAT_MAX).contains(&latitude)
+}
+
+/// Encodes a position as the 52 bit geohash that is used as sorted set score.
+pub fn encode(longitude: f64, latitude: f64) -> Option<u64> {
+    encode_with_ranges(LONG_RANGE, LAT_RANGE, longitude, latitude)
+}
+
+fn encode_with_ranges(
+    long_range: Range,
+    lat_range: Range,
+    longitude: f64,
+    latitude: f64,
+) -> Option<u64> {
+    let in_range = |value: f64, range: Range| (range.min..=range.max).contains(&value);
+    if !is_valid(longitude, latitude)
+        || !in_range(longitude, long_range)
+        || !in_range(latitude, lat_range)
+    {
+        return None;
+    }
+    let scale = (1u64 << GEO_STEP_MAX) as f64;
+    let lat_offset = (latitude - lat_range.min) / (lat_range.max - lat_range.min) * scale;
+    let long_offset = (longitude - long_range.min) / (long_range.max - long_range.min) * scale;
+    Some(interleave(lat_offset as u32, long_offset as u32))
+}
+
+/// Decodes a geohash score into the (longitude, latitude) at the center of its area.
+pub fn decode(bits: u64) -> (f64, f64) {
+    let (ilato, ilono) = deinterleave(bits);
+    let scale = (1u64 << GEO_STEP_MAX) as f64;
+    let area = |index: u32, range: Range| {
+        let min = range.min + (index as f64 / scale) * (range.max - range.min);
+        let max = range.min + ((index as f64 + 1.0) / scale) * (range.max - range.min);
+        ((min + max) / 2.0).clamp(range.min, range.max)
+    };
+    (area(ilono, LONG_RANGE), area(ilato, LAT_RANGE))
+}
+
+/// The standard 11 character geohash string, re-encoded with a latitude range of -90..90.
+pub fn to_geohash_string(bits: u64) -> String {
+    let (longitude, latitude) = decode(bits);
+    let standard_lat_range = Range {
+        min: -90.0,
+        max: 90.0,
+    };
+    let bits = encode_with_ranges(LONG_RANGE, standard_lat_range, longitude, latitude).unwrap_or(0);
+    (0..11)
+        .map(|i| {
+            // only 52 bits available, the last character is padding
+            let index = if i == 10 {
+                0
+            } else {
+                (bits >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            GEO_ALPHABET[index as usize] as char
+        })
+        .collect()
+}
+
+fn deg_rad(angle: f64) -> f64 {
+    angle * (std::f64::consts::PI / 180.0)
+}
+
+pub fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS_IN_METERS * (deg_rad(lat2) - deg_rad(lat1)).abs()
+}
+
+/// Haversine distance in meters between two positions.
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let v = ((deg_rad(lon2) - deg_rad(lon1)) / 2.0).sin();
+    // same longitude: avoid the expensive math
+    if v == 0.0 {
+        return lat_distance(lat1, lat2);
+    }
+    let (lat1r, lat2r) = (deg_rad(lat1), deg_rad(lat2));
+    let u = ((lat2r - lat1r) / 2.0).sin();
+    let a = u * u + lat1r.cos() * lat2r.cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+/// Distance to the center when the position lies within the `width` x `height` box around it.
+pub fn distance_if_in_box(
+    width: f64,
+    height: f64,
+    (center_lon, center_lat): (f64, f64),
+    (lon, lat): (f64, f64),
+) -> Option<f64> {
+    if lat_distance(lat, center_lat) > height / 2.0 {
+        return None;
+    }
+    // the width is measured at the latitude of the position
+    if distance(lon, lat, center_lon, lat) > width / 2.0 {
+        return None;
+    }
+    Some(distance(center_lon, center_lat, lon, lat))
+}
+
+// latitude in the even bits, longitude in the odd bits
+fn interleave(lat: u32, long: u32) -> u64 {
+    (0..GEO_STEP_MAX).fold(0, |bits, i| {
+        bits | ((lat as u64 >> i) & 1) << (2 * i) | ((long as u64 >> i) & 1) << (2 * i + 1)
+    })
+}
+
+fn deinterleave(bits: u64) -> (u32, u32) {
+    (0..GEO_STEP_MAX).fold((0, 0), |(lat, long), i| {
+        (
+            lat | (((bits >> (2 * i)) & 1) as u32) << i,
+            long | (((bits >> (2 * i + 1)) & 1) as u32) << i,
+        )
+    })
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::{decode, distance, encode, to_geohash_string};
+
+    // GEOADD Sicily 13.361389 38.115556 "Palermo" 15.087269 37.502669 "Catania"
+    const PALERMO: (f64, f64) = (13.361389, 38.115556);
+    const CATANIA: (f64, f64) = (15.087269, 37.502669);
+
+    #[test]
+    fn test_encode_decode() {
+        let bits = encode(PALERMO.0, PALERMO.1).unwrap();
+
+        assert_eq!(3479099956230698, bits);
+        let (longitude, latitude) = decode(bits);
+        assert_eq!("13.36138933897018433", format!("{longitude:.17}"));
+        assert_eq!("38.11555639549629859", format!("{latitude:.17}"));
+    }
+
+    #[test]
+    fn test_invalid_positions_are_not_encoded() {
+        assert_eq!(None, encode(181.0, 0.0));
+        assert_eq!(None, encode(0.0, 86.0));
+    }
+
+    #[test]
+    fn test_geohash_string() {
+        assert_eq!(
+            "sqc8b49rny0",
+            to_geohash_string(encode(PALERMO.0, PALERMO.1).unwrap())
+        );
+        assert_eq!(
+            "sqdtr74hyu0",
+            to_geohash_string(encode(CATANIA.0, CATANIA.1).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_distance() {
+        let (lon1, lat1) = decode(encode(PALERMO.0, PALERMO.1).unwrap());
+        let (lon2, lat2) = decode(encode(CATANIA.0, CATANIA.1).unwrap());
+
+        assert_eq!(
+            "166274.1516",
+            format!("{:.4}", distance(lon1, lat1, lon2, lat2))
+        );
+    }
+}
@@ -0,0 +1,423 @@
+// HyperLogLog, stored in a string value with the same layout as Redis = https://redis.io/docs/data-types/probabilistic/hyperloglogs/
+//
+// +------+---+-----+----------+
+// | HYLL | E | N/U | Cardin.  |
+// +------+---+-----+----------+
+//
+// 4 bytes magic, 1 byte encoding (dense/sparse), 3 unused bytes and the cached cardinality
+// as a 64 bit little endian integer (most significant bit set = cache is invalid).
+// 16384 registers of 6 bits give a standard error of 1.04/sqrt(16384) = 0.81%.
+
+const HLL_P: u32 = 14;
+const HLL_Q: u32 = 64 - HLL_P;
+pub const HLL_REGISTERS: usize = 1 << HLL_P;
+const HLL_P_MASK: u64 = HLL_REGISTERS as u64 - 1;
+const HLL_BITS: usize = 6;
+const HLL_REGISTER_MAX: u8 = (1 << HLL_BITS) - 1;
+const HLL_HDR_SIZE: usize = 16;
+const HLL_DENSE_SIZE: usize = HLL_HDR_SIZE + (HLL_REGISTERS * HLL_BITS).div_ceil(8);
+const HLL_ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+const HLL_DENSE: u8 = 0;
+const HLL_SPARSE: u8 = 1;
+
+// above this size a sparse representation gets promoted to the dense one
+const HLL_SPARSE_MAX_BYTES: usize = 3000;
+const HLL_SPARSE_VAL_MAX_VALUE: u8 = 32;
+const HLL_SPARSE_VAL_MAX_LEN: usize = 4;
+const HLL_SPARSE_ZERO_MAX_LEN: usize = 64;
+const HLL_SPARSE_XZERO_MAX_LEN: usize = 16384;
+
+pub const INVALID_HLL_ERR: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+pub const CORRUPTED_HLL_ERR: &str = "INVALIDOBJ Corrupted HLL object detected";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Dense,
+    Sparse,
+}
+
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    encoding: Encoding,
+    registers: Vec<u8>,
+    cached_cardinality: Option<u64>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Sparse,
+            registers: vec![0; HLL_REGISTERS],
+            cached_cardinality: Some(0),
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the string representation, the error is the reply to send to the client.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.len() < HLL_HDR_SIZE || &bytes[..4] != b"HYLL" {
+            return Err(INVALID_HLL_ERR);
+        }
+        let encoding = match bytes[4] {
+            HLL_DENSE if bytes.len() == HLL_DENSE_SIZE => Encoding::Dense,
+            HLL_SPARSE => Encoding::Sparse,
+            _ => return Err(INVALID_HLL_ERR),
+        };
+        let registers = match encoding {
+            Encoding::Dense => dense_registers(&bytes[HLL_HDR_SIZE..]),
+            Encoding::Sparse => {
+                sparse_registers(&bytes[HLL_HDR_SIZE..]).ok_or(CORRUPTED_HLL_ERR)?
+            }
+        };
+        let cached_cardinality = if bytes[15] & (1 << 7) == 0 {
+            let mut card = [0; 8];
+            card.copy_from_slice(&bytes[8..16]);
+            Some(u64::from_le_bytes(card))
+        } else {
+            None
+        };
+        Ok(Self {
+            encoding,
+            registers,
+            cached_cardinality,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let sparse = self.sparse_representation();
+        let mut out = Vec::with_capacity(HLL_DENSE_SIZE);
+        out.extend_from_slice(b"HYLL");
+        out.push(if sparse.is_some() {
+            HLL_SPARSE
+        } else {
+            HLL_DENSE
+        });
+        out.extend_from_slice(&[0; 3]);
+        match self.cached_cardinality {
+            Some(card) => out.extend_from_slice(&card.to_le_bytes()),
+            None => out.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1 << 7]),
+        }
+        match sparse {
+            Some(sparse) => out.extend_from_slice(&sparse),
+            None => out.extend_from_slice(&dense_bytes(&self.registers)),
+        }
+        out
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        match self.encoding {
+            Encoding::Sparse if self.sparse_representation().is_none() => Encoding::Dense,
+            encoding => encoding,
+        }
+    }
+
+    // A sparse HyperLogLog gets promoted to the dense representation (for good)
+    // once a register value or the size of the sparse encoding exceeds the limits
+    fn sparse_representation(&self) -> Option<Vec<u8>> {
+        if self.encoding == Encoding::Dense
+            || self
+                .registers
+                .iter()
+                .any(|&value| value > HLL_SPARSE_VAL_MAX_VALUE)
+        {
+            return None;
+        }
+        let sparse = sparse_bytes(&self.registers);
+        (HLL_HDR_SIZE + sparse.len() <= HLL_SPARSE_MAX_BYTES).then_some(sparse)
+    }
+
+    /// Adds an element, returns true when a register got updated (so the cardinality may have changed).
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let (index, count) = pattern_len(element);
+        if self.registers[index] >= count {
+            return false;
+        }
+        self.registers[index] = count;
+        self.cached_cardinality = None;
+        true
+    }
+
+    /// Merges `other` into this HyperLogLog, keeping the maximum of every register.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, value) in self.registers.iter_mut().zip(&other.registers) {
+            if *value > *register {
+                *register = *value;
+            }
+        }
+        self.cached_cardinality = None;
+    }
+
+    pub fn to_dense(&mut self) {
+        self.encoding = Encoding::Dense;
+    }
+
+    pub fn invalidate_cache(&mut self) {
+        self.cached_cardinality = None;
+    }
+
+    pub fn has_valid_cache(&self) -> bool {
+        self.cached_cardinality.is_some()
+    }
+
+    /// Estimated cardinality, served from the cache when it is still valid.
+    pub fn count(&mut self) -> u64 {
+        if let Some(card) = self.cached_cardinality {
+            return card;
+        }
+        let card = estimate(&self.registers);
+        self.cached_cardinality = Some(card);
+        card
+    }
+}
+
+// Register index + length of the 000..1 pattern of the hashed element
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let mut hash = murmurhash64a(element, 0xadc83b19);
+    let index = (hash & HLL_P_MASK) as usize;
+    hash >>= HLL_P;
+    // make sure the loop terminates
+    hash |= 1 << HLL_Q;
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+// Cardinality estimator from "New cardinality estimation algorithms for HyperLogLog sketches"
+// by Otmar Ertl, like Redis does since 5.0
+fn estimate(registers: &[u8]) -> u64 {
+    let m = HLL_REGISTERS as f64;
+    let mut histogram = [0u32; 64];
+    for &value in registers {
+        histogram[value as usize] += 1;
+    }
+
+    let mut z = m * tau((m - histogram[HLL_Q as usize + 1] as f64) / m);
+    for j in (1..=HLL_Q as usize).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (HLL_ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let z_prime = z;
+        z += x * y;
+        y += y;
+        if z_prime == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let z_prime = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z_prime == z {
+            return z / 3.0;
+        }
+    }
+}
+
+// 6 bit registers, packed starting from the least significant bit of every byte
+fn dense_registers(bytes: &[u8]) -> Vec<u8> {
+    (0..HLL_REGISTERS)
+        .map(|regnum| {
+            let byte = regnum * HLL_BITS / 8;
+            let fb = ((regnum * HLL_BITS) & 7) as u32;
+            let b0 = bytes[byte] as u16;
+            let b1 = *bytes.get(byte + 1).unwrap_or(&0) as u16;
+            (((b0 >> fb) | (b1 << (8 - fb))) & HLL_REGISTER_MAX as u16) as u8
+        })
+        .collect()
+}
+
+fn dense_bytes(registers: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0u8; HLL_DENSE_SIZE - HLL_HDR_SIZE];
+    for (regnum, &value) in registers.iter().enumerate() {
+        let byte = regnum * HLL_BITS / 8;
+        let fb = ((regnum * HLL_BITS) & 7) as u32;
+        let value = value as u16;
+        bytes[byte] |= (value << fb) as u8;
+        if let Some(next) = bytes.get_mut(byte + 1) {
+            *next |= (value >> (8 - fb)) as u8;
+        }
+    }
+    bytes
+}
+
+// Sparse opcodes:
+// ZERO  = 00xxxxxx           -> run of 1..64 zero registers
+// XZERO = 01xxxxxx yyyyyyyy  -> run of 1..16384 zero registers
+// VAL   = 1vvvvvxx           -> run of 1..4 registers set to value 1..32
+fn sparse_registers(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut registers = Vec::with_capacity(HLL_REGISTERS);
+    let mut i = 0;
+    while i < bytes.len() {
+        let opcode = bytes[i];
+        let (value, len) = if opcode & 0xc0 == 0 {
+            i += 1;
+            (0, (opcode & 0x3f) as usize + 1)
+        } else if opcode & 0x80 == 0 {
+            let next = *bytes.get(i + 1)? as usize;
+            i += 2;
+            (0, (((opcode & 0x3f) as usize) << 8 | next) + 1)
+        } else {
+            i += 1;
+            (((opcode >> 2) & 0x1f) + 1, (opcode & 0x3) as usize + 1)
+        };
+        if registers.len() + len > HLL_REGISTERS {
+            return None;
+        }
+        registers.extend(std::iter::repeat_n(value, len));
+    }
+    (registers.len() == HLL_REGISTERS).then_some(registers)
+}
+
+fn sparse_bytes(registers: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < registers.len() {
+        let value = registers[i];
+        let mut run = registers[i..].iter().take_while(|&&v| v == value).count();
+        i += run;
+        if value == 0 {
+            while run > 0 {
+                let len = run.min(HLL_SPARSE_XZERO_MAX_LEN);
+                if len > HLL_SPARSE_ZERO_MAX_LEN {
+                    out.push(0x40 | ((len - 1) >> 8) as u8);
+                    out.push(((len - 1) & 0xff) as u8);
+                } else {
+                    out.push((len - 1) as u8);
+                }
+                run -= len;
+            }
+        } else {
+            while run > 0 {
+                let len = run.min(HLL_SPARSE_VAL_MAX_LEN);
+                out.push(0x80 | (value - 1) << 2 | (len - 1) as u8);
+                run -= len;
+            }
+        }
+    }
+    out
+}
+
+// MurmurHash2, 64 bit version (MurmurHash64A), endian neutral like the one Redis uses
+fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::{Encoding, HyperLogLog, HLL_DENSE_SIZE, INVALID_HLL_ERR};
+
+    #[test]
+    fn test_empty_hll_is_sparse() {
+        let hll = HyperLogLog::new();
+        let bytes = hll.to_bytes();
+
+        // header + a single XZERO opcode covering all 16384 registers
+        assert_eq!(b"HYLL\x01\0\0\0\0\0\0\0\0\0\0\0\x7f\xff", &bytes[..]);
+    }
+
+    #[test]
+    fn test_add_and_count_within_standard_error() {
+        let mut hll = HyperLogLog::new();
+        for i in 0..100_000 {
+            hll.add(format!("visitor:{i}").as_bytes());
+        }
+
+        let count = hll.count() as f64;
+
+        // 0.81% standard error, allow for 3 sigma
+        assert!((count - 100_000.0).abs() / 100_000.0 < 0.0243, "{count}");
+    }
+
+    #[test]
+    fn test_sparse_is_promoted_to_dense() {
+        let mut hll = HyperLogLog::new();
+        for i in 0..10 {
+            hll.add(format!("{i}").as_bytes());
+        }
+        assert_eq!(Encoding::Sparse, hll.encoding());
+        assert_eq!(10, hll.count());
+
+        for i in 10..5000 {
+            hll.add(format!("{i}").as_bytes());
+        }
+        assert_eq!(Encoding::Dense, hll.encoding());
+        assert_eq!(HLL_DENSE_SIZE, hll.to_bytes().len());
+    }
+
+    #[test]
+    fn test_roundtrip_keeps_registers_and_cache() {
+        for n in [10, 5000] {
+            let mut hll = HyperLogLog::new();
+            for i in 0..n {
+                hll.add(format!("{i}").as_bytes());
+            }
+            let count = hll.count();
+
+            let mut decoded = HyperLogLog::from_bytes(&hll.to_bytes()).unwrap();
+
+            assert_eq!(hll.registers, decoded.registers);
+            assert_eq!(Some(count), decoded.cached_cardinality);
+            assert_eq!(count, decoded.count());
+        }
+    }
+
+    #[test]
+    fn test_invalid_hll_is_rejected() {
+        assert_eq!(
+            INVALID_HLL_ERR,
+            HyperLogLog::from_bytes(b"not a hyperloglog").unwrap_err()
+        );
+    }
+}
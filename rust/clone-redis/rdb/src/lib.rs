@@ -1,3 +1,17 @@
+pub mod bitmap;
+pub mod client;
+pub mod commands;
+pub mod conformance;
+pub mod crc64;
+pub mod db;
+pub mod geohash;
+pub mod hyperloglog;
+pub mod resp;
+pub mod sentinel;
+pub mod server;
+pub mod snapshot;
+pub mod sorted_set;
+
 // Redis Bulk Strings = https://redis.io/docs/reference/protocol-spec/#resp-bulk-strings
 // check the balance between using the type system to guard against sending invalid data vs the ease of using Vec<u8>
 
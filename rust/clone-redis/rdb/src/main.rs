@@ -63,7 +63,7 @@ fn encode_and_write<W: Write>(
 
 impl Config {
     fn from_env() -> Self {
-        let args: Vec<_> = std::env::args().into_iter().collect();
+        let args: Vec<_> = std::env::args().collect();
         let host = String::from("localhost:6379");
 
         let pos = 1;
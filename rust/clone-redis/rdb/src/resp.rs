@@ -0,0 +1,272 @@
+// RESP values as they travel over the wire = https://redis.io/docs/reference/protocol-spec/
+
+use std::io::{self, BufRead, Read};
+
+// same limits as Redis: `proto-max-bulk-len` and the multibulk length it accepts
+pub(crate) const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+pub(crate) const MAX_MULTIBULK_LEN: usize = i32::MAX as usize;
+
+// the lengths come from the peer, so buffers start at most this big and grow with the data
+pub(crate) const INITIAL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    SimpleString(String),
+    Error(String),
+    Integer(i64),
+    BulkString(Vec<u8>),
+    Null,
+    Array(Vec<Value>),
+    NullArray,
+}
+
+impl Value {
+    pub fn ok() -> Value {
+        Value::SimpleString("OK".into())
+    }
+
+    pub fn error(msg: impl Into<String>) -> Value {
+        Value::Error(msg.into())
+    }
+
+    pub fn bulk(bytes: impl Into<Vec<u8>>) -> Value {
+        Value::BulkString(bytes.into())
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Value::Error(_))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::SimpleString(s) => {
+                out.push(b'+');
+                out.extend_from_slice(s.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            Value::Error(msg) => {
+                out.push(b'-');
+                out.extend_from_slice(msg.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            Value::Integer(n) => {
+                out.push(b':');
+                out.extend_from_slice(n.to_string().as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            Value::BulkString(bytes) => {
+                out.push(b'$');
+                out.extend_from_slice(bytes.len().to_string().as_bytes());
+                out.extend_from_slice(b"\r\n");
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
+            }
+            Value::Null => out.extend_from_slice(b"$-1\r\n"),
+            Value::Array(items) => {
+                out.push(b'*');
+                out.extend_from_slice(items.len().to_string().as_bytes());
+                out.extend_from_slice(b"\r\n");
+                for item in items {
+                    item.encode_into(out);
+                }
+            }
+            Value::NullArray => out.extend_from_slice(b"*-1\r\n"),
+        }
+    }
+}
+
+/// Reads one RESP value, returns `None` when the stream ended cleanly before a new value started.
+pub fn read_value<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let value = match parse_header(&line)? {
+        Header::Value(value) => value,
+        Header::Bulk(len) => {
+            let mut bytes = Vec::with_capacity((len + 2).min(INITIAL_CAPACITY));
+            reader.take(len as u64 + 2).read_to_end(&mut bytes)?;
+            bulk_from_crlf_terminated(len, bytes)?
+        }
+        Header::Array(len) => {
+            let mut items = Vec::with_capacity(len.min(INITIAL_CAPACITY));
+            for _ in 0..len {
+                let item =
+                    read_value(reader)?.ok_or_else(|| protocol_error("unexpected end of array"))?;
+                items.push(item);
+            }
+            Value::Array(items)
+        }
+    };
+    Ok(Some(value))
+}
+
+/// The first line of a RESP value: the value itself, or the length of what follows it.
+pub(crate) enum Header {
+    Value(Value),
+    Bulk(usize),
+    Array(usize),
+}
+
+pub(crate) fn parse_header(line: &[u8]) -> io::Result<Header> {
+    if line.is_empty() {
+        return Err(protocol_error("empty line"));
+    }
+
+    let (prefix, rest) = (line[0], &line[1..]);
+    let header = match prefix {
+        b'+' => Header::Value(Value::SimpleString(
+            String::from_utf8_lossy(rest).into_owned(),
+        )),
+        b'-' => Header::Value(Value::Error(String::from_utf8_lossy(rest).into_owned())),
+        b':' => Header::Value(Value::Integer(parse_number(rest)?)),
+        b'$' => match parse_number(rest)? {
+            -1 => Header::Value(Value::Null),
+            len if len < 0 || len as u64 > MAX_BULK_LEN as u64 => {
+                return Err(protocol_error("invalid bulk length"))
+            }
+            len => Header::Bulk(len as usize),
+        },
+        b'*' => match parse_number(rest)? {
+            -1 => Header::Value(Value::NullArray),
+            len if len < 0 || len as u64 > MAX_MULTIBULK_LEN as u64 => {
+                return Err(protocol_error("invalid multibulk length"))
+            }
+            len => Header::Array(len as usize),
+        },
+        // inline command, like the ones typed in a telnet session
+        _ => Header::Value(Value::Array(
+            line.split(|b| b.is_ascii_whitespace())
+                .filter(|part| !part.is_empty())
+                .map(|part| Value::BulkString(part.to_vec()))
+                .collect(),
+        )),
+    };
+    Ok(header)
+}
+
+// `bytes` = the content of a bulk string of `len` bytes followed by CRLF
+pub(crate) fn bulk_from_crlf_terminated(len: usize, mut bytes: Vec<u8>) -> io::Result<Value> {
+    if bytes.len() < len + 2 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    if !bytes.ends_with(b"\r\n") {
+        return Err(protocol_error("bulk string not terminated by CRLF"));
+    }
+    bytes.truncate(bytes.len() - 2);
+    Ok(Value::BulkString(bytes))
+}
+
+/// Reads a command sent by a client: an array of bulk strings (or an inline command).
+pub fn read_command<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<Vec<u8>>>> {
+    match read_value(reader)? {
+        None => Ok(None),
+        Some(Value::Array(items)) => items
+            .into_iter()
+            .map(|item| match item {
+                Value::BulkString(bytes) => Ok(bytes),
+                _ => Err(protocol_error("expected bulk string")),
+            })
+            .collect::<io::Result<Vec<_>>>()
+            .map(Some),
+        Some(_) => Err(protocol_error("expected array")),
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.ends_with(b"\r\n") {
+        line.truncate(line.len() - 2);
+    } else if line.ends_with(b"\n") {
+        line.truncate(line.len() - 1);
+    } else {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(Some(line))
+}
+
+fn parse_number(bytes: &[u8]) -> io::Result<i64> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| protocol_error("invalid number"))
+}
+
+pub(crate) fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Protocol error: {msg}"))
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::{read_command, read_value, Value};
+    use std::io;
+
+    #[test]
+    fn test_encode_nested_array() {
+        let value = Value::Array(vec![
+            Value::Integer(1),
+            Value::bulk("rdb"),
+            Value::Null,
+            Value::Array(vec![Value::ok()]),
+        ]);
+
+        assert_eq!(
+            "*4\r\n:1\r\n$3\r\nrdb\r\n$-1\r\n*1\r\n+OK\r\n",
+            String::from_utf8_lossy(&value.encode())
+        );
+    }
+
+    #[test]
+    fn test_decode_roundtrip() {
+        let value = Value::Array(vec![
+            Value::error("ERR syntax error"),
+            Value::bulk(&b"binary\r\nsafe"[..]),
+            Value::NullArray,
+        ]);
+        let encoded = value.encode();
+
+        let decoded = read_value(&mut &encoded[..]).unwrap();
+
+        assert_eq!(Some(value), decoded);
+    }
+
+    #[test]
+    fn test_read_inline_command() {
+        let mut input = &b"SET  greeting hello\r\n"[..];
+
+        let command = read_command(&mut input).unwrap().unwrap();
+
+        assert_eq!(
+            vec![b"SET".to_vec(), b"greeting".to_vec(), b"hello".to_vec()],
+            command
+        );
+    }
+
+    #[test]
+    fn test_lengths_over_the_limits_are_rejected_before_reading_the_data() {
+        for input in [&b"$536870913\r\n"[..], &b"*2147483648\r\n"[..]] {
+            let err = read_value(&mut &input[..]).unwrap_err();
+
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+    }
+
+    #[test]
+    fn test_truncated_bulk_string() {
+        let mut input = &b"$1000000\r\nshort\r\n"[..];
+
+        let err = read_value(&mut input).unwrap_err();
+
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+}
@@ -0,0 +1,584 @@
+// Sentinel: monitors a primary, agrees with the other sentinels that it is down and promotes a replica
+// = https://redis.io/docs/management/sentinel/
+//
+// - every sentinel PINGs the primary, no valid reply for `down_after` = subjectively down (SDOWN)
+// - a SDOWN sentinel asks its peers with SENTINEL is-master-down-by-addr, when at least `quorum`
+//   sentinels agree the primary is objectively down (ODOWN)
+// - the sentinels then vote (once per epoch) for a leader, the leader sends REPLICAOF NO ONE to a
+//   healthy replica and points the other replicas to it
+// - the other sentinels notice the promoted replica with ROLE and switch to it too
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::resp::{self, Value};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The name of the monitored primary, as used by SENTINEL get-master-addr-by-name.
+    pub name: String,
+    /// `host:port` of the primary.
+    pub primary: String,
+    /// Number of sentinels that need to agree that the primary is down.
+    pub quorum: usize,
+    /// `host:port` of the replicas that can be promoted.
+    pub replicas: Vec<String>,
+    /// `host:port` of the other sentinels monitoring the same primary.
+    pub sentinels: Vec<String>,
+    /// Time without a valid PING reply after which the primary is considered down.
+    pub down_after: Duration,
+}
+
+#[derive(Debug)]
+struct State {
+    primary: String,
+    replicas: Vec<String>,
+    // former primaries, turned into replicas when they come back
+    demoted: Vec<String>,
+    last_ok: Instant,
+    sdown: bool,
+    current_epoch: u64,
+    // the sentinel voted for in `leader_epoch`
+    leader: Option<String>,
+    leader_epoch: u64,
+    next_election: Option<Instant>,
+}
+
+pub struct Sentinel {
+    config: Config,
+    id: String,
+    state: Mutex<State>,
+}
+
+impl Sentinel {
+    pub fn new(config: Config) -> Self {
+        let state = State {
+            primary: config.primary.clone(),
+            replicas: config.replicas.clone(),
+            demoted: vec![],
+            last_ok: Instant::now(),
+            sdown: false,
+            current_epoch: 0,
+            leader: None,
+            leader_epoch: 0,
+            next_election: None,
+        };
+        Sentinel {
+            config,
+            id: new_run_id(),
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// `host:port` of the current primary.
+    pub fn primary(&self) -> String {
+        self.state.lock().unwrap().primary.clone()
+    }
+
+    /// Monitors the primary forever.
+    pub fn run(&self) {
+        let interval =
+            (self.config.down_after / 10).clamp(Duration::from_millis(10), Duration::from_secs(1));
+        loop {
+            self.tick();
+            thread::sleep(interval);
+        }
+    }
+
+    /// Answers the commands sent to the sentinel by clients and by the other sentinels.
+    pub fn handle(&self, args: &[Vec<u8>]) -> Value {
+        let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+        match (name.as_str(), args.len()) {
+            ("ping", 1) => Value::SimpleString("PONG".into()),
+            ("sentinel", 2..) => self.handle_sentinel(args),
+            ("ping" | "sentinel", _) => Value::error(format!(
+                "ERR wrong number of arguments for '{name}' command"
+            )),
+            _ => Value::error(format!(
+                "ERR unknown command '{}', with args beginning with: {}",
+                String::from_utf8_lossy(&args[0]),
+                args[1..]
+                    .iter()
+                    .map(|arg| format!("'{}' ", String::from_utf8_lossy(arg)))
+                    .collect::<String>()
+            )),
+        }
+    }
+
+    fn handle_sentinel(&self, args: &[Vec<u8>]) -> Value {
+        let subcommand = String::from_utf8_lossy(&args[1]).to_lowercase();
+        match (subcommand.as_str(), &args[2..]) {
+            ("myid", []) => Value::bulk(self.id.as_str()),
+            ("get-master-addr-by-name", [name]) => {
+                if name != self.config.name.as_bytes() {
+                    return Value::NullArray;
+                }
+                let primary = self.primary();
+                let (host, port) = split_addr(&primary);
+                Value::Array(vec![Value::bulk(host), Value::bulk(port)])
+            }
+            ("is-master-down-by-addr", [host, port, epoch, runid]) => {
+                let Ok(epoch) = String::from_utf8_lossy(epoch).parse() else {
+                    return Value::error("ERR value is not an integer or out of range");
+                };
+                let addr = format!(
+                    "{}:{}",
+                    String::from_utf8_lossy(host),
+                    String::from_utf8_lossy(port)
+                );
+                self.is_primary_down(&addr, epoch, &String::from_utf8_lossy(runid))
+            }
+            _ => Value::error(format!(
+                "ERR Unknown sentinel subcommand '{}'",
+                String::from_utf8_lossy(&args[1])
+            )),
+        }
+    }
+
+    // reply = down state, the leader voted for (`*` when only the state is asked), leader epoch
+    fn is_primary_down(&self, addr: &str, epoch: u64, runid: &str) -> Value {
+        let mut state = self.state.lock().unwrap();
+        let down = state.primary == addr && state.sdown;
+        if runid == "*" {
+            return Value::Array(vec![
+                Value::Integer(down as i64),
+                Value::bulk("*"),
+                Value::Integer(0),
+            ]);
+        }
+        // one vote per epoch: the first candidate asking gets it
+        if epoch > state.leader_epoch {
+            state.leader = Some(runid.to_string());
+            state.leader_epoch = epoch;
+            state.current_epoch = state.current_epoch.max(epoch);
+            if runid != self.id {
+                // give the leader time to fail over before starting an election
+                state.next_election = Some(Instant::now() + self.failover_timeout());
+            }
+        }
+        Value::Array(vec![
+            Value::Integer(down as i64),
+            Value::bulk(state.leader.clone().unwrap_or_else(|| "*".into())),
+            Value::Integer(state.leader_epoch as i64),
+        ])
+    }
+
+    fn failover_timeout(&self) -> Duration {
+        self.config.down_after * 3
+    }
+
+    fn timeout(&self) -> Duration {
+        (self.config.down_after / 2).min(Duration::from_secs(1))
+    }
+
+    /// One round of monitoring, the lock is never held during network calls.
+    pub fn tick(&self) {
+        let primary = self.primary();
+        let alive = matches!(
+            call(&primary, &[b"PING"], self.timeout()),
+            Ok(Value::SimpleString(_))
+        );
+        let sdown = {
+            let mut state = self.state.lock().unwrap();
+            if alive {
+                state.last_ok = Instant::now();
+                state.sdown = false;
+            } else if !state.sdown && state.last_ok.elapsed() > self.config.down_after {
+                eprintln!("info: +sdown master {} {primary}", self.config.name);
+                state.sdown = true;
+            }
+            state.sdown
+        };
+        if alive {
+            self.reconfigure_demoted(&primary);
+            return;
+        }
+        if !sdown {
+            return;
+        }
+
+        // another sentinel may already have promoted a replica
+        if let Some(promoted) = self.find_promoted_replica() {
+            self.switch_primary(&primary, &promoted);
+            return;
+        }
+
+        let agreeing = 1 + self
+            .ask_peers(&primary, 0, "*")
+            .iter()
+            .filter(|r| r.0)
+            .count();
+        if agreeing < self.config.quorum {
+            return;
+        }
+
+        let epoch = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            match state.next_election {
+                None => {
+                    // like Redis, desynchronize the sentinels to avoid split votes
+                    eprintln!("info: +odown master {} {primary}", self.config.name);
+                    state.next_election = Some(now + random_delay(self.config.down_after));
+                    return;
+                }
+                Some(at) if now < at => return,
+                Some(_) => {}
+            }
+            state.current_epoch += 1;
+            state.leader = Some(self.id.clone());
+            state.leader_epoch = state.current_epoch;
+            state.next_election =
+                Some(now + self.failover_timeout() + random_delay(self.config.down_after));
+            state.current_epoch
+        };
+
+        let votes = 1 + self
+            .ask_peers(&primary, epoch, &self.id)
+            .iter()
+            .filter(|r| r.1 == self.id && r.2 == epoch)
+            .count();
+        let total = self.config.sentinels.len() + 1;
+        let majority = total / 2 + 1;
+        if votes >= self.config.quorum.max(majority) {
+            eprintln!(
+                "info: +elected-leader master {} epoch {epoch}",
+                self.config.name
+            );
+            self.failover(&primary);
+        }
+    }
+
+    fn ask_peers(&self, primary: &str, epoch: u64, runid: &str) -> Vec<(bool, String, u64)> {
+        let (host, port) = split_addr(primary);
+        let epoch = epoch.to_string();
+        let args: [&[u8]; 6] = [
+            b"SENTINEL",
+            b"is-master-down-by-addr",
+            host.as_bytes(),
+            port.as_bytes(),
+            epoch.as_bytes(),
+            runid.as_bytes(),
+        ];
+        self.config
+            .sentinels
+            .iter()
+            .filter_map(|peer| match call(peer, &args, self.timeout()) {
+                Ok(Value::Array(reply)) => match reply.as_slice() {
+                    [Value::Integer(down), Value::BulkString(leader), Value::Integer(epoch)] => {
+                        Some((
+                            *down == 1,
+                            String::from_utf8_lossy(leader).into_owned(),
+                            *epoch as u64,
+                        ))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn find_promoted_replica(&self) -> Option<String> {
+        let replicas = self.state.lock().unwrap().replicas.clone();
+        replicas
+            .into_iter()
+            .find(|replica| role(replica, self.timeout()).as_deref() == Some("master"))
+    }
+
+    fn failover(&self, primary: &str) {
+        let replicas = self.state.lock().unwrap().replicas.clone();
+        let promoted = replicas.iter().find(|replica| {
+            matches!(
+                call(replica, &[b"REPLICAOF", b"NO", b"ONE"], self.timeout()),
+                Ok(Value::SimpleString(_))
+            )
+        });
+        let Some(promoted) = promoted else {
+            eprintln!(
+                "error: -failover-abort-no-good-slave master {}",
+                self.config.name
+            );
+            return;
+        };
+
+        let (host, port) = split_addr(promoted);
+        for replica in replicas.iter().filter(|replica| *replica != promoted) {
+            let args: [&[u8]; 3] = [b"REPLICAOF", host.as_bytes(), port.as_bytes()];
+            if let Err(e) = call(replica, &args, self.timeout()) {
+                eprintln!("error: could not reconfigure replica {replica}: {e}");
+            }
+        }
+        self.switch_primary(primary, promoted);
+    }
+
+    fn switch_primary(&self, old: &str, new: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.primary != old {
+            return;
+        }
+        eprintln!(
+            "info: +switch-master {} {} {}",
+            self.config.name,
+            old.replace(':', " "),
+            new.replace(':', " ")
+        );
+        state.replicas.retain(|replica| replica != new);
+        state.replicas.push(old.to_string());
+        state.demoted.push(old.to_string());
+        state.primary = new.to_string();
+        state.last_ok = Instant::now();
+        state.sdown = false;
+        state.next_election = None;
+    }
+
+    // a former primary that is reachable again must follow the new primary
+    fn reconfigure_demoted(&self, primary: &str) {
+        let demoted = self.state.lock().unwrap().demoted.clone();
+        let (host, port) = split_addr(primary);
+        for addr in demoted {
+            let done = match role(&addr, self.timeout()).as_deref() {
+                Some("master") => {
+                    let args: [&[u8]; 3] = [b"REPLICAOF", host.as_bytes(), port.as_bytes()];
+                    call(&addr, &args, self.timeout()).is_ok()
+                }
+                Some(_) => true,
+                None => false,
+            };
+            if done {
+                self.state.lock().unwrap().demoted.retain(|d| *d != addr);
+            }
+        }
+    }
+}
+
+/// Asks the sentinels (`host:port`) for the address of the primary `name`, the first answer wins.
+pub fn discover_primary(sentinels: &[&str], name: &str) -> io::Result<String> {
+    let args: [&[u8]; 3] = [b"SENTINEL", b"get-master-addr-by-name", name.as_bytes()];
+    for sentinel in sentinels {
+        if let Ok(Value::Array(reply)) = call(sentinel, &args, Duration::from_secs(1)) {
+            if let [Value::BulkString(host), Value::BulkString(port)] = reply.as_slice() {
+                return Ok(format!(
+                    "{}:{}",
+                    String::from_utf8_lossy(host),
+                    String::from_utf8_lossy(port)
+                ));
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no sentinel knows the primary {name}"),
+    ))
+}
+
+// sends a single command and waits for the reply
+fn call(addr: &str, args: &[&[u8]], timeout: Duration) -> io::Result<Value> {
+    let socket_addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid address"))?;
+    let mut stream = TcpStream::connect_timeout(&socket_addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let command = Value::Array(args.iter().map(|arg| Value::bulk(*arg)).collect());
+    stream.write_all(&command.encode())?;
+    resp::read_value(&mut BufReader::new(&stream))?
+        .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+}
+
+// "master" or "slave"
+fn role(addr: &str, timeout: Duration) -> Option<String> {
+    match call(addr, &[b"ROLE"], timeout) {
+        Ok(Value::Array(reply)) => match reply.first() {
+            Some(Value::BulkString(role)) => Some(String::from_utf8_lossy(role).into_owned()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn split_addr(addr: &str) -> (&str, &str) {
+    addr.rsplit_once(':').unwrap_or((addr, ""))
+}
+
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+fn random_delay(max: Duration) -> Duration {
+    let max_ms = max.as_millis().max(1) as u64;
+    Duration::from_millis(random_u64() % max_ms)
+}
+
+// 40 hex characters, like the run id of Redis
+fn new_run_id() -> String {
+    let id: String = (0..3).map(|_| format!("{:016x}", random_u64())).collect();
+    id[..40].to_string()
+}
+
+/// Creates a sentinel that monitors its primary in a background thread.
+pub fn spawn(config: Config) -> Arc<Sentinel> {
+    let sentinel = Arc::new(Sentinel::new(config));
+    let monitor = Arc::clone(&sentinel);
+    thread::spawn(move || monitor.run());
+    sentinel
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::{discover_primary, role, spawn, Config, Sentinel};
+    use crate::db::Db;
+    use crate::resp::Value;
+    use crate::server;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn args(command: &str) -> Vec<Vec<u8>> {
+        command
+            .split_whitespace()
+            .map(|arg| arg.as_bytes().to_vec())
+            .collect()
+    }
+
+    fn config(primary: &str) -> Config {
+        Config {
+            name: "mymaster".into(),
+            primary: primary.into(),
+            quorum: 2,
+            replicas: vec![],
+            sentinels: vec![],
+            down_after: Duration::from_millis(200),
+        }
+    }
+
+    fn start_rdb_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let db = Arc::new(Mutex::new(Db::new()));
+        thread::spawn(move || server::serve(listener, db));
+        addr
+    }
+
+    fn start_sentinel(listener: TcpListener, config: Config) -> Arc<Sentinel> {
+        let sentinel = spawn(config);
+        let handler = Arc::clone(&sentinel);
+        thread::spawn(move || server::serve_with(listener, move |args| handler.handle(args)));
+        sentinel
+    }
+
+    #[test]
+    fn test_get_master_addr_by_name() {
+        let sentinel = Sentinel::new(config("127.0.0.1:6379"));
+
+        assert_eq!(
+            Value::Array(vec![Value::bulk("127.0.0.1"), Value::bulk("6379")]),
+            sentinel.handle(&args("SENTINEL get-master-addr-by-name mymaster"))
+        );
+        assert_eq!(
+            Value::NullArray,
+            sentinel.handle(&args("SENTINEL get-master-addr-by-name other"))
+        );
+        assert_eq!(
+            Value::error("ERR Unknown sentinel subcommand 'foo'"),
+            sentinel.handle(&args("SENTINEL foo"))
+        );
+    }
+
+    #[test]
+    fn test_one_vote_per_epoch() {
+        let sentinel = Sentinel::new(config("127.0.0.1:6379"));
+        let vote = |runid: &str, epoch: u64| {
+            sentinel.handle(&args(&format!(
+                "SENTINEL is-master-down-by-addr 127.0.0.1 6379 {epoch} {runid}"
+            )))
+        };
+
+        let expected = |leader: &str, epoch: i64| {
+            Value::Array(vec![
+                Value::Integer(0),
+                Value::bulk(leader),
+                Value::Integer(epoch),
+            ])
+        };
+        assert_eq!(expected("a", 1), vote("a", 1));
+        assert_eq!(expected("a", 1), vote("b", 1));
+        assert_eq!(expected("b", 2), vote("b", 2));
+        assert_eq!(expected("*", 0), vote("*", 0));
+    }
+
+    #[test]
+    fn test_failover_when_the_primary_is_down() {
+        // nothing listens on the address of the primary
+        let primary = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let replicas = vec![start_rdb_server(), start_rdb_server()];
+        for replica in &replicas {
+            let (host, port) = replica.split_once(':').unwrap();
+            let command = [&b"REPLICAOF"[..], host.as_bytes(), port.as_bytes()];
+            super::call(replica, &command, Duration::from_secs(1)).unwrap();
+        }
+
+        let listeners: Vec<_> = (0..3)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let addrs: Vec<String> = listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap().to_string())
+            .collect();
+        let sentinels: Vec<_> = listeners
+            .into_iter()
+            .enumerate()
+            .map(|(i, listener)| {
+                let mut config = config(&primary.to_string());
+                config.replicas = replicas.clone();
+                config.sentinels = addrs.iter().filter(|a| **a != addrs[i]).cloned().collect();
+                start_sentinel(listener, config)
+            })
+            .collect();
+
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while sentinels.iter().any(|s| s.primary() == primary.to_string()) {
+            assert!(Instant::now() < deadline, "no failover");
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        let promoted = sentinels[0].primary();
+        assert!(replicas.contains(&promoted));
+        assert!(sentinels.iter().all(|s| s.primary() == promoted));
+        assert_eq!(
+            Some("master".into()),
+            role(&promoted, Duration::from_secs(1))
+        );
+        let other = replicas.iter().find(|r| **r != promoted).unwrap();
+        assert_eq!(Some("slave".into()), role(other, Duration::from_secs(1)));
+
+        let sentinel_addrs: Vec<&str> = addrs.iter().map(|a| a.as_str()).collect();
+        assert_eq!(
+            promoted,
+            discover_primary(&sentinel_addrs, "mymaster").unwrap()
+        );
+    }
+}
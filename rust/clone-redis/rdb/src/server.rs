@@ -0,0 +1,56 @@
+// The rdb server: one thread per connected client, all sharing the same keyspace
+
+use std::io::{self, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::commands;
+use crate::db::Db;
+use crate::resp::{self, Value};
+
+pub fn serve(listener: TcpListener, db: Arc<Mutex<Db>>) -> io::Result<()> {
+    serve_with(listener, move |args| {
+        commands::execute(&mut db.lock().unwrap(), args)
+    })
+}
+
+/// Accepts clients and answers each of their commands with `handler`, also used by rdb-sentinel.
+pub fn serve_with<F>(listener: TcpListener, handler: F) -> io::Result<()>
+where
+    F: Fn(&[Vec<u8>]) -> Value + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let handler = Arc::clone(&handler);
+        thread::spawn(move || {
+            if let Err(e) = handle_client(stream, &*handler) {
+                eprintln!("error: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_client(stream: TcpStream, handler: &dyn Fn(&[Vec<u8>]) -> Value) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut writer = &stream;
+    loop {
+        let args = match resp::read_command(&mut reader) {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                // like Redis: reply with the protocol error and close the connection
+                writer.write_all(&Value::error(format!("ERR {e}")).encode())?;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        if args.is_empty() {
+            continue;
+        }
+        let reply = handler(&args);
+        writer.write_all(&reply.encode())?;
+    }
+}
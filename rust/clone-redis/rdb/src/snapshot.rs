@@ -0,0 +1,768 @@
+// Serialization of values (DUMP / RESTORE) and of the whole keyspace (SAVE), in the RDB format of Redis
+// = https://rdb.fnordig.de/file_format.html
+//
+// Values are written with the encodings Redis reads back: strings (type 0) and sorted sets (type 5),
+// so the payloads and snapshot files can be loaded by Redis too. On top of those, the compressed
+// strings and the listpack sorted sets written by Redis can be read. Other types are rejected.
+//
+// DUMP payload = value + RDB version (2 bytes) + CRC-64 of everything before (8 bytes)
+// snapshot file = "REDIS" + version (4 digits) + aux fields + keys + EOF + CRC-64
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::crc64::crc64;
+use crate::db::{now_ms, Db, Object};
+use crate::sorted_set::SortedSet;
+
+pub const RDB_VERSION: u16 = 11;
+
+/// The snapshot file written by SAVE when none is configured, in the working directory.
+pub const DEFAULT_DBFILENAME: &str = "dump.rdb";
+
+const TYPE_STRING: u8 = 0;
+const TYPE_ZSET: u8 = 3;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+
+const OPCODE_IDLE: u8 = 0xf8;
+const OPCODE_FREQ: u8 = 0xf9;
+const OPCODE_AUX: u8 = 0xfa;
+const OPCODE_RESIZEDB: u8 = 0xfb;
+const OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const OPCODE_EXPIRETIME: u8 = 0xfd;
+const OPCODE_SELECTDB: u8 = 0xfe;
+const OPCODE_EOF: u8 = 0xff;
+
+// special string encodings, the 2 high bits of the length are set
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+/// The name of a value type in RDB files, like the TYPE command.
+pub fn type_name(object: &Object) -> &'static str {
+    match object {
+        Object::String(_) | Object::Int(_) => "string",
+        Object::SortedSet(_) => "zset",
+    }
+}
+
+// ------------------------------------------------------------------------------
+// writing
+
+fn write_len(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.extend_from_slice(&[0x40 | (len >> 8) as u8, len as u8]);
+    } else if len <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+// integers that fit in 32 bits are stored as such, the others as their decimal representation
+fn write_int(out: &mut Vec<u8>, n: i64) {
+    if let Ok(n) = i8::try_from(n) {
+        out.extend_from_slice(&[0xc0 | ENC_INT8, n as u8]);
+    } else if let Ok(n) = i16::try_from(n) {
+        out.push(0xc0 | ENC_INT16);
+        out.extend_from_slice(&n.to_le_bytes());
+    } else if let Ok(n) = i32::try_from(n) {
+        out.push(0xc0 | ENC_INT32);
+        out.extend_from_slice(&n.to_le_bytes());
+    } else {
+        write_raw_string(out, n.to_string().as_bytes());
+    }
+}
+
+fn write_raw_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    // like Redis, only short strings are tried as integers
+    match std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+    {
+        Some(n) if bytes.len() <= 11 && n.to_string().as_bytes() == bytes => write_int(out, n),
+        _ => write_raw_string(out, bytes),
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, object: &Object) {
+    match object {
+        Object::String(bytes) => {
+            out.push(TYPE_STRING);
+            write_string(out, bytes);
+        }
+        Object::Int(n) => {
+            out.push(TYPE_STRING);
+            write_int(out, *n);
+        }
+        Object::SortedSet(set) => {
+            out.push(TYPE_ZSET_2);
+            write_len(out, set.len() as u64);
+            // highest scores first, as Redis does
+            for (member, score) in set.iter().rev() {
+                write_string(out, member);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+    }
+}
+
+/// Serializes a value for RESTORE.
+pub fn dump(object: &Object) -> Vec<u8> {
+    let mut payload = vec![];
+    write_value(&mut payload, object);
+    payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(0, &payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    payload
+}
+
+/// Serializes all the keys that are not expired, with their expiration.
+pub fn save(db: &Db) -> Vec<u8> {
+    let mut out = format!("REDIS{RDB_VERSION:04}").into_bytes();
+    out.push(OPCODE_AUX);
+    write_string(&mut out, b"rdb-ver");
+    write_string(&mut out, env!("CARGO_PKG_VERSION").as_bytes());
+    out.push(OPCODE_AUX);
+    write_string(&mut out, b"ctime");
+    write_string(&mut out, (now_ms() / 1000).to_string().as_bytes());
+
+    let keys: Vec<_> = db.iter().collect();
+    out.push(OPCODE_SELECTDB);
+    write_len(&mut out, 0);
+    out.push(OPCODE_RESIZEDB);
+    write_len(&mut out, keys.len() as u64);
+    let expires = keys.iter().filter(|(_, _, at)| at.is_some()).count();
+    write_len(&mut out, expires as u64);
+    for (key, object, expire_at) in keys {
+        if let Some(at) = expire_at {
+            out.push(OPCODE_EXPIRETIME_MS);
+            out.extend_from_slice(&at.to_le_bytes());
+        }
+        // the type comes before the key
+        let mut value = vec![];
+        write_value(&mut value, object);
+        out.push(value[0]);
+        write_string(&mut out, key);
+        out.extend_from_slice(&value[1..]);
+    }
+
+    out.push(OPCODE_EOF);
+    let crc = crc64(0, &out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+}
+
+/// Writes the snapshot to a temporary file renamed to `path`: a crash never leaves a partial file.
+pub fn save_to_file(db: &Db, path: &Path) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".tmp-{}", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(&save(db))?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+// ------------------------------------------------------------------------------
+// reading
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatError {
+    /// Position in the input where the problem was found.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RestoreError {
+    /// The payload was written by a newer version or is corrupted.
+    VersionOrChecksum,
+    BadFormat(FormatError),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotEntry {
+    pub key: Vec<u8>,
+    pub object: Object,
+    /// Unix time in ms.
+    pub expire_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub version: u16,
+    /// Metadata of the file, like the version of the server that wrote it.
+    pub aux: Vec<(Vec<u8>, Vec<u8>)>,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+impl Snapshot {
+    /// A keyspace with the entries that are not expired yet.
+    pub fn into_db(self) -> Db {
+        let mut db = Db::new();
+        let now = now_ms();
+        for entry in self.entries {
+            if entry.expire_at.is_some_and(|at| at <= now) {
+                continue;
+            }
+            db.set(entry.key.clone(), entry.object);
+            if let Some(at) = entry.expire_at {
+                db.set_expire_at(&entry.key, at);
+            }
+        }
+        db
+    }
+}
+
+enum Len {
+    Len(u64),
+    // one of the ENC_ special string encodings
+    Encoded(u8),
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, FormatError> {
+        Err(FormatError {
+            offset: self.pos,
+            message: message.into(),
+        })
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], FormatError> {
+        match self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.bytes.len())
+        {
+            Some(end) => {
+                let bytes = &self.bytes[self.pos..end];
+                self.pos = end;
+                Ok(bytes)
+            }
+            None => self.error("unexpected end of data"),
+        }
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], FormatError> {
+        Ok(self.take(N)?.try_into().expect("N bytes were taken"))
+    }
+
+    fn byte(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_len_or_encoding(&mut self) -> Result<Len, FormatError> {
+        let first = self.byte()?;
+        let len = match first >> 6 {
+            0 => (first & 0x3f) as u64,
+            1 => ((first as u64 & 0x3f) << 8) | self.byte()? as u64,
+            2 if first == 0x80 => u32::from_be_bytes(self.take_array()?) as u64,
+            2 if first == 0x81 => u64::from_be_bytes(self.take_array()?),
+            2 => return self.error(format!("unknown length encoding 0x{first:02x}")),
+            _ => return Ok(Len::Encoded(first & 0x3f)),
+        };
+        Ok(Len::Len(len))
+    }
+
+    fn read_len(&mut self) -> Result<u64, FormatError> {
+        match self.read_len_or_encoding()? {
+            Len::Len(len) => Ok(len),
+            Len::Encoded(_) => self.error("a length was expected"),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Vec<u8>, FormatError> {
+        let encoding = match self.read_len_or_encoding()? {
+            Len::Len(len) => {
+                let len = usize::try_from(len).or_else(|_| self.error("string too long"))?;
+                return Ok(self.take(len)?.to_vec());
+            }
+            Len::Encoded(encoding) => encoding,
+        };
+        let n = match encoding {
+            ENC_INT8 => self.byte()? as i8 as i64,
+            ENC_INT16 => i16::from_le_bytes(self.take_array()?) as i64,
+            ENC_INT32 => i32::from_le_bytes(self.take_array()?) as i64,
+            ENC_LZF => {
+                let compressed_len = self.read_count()?;
+                let len = self.read_len()?;
+                let start = self.pos;
+                let compressed = self.take(compressed_len)?;
+                return lzf_decompress(compressed, len).ok_or(FormatError {
+                    offset: start,
+                    message: "invalid LZF compressed string".into(),
+                });
+            }
+            _ => return self.error(format!("unknown string encoding {encoding}")),
+        };
+        Ok(n.to_string().into_bytes())
+    }
+
+    // a number of items or bytes, that can't be more than the bytes left
+    fn read_count(&mut self) -> Result<usize, FormatError> {
+        let len = self.read_len()?;
+        match usize::try_from(len) {
+            Ok(len) if len <= self.bytes.len() - self.pos => Ok(len),
+            _ => self.error(format!("invalid length {len}")),
+        }
+    }
+
+    fn check_score(&self, score: f64) -> Result<f64, FormatError> {
+        if score.is_nan() {
+            return self.error("invalid score NaN");
+        }
+        Ok(score)
+    }
+
+    fn read_value(&mut self, value_type: u8) -> Result<Object, FormatError> {
+        match value_type {
+            TYPE_STRING => Ok(Object::string(self.read_string()?)),
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let mut set = SortedSet::new();
+                for _ in 0..self.read_count()? {
+                    let member = self.read_string()?;
+                    let score = if value_type == TYPE_ZSET_2 {
+                        f64::from_le_bytes(self.take_array()?)
+                    } else {
+                        self.read_text_score()?
+                    };
+                    set.insert(member, self.check_score(score)?);
+                }
+                Ok(Object::SortedSet(set))
+            }
+            TYPE_ZSET_LISTPACK => {
+                let start = self.pos;
+                let listpack = self.read_string()?;
+                let items = parse_listpack(&listpack).ok_or(FormatError {
+                    offset: start,
+                    message: "invalid listpack".into(),
+                })?;
+                if !items.len().is_multiple_of(2) {
+                    return self.error("sorted set listpack with an odd number of items");
+                }
+                let mut set = SortedSet::new();
+                for pair in items.chunks(2) {
+                    let score = std::str::from_utf8(&pair[1])
+                        .ok()
+                        .and_then(|s| s.parse::<f64>().ok());
+                    let Some(score) = score else {
+                        return self.error("invalid score in listpack");
+                    };
+                    set.insert(pair[0].clone(), self.check_score(score)?);
+                }
+                Ok(Object::SortedSet(set))
+            }
+            _ => self.error(format!("value type {value_type} is not supported")),
+        }
+    }
+
+    // RDB_TYPE_ZSET: the score as text, with special lengths for the infinities and NaN
+    fn read_text_score(&mut self) -> Result<f64, FormatError> {
+        match self.byte()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => {
+                let text = self.take(len as usize)?;
+                match std::str::from_utf8(text).ok().and_then(|s| s.parse().ok()) {
+                    Some(score) => Ok(score),
+                    None => self.error("invalid score"),
+                }
+            }
+        }
+    }
+}
+
+// LZF = https://github.com/ning/compress/wiki/LZFFormat (the format of a chunk, without header)
+fn lzf_decompress(input: &[u8], len: u64) -> Option<Vec<u8>> {
+    let len = usize::try_from(len).ok()?;
+    let mut out = Vec::with_capacity(len.min(input.len().saturating_mul(256)));
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            // literal run
+            let run = input.get(i..i + ctrl + 1)?;
+            out.extend_from_slice(run);
+            i += ctrl + 1;
+        } else {
+            // back reference, that can overlap the bytes being copied
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i)? as usize;
+                i += 1;
+            }
+            let distance = ((ctrl & 0x1f) << 8) + *input.get(i)? as usize + 1;
+            i += 1;
+            let from = out.len().checked_sub(distance)?;
+            for k in 0..run + 2 {
+                out.push(out[from + k]);
+            }
+        }
+        if out.len() > len {
+            return None;
+        }
+    }
+    (out.len() == len).then_some(out)
+}
+
+// listpack = https://github.com/antirez/listpack/blob/master/listpack.md
+// the items are returned as strings, integers in their decimal representation
+fn parse_listpack(bytes: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let total = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?) as usize;
+    if total != bytes.len() {
+        return None;
+    }
+    let count = u16::from_le_bytes(bytes.get(4..6)?.try_into().ok()?) as usize;
+    let mut items = Vec::with_capacity(count);
+    let mut i = 6;
+    loop {
+        let first = *bytes.get(i)?;
+        if first == 0xff {
+            break;
+        }
+        let start = i;
+        let (item, header_len, data_len) = match first {
+            0x00..=0x7f => ((first as i64).to_string().into_bytes(), 1, 0),
+            0x80..=0xbf => {
+                let len = (first & 0x3f) as usize;
+                (bytes.get(i + 1..i + 1 + len)?.to_vec(), 1, len)
+            }
+            0xc0..=0xdf => {
+                // 13 bits two's complement
+                let n = ((first as i64 & 0x1f) << 8) | *bytes.get(i + 1)? as i64;
+                let n = if n >= 1 << 12 { n - (1 << 13) } else { n };
+                (n.to_string().into_bytes(), 2, 0)
+            }
+            0xe0..=0xef => {
+                let len = ((first as usize & 0x0f) << 8) | *bytes.get(i + 1)? as usize;
+                (bytes.get(i + 2..i + 2 + len)?.to_vec(), 2, len)
+            }
+            0xf0 => {
+                let len = u32::from_le_bytes(bytes.get(i + 1..i + 5)?.try_into().ok()?) as usize;
+                (bytes.get(i + 5..i + 5 + len)?.to_vec(), 5, len)
+            }
+            0xf1..=0xf4 => {
+                let size = [2, 3, 4, 8][(first - 0xf1) as usize];
+                let mut le = [0; 8];
+                le[..size].copy_from_slice(bytes.get(i + 1..i + 1 + size)?);
+                // sign extension
+                let shift = 64 - 8 * size as u32;
+                let n = (i64::from_le_bytes(le) << shift) >> shift;
+                (n.to_string().into_bytes(), 1, size)
+            }
+            _ => return None,
+        };
+        let entry_len = header_len + data_len;
+        // the entry is followed by its length, for backward traversal
+        let backlen_len = match entry_len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        i = start + entry_len + backlen_len;
+        items.push(item);
+    }
+    // the count saturates at 65535, the items must then be counted
+    let count_ok = count == u16::MAX as usize || count == items.len();
+    (i == bytes.len() - 1 && count_ok).then_some(items)
+}
+
+/// Deserializes a DUMP payload, after checking its version and checksum.
+pub fn restore(payload: &[u8]) -> Result<Object, RestoreError> {
+    let Some(body_len) = payload.len().checked_sub(10) else {
+        return Err(RestoreError::VersionOrChecksum);
+    };
+    let (body, footer) = payload.split_at(body_len);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let crc = u64::from_le_bytes(footer[2..].try_into().expect("8 bytes"));
+    if version > RDB_VERSION || crc != crc64(0, &payload[..body_len + 2]) {
+        return Err(RestoreError::VersionOrChecksum);
+    }
+
+    let mut reader = Reader::new(body);
+    let object = reader
+        .byte()
+        .and_then(|value_type| reader.read_value(value_type))
+        .map_err(RestoreError::BadFormat)?;
+    if reader.pos != body.len() {
+        return Err(RestoreError::BadFormat(FormatError {
+            offset: reader.pos,
+            message: "unexpected data after the value".into(),
+        }));
+    }
+    Ok(object)
+}
+
+/// Parses and checks a snapshot file.
+pub fn load(bytes: &[u8]) -> Result<Snapshot, FormatError> {
+    let mut reader = Reader::new(bytes);
+    let magic = reader.take(9)?;
+    let version = match magic.strip_prefix(b"REDIS") {
+        Some(version) => std::str::from_utf8(version)
+            .ok()
+            .and_then(|v| v.parse().ok()),
+        None => None,
+    };
+    let Some(version) = version else {
+        return Err(FormatError {
+            offset: 0,
+            message: "wrong signature, not a RDB file".into(),
+        });
+    };
+    if version == 0 || version > RDB_VERSION {
+        return Err(FormatError {
+            offset: 5,
+            message: format!("can't handle RDB format version {version}"),
+        });
+    }
+
+    let mut snapshot = Snapshot {
+        version,
+        aux: vec![],
+        entries: vec![],
+    };
+    let mut expire_at = None;
+    loop {
+        let opcode = reader.byte()?;
+        match opcode {
+            OPCODE_EOF => break,
+            OPCODE_AUX => {
+                let key = reader.read_string()?;
+                let value = reader.read_string()?;
+                snapshot.aux.push((key, value));
+            }
+            OPCODE_SELECTDB => {
+                let db = reader.read_len()?;
+                if db != 0 {
+                    return Err(FormatError {
+                        offset: reader.pos,
+                        message: format!("only database 0 is supported, found {db}"),
+                    });
+                }
+            }
+            OPCODE_RESIZEDB => {
+                // size hints
+                reader.read_len()?;
+                reader.read_len()?;
+            }
+            OPCODE_EXPIRETIME_MS => expire_at = Some(u64::from_le_bytes(reader.take_array()?)),
+            OPCODE_EXPIRETIME => {
+                expire_at = Some(u32::from_le_bytes(reader.take_array()?) as u64 * 1000)
+            }
+            // eviction metadata of the next key
+            OPCODE_IDLE => {
+                reader.read_len()?;
+            }
+            OPCODE_FREQ => {
+                reader.byte()?;
+            }
+            value_type => {
+                let key = reader.read_string()?;
+                let object = reader.read_value(value_type)?;
+                snapshot.entries.push(SnapshotEntry {
+                    key,
+                    object,
+                    expire_at: expire_at.take(),
+                });
+            }
+        }
+    }
+
+    // versions before 5 have no checksum, a checksum of 0 means it was disabled
+    if version >= 5 {
+        let end = reader.pos;
+        let crc = u64::from_le_bytes(reader.take_array()?);
+        if crc != 0 && crc != crc64(0, &bytes[..end]) {
+            return Err(FormatError {
+                offset: end,
+                message: "wrong RDB checksum".into(),
+            });
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Reads a snapshot file written by `save_to_file`.
+pub fn load_file(path: &Path) -> io::Result<Db> {
+    let bytes = fs::read(path)?;
+    let snapshot =
+        load(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    Ok(snapshot.into_db())
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::{dump, load, lzf_decompress, restore, save, RestoreError, RDB_VERSION};
+    use crate::crc64::crc64;
+    use crate::db::{now_ms, Db, Object};
+    use crate::sorted_set::SortedSet;
+
+    fn sorted_set(members: &[(&str, f64)]) -> Object {
+        let mut set = SortedSet::new();
+        for (member, score) in members {
+            set.insert(member.as_bytes().to_vec(), *score);
+        }
+        Object::SortedSet(set)
+    }
+
+    #[test]
+    fn test_dump_restore() {
+        let long = "x".repeat(20_000);
+        for object in [
+            Object::Int(-3),
+            Object::Int(1 << 40),
+            Object::String(b"hello".to_vec()),
+            Object::String(long.into_bytes()),
+            sorted_set(&[("a", 1.5), ("b", f64::NEG_INFINITY), ("c", -0.25)]),
+        ] {
+            assert_eq!(Ok(object.clone()), restore(&dump(&object)));
+        }
+    }
+
+    #[test]
+    fn test_dump_format() {
+        let mut expected = vec![0, 0x05, b'h', b'e', b'l', b'l', b'o'];
+        expected.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        expected.extend_from_slice(&crc64(0, &expected).to_le_bytes());
+
+        assert_eq!(expected, dump(&Object::String(b"hello".to_vec())));
+    }
+
+    #[test]
+    fn test_restore_payload_of_redis() {
+        // DUMP of the integer 10, RDB version 9
+        let payload = b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n";
+
+        assert_eq!(Ok(Object::Int(10)), restore(payload));
+    }
+
+    #[test]
+    fn test_restore_rejects_corrupted_payloads() {
+        let mut payload = dump(&Object::String(b"hello".to_vec()));
+        payload[3] = b'X';
+        assert_eq!(Err(RestoreError::VersionOrChecksum), restore(&payload));
+
+        // from a newer version
+        let mut payload = vec![0, 0xc0, 1];
+        payload.extend_from_slice(&(RDB_VERSION + 1).to_le_bytes());
+        payload.extend_from_slice(&crc64(0, &payload).to_le_bytes());
+        assert_eq!(Err(RestoreError::VersionOrChecksum), restore(&payload));
+
+        assert_eq!(Err(RestoreError::VersionOrChecksum), restore(b"short"));
+
+        // valid checksum, but a type rdb doesn't know
+        let mut payload = vec![4, 0];
+        payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        payload.extend_from_slice(&crc64(0, &payload).to_le_bytes());
+        assert!(matches!(restore(&payload), Err(RestoreError::BadFormat(_))));
+    }
+
+    #[test]
+    fn test_restore_listpack_sorted_set() {
+        // as Redis 7 dumps small sorted sets: {"a": 1, "b": -2}, the scores as small integers
+        let mut listpack = vec![0, 0, 0, 0, 4, 0];
+        listpack.extend_from_slice(&[0x81, b'a', 2, 0x01, 1]);
+        listpack.extend_from_slice(&[0x81, b'b', 2, 0xdf, 0xfe, 2]);
+        listpack.push(0xff);
+        let total = listpack.len() as u32;
+        listpack[..4].copy_from_slice(&total.to_le_bytes());
+
+        let mut payload = vec![17, listpack.len() as u8];
+        payload.extend_from_slice(&listpack);
+        payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        payload.extend_from_slice(&crc64(0, &payload).to_le_bytes());
+
+        let expected = sorted_set(&[("a", 1.0), ("b", -2.0)]);
+        assert_eq!(Ok(expected), restore(&payload));
+    }
+
+    #[test]
+    fn test_lzf_decompress() {
+        // a literal 'a', then 9 bytes copied from 1 byte back
+        assert_eq!(
+            Some(b"aaaaaaaaaa".to_vec()),
+            lzf_decompress(&[0x00, b'a', 0xe0, 0x00, 0x00], 10)
+        );
+        // reference before the start of the output
+        assert_eq!(None, lzf_decompress(&[0x20, 0x05], 3));
+        assert_eq!(None, lzf_decompress(&[0x00, b'a'], 2));
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut db = Db::new();
+        db.set(b"greeting".to_vec(), Object::String(b"hello".to_vec()));
+        db.set(b"counter".to_vec(), Object::Int(42));
+        db.set(b"z".to_vec(), sorted_set(&[("one", 1.0), ("two", 2.0)]));
+        let expire_at = now_ms() + 60_000;
+        db.set_expire_at(b"counter", expire_at);
+
+        let bytes = save(&db);
+        assert!(bytes.starts_with(b"REDIS0011"));
+        let snapshot = load(&bytes).unwrap();
+        assert_eq!(RDB_VERSION, snapshot.version);
+        assert_eq!(3, snapshot.entries.len());
+
+        let loaded = snapshot.into_db();
+        assert_eq!(db.get(b"z"), loaded.get(b"z"));
+        assert_eq!(Some(&Object::Int(42)), loaded.get(b"counter"));
+        assert_eq!(Some(expire_at), loaded.expire_at(b"counter"));
+        assert_eq!(None, loaded.expire_at(b"greeting"));
+    }
+
+    #[test]
+    fn test_load_detects_corruption() {
+        let mut db = Db::new();
+        db.set(b"greeting".to_vec(), Object::String(b"hello".to_vec()));
+        let bytes = save(&db);
+
+        let mut corrupted = bytes.clone();
+        let len = corrupted.len();
+        corrupted[len - 12] ^= 1;
+        assert_eq!("wrong RDB checksum", load(&corrupted).unwrap_err().message);
+
+        let truncated = &bytes[..bytes.len() - 20];
+        assert_eq!(
+            "unexpected end of data",
+            load(truncated).unwrap_err().message
+        );
+        assert_eq!(0, load(b"NOTREDIS0011").unwrap_err().offset);
+    }
+}
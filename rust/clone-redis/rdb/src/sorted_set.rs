@@ -0,0 +1,110 @@
+// Sorted set: unique members ordered by score (members with the same score ordered lexicographically)
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+// scores are never NaN, so they can be totally ordered
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    ordered: BTreeSet<(Score, Vec<u8>)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the member or updates its score, returns true when the member is new.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> bool {
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.ordered.remove(&(Score(previous), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        previous.is_none()
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.ordered.remove(&(Score(score), member.to_vec()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Members with their score, from the lowest to the highest score.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&[u8], f64)> {
+        self.ordered
+            .iter()
+            .map(|(score, member)| (member.as_slice(), score.0))
+    }
+}
+
+// ------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::SortedSet;
+
+    #[test]
+    fn test_members_are_ordered_by_score_then_member() {
+        let mut set = SortedSet::new();
+        assert!(set.insert(b"b".to_vec(), 2.0));
+        assert!(set.insert(b"c".to_vec(), 1.0));
+        assert!(set.insert(b"a".to_vec(), 2.0));
+        assert!(!set.insert(b"c".to_vec(), 3.0));
+
+        let members: Vec<_> = set.iter().map(|(member, _)| member).collect();
+
+        assert_eq!(vec![&b"a"[..], b"b", b"c"], members);
+        assert_eq!(Some(3.0), set.score(b"c"));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut set = SortedSet::new();
+        set.insert(b"a".to_vec(), 1.0);
+
+        assert!(set.remove(b"a"));
+        assert!(!set.remove(b"a"));
+        assert!(set.is_empty());
+        assert_eq!(0, set.iter().count());
+    }
+}